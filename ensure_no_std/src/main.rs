@@ -17,5 +17,17 @@ pub extern "C" fn _start() -> ! {
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-#[allow(unused_imports)]
-use bonsai_trie;
+use bonsai_trie::proof::{felt_key, global_state_root, verify_proof};
+use starknet_types_core::{
+    felt::Felt,
+    hash::{Pedersen, Poseidon},
+};
+
+/// Makes sure the proof verifier builds and links, with both the Pedersen and Poseidon hashes.
+#[no_mangle]
+pub extern "C" fn verify_proof_no_std() -> bool {
+    let root = global_state_root(Felt::ONE, Felt::from(2u64));
+    let key = felt_key(&Felt::ONE);
+    verify_proof::<Pedersen>(root, &key, Felt::ONE, &[]).is_some()
+        && verify_proof::<Poseidon>(root, &key, Felt::ONE, &[]).is_some()
+}
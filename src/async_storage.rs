@@ -0,0 +1,187 @@
+//! Asynchronous read access to a trie, see [`AsyncBonsaiStorage`].
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::ToString, vec::Vec};
+use bitvec::{order::Msb0, slice::BitSlice, vec::BitVec};
+use core::marker::PhantomData;
+use parity_scale_codec::Decode;
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+
+use crate::{
+    bonsai_database::{BonsaiDatabaseAsync, DatabaseKey},
+    error::BonsaiStorageError,
+    trie::{
+        merkle_node::{Direction, Node, NodeHandle},
+        merkle_tree::{bitslice_to_bytes, bytes_to_bitvec},
+        path::Path,
+        trie_db::is_node_key,
+        TrieKey,
+    },
+    BonsaiTrieHash, ProofNode,
+};
+
+/// Asynchronous read-only handle over a trie stored in a [`BonsaiDatabaseAsync`].
+///
+/// Reads are awaited on the database instead of blocking the thread, writes still have to go
+/// through a [`BonsaiStorage`](crate::BonsaiStorage) over the same data.
+pub struct AsyncBonsaiStorage<DB, H>
+where
+    DB: BonsaiDatabaseAsync,
+    H: StarkHash,
+{
+    db: DB,
+    root_hash: Felt,
+    _hasher: PhantomData<H>,
+}
+
+impl<DB, H> AsyncBonsaiStorage<DB, H>
+where
+    DB: BonsaiDatabaseAsync,
+    H: StarkHash,
+{
+    /// Create a new asynchronous bonsai storage instance, an empty database is read as an empty
+    /// trie.
+    pub async fn new(db: DB) -> Result<Self, BonsaiStorageError<DB::DatabaseError>> {
+        let mut storage = Self {
+            db,
+            root_hash: Felt::ZERO,
+            _hasher: PhantomData,
+        };
+        storage.reload().await?;
+        Ok(storage)
+    }
+
+    /// Reload the root of the trie from the database, to see the latest commits.
+    pub async fn reload(&mut self) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.root_hash = match self.get_node(&Path(BitVec::new())).await? {
+            Some(root) => root.hash().ok_or(BonsaiStorageError::Trie(
+                "Root doesn't exist in the storage".to_string(),
+            ))?,
+            None => Felt::ZERO,
+        };
+        Ok(())
+    }
+
+    /// Get trie root hash at the latest commit
+    pub fn root_hash(&self) -> Result<BonsaiTrieHash, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(self.root_hash)
+    }
+
+    /// Get a value in the trie.
+    pub async fn get(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::Flat(bitslice_to_bytes(key));
+        match self.db.get(&DatabaseKey::from(&key)).await? {
+            Some(value) => Ok(Some(Felt::decode(&mut value.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Checks if the key exists in the trie.
+    pub async fn contains(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::Flat(bitslice_to_bytes(key));
+        Ok(self.db.contains(&DatabaseKey::from(&key)).await?)
+    }
+
+    /// Iterate over all the key/value pairs of the trie, in key order.
+    #[allow(clippy::type_complexity)]
+    pub async fn iter(
+        &self,
+    ) -> Result<impl Iterator<Item = (BitVec<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>>
+    {
+        let mut leaves = Vec::new();
+        for (key, value) in self.db.get_by_prefix(&DatabaseKey::Flat(&[])).await? {
+            leaves.push((bytes_to_bitvec(&key), Felt::decode(&mut value.as_slice())?));
+        }
+        leaves.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(leaves.into_iter())
+    }
+
+    /// Generates a merkle-proof for a given `key`, see
+    /// [`BonsaiStorage::get_proof`](crate::BonsaiStorage::get_proof).
+    pub async fn get_proof(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Vec<ProofNode>, BonsaiStorageError<DB::DatabaseError>> {
+        let mut nodes = Vec::with_capacity(251);
+        let Some(mut node) = self.get_node(&Path(BitVec::new())).await? else {
+            return Ok(nodes);
+        };
+        if node.is_empty() {
+            return Ok(nodes);
+        }
+        loop {
+            let child_path = match node {
+                Node::Unresolved(hash) => {
+                    nodes.push(ProofNode::Edge {
+                        child: hash,
+                        path: Path(BitVec::new()),
+                    });
+                    return Ok(nodes);
+                }
+                Node::Binary(binary) => {
+                    if binary.height as usize >= key.len() {
+                        return Err(BonsaiStorageError::Trie("Key too short".to_string()));
+                    }
+                    nodes.push(ProofNode::Binary {
+                        left: handle_hash(binary.get_child(Direction::Left))?,
+                        right: handle_hash(binary.get_child(Direction::Right))?,
+                    });
+                    key[..binary.height as usize + 1].to_bitvec()
+                }
+                Node::Edge(edge) => {
+                    let end = edge.height as usize + edge.path.0.len();
+                    if end > key.len() {
+                        return Err(BonsaiStorageError::Trie("Key too short".to_string()));
+                    }
+                    nodes.push(ProofNode::Edge {
+                        child: handle_hash(edge.child)?,
+                        path: edge.path.clone(),
+                    });
+                    if !edge.path_matches(key) || end == key.len() {
+                        return Ok(nodes);
+                    }
+                    key[..end].to_bitvec()
+                }
+            };
+            // Leaves are not stored as nodes, the proof ends at their parent.
+            node = match self.get_node(&Path(child_path)).await? {
+                Some(child) => child,
+                None => return Ok(nodes),
+            };
+        }
+    }
+
+    async fn get_node(
+        &self,
+        path: &Path,
+    ) -> Result<Option<Node>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::from(path);
+        if !is_node_key(key.as_slice()) {
+            return Ok(None);
+        }
+        self.db
+            .get(&DatabaseKey::from(&key))
+            .await?
+            .map(|node| {
+                Node::decode(&mut node.as_slice()).map_err(|err| {
+                    BonsaiStorageError::Trie(format!("Couldn't decode node: {}", err))
+                })
+            })
+            .map_or(Ok(None), |r| r.map(Some))
+    }
+}
+
+/// Hash of a committed child, committed nodes never reference in-memory nodes.
+fn handle_hash<E: crate::DBError>(handle: NodeHandle) -> Result<Felt, BonsaiStorageError<E>> {
+    match handle {
+        NodeHandle::Hash(hash) => Ok(hash),
+        NodeHandle::InMemory(_) => Err(BonsaiStorageError::Trie(
+            "Committed node references an in-memory node".to_string(),
+        )),
+    }
+}
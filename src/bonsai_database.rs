@@ -1,11 +1,15 @@
 use crate::id::Id;
 #[cfg(not(feature = "std"))]
-use alloc::vec::Vec;
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    future::{ready, Future},
+    ops::ControlFlow,
+};
 #[cfg(feature = "std")]
 use std::error::Error;
 
 /// Key in the database of the different elements that can be stored in the database.
-#[derive(Debug, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum DatabaseKey<'a> {
     Trie(&'a [u8]),
     Flat(&'a [u8]),
@@ -22,6 +26,9 @@ impl DatabaseKey<'_> {
     }
 }
 
+/// Iterator over the key/value pairs read by [`BonsaiDatabase::iter_prefix`]
+pub type KeyValueIter<'a, E> = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), E>> + 'a>;
+
 #[cfg(feature = "std")]
 pub trait DBError: Error + Send + Sync {}
 
@@ -42,6 +49,13 @@ pub trait BonsaiDatabase {
     /// Returns the value of the key if it exists
     fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError>;
 
+    /// Returns the values of the keys if they exist, in the same order as the keys.
+    /// Databases that can read several keys at once should override it, it reads them one by one
+    /// by default.
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<Vec<u8>>>, Self::DatabaseError> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
     #[allow(clippy::type_complexity)]
     /// Returns all values with keys that start with the given prefix
     fn get_by_prefix(
@@ -49,6 +63,34 @@ pub trait BonsaiDatabase {
         prefix: &DatabaseKey,
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError>;
 
+    /// Returns an iterator over all values with keys that start with the given prefix.
+    /// Databases that can read their keys lazily should override it so that big tries can be read
+    /// without loading them in memory, it reads them all with `get_by_prefix` by default.
+    fn iter_prefix(&self, prefix: &DatabaseKey) -> KeyValueIter<'_, Self::DatabaseError> {
+        match self.get_by_prefix(prefix) {
+            Ok(values) => Box::new(values.into_iter().map(Ok)),
+            Err(err) => Box::new(core::iter::once(Err(err))),
+        }
+    }
+
+    /// Calls `visitor` with all the key/value pairs with keys that start with the given prefix,
+    /// until it returns [`ControlFlow::Break`].
+    /// Databases that can lend their keys and values should override it so that pairs are visited
+    /// without being copied, it copies them with `iter_prefix` by default.
+    fn visit_prefix(
+        &self,
+        prefix: &DatabaseKey,
+        visitor: &mut dyn FnMut(&[u8], &[u8]) -> ControlFlow<()>,
+    ) -> Result<(), Self::DatabaseError> {
+        for kv in self.iter_prefix(prefix) {
+            let (key, value) = kv?;
+            if visitor(&key, &value).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     /// Returns true if the key exists
     fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError>;
 
@@ -96,3 +138,59 @@ pub trait BonsaiPersistentDatabase<ID: Id> {
     /// Merge a transaction in the current persistent database
     fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError>;
 }
+
+/// Asynchronous counterpart of the reading part of [`BonsaiDatabase`], for databases that are
+/// reached through the network or an async runtime.
+///
+/// It is implemented for every [`BonsaiDatabase`], the returned futures then resolve immediately
+/// as the reads are done synchronously when the function is called.
+pub trait BonsaiDatabaseAsync {
+    #[cfg(feature = "std")]
+    type DatabaseError: Error + DBError;
+    #[cfg(not(feature = "std"))]
+    type DatabaseError: DBError;
+
+    /// Returns the value of the key if it exists
+    fn get(
+        &self,
+        key: &DatabaseKey,
+    ) -> impl Future<Output = Result<Option<Vec<u8>>, Self::DatabaseError>> + Send;
+
+    #[allow(clippy::type_complexity)]
+    /// Returns all values with keys that start with the given prefix
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> impl Future<Output = Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError>> + Send;
+
+    /// Returns true if the key exists
+    fn contains(
+        &self,
+        key: &DatabaseKey,
+    ) -> impl Future<Output = Result<bool, Self::DatabaseError>> + Send;
+}
+
+impl<DB: BonsaiDatabase> BonsaiDatabaseAsync for DB {
+    type DatabaseError = DB::DatabaseError;
+
+    fn get(
+        &self,
+        key: &DatabaseKey,
+    ) -> impl Future<Output = Result<Option<Vec<u8>>, Self::DatabaseError>> + Send {
+        ready(BonsaiDatabase::get(self, key))
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> impl Future<Output = Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError>> + Send {
+        ready(BonsaiDatabase::get_by_prefix(self, prefix))
+    }
+
+    fn contains(
+        &self,
+        key: &DatabaseKey,
+    ) -> impl Future<Output = Result<bool, Self::DatabaseError>> + Send {
+        ready(BonsaiDatabase::contains(self, key))
+    }
+}
@@ -37,12 +37,24 @@ impl ChangeBatch {
         }
     }
 
+    /// Merges `later`, made after the changes of the batch, into it: a key changed by both keeps
+    /// its old value from the batch and takes its new value from `later`.
+    pub fn merge_later(&mut self, later: ChangeBatch) {
+        for (key, change) in later.0 {
+            match self.0.entry(key) {
+                Entry::Occupied(mut entry) => entry.get_mut().new_value = change.new_value,
+                Entry::Vacant(entry) => {
+                    entry.insert(change);
+                }
+            }
+        }
+    }
+
     pub fn serialize<ID: Id>(&self, id: &ID) -> Vec<(Vec<u8>, &[u8])> {
         let id = id.to_bytes();
         self.0
             .iter()
             .flat_map(|(change_key, change)| {
-                let key_slice = change_key.as_slice();
                 let mut changes = Vec::new();
 
                 if let Some(old_value) = &change.old_value {
@@ -51,33 +63,24 @@ impl ChangeBatch {
                             return changes;
                         }
                     }
-                    let key = [
-                        id.as_slice(),
-                        &[KEY_SEPARATOR],
-                        key_slice,
-                        &[change_key.into()],
-                        &[OLD_VALUE],
-                    ]
-                    .concat();
-                    changes.push((key, old_value.as_slice()));
+                    changes.push((log_key(&id, change_key, OLD_VALUE), old_value.as_slice()));
                 }
 
                 if let Some(new_value) = &change.new_value {
-                    let key = [
-                        id.as_slice(),
-                        &[KEY_SEPARATOR],
-                        key_slice,
-                        &[change_key.into()],
-                        &[NEW_VALUE],
-                    ]
-                    .concat();
-                    changes.push((key, new_value.as_slice()));
+                    changes.push((log_key(&id, change_key, NEW_VALUE), new_value.as_slice()));
                 }
                 changes
             })
             .collect()
     }
 
+    /// Keys of the old and new values of `key` in the trie log of `id`, a key missing from the
+    /// log has no old value or no new value.
+    pub fn log_keys<ID: Id>(id: &ID, key: &TrieKey) -> (Vec<u8>, Vec<u8>) {
+        let id = id.to_bytes();
+        (log_key(&id, key, OLD_VALUE), log_key(&id, key, NEW_VALUE))
+    }
+
     pub fn deserialize<ID: Id>(id: &ID, changes: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
         let id = id.to_bytes();
         let mut change_batch = ChangeBatch(HashMap::new());
@@ -115,6 +118,17 @@ impl ChangeBatch {
     }
 }
 
+fn log_key(id: &[u8], key: &TrieKey, change_type: u8) -> Vec<u8> {
+    [
+        id,
+        &[KEY_SEPARATOR],
+        key.as_slice(),
+        &[key.into()],
+        &[change_type],
+    ]
+    .concat()
+}
+
 pub struct ChangeStore<ID>
 where
     ID: Id,
@@ -0,0 +1,102 @@
+use bitvec::{order::Msb0, slice::BitSlice, vec::BitVec};
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+
+use crate::{
+    id::Id, trie::merkle_tree::MerkleTree, BonsaiDatabase, BonsaiStorage, BonsaiStorageError,
+};
+
+/// Position of a [`TrieCursor`].
+enum Position {
+    /// Before the first leaf
+    Start,
+    At(BitVec<u8, Msb0>, Felt),
+    /// After the last leaf
+    End,
+}
+
+/// Cursor over the key/value pairs of a [`BonsaiStorage`], in key order, uncommitted changes
+/// included.
+///
+/// The cursor starts before the first pair. Each move walks the trie from the root down to the
+/// next pair, without iterating over the pairs in between.
+pub struct TrieCursor<'a, ChangeID, DB, H>
+where
+    DB: BonsaiDatabase,
+    ChangeID: Id,
+    H: StarkHash,
+{
+    trie: &'a MerkleTree<H, DB, ChangeID>,
+    position: Position,
+}
+
+impl<'a, ChangeID, DB, H> TrieCursor<'a, ChangeID, DB, H>
+where
+    DB: BonsaiDatabase,
+    ChangeID: Id,
+    H: StarkHash,
+{
+    pub(crate) fn new(storage: &'a BonsaiStorage<ChangeID, DB, H>) -> Self {
+        Self {
+            trie: &storage.trie,
+            position: Position::Start,
+        }
+    }
+
+    /// Returns the pair the cursor is at, `None` if it's before the first pair or after the last
+    /// one.
+    pub fn current(&self) -> Option<(&BitSlice<u8, Msb0>, Felt)> {
+        match &self.position {
+            Position::At(key, value) => Some((key, *value)),
+            Position::Start | Position::End => None,
+        }
+    }
+
+    /// Moves to the first pair with a key greater than or equal to `key` and returns it. The
+    /// cursor is after the last pair if there is none.
+    #[allow(clippy::type_complexity)]
+    pub fn seek(
+        &mut self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<(&BitSlice<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        let leaf = self.trie.seek(key)?;
+        self.move_to(leaf, Position::End);
+        Ok(self.current())
+    }
+
+    /// Moves to the next pair and returns it, or to the first pair if the cursor is before it.
+    /// The cursor is after the last pair if there is none.
+    #[allow(clippy::type_complexity, clippy::should_implement_trait)]
+    pub fn next(
+        &mut self,
+    ) -> Result<Option<(&BitSlice<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        let leaf = match &self.position {
+            Position::Start => self.trie.seek(BitSlice::empty())?,
+            Position::At(key, _) => self.trie.next_leaf(key)?,
+            Position::End => None,
+        };
+        self.move_to(leaf, Position::End);
+        Ok(self.current())
+    }
+
+    /// Moves to the previous pair and returns it, or to the last pair if the cursor is after it.
+    /// The cursor is before the first pair if there is none.
+    #[allow(clippy::type_complexity)]
+    pub fn prev(
+        &mut self,
+    ) -> Result<Option<(&BitSlice<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        let leaf = match &self.position {
+            Position::Start => None,
+            Position::At(key, _) => self.trie.prev_leaf(key)?,
+            Position::End => self.trie.iter().reversed().next().transpose()?,
+        };
+        self.move_to(leaf, Position::Start);
+        Ok(self.current())
+    }
+
+    fn move_to(&mut self, leaf: Option<(BitVec<u8, Msb0>, Felt)>, otherwise: Position) {
+        self.position = match leaf {
+            Some((key, value)) => Position::At(key, value),
+            None => otherwise,
+        };
+    }
+}
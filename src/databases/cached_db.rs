@@ -0,0 +1,339 @@
+use std::{
+    num::NonZeroUsize,
+    ops::ControlFlow,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use lru::LruCache;
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DatabaseKey, KeyValueIter},
+    id::Id,
+};
+use log::trace;
+
+/// Key along with the discriminant of its kind.
+type CacheKey = (u8, Vec<u8>);
+
+fn cache_key(key: &DatabaseKey) -> CacheKey {
+    let kind = match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    };
+    (kind, key.as_slice().to_vec())
+}
+
+fn database_key((kind, key): &CacheKey) -> DatabaseKey {
+    match kind {
+        0 => DatabaseKey::Trie(key),
+        1 => DatabaseKey::Flat(key),
+        _ => DatabaseKey::TrieLog(key),
+    }
+}
+
+/// Configuration for the cached database
+#[derive(Clone, Copy)]
+pub struct CachedDbConfig {
+    /// Maximum number of entries kept in the cache
+    pub capacity: NonZeroUsize,
+}
+
+impl Default for CachedDbConfig {
+    fn default() -> Self {
+        Self {
+            capacity: NonZeroUsize::new(100_000).unwrap(),
+        }
+    }
+}
+
+/// Statistics of the cache of a [`CachedDb`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of reads answered by the cache
+    pub hits: u64,
+    /// Number of reads that went to the inner database
+    pub misses: u64,
+    /// Number of entries evicted to make room for new ones
+    pub evictions: u64,
+    /// Number of entries removed because their key was written
+    pub invalidations: u64,
+}
+
+struct Cache {
+    /// Values of the keys, `None` when the key is known to be absent
+    entries: LruCache<CacheKey, Option<Vec<u8>>>,
+    stats: CacheStats,
+}
+
+impl Cache {
+    fn put(&mut self, key: CacheKey, value: Option<Vec<u8>>) {
+        if let Some((evicted, _)) = self.entries.push(key.clone(), value) {
+            if evicted != key {
+                self.stats.evictions += 1;
+            }
+        }
+    }
+
+    fn invalidate(&mut self, key: &CacheKey) {
+        if self.entries.pop(key).is_some() {
+            self.stats.invalidations += 1;
+        }
+    }
+}
+
+/// A database wrapper keeping the most recently used key/value pairs of the inner database in
+/// memory
+///
+/// Reads of single keys, including the ones of absent keys, are cached. Writes without batch
+/// update the cache, while keys written in a batch are invalidated when they are added to it and
+/// when it's written. Reads by prefix always go to the inner database.
+pub struct CachedDb<D> {
+    inner: D,
+    config: CachedDbConfig,
+    cache: Mutex<Cache>,
+}
+
+impl<D: BonsaiDatabase> CachedDb<D> {
+    /// Creates a new cached database over the given database
+    pub fn new(inner: D, config: CachedDbConfig) -> Self {
+        Self {
+            inner,
+            config,
+            cache: Mutex::new(Cache {
+                entries: LruCache::new(config.capacity),
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    /// Returns the inner database
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Returns the statistics of the cache
+    pub fn cache_stats(&self) -> CacheStats {
+        self.lock_cache().stats
+    }
+
+    /// Removes all the entries of the cache
+    pub fn clear_cache(&self) {
+        self.lock_cache().entries.clear();
+    }
+
+    fn lock_cache(&self) -> MutexGuard<'_, Cache> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn cached(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, D::DatabaseError> {
+        let key = cache_key(key);
+        {
+            let mut cache = self.lock_cache();
+            if let Some(value) = cache.entries.get(&key).cloned() {
+                cache.stats.hits += 1;
+                return Ok(value);
+            }
+            cache.stats.misses += 1;
+        }
+        let value = self.inner.get(&database_key(&key))?;
+        self.lock_cache().put(key, value.clone());
+        Ok(value)
+    }
+}
+
+/// A batch of changes along with the keys to invalidate once it's written
+pub struct CachedDbBatch<D: BonsaiDatabase> {
+    inner: D::Batch,
+    keys: Vec<CacheKey>,
+}
+
+impl<D: BonsaiDatabase> Default for CachedDbBatch<D> {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            keys: Vec::new(),
+        }
+    }
+}
+
+impl<D: BonsaiDatabase> BonsaiDatabase for CachedDb<D> {
+    type Batch = CachedDbBatch<D>;
+    type DatabaseError = D::DatabaseError;
+
+    fn create_batch(&self) -> Self::Batch {
+        CachedDbBatch {
+            inner: self.inner.create_batch(),
+            keys: Vec::new(),
+        }
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        self.inner.dump_database();
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into cached database: {:?} {:?}", key, value);
+        match batch {
+            Some(batch) => {
+                let old_value = self.inner.insert(key, value, Some(&mut batch.inner))?;
+                let key = cache_key(key);
+                self.lock_cache().invalidate(&key);
+                batch.keys.push(key);
+                Ok(old_value)
+            }
+            None => {
+                let old_value = self.inner.insert(key, value, None)?;
+                self.lock_cache().put(cache_key(key), Some(value.to_vec()));
+                Ok(old_value)
+            }
+        }
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from cached database: {:?}", key);
+        self.cached(key)
+    }
+
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<Vec<u8>>>, Self::DatabaseError> {
+        trace!("Getting {} keys from cached database", keys.len());
+        let mut values = Vec::with_capacity(keys.len());
+        let mut missing = Vec::new();
+        {
+            let mut cache = self.lock_cache();
+            for (index, key) in keys.iter().enumerate() {
+                match cache.entries.get(&cache_key(key)).cloned() {
+                    Some(value) => {
+                        cache.stats.hits += 1;
+                        values.push(value);
+                    }
+                    None => {
+                        cache.stats.misses += 1;
+                        values.push(None);
+                        missing.push(index);
+                    }
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(values);
+        }
+        let missing_keys: Vec<_> = missing.iter().map(|index| keys[*index]).collect();
+        let read = self.inner.get_many(&missing_keys)?;
+        let mut cache = self.lock_cache();
+        for (index, value) in missing.into_iter().zip(read) {
+            cache.put(cache_key(&keys[index]), value.clone());
+            values[index] = value;
+        }
+        Ok(values)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from cached database: {:?}", prefix);
+        self.inner.get_by_prefix(prefix)
+    }
+
+    fn iter_prefix(&self, prefix: &DatabaseKey) -> KeyValueIter<'_, Self::DatabaseError> {
+        trace!("Iterating over cached database: {:?}", prefix);
+        self.inner.iter_prefix(prefix)
+    }
+
+    fn visit_prefix(
+        &self,
+        prefix: &DatabaseKey,
+        visitor: &mut dyn FnMut(&[u8], &[u8]) -> ControlFlow<()>,
+    ) -> Result<(), Self::DatabaseError> {
+        trace!("Visiting cached database: {:?}", prefix);
+        self.inner.visit_prefix(prefix, visitor)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if cached database contains: {:?}", key);
+        Ok(self.cached(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from cached database: {:?}", key);
+        match batch {
+            Some(batch) => {
+                let old_value = self.inner.remove(key, Some(&mut batch.inner))?;
+                let key = cache_key(key);
+                self.lock_cache().invalidate(&key);
+                batch.keys.push(key);
+                Ok(old_value)
+            }
+            None => {
+                let old_value = self.inner.remove(key, None)?;
+                self.lock_cache().put(cache_key(key), None);
+                Ok(old_value)
+            }
+        }
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from cached database: {:?}", prefix);
+        self.inner.remove_by_prefix(prefix)?;
+        let (kind, prefix) = cache_key(prefix);
+        let mut cache = self.lock_cache();
+        let keys: Vec<_> = cache
+            .entries
+            .iter()
+            .filter(|((key_kind, key), _)| *key_kind == kind && key.starts_with(&prefix))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            cache.invalidate(&key);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        self.inner.write_batch(batch.inner)?;
+        // Reads made while the batch was being filled may have cached the previous values
+        let mut cache = self.lock_cache();
+        for key in batch.keys.iter() {
+            cache.invalidate(key);
+        }
+        Ok(())
+    }
+}
+
+impl<ID, D> BonsaiPersistentDatabase<ID> for CachedDb<D>
+where
+    ID: Id,
+    D: BonsaiDatabase + BonsaiPersistentDatabase<ID>,
+{
+    // Transactions see another state of the database, they get a cache of their own
+    type Transaction = CachedDb<D::Transaction>;
+    type DatabaseError = <D as BonsaiPersistentDatabase<ID>>::DatabaseError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating cached database snapshot");
+        self.inner.snapshot(id);
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating cached database transaction");
+        Some(CachedDb::new(self.inner.transaction(id)?, self.config))
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        self.inner.merge(transaction.inner)?;
+        // The keys written by the transaction are not known
+        self.clear_cache();
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+use std::{error::Error as StdError, fmt, io, sync::Arc};
+
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+use crate::{
+    bonsai_database::{
+        BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey, KeyValueIter,
+    },
+    id::Id,
+};
+use log::trace;
+
+/// Tag of a value stored as is
+const RAW: u8 = 0;
+/// Tag of a value compressed without dictionary
+const ZSTD: u8 = 1;
+/// Tag of a value compressed with the dictionary
+const ZSTD_DICTIONARY: u8 = 2;
+
+/// Trains a zstd dictionary of at most `max_size` bytes on sample values, typically encoded
+/// trie nodes read from an existing database.
+pub fn train_dictionary(samples: &[Vec<u8>], max_size: usize) -> io::Result<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_size)
+}
+
+/// Configuration for the compressed database
+#[derive(Clone)]
+pub struct CompressedDbConfig {
+    /// Values smaller than this number of bytes are stored uncompressed
+    pub threshold: usize,
+    /// zstd compression level
+    pub level: i32,
+    /// Dictionary used to compress values, see [`train_dictionary`]. Values compressed with a
+    /// dictionary can't be read back without it.
+    pub dictionary: Option<Vec<u8>>,
+}
+
+impl Default for CompressedDbConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 64,
+            level: 3,
+            dictionary: None,
+        }
+    }
+}
+
+/// Compression settings shared by a database and its transactions.
+struct Codec {
+    threshold: usize,
+    level: i32,
+    dictionary: Option<(EncoderDictionary<'static>, DecoderDictionary<'static>)>,
+}
+
+impl Codec {
+    fn new(config: CompressedDbConfig) -> Self {
+        Self {
+            threshold: config.threshold,
+            level: config.level,
+            dictionary: config.dictionary.map(|dictionary| {
+                (
+                    EncoderDictionary::copy(&dictionary, config.level),
+                    DecoderDictionary::copy(&dictionary),
+                )
+            }),
+        }
+    }
+
+    /// Encodes a value as a tag, followed by the length of the value and the zstd frame when it is
+    /// compressed.
+    fn compress(&self, value: &[u8]) -> io::Result<Vec<u8>> {
+        if value.len() < self.threshold {
+            let mut encoded = Vec::with_capacity(value.len() + 1);
+            encoded.push(RAW);
+            encoded.extend_from_slice(value);
+            return Ok(encoded);
+        }
+        let (tag, frame) = match &self.dictionary {
+            Some((dictionary, _)) => (
+                ZSTD_DICTIONARY,
+                zstd::bulk::Compressor::with_prepared_dictionary(dictionary)?.compress(value)?,
+            ),
+            None => (ZSTD, zstd::bulk::compress(value, self.level)?),
+        };
+        // Keep values that don't compress well as is
+        if frame.len() + 4 >= value.len() {
+            let mut encoded = Vec::with_capacity(value.len() + 1);
+            encoded.push(RAW);
+            encoded.extend_from_slice(value);
+            return Ok(encoded);
+        }
+        let mut encoded = Vec::with_capacity(frame.len() + 5);
+        encoded.push(tag);
+        encoded.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        encoded.extend_from_slice(&frame);
+        Ok(encoded)
+    }
+
+    fn decompress(&self, encoded: &[u8]) -> io::Result<Vec<u8>> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let (tag, rest) = encoded
+            .split_first()
+            .ok_or_else(|| invalid("Empty compressed value"))?;
+        if *tag == RAW {
+            return Ok(rest.to_vec());
+        }
+        if rest.len() < 4 {
+            return Err(invalid("Truncated compressed value"));
+        }
+        let len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+        let frame = &rest[4..];
+        match *tag {
+            ZSTD => zstd::bulk::decompress(frame, len),
+            ZSTD_DICTIONARY => match &self.dictionary {
+                Some((_, dictionary)) => {
+                    zstd::bulk::Decompressor::with_prepared_dictionary(dictionary)?
+                        .decompress(frame, len)
+                }
+                None => Err(invalid("Value was compressed with a dictionary")),
+            },
+            _ => Err(invalid("Unknown compression tag")),
+        }
+    }
+}
+
+/// A database wrapper compressing values with zstd before writing them to the inner database
+///
+/// Keys are left as is so that prefix reads keep working. Values smaller than
+/// [`CompressedDbConfig::threshold`] or that don't compress well are stored uncompressed.
+pub struct CompressedDb<D> {
+    inner: D,
+    codec: Arc<Codec>,
+}
+
+impl<D: BonsaiDatabase> CompressedDb<D> {
+    /// Creates a new compressed database over the given database
+    pub fn new(inner: D, config: CompressedDbConfig) -> Self {
+        Self {
+            inner,
+            codec: Arc::new(Codec::new(config)),
+        }
+    }
+
+    /// Returns the inner database
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    fn decompress(
+        &self,
+        value: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, CompressedDbError<D::DatabaseError>> {
+        value
+            .map(|value| self.codec.decompress(&value))
+            .transpose()
+            .map_err(CompressedDbError::Compression)
+    }
+}
+
+#[derive(Debug)]
+pub enum CompressedDbError<E> {
+    Inner(E),
+    Compression(io::Error),
+}
+
+impl<E> From<E> for CompressedDbError<E> {
+    fn from(err: E) -> Self {
+        Self::Inner(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for CompressedDbError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inner(err) => write!(f, "{}", err),
+            Self::Compression(err) => write!(f, "Compression error: {}", err),
+        }
+    }
+}
+
+impl<E: DBError> DBError for CompressedDbError<E> {}
+
+impl<E: StdError> StdError for CompressedDbError<E> {}
+
+impl<D: BonsaiDatabase> BonsaiDatabase for CompressedDb<D> {
+    type Batch = D::Batch;
+    type DatabaseError = CompressedDbError<D::DatabaseError>;
+
+    fn create_batch(&self) -> Self::Batch {
+        self.inner.create_batch()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        self.inner.dump_database();
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into compressed database: {:?} {:?}", key, value);
+        let value = self
+            .codec
+            .compress(value)
+            .map_err(CompressedDbError::Compression)?;
+        let old_value = self.inner.insert(key, &value, batch)?;
+        self.decompress(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from compressed database: {:?}", key);
+        let value = self.inner.get(key)?;
+        self.decompress(value)
+    }
+
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<Vec<u8>>>, Self::DatabaseError> {
+        trace!("Getting {} keys from compressed database", keys.len());
+        self.inner
+            .get_many(keys)?
+            .into_iter()
+            .map(|value| self.decompress(value))
+            .collect()
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from compressed database: {:?}", prefix);
+        self.inner
+            .get_by_prefix(prefix)?
+            .into_iter()
+            .map(|(key, value)| {
+                let value = self
+                    .codec
+                    .decompress(&value)
+                    .map_err(CompressedDbError::Compression)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    fn iter_prefix(&self, prefix: &DatabaseKey) -> KeyValueIter<'_, Self::DatabaseError> {
+        trace!("Iterating over compressed database: {:?}", prefix);
+        Box::new(self.inner.iter_prefix(prefix).map(|kv| {
+            let (key, value) = kv?;
+            let value = self
+                .codec
+                .decompress(&value)
+                .map_err(CompressedDbError::Compression)?;
+            Ok((key, value))
+        }))
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if compressed database contains: {:?}", key);
+        Ok(self.inner.contains(key)?)
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from compressed database: {:?}", key);
+        let old_value = self.inner.remove(key, batch)?;
+        self.decompress(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from compressed database: {:?}", prefix);
+        Ok(self.inner.remove_by_prefix(prefix)?)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Ok(self.inner.write_batch(batch)?)
+    }
+}
+
+impl<ID, D> BonsaiPersistentDatabase<ID> for CompressedDb<D>
+where
+    ID: Id,
+    D: BonsaiDatabase + BonsaiPersistentDatabase<ID>,
+{
+    type Transaction = CompressedDb<D::Transaction>;
+    type DatabaseError = CompressedDbError<<D as BonsaiPersistentDatabase<ID>>::DatabaseError>;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating compressed database snapshot");
+        self.inner.snapshot(id);
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating compressed database transaction");
+        Some(CompressedDb {
+            inner: self.inner.transaction(id)?,
+            codec: Arc::clone(&self.codec),
+        })
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        Ok(self.inner.merge(transaction.inner)?)
+    }
+}
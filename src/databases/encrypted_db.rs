@@ -0,0 +1,357 @@
+use std::{error::Error as StdError, fmt, sync::Arc};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use aes_gcm_siv::Aes256GcmSiv;
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+};
+use log::trace;
+
+const NONCE_LEN: usize = 12;
+
+fn kind(key: &DatabaseKey) -> u8 {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+/// Same kind of key as `key`, over other bytes.
+fn with_bytes<'a>(key: &DatabaseKey, bytes: &'a [u8]) -> DatabaseKey<'a> {
+    match key {
+        DatabaseKey::Trie(_) => DatabaseKey::Trie(bytes),
+        DatabaseKey::Flat(_) => DatabaseKey::Flat(bytes),
+        DatabaseKey::TrieLog(_) => DatabaseKey::TrieLog(bytes),
+    }
+}
+
+/// Key as stored in the inner database, `encrypted` being the result of `encrypt_key`.
+fn with_stored_key<'a>(key: &'a DatabaseKey, encrypted: &'a Option<Vec<u8>>) -> DatabaseKey<'a> {
+    match encrypted {
+        Some(encrypted) => with_bytes(key, encrypted),
+        None => with_bytes(key, key.as_slice()),
+    }
+}
+
+/// Configuration for the encrypted database
+#[derive(Clone)]
+pub struct EncryptedDbConfig {
+    /// AES-256 key used to encrypt the values
+    pub key: [u8; 32],
+    /// AES-256 key used to encrypt the keys (None = keys are stored in clear). It must be
+    /// different from [`EncryptedDbConfig::key`].
+    pub key_encryption_key: Option<[u8; 32]>,
+}
+
+/// Ciphers shared by a database and its transactions.
+struct Ciphers {
+    values: Aes256Gcm,
+    keys: Option<Aes256GcmSiv>,
+}
+
+impl Ciphers {
+    /// Encrypts a value with a random nonce stored in front of the ciphertext. The key is used as
+    /// associated data so that a value can't be moved to another key.
+    fn encrypt_value(&self, key: &DatabaseKey, value: &[u8]) -> Result<Vec<u8>, aes_gcm::Error> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = associated_data(key);
+        let ciphertext = self.values.encrypt(
+            &nonce,
+            Payload {
+                msg: value,
+                aad: &aad,
+            },
+        )?;
+        let mut encrypted = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        Ok(encrypted)
+    }
+
+    fn decrypt_value(&self, key: &DatabaseKey, value: &[u8]) -> Result<Vec<u8>, aes_gcm::Error> {
+        if value.len() < NONCE_LEN {
+            return Err(aes_gcm::Error);
+        }
+        let (nonce, ciphertext) = value.split_at(NONCE_LEN);
+        let aad = associated_data(key);
+        self.values.decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &aad,
+            },
+        )
+    }
+
+    /// Keys are encrypted deterministically with AES-GCM-SIV and a fixed nonce, so that the same
+    /// key is always stored under the same ciphertext. Only the equality of keys is revealed.
+    fn encrypt_key(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, aes_gcm::Error> {
+        let Some(cipher) = &self.keys else {
+            return Ok(None);
+        };
+        cipher
+            .encrypt(
+                aes_gcm_siv::Nonce::from_slice(&[0; NONCE_LEN]),
+                Payload {
+                    msg: key.as_slice(),
+                    aad: &[kind(key)],
+                },
+            )
+            .map(Some)
+    }
+
+    fn decrypt_key(
+        &self,
+        cipher: &Aes256GcmSiv,
+        prefix: &DatabaseKey,
+        key: &[u8],
+    ) -> Result<Vec<u8>, aes_gcm::Error> {
+        cipher.decrypt(
+            aes_gcm_siv::Nonce::from_slice(&[0; NONCE_LEN]),
+            Payload {
+                msg: key,
+                aad: &[kind(prefix)],
+            },
+        )
+    }
+}
+
+fn associated_data(key: &DatabaseKey) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(key.as_slice().len() + 1);
+    aad.push(kind(key));
+    aad.extend_from_slice(key.as_slice());
+    aad
+}
+
+/// A database wrapper encrypting values, and optionally keys, with AES-GCM before writing them to
+/// the inner database
+///
+/// When keys are encrypted their order is lost, so reads and removals by prefix go through all
+/// the keys of the same kind.
+pub struct EncryptedDb<D> {
+    inner: D,
+    ciphers: Arc<Ciphers>,
+}
+
+impl<D: BonsaiDatabase> EncryptedDb<D> {
+    /// Creates a new encrypted database over the given database
+    pub fn new(inner: D, config: EncryptedDbConfig) -> Self {
+        Self {
+            inner,
+            ciphers: Arc::new(Ciphers {
+                values: Aes256Gcm::new(&config.key.into()),
+                keys: config
+                    .key_encryption_key
+                    .map(|key| Aes256GcmSiv::new(&key.into())),
+            }),
+        }
+    }
+
+    /// Returns the inner database
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Encrypted key when keys are encrypted, None when they are stored in clear.
+    fn encrypt_key(
+        &self,
+        key: &DatabaseKey,
+    ) -> Result<Option<Vec<u8>>, EncryptedDbError<D::DatabaseError>> {
+        self.ciphers
+            .encrypt_key(key)
+            .map_err(|_| EncryptedDbError::Encryption("Couldn't encrypt key".to_string()))
+    }
+
+    fn decrypt(
+        &self,
+        key: &DatabaseKey,
+        value: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, EncryptedDbError<D::DatabaseError>> {
+        value
+            .map(|value| self.ciphers.decrypt_value(key, &value))
+            .transpose()
+            .map_err(|_| EncryptedDbError::Encryption("Couldn't decrypt value".to_string()))
+    }
+
+    /// Key/value pairs in clear whose key starts with the prefix, along with their key as stored
+    /// in the inner database.
+    #[allow(clippy::type_complexity)]
+    fn scan_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>, Vec<u8>)>, EncryptedDbError<D::DatabaseError>> {
+        let decryption_error = |_| EncryptedDbError::Encryption("Couldn't decrypt key".to_string());
+        let mut result = Vec::new();
+        match &self.ciphers.keys {
+            Some(cipher) => {
+                for (stored_key, value) in self.inner.get_by_prefix(&with_bytes(prefix, &[]))? {
+                    let key = self
+                        .ciphers
+                        .decrypt_key(cipher, prefix, &stored_key)
+                        .map_err(decryption_error)?;
+                    if key.starts_with(prefix.as_slice()) {
+                        result.push((key, stored_key, value));
+                    }
+                }
+                result.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+            }
+            None => {
+                for (key, value) in self.inner.get_by_prefix(prefix)? {
+                    result.push((key.clone(), key, value));
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[derive(Debug)]
+pub enum EncryptedDbError<E> {
+    Inner(E),
+    Encryption(String),
+}
+
+impl<E> From<E> for EncryptedDbError<E> {
+    fn from(err: E) -> Self {
+        Self::Inner(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for EncryptedDbError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inner(err) => write!(f, "{}", err),
+            Self::Encryption(err) => write!(f, "Encryption error: {}", err),
+        }
+    }
+}
+
+impl<E: DBError> DBError for EncryptedDbError<E> {}
+
+impl<E: StdError> StdError for EncryptedDbError<E> {}
+
+impl<D: BonsaiDatabase> BonsaiDatabase for EncryptedDb<D> {
+    type Batch = D::Batch;
+    type DatabaseError = EncryptedDbError<D::DatabaseError>;
+
+    fn create_batch(&self) -> Self::Batch {
+        self.inner.create_batch()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        self.inner.dump_database();
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into encrypted database: {:?}", key);
+        let value = self
+            .ciphers
+            .encrypt_value(key, value)
+            .map_err(|_| EncryptedDbError::Encryption("Couldn't encrypt value".to_string()))?;
+        let encrypted = self.encrypt_key(key)?;
+        let old_value = self
+            .inner
+            .insert(&with_stored_key(key, &encrypted), &value, batch)?;
+        self.decrypt(key, old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from encrypted database: {:?}", key);
+        let encrypted = self.encrypt_key(key)?;
+        let value = self.inner.get(&with_stored_key(key, &encrypted))?;
+        self.decrypt(key, value)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from encrypted database: {:?}", prefix);
+        self.scan_prefix(prefix)?
+            .into_iter()
+            .map(|(key, _, value)| {
+                let value = self
+                    .ciphers
+                    .decrypt_value(&with_bytes(prefix, &key), &value)
+                    .map_err(|_| {
+                        EncryptedDbError::Encryption("Couldn't decrypt value".to_string())
+                    })?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if encrypted database contains: {:?}", key);
+        let encrypted = self.encrypt_key(key)?;
+        Ok(self.inner.contains(&with_stored_key(key, &encrypted))?)
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from encrypted database: {:?}", key);
+        let encrypted = self.encrypt_key(key)?;
+        let old_value = self
+            .inner
+            .remove(&with_stored_key(key, &encrypted), batch)?;
+        self.decrypt(key, old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from encrypted database: {:?}", prefix);
+        if self.ciphers.keys.is_none() {
+            return Ok(self.inner.remove_by_prefix(prefix)?);
+        }
+        let mut batch = self.inner.create_batch();
+        for (_, stored_key, _) in self.scan_prefix(prefix)? {
+            self.inner
+                .remove(&with_bytes(prefix, &stored_key), Some(&mut batch))?;
+        }
+        Ok(self.inner.write_batch(batch)?)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Ok(self.inner.write_batch(batch)?)
+    }
+}
+
+impl<ID, D> BonsaiPersistentDatabase<ID> for EncryptedDb<D>
+where
+    ID: Id,
+    D: BonsaiDatabase + BonsaiPersistentDatabase<ID>,
+{
+    type Transaction = EncryptedDb<D::Transaction>;
+    type DatabaseError = EncryptedDbError<<D as BonsaiPersistentDatabase<ID>>::DatabaseError>;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating encrypted database snapshot");
+        self.inner.snapshot(id);
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating encrypted database transaction");
+        Some(EncryptedDb {
+            inner: self.inner.transaction(id)?,
+            ciphers: Arc::clone(&self.ciphers),
+        })
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        Ok(self.inner.merge(transaction.inner)?)
+    }
+}
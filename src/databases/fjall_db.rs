@@ -0,0 +1,415 @@
+use std::{collections::BTreeMap, error::Error as StdError, fmt, path::Path, sync::Arc};
+
+pub use fjall::PersistMode;
+use fjall::{Config, Keyspace, PartitionCreateOptions, PartitionHandle, Snapshot};
+
+use crate::{
+    bonsai_database::{
+        BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey, KeyValueIter,
+    },
+    id::Id,
+};
+use log::trace;
+
+const TRIE_LOG_PARTITION: &str = "trie_log";
+const TRIE_PARTITION: &str = "trie";
+const FLAT_PARTITION: &str = "flat";
+
+/// Creates a new fjall keyspace from the given path
+pub fn create_fjall_db(path: impl AsRef<Path>) -> Result<Keyspace, fjall::Error> {
+    // Delete folder content
+    if path.as_ref().exists() {
+        std::fs::remove_dir_all(path.as_ref()).unwrap();
+    }
+    std::fs::create_dir_all(path.as_ref()).unwrap();
+    Config::new(path).open()
+}
+
+/// Index of the fjall partition used to store the given key.
+fn partition_index(key: &DatabaseKey) -> usize {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+/// Views of the three partitions at the same instant.
+type FjallSnapshot = Arc<[Snapshot; 3]>;
+
+/// A struct that implements the `BonsaiDatabase` trait using fjall as the underlying database
+///
+/// Each kind of key is stored in its own partition of the keyspace. Batches are written as a
+/// single atomic fjall batch, and the journal is persisted once the trie logs of a commit are
+/// written.
+pub struct FjallDb<ID: Id> {
+    keyspace: Keyspace,
+    partitions: [PartitionHandle; 3],
+    config: FjallDbConfig,
+    snapshots: BTreeMap<ID, FjallSnapshot>,
+}
+
+/// Configuration for fjall database
+pub struct FjallDbConfig {
+    /// Maximum number of snapshots kept in database
+    pub max_saved_snapshots: Option<usize>,
+    /// How the journal is persisted at the end of each commit, `None` leaves it to fjall,
+    /// a crash can then lose the last commits
+    pub commit_persist_mode: Option<PersistMode>,
+}
+
+impl Default for FjallDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(100),
+            commit_persist_mode: Some(PersistMode::SyncAll),
+        }
+    }
+}
+
+impl<ID: Id> FjallDb<ID> {
+    /// Creates a new fjall wrapper from the given fjall keyspace
+    pub fn new(keyspace: Keyspace, config: FjallDbConfig) -> Result<Self, FjallDbError> {
+        trace!("Fjall database opened");
+        let partitions = [
+            keyspace.open_partition(TRIE_PARTITION, PartitionCreateOptions::default())?,
+            keyspace.open_partition(FLAT_PARTITION, PartitionCreateOptions::default())?,
+            keyspace.open_partition(TRIE_LOG_PARTITION, PartitionCreateOptions::default())?,
+        ];
+        Ok(Self {
+            keyspace,
+            partitions,
+            config,
+            snapshots: BTreeMap::default(),
+        })
+    }
+
+    /// Persists the journal of the keyspace to disk.
+    pub fn persist(&self, mode: PersistMode) -> Result<(), FjallDbError> {
+        self.keyspace.persist(mode)?;
+        Ok(())
+    }
+
+    fn partition(&self, key: &DatabaseKey) -> &PartitionHandle {
+        &self.partitions[partition_index(key)]
+    }
+}
+
+/// A batch used to write changes in the fjall database
+///
+/// Fjall batches are created from the keyspace, so the changes are buffered here and written in
+/// a single fjall batch.
+#[derive(Default)]
+pub struct FjallDbBatch {
+    changes: Vec<(usize, Vec<u8>, Option<Vec<u8>>)>,
+    ends_commit: bool,
+}
+
+#[derive(Debug)]
+pub enum FjallDbError {
+    Fjall(fjall::Error),
+    Custom(String),
+}
+
+impl From<fjall::Error> for FjallDbError {
+    fn from(err: fjall::Error) -> Self {
+        Self::Fjall(err)
+    }
+}
+
+impl fmt::Display for FjallDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fjall(err) => write!(f, "Fjall error: {}", err),
+            Self::Custom(err) => write!(f, "Fjall error in trie: {}", err),
+        }
+    }
+}
+
+impl DBError for FjallDbError {}
+
+impl StdError for FjallDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Fjall(err) => Some(err),
+            Self::Custom(_) => None,
+        }
+    }
+}
+
+impl<ID: Id> BonsaiDatabase for FjallDb<ID> {
+    type Batch = FjallDbBatch;
+    type DatabaseError = FjallDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for partition in self.partitions.iter() {
+            for (key, value) in partition.iter().flatten() {
+                println!("{:?} {:?}", key, value);
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into fjall: {:?} {:?}", key, value);
+        let partition = self.partition(key);
+        let old_value = partition.get(key.as_slice())?;
+        if let Some(batch) = batch {
+            batch.ends_commit |= matches!(key, DatabaseKey::TrieLog(_));
+            batch.changes.push((
+                partition_index(key),
+                key.as_slice().to_vec(),
+                Some(value.to_vec()),
+            ));
+        } else {
+            partition.insert(key.as_slice(), value)?;
+        }
+        Ok(old_value.map(|value| value.to_vec()))
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from fjall: {:?}", key);
+        Ok(self
+            .partition(key)
+            .get(key.as_slice())?
+            .map(|value| value.to_vec()))
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from fjall: {:?}", prefix);
+        self.iter_prefix(prefix).collect()
+    }
+
+    fn iter_prefix(&self, prefix: &DatabaseKey) -> KeyValueIter<'_, Self::DatabaseError> {
+        trace!("Iterating over fjall: {:?}", prefix);
+        Box::new(
+            self.partition(prefix)
+                .prefix(prefix.as_slice().to_vec())
+                .map(|kv| {
+                    let (key, value) = kv?;
+                    Ok((key.to_vec(), value.to_vec()))
+                }),
+        )
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if fjall contains: {:?}", key);
+        Ok(self.partition(key).contains_key(key.as_slice())?)
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from fjall: {:?}", key);
+        let partition = self.partition(key);
+        let old_value = partition.get(key.as_slice())?;
+        if let Some(batch) = batch {
+            batch
+                .changes
+                .push((partition_index(key), key.as_slice().to_vec(), None));
+        } else {
+            partition.remove(key.as_slice())?;
+        }
+        Ok(old_value.map(|value| value.to_vec()))
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from fjall: {:?}", prefix);
+        let mut batch = self.create_batch();
+        for kv in self.partition(prefix).prefix(prefix.as_slice()) {
+            let (key, _) = kv?;
+            batch
+                .changes
+                .push((partition_index(prefix), key.to_vec(), None));
+        }
+        self.write_batch(batch)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        if batch.changes.is_empty() {
+            return Ok(());
+        }
+        let mut fjall_batch = self.keyspace.batch();
+        for (index, key, value) in batch.changes {
+            let partition = &self.partitions[index];
+            match value {
+                Some(value) => fjall_batch.insert(partition, key, value),
+                None => fjall_batch.remove(partition, key),
+            }
+        }
+        fjall_batch.commit()?;
+        // The trie logs are the last batch written by a commit, persisting the journal once
+        // there makes the whole commit durable without syncing every batch
+        if batch.ends_commit {
+            if let Some(mode) = self.config.commit_persist_mode {
+                self.keyspace.persist(mode)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A transaction over a fjall snapshot.
+///
+/// Reads go through views of the partitions at the time of the snapshot, so commits made on the
+/// database afterwards are not seen. The changes made to the transaction are kept in memory and
+/// written back to the database on merge.
+pub struct FjallTransaction {
+    snapshot: FjallSnapshot,
+    changes: [BTreeMap<Vec<u8>, Option<Vec<u8>>>; 3],
+}
+
+impl FjallTransaction {
+    fn read(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, FjallDbError> {
+        let index = partition_index(key);
+        if let Some(value) = self.changes[index].get(key.as_slice()) {
+            return Ok(value.clone());
+        }
+        Ok(self.snapshot[index]
+            .get(key.as_slice())?
+            .map(|value| value.to_vec()))
+    }
+}
+
+impl BonsaiDatabase for FjallTransaction {
+    type Batch = ();
+    type DatabaseError = FjallDbError;
+
+    fn create_batch(&self) -> Self::Batch {}
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for (snapshot, changes) in self.snapshot.iter().zip(self.changes.iter()) {
+            for (key, value) in snapshot.iter().flatten() {
+                println!("{:?} {:?}", key, value);
+            }
+            for (key, value) in changes.iter() {
+                println!("{:?} {:?}", key, value);
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into fjall transaction: {:?} {:?}", key, value);
+        let old_value = self.read(key)?;
+        self.changes[partition_index(key)].insert(key.as_slice().to_vec(), Some(value.to_vec()));
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from fjall transaction: {:?}", key);
+        self.read(key)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from fjall transaction: {:?}", prefix);
+        let index = partition_index(prefix);
+        let mut result = BTreeMap::new();
+        for kv in self.snapshot[index].prefix(prefix.as_slice()) {
+            let (key, value) = kv?;
+            result.insert(key.to_vec(), value.to_vec());
+        }
+        for (key, value) in self.changes[index]
+            .range(prefix.as_slice().to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix.as_slice()))
+        {
+            match value {
+                Some(value) => result.insert(key.clone(), value.clone()),
+                None => result.remove(key),
+            };
+        }
+        Ok(result.into_iter().collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if fjall transaction contains: {:?}", key);
+        Ok(self.read(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from fjall transaction: {:?}", key);
+        let old_value = self.read(key)?;
+        self.changes[partition_index(key)].insert(key.as_slice().to_vec(), None);
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from fjall transaction: {:?}", prefix);
+        let index = partition_index(prefix);
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.changes[index].insert(key, None);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, _batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Ok(())
+    }
+}
+
+impl<ID: Id> BonsaiPersistentDatabase<ID> for FjallDb<ID> {
+    type Transaction = FjallTransaction;
+    type DatabaseError = FjallDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating fjall snapshot");
+        // Fjall keeps the versions of the keys needed by the snapshots, taking one is cheap
+        let instant = self.keyspace.instant();
+        let [trie, flat, trie_log] = &self.partitions;
+        let snapshot = [
+            trie.snapshot_at(instant),
+            flat.snapshot_at(instant),
+            trie_log.snapshot_at(instant),
+        ];
+        self.snapshots.insert(id, Arc::new(snapshot));
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating fjall transaction");
+        self.snapshots.get(&id).map(|snapshot| FjallTransaction {
+            snapshot: Arc::clone(snapshot),
+            changes: Default::default(),
+        })
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        let mut batch = self.create_batch();
+        for (index, changes) in transaction.changes.into_iter().enumerate() {
+            for (key, value) in changes {
+                batch.changes.push((index, key, value));
+            }
+        }
+        self.write_batch(batch)
+    }
+}
@@ -5,7 +5,7 @@ use crate::{
 };
 #[cfg(not(feature = "std"))]
 use alloc::{collections::BTreeMap, vec::Vec};
-use core::{fmt, fmt::Display};
+use core::{fmt, fmt::Display, ops::ControlFlow};
 #[cfg(not(feature = "std"))]
 use hashbrown::HashMap;
 #[cfg(feature = "std")]
@@ -73,6 +73,19 @@ impl<ID: Id> BonsaiDatabase for HashMapDb<ID> {
         Ok(result)
     }
 
+    fn visit_prefix(
+        &self,
+        prefix: &crate::bonsai_database::DatabaseKey,
+        visitor: &mut dyn FnMut(&[u8], &[u8]) -> ControlFlow<()>,
+    ) -> Result<(), Self::DatabaseError> {
+        for (key, value) in self.db.iter() {
+            if key.starts_with(prefix.as_slice()) && visitor(key, value).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     fn insert(
         &mut self,
         key: &crate::bonsai_database::DatabaseKey,
@@ -0,0 +1,397 @@
+use std::{collections::BTreeMap, error::Error as StdError, fmt, path::Path, sync::Arc};
+
+use heed::{types::Bytes, Database, Env, EnvOpenOptions, RoTxn};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+};
+use log::trace;
+
+const TRIE_LOG_DB: &str = "trie_log";
+const TRIE_DB: &str = "trie";
+const FLAT_DB: &str = "flat";
+
+type BytesDatabase = Database<Bytes, Bytes>;
+
+/// Creates a new LMDB environment from the given path
+pub fn create_heed_env(path: impl AsRef<Path>, map_size: usize) -> Result<Env, heed::Error> {
+    // Delete folder content
+    if path.as_ref().exists() {
+        std::fs::remove_dir_all(path.as_ref()).unwrap();
+    }
+    std::fs::create_dir_all(path.as_ref()).unwrap();
+    // SAFETY: the environment is created in a freshly emptied directory, so no other process
+    // has it opened.
+    unsafe {
+        EnvOpenOptions::new()
+            .map_size(map_size)
+            .max_dbs(3)
+            .open(path)
+    }
+}
+
+/// Index of the LMDB database used to store the given key.
+fn database_index(key: &DatabaseKey) -> usize {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn read_prefix(
+    db: &BytesDatabase,
+    txn: &RoTxn,
+    prefix: &DatabaseKey,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>, HeedDbError> {
+    let mut result = Vec::new();
+    for kv in db.prefix_iter(txn, prefix.as_slice())? {
+        let (key, value) = kv?;
+        result.push((key.to_vec(), value.to_vec()));
+    }
+    Ok(result)
+}
+
+/// A struct that implements the `BonsaiDatabase` trait using LMDB (through heed) as the underlying database
+pub struct HeedDb<ID: Id> {
+    env: Env,
+    databases: [BytesDatabase; 3],
+    config: HeedDbConfig,
+    snapshots: BTreeMap<ID, Arc<RoTxn<'static>>>,
+}
+
+/// Configuration for LMDB database
+pub struct HeedDbConfig {
+    /// Maximum number of snapshots kept in database
+    pub max_saved_snapshots: Option<usize>,
+}
+
+impl Default for HeedDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(100),
+        }
+    }
+}
+
+impl<ID: Id> HeedDb<ID> {
+    /// Creates a new LMDB wrapper from the given heed environment
+    pub fn new(env: Env, config: HeedDbConfig) -> Result<Self, HeedDbError> {
+        trace!("LMDB database opened");
+        let mut txn = env.write_txn()?;
+        let databases = [
+            env.create_database(&mut txn, Some(TRIE_DB))?,
+            env.create_database(&mut txn, Some(FLAT_DB))?,
+            env.create_database(&mut txn, Some(TRIE_LOG_DB))?,
+        ];
+        txn.commit()?;
+        Ok(Self {
+            env,
+            databases,
+            config,
+            snapshots: BTreeMap::default(),
+        })
+    }
+
+    fn database(&self, key: &DatabaseKey) -> &BytesDatabase {
+        &self.databases[database_index(key)]
+    }
+}
+
+/// A batch used to write changes in the LMDB database, applied in a single write transaction
+#[derive(Default)]
+pub struct HeedDbBatch {
+    changes: Vec<(usize, Vec<u8>, Option<Vec<u8>>)>,
+}
+
+#[derive(Debug)]
+pub enum HeedDbError {
+    Heed(heed::Error),
+    Custom(String),
+}
+
+impl From<heed::Error> for HeedDbError {
+    fn from(err: heed::Error) -> Self {
+        Self::Heed(err)
+    }
+}
+
+impl fmt::Display for HeedDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Heed(err) => write!(f, "LMDB error: {}", err),
+            Self::Custom(err) => write!(f, "LMDB error in trie: {}", err),
+        }
+    }
+}
+
+impl DBError for HeedDbError {}
+
+impl StdError for HeedDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Heed(err) => Some(err),
+            Self::Custom(_) => None,
+        }
+    }
+}
+
+impl<ID: Id> BonsaiDatabase for HeedDb<ID> {
+    type Batch = HeedDbBatch;
+    type DatabaseError = HeedDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        let txn = self.env.read_txn().unwrap();
+        for database in self.databases.iter() {
+            for (key, value) in database.iter(&txn).unwrap().flatten() {
+                println!("{:?} {:?}", key, value);
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into LMDB: {:?} {:?}", key, value);
+        if let Some(batch) = batch {
+            let old_value = self.get(key)?;
+            batch.changes.push((
+                database_index(key),
+                key.as_slice().to_vec(),
+                Some(value.to_vec()),
+            ));
+            Ok(old_value)
+        } else {
+            let mut txn = self.env.write_txn()?;
+            let database = self.database(key);
+            let old_value = database.get(&txn, key.as_slice())?.map(|v| v.to_vec());
+            database.put(&mut txn, key.as_slice(), value)?;
+            txn.commit()?;
+            Ok(old_value)
+        }
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from LMDB: {:?}", key);
+        let txn = self.env.read_txn()?;
+        Ok(self
+            .database(key)
+            .get(&txn, key.as_slice())?
+            .map(|value| value.to_vec()))
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from LMDB: {:?}", prefix);
+        let txn = self.env.read_txn()?;
+        read_prefix(self.database(prefix), &txn, prefix)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if LMDB contains: {:?}", key);
+        let txn = self.env.read_txn()?;
+        Ok(self.database(key).get(&txn, key.as_slice())?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from LMDB: {:?}", key);
+        if let Some(batch) = batch {
+            let old_value = self.get(key)?;
+            batch
+                .changes
+                .push((database_index(key), key.as_slice().to_vec(), None));
+            Ok(old_value)
+        } else {
+            let mut txn = self.env.write_txn()?;
+            let database = self.database(key);
+            let old_value = database.get(&txn, key.as_slice())?.map(|v| v.to_vec());
+            database.delete(&mut txn, key.as_slice())?;
+            txn.commit()?;
+            Ok(old_value)
+        }
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from LMDB: {:?}", prefix);
+        let mut txn = self.env.write_txn()?;
+        let database = self.database(prefix);
+        for (key, _) in read_prefix(database, &txn, prefix)? {
+            database.delete(&mut txn, &key)?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        let mut txn = self.env.write_txn()?;
+        for (index, key, value) in batch.changes {
+            match value {
+                Some(value) => self.databases[index].put(&mut txn, &key, &value)?,
+                None => {
+                    self.databases[index].delete(&mut txn, &key)?;
+                }
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+/// A transaction over an LMDB snapshot.
+///
+/// Reads are served from an LMDB read transaction opened at snapshot time, while changes are kept
+/// in memory on top of it until the transaction is merged.
+pub struct HeedTransaction {
+    databases: [BytesDatabase; 3],
+    snapshot: Arc<RoTxn<'static>>,
+    changes: BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl BonsaiDatabase for HeedTransaction {
+    type Batch = ();
+    type DatabaseError = HeedDbError;
+
+    fn create_batch(&self) -> Self::Batch {}
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for (key, value) in self.changes.iter() {
+            println!("{:?} {:?}", key, value);
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into LMDB transaction: {:?} {:?}", key, value);
+        let old_value = self.get(key)?;
+        self.changes.insert(
+            (database_index(key), key.as_slice().to_vec()),
+            Some(value.to_vec()),
+        );
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from LMDB transaction: {:?}", key);
+        let index = database_index(key);
+        match self.changes.get(&(index, key.as_slice().to_vec())) {
+            Some(value) => Ok(value.clone()),
+            None => Ok(self.databases[index]
+                .get(&self.snapshot, key.as_slice())?
+                .map(|value| value.to_vec())),
+        }
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from LMDB transaction: {:?}", prefix);
+        let index = database_index(prefix);
+        let mut result: BTreeMap<Vec<u8>, Vec<u8>> =
+            read_prefix(&self.databases[index], &self.snapshot, prefix)?
+                .into_iter()
+                .collect();
+        let changes = self
+            .changes
+            .range((index, prefix.as_slice().to_vec())..)
+            .take_while(|((change_index, key), _)| {
+                *change_index == index && key.starts_with(prefix.as_slice())
+            });
+        for ((_, key), value) in changes {
+            match value {
+                Some(value) => result.insert(key.clone(), value.clone()),
+                None => result.remove(key),
+            };
+        }
+        Ok(result.into_iter().collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if LMDB transaction contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from LMDB transaction: {:?}", key);
+        let old_value = self.get(key)?;
+        self.changes
+            .insert((database_index(key), key.as_slice().to_vec()), None);
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from LMDB transaction: {:?}", prefix);
+        let index = database_index(prefix);
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.changes.insert((index, key), None);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, _batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Ok(())
+    }
+}
+
+impl<ID: Id> BonsaiPersistentDatabase<ID> for HeedDb<ID> {
+    type Transaction = HeedTransaction;
+    type DatabaseError = HeedDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating LMDB snapshot");
+        // A snapshot that can't be opened is not recorded, transactions at this id will then be
+        // unavailable.
+        if let Ok(snapshot) = self.env.clone().static_read_txn() {
+            self.snapshots.insert(id, Arc::new(snapshot));
+        }
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating LMDB transaction");
+        self.snapshots.get(&id).map(|snapshot| HeedTransaction {
+            databases: self.databases,
+            snapshot: Arc::clone(snapshot),
+            changes: BTreeMap::new(),
+        })
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        let mut batch = self.create_batch();
+        batch.changes = transaction
+            .changes
+            .into_iter()
+            .map(|((index, key), value)| (index, key, value))
+            .collect();
+        self.write_batch(batch)
+    }
+}
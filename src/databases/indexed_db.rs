@@ -0,0 +1,388 @@
+use std::{collections::BTreeMap, error::Error as StdError, fmt, sync::Arc};
+
+use js_sys::Uint8Array;
+use rexie::{ObjectStore, Rexie, TransactionMode};
+use wasm_bindgen::JsValue;
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+};
+use log::trace;
+
+const TRIE_STORE: &str = "trie";
+const FLAT_STORE: &str = "flat";
+const TRIE_LOG_STORE: &str = "trie_log";
+
+const STORES: [&str; 3] = [TRIE_STORE, FLAT_STORE, TRIE_LOG_STORE];
+
+/// Index of the object store used to store the given key.
+fn store_index(key: &DatabaseKey) -> usize {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+fn to_js(bytes: &[u8]) -> JsValue {
+    Uint8Array::from(bytes).into()
+}
+
+fn from_js(value: &JsValue) -> Vec<u8> {
+    Uint8Array::new(value).to_vec()
+}
+
+/// Copy of the whole content of the database, one map per object store.
+type IndexedDbSnapshot = Arc<[BTreeMap<Vec<u8>, Vec<u8>>; 3]>;
+
+/// A struct that implements the `BonsaiDatabase` trait using the browser IndexedDB as the
+/// underlying database
+///
+/// IndexedDB is only reachable through asynchronous calls while `BonsaiDatabase` is synchronous,
+/// so the whole content is loaded in memory by [`IndexedDbStore::open`] and written changes are
+/// buffered until they are persisted with [`IndexedDbStore::flush`].
+pub struct IndexedDbStore<ID: Id> {
+    rexie: Rexie,
+    data: [BTreeMap<Vec<u8>, Vec<u8>>; 3],
+    pending: BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>,
+    config: IndexedDbConfig,
+    snapshots: BTreeMap<ID, IndexedDbSnapshot>,
+}
+
+/// Configuration for IndexedDB database
+pub struct IndexedDbConfig {
+    /// Maximum number of snapshots kept in database
+    pub max_saved_snapshots: Option<usize>,
+}
+
+impl Default for IndexedDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(100),
+        }
+    }
+}
+
+impl<ID: Id> IndexedDbStore<ID> {
+    /// Opens (or creates) the IndexedDB database with the given name and loads its content.
+    pub async fn open(name: &str, config: IndexedDbConfig) -> Result<Self, IndexedDbError> {
+        let mut builder = Rexie::builder(name).version(1);
+        for store in STORES {
+            builder = builder.add_object_store(ObjectStore::new(store));
+        }
+        let rexie = builder.build().await?;
+
+        let mut data: [BTreeMap<Vec<u8>, Vec<u8>>; 3] = Default::default();
+        let transaction = rexie.transaction(&STORES, TransactionMode::ReadOnly)?;
+        for (index, store) in STORES.iter().enumerate() {
+            let entries = transaction
+                .store(store)?
+                .get_all(None, None, None, None)
+                .await?;
+            data[index] = entries
+                .iter()
+                .map(|(key, value)| (from_js(key), from_js(value)))
+                .collect();
+        }
+        transaction.done().await?;
+        trace!("IndexedDB database opened");
+
+        Ok(Self {
+            rexie,
+            data,
+            pending: BTreeMap::new(),
+            config,
+            snapshots: BTreeMap::default(),
+        })
+    }
+
+    /// Persists all the buffered changes to IndexedDB in a single transaction.
+    pub async fn flush(&mut self) -> Result<(), IndexedDbError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        trace!("Flushing {} changes to IndexedDB", self.pending.len());
+        let transaction = self
+            .rexie
+            .transaction(&STORES, TransactionMode::ReadWrite)?;
+        for ((index, key), value) in self.pending.iter() {
+            let store = transaction.store(STORES[*index])?;
+            match value {
+                Some(value) => {
+                    store.put(&to_js(value), Some(&to_js(key))).await?;
+                }
+                None => store.delete(&to_js(key)).await?,
+            }
+        }
+        transaction.done().await?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Returns `true` if there are changes that haven't been flushed to IndexedDB yet.
+    pub fn has_pending_changes(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    fn apply(&mut self, index: usize, key: Vec<u8>, value: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        let old_value = match &value {
+            Some(value) => self.data[index].insert(key.clone(), value.clone()),
+            None => self.data[index].remove(&key),
+        };
+        self.pending.insert((index, key), value);
+        old_value
+    }
+}
+
+/// A batch used to write changes in the IndexedDB buffer
+#[derive(Default)]
+pub struct IndexedDbBatch {
+    changes: Vec<(usize, Vec<u8>, Option<Vec<u8>>)>,
+}
+
+/// IndexedDB errors are JavaScript values that can't be sent across threads, so only their
+/// message is kept.
+#[derive(Debug)]
+pub enum IndexedDbError {
+    IndexedDb(String),
+    Custom(String),
+}
+
+impl From<rexie::Error> for IndexedDbError {
+    fn from(err: rexie::Error) -> Self {
+        Self::IndexedDb(err.to_string())
+    }
+}
+
+impl fmt::Display for IndexedDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IndexedDb(err) => write!(f, "IndexedDB error: {}", err),
+            Self::Custom(err) => write!(f, "IndexedDB error in trie: {}", err),
+        }
+    }
+}
+
+impl DBError for IndexedDbError {}
+
+impl StdError for IndexedDbError {}
+
+impl<ID: Id> BonsaiDatabase for IndexedDbStore<ID> {
+    type Batch = IndexedDbBatch;
+    type DatabaseError = IndexedDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for store in self.data.iter() {
+            for (key, value) in store.iter() {
+                println!("{:?} {:?}", key, value);
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into IndexedDB: {:?} {:?}", key, value);
+        let index = store_index(key);
+        if let Some(batch) = batch {
+            batch
+                .changes
+                .push((index, key.as_slice().to_vec(), Some(value.to_vec())));
+            Ok(self.data[index].get(key.as_slice()).cloned())
+        } else {
+            Ok(self.apply(index, key.as_slice().to_vec(), Some(value.to_vec())))
+        }
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from IndexedDB: {:?}", key);
+        Ok(self.data[store_index(key)].get(key.as_slice()).cloned())
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from IndexedDB: {:?}", prefix);
+        Ok(self.data[store_index(prefix)]
+            .range(prefix.as_slice().to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix.as_slice()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if IndexedDB contains: {:?}", key);
+        Ok(self.data[store_index(key)].contains_key(key.as_slice()))
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from IndexedDB: {:?}", key);
+        let index = store_index(key);
+        if let Some(batch) = batch {
+            batch.changes.push((index, key.as_slice().to_vec(), None));
+            Ok(self.data[index].get(key.as_slice()).cloned())
+        } else {
+            Ok(self.apply(index, key.as_slice().to_vec(), None))
+        }
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from IndexedDB: {:?}", prefix);
+        let index = store_index(prefix);
+        let mut batch = self.create_batch();
+        for (key, _) in self.get_by_prefix(prefix)? {
+            batch.changes.push((index, key, None));
+        }
+        self.write_batch(batch)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        for (index, key, value) in batch.changes {
+            self.apply(index, key, value);
+        }
+        Ok(())
+    }
+}
+
+/// A transaction over an IndexedDB snapshot.
+///
+/// The snapshot is a copy of the in-memory buffer, changes are recorded on top of it and are
+/// written back to the buffer when the transaction is merged.
+pub struct IndexedDbTransaction {
+    data: [BTreeMap<Vec<u8>, Vec<u8>>; 3],
+    changes: BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl BonsaiDatabase for IndexedDbTransaction {
+    type Batch = ();
+    type DatabaseError = IndexedDbError;
+
+    fn create_batch(&self) -> Self::Batch {}
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for store in self.data.iter() {
+            for (key, value) in store.iter() {
+                println!("{:?} {:?}", key, value);
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!(
+            "Inserting into IndexedDB transaction: {:?} {:?}",
+            key,
+            value
+        );
+        let index = store_index(key);
+        self.changes
+            .insert((index, key.as_slice().to_vec()), Some(value.to_vec()));
+        Ok(self.data[index].insert(key.as_slice().to_vec(), value.to_vec()))
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from IndexedDB transaction: {:?}", key);
+        Ok(self.data[store_index(key)].get(key.as_slice()).cloned())
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from IndexedDB transaction: {:?}", prefix);
+        Ok(self.data[store_index(prefix)]
+            .range(prefix.as_slice().to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix.as_slice()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if IndexedDB transaction contains: {:?}", key);
+        Ok(self.data[store_index(key)].contains_key(key.as_slice()))
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from IndexedDB transaction: {:?}", key);
+        let index = store_index(key);
+        self.changes.insert((index, key.as_slice().to_vec()), None);
+        Ok(self.data[index].remove(key.as_slice()))
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from IndexedDB transaction: {:?}", prefix);
+        let index = store_index(prefix);
+        let keys: Vec<Vec<u8>> = self.data[index]
+            .range(prefix.as_slice().to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix.as_slice()))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            self.data[index].remove(&key);
+            self.changes.insert((index, key), None);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, _batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Ok(())
+    }
+}
+
+impl<ID: Id> BonsaiPersistentDatabase<ID> for IndexedDbStore<ID> {
+    type Transaction = IndexedDbTransaction;
+    type DatabaseError = IndexedDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating IndexedDB snapshot");
+        self.snapshots.insert(id, Arc::new(self.data.clone()));
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating IndexedDB transaction");
+        self.snapshots
+            .get(&id)
+            .map(|snapshot| IndexedDbTransaction {
+                data: snapshot.as_ref().clone(),
+                changes: BTreeMap::new(),
+            })
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        let mut batch = self.create_batch();
+        batch.changes = transaction
+            .changes
+            .into_iter()
+            .map(|((index, key), value)| (index, key, value))
+            .collect();
+        self.write_batch(batch)
+    }
+}
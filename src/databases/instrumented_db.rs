@@ -0,0 +1,354 @@
+use std::{
+    ops::ControlFlow,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DatabaseKey, KeyValueIter},
+    id::Id,
+};
+use log::trace;
+
+/// Number of buckets of a latency histogram, the last one holds all latencies above 2^22µs.
+pub const LATENCY_BUCKETS: usize = 24;
+
+/// Distribution of operation latencies in power of two buckets of microseconds
+///
+/// Bucket 0 counts operations that took less than 1µs, bucket `i` the ones that took between
+/// 2^(i-1)µs and 2^iµs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; LATENCY_BUCKETS],
+    total: Duration,
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(LATENCY_BUCKETS - 1)] += 1;
+        self.total += latency;
+    }
+
+    /// Number of operations recorded in each bucket
+    pub fn buckets(&self) -> &[u64; LATENCY_BUCKETS] {
+        &self.buckets
+    }
+
+    /// Number of operations recorded
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Time spent in all the recorded operations
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Upper bound of the bucket holding the `quantile` (between 0 and 1) of the latencies
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        let target = (self.count() as f64 * quantile.clamp(0.0, 1.0)).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target && *count > 0 {
+                return Duration::from_micros(1 << bucket);
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+/// Operations made on one column of the database
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ColumnStats {
+    /// Number of values read, including the ones read by prefix
+    pub reads: u64,
+    /// Number of bytes of the values read
+    pub read_bytes: u64,
+    /// Number of values inserted
+    pub writes: u64,
+    /// Number of bytes of the keys and values inserted
+    pub write_bytes: u64,
+    /// Number of removals, a removal by prefix counts as one
+    pub deletes: u64,
+    /// Latency of reads, including the checks that a key exists
+    pub read_latency: LatencyHistogram,
+    /// Latency of inserts and removals, added to a batch or applied directly
+    pub write_latency: LatencyHistogram,
+}
+
+/// Snapshot of the operations made on an [`InstrumentedDb`] and its transactions
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DatabaseStats {
+    /// Operations on trie nodes
+    pub trie: ColumnStats,
+    /// Operations on the key/value pairs of the trie
+    pub flat: ColumnStats,
+    /// Operations on trie logs
+    pub trie_log: ColumnStats,
+    /// Latency of batch writes, each commit writes its changes in batches
+    pub batch_latency: LatencyHistogram,
+}
+
+impl DatabaseStats {
+    fn column(&mut self, key: &DatabaseKey) -> &mut ColumnStats {
+        match key {
+            DatabaseKey::Trie(_) => &mut self.trie,
+            DatabaseKey::Flat(_) => &mut self.flat,
+            DatabaseKey::TrieLog(_) => &mut self.trie_log,
+        }
+    }
+
+    /// Time spent in the database, in all columns
+    pub fn total_time(&self) -> Duration {
+        [&self.trie, &self.flat, &self.trie_log]
+            .iter()
+            .map(|column| column.read_latency.total() + column.write_latency.total())
+            .sum::<Duration>()
+            + self.batch_latency.total()
+    }
+}
+
+/// A database wrapper counting the operations made on the inner database
+///
+/// Counts, bytes and latencies are recorded per column and can be read with
+/// [`InstrumentedDb::stats`]. Transactions created from this database record their operations in
+/// the same statistics.
+pub struct InstrumentedDb<D> {
+    inner: D,
+    stats: Arc<Mutex<DatabaseStats>>,
+}
+
+impl<D: BonsaiDatabase> InstrumentedDb<D> {
+    /// Creates a new instrumented database over the given database
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            stats: Arc::default(),
+        }
+    }
+
+    /// Returns the inner database
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Returns a snapshot of the statistics recorded so far
+    pub fn stats(&self) -> DatabaseStats {
+        self.lock_stats().clone()
+    }
+
+    /// Resets all the statistics, returning the ones recorded so far
+    pub fn reset_stats(&self) -> DatabaseStats {
+        core::mem::take(&mut *self.lock_stats())
+    }
+
+    fn lock_stats(&self) -> MutexGuard<'_, DatabaseStats> {
+        self.stats.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<D: BonsaiDatabase> BonsaiDatabase for InstrumentedDb<D> {
+    type Batch = D::Batch;
+    type DatabaseError = D::DatabaseError;
+
+    fn create_batch(&self) -> Self::Batch {
+        self.inner.create_batch()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        self.inner.dump_database();
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into instrumented database: {:?}", key);
+        let start = Instant::now();
+        let old_value = self.inner.insert(key, value, batch)?;
+        let latency = start.elapsed();
+        let mut stats = self.lock_stats();
+        let column = stats.column(key);
+        column.writes += 1;
+        column.write_bytes += (key.as_slice().len() + value.len()) as u64;
+        column.write_latency.record(latency);
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from instrumented database: {:?}", key);
+        let start = Instant::now();
+        let value = self.inner.get(key)?;
+        let latency = start.elapsed();
+        let mut stats = self.lock_stats();
+        let column = stats.column(key);
+        if let Some(value) = &value {
+            column.reads += 1;
+            column.read_bytes += value.len() as u64;
+        }
+        column.read_latency.record(latency);
+        Ok(value)
+    }
+
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<Vec<u8>>>, Self::DatabaseError> {
+        trace!("Getting {} keys from instrumented database", keys.len());
+        let start = Instant::now();
+        let values = self.inner.get_many(keys)?;
+        // The latency of the whole read is recorded once, in the column of the first key
+        let latency = start.elapsed();
+        let mut stats = self.lock_stats();
+        for (key, value) in keys.iter().zip(values.iter()) {
+            if let Some(value) = value {
+                let column = stats.column(key);
+                column.reads += 1;
+                column.read_bytes += value.len() as u64;
+            }
+        }
+        if let Some(key) = keys.first() {
+            stats.column(key).read_latency.record(latency);
+        }
+        Ok(values)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from instrumented database: {:?}", prefix);
+        let start = Instant::now();
+        let values = self.inner.get_by_prefix(prefix)?;
+        let latency = start.elapsed();
+        let mut stats = self.lock_stats();
+        let column = stats.column(prefix);
+        column.reads += values.len() as u64;
+        column.read_bytes += values
+            .iter()
+            .map(|(_, value)| value.len() as u64)
+            .sum::<u64>();
+        column.read_latency.record(latency);
+        Ok(values)
+    }
+
+    fn iter_prefix(&self, prefix: &DatabaseKey) -> KeyValueIter<'_, Self::DatabaseError> {
+        trace!("Iterating over instrumented database: {:?}", prefix);
+        // Values are counted as they are read, the time spent between two reads is up to the caller
+        // so no latency is recorded
+        let column: fn(&mut DatabaseStats) -> &mut ColumnStats = match prefix {
+            DatabaseKey::Trie(_) => |stats| &mut stats.trie,
+            DatabaseKey::Flat(_) => |stats| &mut stats.flat,
+            DatabaseKey::TrieLog(_) => |stats| &mut stats.trie_log,
+        };
+        Box::new(self.inner.iter_prefix(prefix).inspect(move |kv| {
+            if let Ok((_, value)) = kv {
+                let mut stats = self.lock_stats();
+                let column = column(&mut stats);
+                column.reads += 1;
+                column.read_bytes += value.len() as u64;
+            }
+        }))
+    }
+
+    fn visit_prefix(
+        &self,
+        prefix: &DatabaseKey,
+        visitor: &mut dyn FnMut(&[u8], &[u8]) -> ControlFlow<()>,
+    ) -> Result<(), Self::DatabaseError> {
+        trace!("Visiting instrumented database: {:?}", prefix);
+        let (mut reads, mut read_bytes) = (0, 0);
+        let start = Instant::now();
+        self.inner.visit_prefix(prefix, &mut |key, value| {
+            reads += 1;
+            read_bytes += value.len() as u64;
+            visitor(key, value)
+        })?;
+        // Like the other reads, the time spent in the visitor is part of the latency
+        let latency = start.elapsed();
+        let mut stats = self.lock_stats();
+        let column = stats.column(prefix);
+        column.reads += reads;
+        column.read_bytes += read_bytes;
+        column.read_latency.record(latency);
+        Ok(())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if instrumented database contains: {:?}", key);
+        let start = Instant::now();
+        let contains = self.inner.contains(key)?;
+        self.lock_stats()
+            .column(key)
+            .read_latency
+            .record(start.elapsed());
+        Ok(contains)
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from instrumented database: {:?}", key);
+        let start = Instant::now();
+        let old_value = self.inner.remove(key, batch)?;
+        let latency = start.elapsed();
+        let mut stats = self.lock_stats();
+        let column = stats.column(key);
+        column.deletes += 1;
+        column.write_latency.record(latency);
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from instrumented database: {:?}", prefix);
+        let start = Instant::now();
+        self.inner.remove_by_prefix(prefix)?;
+        let latency = start.elapsed();
+        let mut stats = self.lock_stats();
+        let column = stats.column(prefix);
+        column.deletes += 1;
+        column.write_latency.record(latency);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        let start = Instant::now();
+        self.inner.write_batch(batch)?;
+        self.lock_stats().batch_latency.record(start.elapsed());
+        Ok(())
+    }
+}
+
+impl<ID, D> BonsaiPersistentDatabase<ID> for InstrumentedDb<D>
+where
+    ID: Id,
+    D: BonsaiDatabase + BonsaiPersistentDatabase<ID>,
+{
+    type Transaction = InstrumentedDb<D::Transaction>;
+    type DatabaseError = <D as BonsaiPersistentDatabase<ID>>::DatabaseError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating instrumented database snapshot");
+        self.inner.snapshot(id);
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating instrumented database transaction");
+        Some(InstrumentedDb {
+            inner: self.inner.transaction(id)?,
+            stats: Arc::clone(&self.stats),
+        })
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        let start = Instant::now();
+        self.inner.merge(transaction.inner)?;
+        self.lock_stats().batch_latency.record(start.elapsed());
+        Ok(())
+    }
+}
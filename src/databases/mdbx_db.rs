@@ -0,0 +1,439 @@
+use std::{collections::BTreeMap, error::Error as StdError, fmt, path::Path, sync::Arc};
+
+use libmdbx::{
+    Database, DatabaseKind, DatabaseOptions, Mode, ReadWriteOptions, SyncMode, TableFlags,
+    Transaction, WriteFlags, RO,
+};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+};
+use log::trace;
+
+pub use libmdbx::{NoWriteMap, WriteMap};
+
+const TRIE_LOG_TABLE: &str = "trie_log";
+const TRIE_TABLE: &str = "trie";
+const FLAT_TABLE: &str = "flat";
+
+const TABLES: [&str; 3] = [TRIE_TABLE, FLAT_TABLE, TRIE_LOG_TABLE];
+
+/// Geometry of the MDBX database file, all sizes are in bytes.
+/// A `None` value keeps the MDBX default.
+#[derive(Clone, Debug, Default)]
+pub struct MdbxGeometry {
+    /// Lower bound of the database file size.
+    pub min_size: Option<isize>,
+    /// Upper bound of the database file size.
+    pub max_size: Option<isize>,
+    /// Step used when the database file needs to grow.
+    pub growth_step: Option<isize>,
+    /// Amount of free space at the end of the file above which it is shrunk.
+    pub shrink_threshold: Option<isize>,
+}
+
+/// Creates a new MDBX database from the given path.
+///
+/// Use [WriteMap] as `E` to enable the write-map mode (writes go directly to the memory map,
+/// which is faster but less protected against stray pointer writes), or [NoWriteMap] otherwise.
+pub fn create_mdbx_db<E: DatabaseKind>(
+    path: impl AsRef<Path>,
+    geometry: MdbxGeometry,
+) -> Result<Database<E>, libmdbx::Error> {
+    // Delete folder content
+    if path.as_ref().exists() {
+        std::fs::remove_dir_all(path.as_ref()).unwrap();
+    }
+    std::fs::create_dir_all(path.as_ref()).unwrap();
+    let db = Database::<E>::open_with_options(
+        path.as_ref(),
+        DatabaseOptions {
+            max_tables: Some(TABLES.len() as u64),
+            mode: Mode::ReadWrite(ReadWriteOptions {
+                sync_mode: SyncMode::Durable,
+                min_size: geometry.min_size,
+                max_size: geometry.max_size,
+                growth_step: geometry.growth_step,
+                shrink_threshold: geometry.shrink_threshold,
+            }),
+            ..Default::default()
+        },
+    )?;
+    let txn = db.begin_rw_txn()?;
+    for table in TABLES {
+        txn.create_table(Some(table), TableFlags::default())?;
+    }
+    txn.commit()?;
+    Ok(db)
+}
+
+/// Index of the MDBX table used to store the given key.
+fn table_index(key: &DatabaseKey) -> usize {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+fn read_value<K: libmdbx::TransactionKind, E: DatabaseKind>(
+    txn: &Transaction<'_, K, E>,
+    key: &DatabaseKey,
+) -> Result<Option<Vec<u8>>, MdbxDbError> {
+    let table = txn.open_table(Some(TABLES[table_index(key)]))?;
+    Ok(txn.get::<Vec<u8>>(&table, key.as_slice())?)
+}
+
+#[allow(clippy::type_complexity)]
+fn read_prefix<K: libmdbx::TransactionKind, E: DatabaseKind>(
+    txn: &Transaction<'_, K, E>,
+    prefix: &DatabaseKey,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>, MdbxDbError> {
+    let table = txn.open_table(Some(TABLES[table_index(prefix)]))?;
+    let mut cursor = txn.cursor(&table)?;
+    let mut result = Vec::new();
+    for kv in cursor.iter_from::<Vec<u8>, Vec<u8>>(prefix.as_slice()) {
+        let (key, value) = kv?;
+        if !key.starts_with(prefix.as_slice()) {
+            break;
+        }
+        result.push((key, value));
+    }
+    Ok(result)
+}
+
+/// A struct that implements the `BonsaiDatabase` trait using MDBX as the underlying database
+pub struct MdbxDb<'db, ID: Id, E: DatabaseKind> {
+    db: &'db Database<E>,
+    config: MdbxDbConfig,
+    snapshots: BTreeMap<ID, Arc<Transaction<'db, RO, E>>>,
+}
+
+/// Configuration for MDBX database
+pub struct MdbxDbConfig {
+    /// Maximum number of snapshots kept in database
+    pub max_saved_snapshots: Option<usize>,
+}
+
+impl Default for MdbxDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(100),
+        }
+    }
+}
+
+impl<'db, ID: Id, E: DatabaseKind> MdbxDb<'db, ID, E> {
+    /// Creates a new MDBX wrapper from the given MDBX database
+    pub fn new(db: &'db Database<E>, config: MdbxDbConfig) -> Self {
+        trace!("MDBX database opened");
+        Self {
+            db,
+            config,
+            snapshots: BTreeMap::default(),
+        }
+    }
+}
+
+/// A batch used to write changes in the MDBX database, applied in a single write transaction
+#[derive(Default)]
+pub struct MdbxDbBatch {
+    changes: Vec<(usize, Vec<u8>, Option<Vec<u8>>)>,
+}
+
+#[derive(Debug)]
+pub enum MdbxDbError {
+    Mdbx(libmdbx::Error),
+    Custom(String),
+}
+
+impl From<libmdbx::Error> for MdbxDbError {
+    fn from(err: libmdbx::Error) -> Self {
+        Self::Mdbx(err)
+    }
+}
+
+impl fmt::Display for MdbxDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mdbx(err) => write!(f, "MDBX error: {}", err),
+            Self::Custom(err) => write!(f, "MDBX error in trie: {}", err),
+        }
+    }
+}
+
+impl DBError for MdbxDbError {}
+
+impl StdError for MdbxDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Mdbx(err) => Some(err),
+            Self::Custom(_) => None,
+        }
+    }
+}
+
+impl<'db, ID: Id, E: DatabaseKind> BonsaiDatabase for MdbxDb<'db, ID, E> {
+    type Batch = MdbxDbBatch;
+    type DatabaseError = MdbxDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        let txn = self.db.begin_ro_txn().unwrap();
+        for table in TABLES {
+            let table = txn.open_table(Some(table)).unwrap();
+            let mut cursor = txn.cursor(&table).unwrap();
+            for (key, value) in cursor.iter::<Vec<u8>, Vec<u8>>().flatten() {
+                println!("{:?} {:?}", key, value);
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into MDBX: {:?} {:?}", key, value);
+        if let Some(batch) = batch {
+            let old_value = self.get(key)?;
+            batch.changes.push((
+                table_index(key),
+                key.as_slice().to_vec(),
+                Some(value.to_vec()),
+            ));
+            Ok(old_value)
+        } else {
+            let txn = self.db.begin_rw_txn()?;
+            let old_value = read_value(&txn, key)?;
+            {
+                let table = txn.open_table(Some(TABLES[table_index(key)]))?;
+                txn.put(&table, key.as_slice(), value, WriteFlags::UPSERT)?;
+            }
+            txn.commit()?;
+            Ok(old_value)
+        }
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from MDBX: {:?}", key);
+        read_value(&self.db.begin_ro_txn()?, key)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from MDBX: {:?}", prefix);
+        read_prefix(&self.db.begin_ro_txn()?, prefix)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if MDBX contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from MDBX: {:?}", key);
+        if let Some(batch) = batch {
+            let old_value = self.get(key)?;
+            batch
+                .changes
+                .push((table_index(key), key.as_slice().to_vec(), None));
+            Ok(old_value)
+        } else {
+            let txn = self.db.begin_rw_txn()?;
+            let old_value = read_value(&txn, key)?;
+            {
+                let table = txn.open_table(Some(TABLES[table_index(key)]))?;
+                txn.del(&table, key.as_slice(), None)?;
+            }
+            txn.commit()?;
+            Ok(old_value)
+        }
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from MDBX: {:?}", prefix);
+        let txn = self.db.begin_rw_txn()?;
+        let keys = read_prefix(&txn, prefix)?;
+        {
+            let table = txn.open_table(Some(TABLES[table_index(prefix)]))?;
+            for (key, _) in keys {
+                txn.del(&table, key, None)?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        let txn = self.db.begin_rw_txn()?;
+        {
+            let tables = [
+                txn.open_table(Some(TRIE_TABLE))?,
+                txn.open_table(Some(FLAT_TABLE))?,
+                txn.open_table(Some(TRIE_LOG_TABLE))?,
+            ];
+            for (index, key, value) in batch.changes {
+                match value {
+                    Some(value) => txn.put(&tables[index], key, value, WriteFlags::UPSERT)?,
+                    None => {
+                        txn.del(&tables[index], key, None)?;
+                    }
+                }
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+/// A transaction over an MDBX snapshot.
+///
+/// Reads are served from an MDBX read transaction opened at snapshot time, while changes are kept
+/// in memory on top of it until the transaction is merged.
+pub struct MdbxTransaction<'db, E: DatabaseKind> {
+    snapshot: Arc<Transaction<'db, RO, E>>,
+    changes: BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl<'db, E: DatabaseKind> BonsaiDatabase for MdbxTransaction<'db, E> {
+    type Batch = ();
+    type DatabaseError = MdbxDbError;
+
+    fn create_batch(&self) -> Self::Batch {}
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for (key, value) in self.changes.iter() {
+            println!("{:?} {:?}", key, value);
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into MDBX transaction: {:?} {:?}", key, value);
+        let old_value = self.get(key)?;
+        self.changes.insert(
+            (table_index(key), key.as_slice().to_vec()),
+            Some(value.to_vec()),
+        );
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from MDBX transaction: {:?}", key);
+        match self
+            .changes
+            .get(&(table_index(key), key.as_slice().to_vec()))
+        {
+            Some(value) => Ok(value.clone()),
+            None => read_value(&self.snapshot, key),
+        }
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from MDBX transaction: {:?}", prefix);
+        let index = table_index(prefix);
+        let mut result: BTreeMap<Vec<u8>, Vec<u8>> =
+            read_prefix(&self.snapshot, prefix)?.into_iter().collect();
+        let changes = self
+            .changes
+            .range((index, prefix.as_slice().to_vec())..)
+            .take_while(|((change_index, key), _)| {
+                *change_index == index && key.starts_with(prefix.as_slice())
+            });
+        for ((_, key), value) in changes {
+            match value {
+                Some(value) => result.insert(key.clone(), value.clone()),
+                None => result.remove(key),
+            };
+        }
+        Ok(result.into_iter().collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if MDBX transaction contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from MDBX transaction: {:?}", key);
+        let old_value = self.get(key)?;
+        self.changes
+            .insert((table_index(key), key.as_slice().to_vec()), None);
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from MDBX transaction: {:?}", prefix);
+        let index = table_index(prefix);
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.changes.insert((index, key), None);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, _batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Ok(())
+    }
+}
+
+impl<'db, ID: Id, E: DatabaseKind> BonsaiPersistentDatabase<ID> for MdbxDb<'db, ID, E> {
+    type Transaction = MdbxTransaction<'db, E>;
+    type DatabaseError = MdbxDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating MDBX snapshot");
+        // A snapshot that can't be opened is not recorded, transactions at this id will then be
+        // unavailable.
+        if let Ok(snapshot) = self.db.begin_ro_txn() {
+            self.snapshots.insert(id, Arc::new(snapshot));
+        }
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating MDBX transaction");
+        self.snapshots.get(&id).map(|snapshot| MdbxTransaction {
+            snapshot: Arc::clone(snapshot),
+            changes: BTreeMap::new(),
+        })
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        let mut batch = self.create_batch();
+        batch.changes = transaction
+            .changes
+            .into_iter()
+            .map(|((index, key), value)| (index, key, value))
+            .collect();
+        self.write_batch(batch)
+    }
+}
@@ -0,0 +1,601 @@
+use std::{
+    collections::BTreeMap,
+    error::Error as StdError,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+};
+use log::trace;
+
+const PUT: u8 = 0;
+const DELETE: u8 = 1;
+
+/// Size of a record header: kind, operation, key length and value length.
+const HEADER_LEN: usize = 10;
+
+/// Index of the in-memory index used to store the given key.
+fn index_of(key: &DatabaseKey) -> usize {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+/// Position of a value in the file, along with the size of the whole record holding it.
+#[derive(Clone, Copy, Debug)]
+struct Location {
+    offset: usize,
+    len: usize,
+    record_len: usize,
+}
+
+/// One map per kind of key, from the key to the location of its value in the file.
+type Index = [BTreeMap<Vec<u8>, Location>; 3];
+
+/// Appends a record to the buffer, a `None` value is a removal of the key.
+fn encode_record(buffer: &mut Vec<u8>, kind: usize, key: &[u8], value: Option<&[u8]>) {
+    buffer.push(kind as u8);
+    buffer.push(if value.is_some() { PUT } else { DELETE });
+    let value = value.unwrap_or_default();
+    buffer.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(key);
+    buffer.extend_from_slice(value);
+}
+
+fn map(file: &File, len: u64) -> io::Result<Option<Arc<Mmap>>> {
+    if len == 0 {
+        return Ok(None);
+    }
+    // SAFETY: the file is only modified by appending to it, and compaction writes a new file, so
+    // the mapped bytes are never modified.
+    Ok(Some(Arc::new(unsafe { Mmap::map(file)? })))
+}
+
+fn read(mmap: &Option<Arc<Mmap>>, location: &Location) -> Vec<u8> {
+    match mmap {
+        Some(mmap) => mmap[location.offset..location.offset + location.len].to_vec(),
+        None => Vec::new(),
+    }
+}
+
+/// A struct that implements the `BonsaiDatabase` trait over a single append-only file
+///
+/// Every change is appended to the file which is memory-mapped for reads, the location of the
+/// latest value of each key is kept in an in-memory index rebuilt when the file is opened.
+/// Overwritten and removed values are reclaimed by [`MmapDb::compact`].
+pub struct MmapDb<ID: Id> {
+    path: PathBuf,
+    file: File,
+    mmap: Option<Arc<Mmap>>,
+    len: u64,
+    garbage: u64,
+    index: Index,
+    config: MmapDbConfig,
+    snapshots: BTreeMap<ID, Arc<MmapSnapshot>>,
+}
+
+/// Configuration for the memory-mapped database
+pub struct MmapDbConfig {
+    /// Maximum number of snapshots kept in database
+    pub max_saved_snapshots: Option<usize>,
+    /// Ratio of the file occupied by overwritten and removed values above which the file is
+    /// compacted after a write (None = only compact with [`MmapDb::compact`]).
+    pub compaction_ratio: Option<f64>,
+    /// Minimal size of the file, in bytes, before it is automatically compacted
+    pub min_compaction_size: u64,
+    /// Sync the file to the disk after each write
+    pub sync_writes: bool,
+}
+
+impl Default for MmapDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(100),
+            compaction_ratio: Some(0.5),
+            min_compaction_size: 64 * 1024 * 1024,
+            sync_writes: true,
+        }
+    }
+}
+
+impl<ID: Id> MmapDb<ID> {
+    /// Opens (or creates) the database file at the given path and rebuilds its index.
+    ///
+    /// A record partially written at the end of the file, after a crash, is discarded.
+    pub fn open(path: impl AsRef<Path>, config: MmapDbConfig) -> Result<Self, MmapDbError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let len = file.metadata()?.len();
+        let mut db = Self {
+            path,
+            mmap: map(&file, len)?,
+            file,
+            len,
+            garbage: 0,
+            index: Default::default(),
+            config,
+            snapshots: BTreeMap::default(),
+        };
+        let valid_len = db.replay();
+        if valid_len < db.len {
+            trace!(
+                "Discarding {} bytes at the end of the memory-mapped database",
+                db.len - valid_len
+            );
+            db.file.set_len(valid_len)?;
+            db.len = valid_len;
+            db.mmap = map(&db.file, valid_len)?;
+        }
+        trace!("Memory-mapped database opened");
+        Ok(db)
+    }
+
+    /// Rebuilds the index from the records of the file, returns the length of the valid part.
+    fn replay(&mut self) -> u64 {
+        let Some(mmap) = self.mmap.clone() else {
+            return 0;
+        };
+        let mut offset = 0;
+        while offset + HEADER_LEN <= mmap.len() {
+            let header = &mmap[offset..offset + HEADER_LEN];
+            let (kind, operation) = (header[0] as usize, header[1]);
+            let key_len = u32::from_le_bytes(header[2..6].try_into().unwrap()) as usize;
+            let value_len = u32::from_le_bytes(header[6..10].try_into().unwrap()) as usize;
+            let record_len = HEADER_LEN + key_len + value_len;
+            if kind >= 3 || offset + record_len > mmap.len() {
+                break;
+            }
+            let key = mmap[offset + HEADER_LEN..offset + HEADER_LEN + key_len].to_vec();
+            let location = Location {
+                offset: offset + HEADER_LEN + key_len,
+                len: value_len,
+                record_len,
+            };
+            self.apply(
+                kind,
+                key,
+                (operation == PUT).then_some(location),
+                record_len,
+            );
+            offset += record_len;
+        }
+        offset as u64
+    }
+
+    /// Updates the index with a record of `record_len` bytes, `None` being a removal.
+    fn apply(&mut self, kind: usize, key: Vec<u8>, location: Option<Location>, record_len: usize) {
+        let previous = match location {
+            Some(location) => self.index[kind].insert(key, location),
+            None => {
+                // The removal record itself is garbage
+                self.garbage += record_len as u64;
+                self.index[kind].remove(&key)
+            }
+        };
+        if let Some(previous) = previous {
+            self.garbage += previous.record_len as u64;
+        }
+    }
+
+    /// Appends the changes to the file and updates the index accordingly.
+    fn append(
+        &mut self,
+        changes: Vec<(usize, Vec<u8>, Option<Vec<u8>>)>,
+    ) -> Result<(), MmapDbError> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let mut buffer = Vec::new();
+        let mut locations = Vec::with_capacity(changes.len());
+        for (kind, key, value) in changes {
+            let start = buffer.len();
+            encode_record(&mut buffer, kind, &key, value.as_deref());
+            let record_len = buffer.len() - start;
+            let location = value.map(|value| Location {
+                offset: self.len as usize + start + HEADER_LEN + key.len(),
+                len: value.len(),
+                record_len,
+            });
+            locations.push((kind, key, location, record_len));
+        }
+        self.file.write_all(&buffer)?;
+        if self.config.sync_writes {
+            self.file.sync_data()?;
+        }
+        self.len += buffer.len() as u64;
+        self.mmap = map(&self.file, self.len)?;
+        for (kind, key, location, record_len) in locations {
+            self.apply(kind, key, location, record_len);
+        }
+        self.maybe_compact()
+    }
+
+    fn maybe_compact(&mut self) -> Result<(), MmapDbError> {
+        let Some(ratio) = self.config.compaction_ratio else {
+            return Ok(());
+        };
+        if self.len >= self.config.min_compaction_size
+            && self.garbage as f64 >= ratio * self.len as f64
+        {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the file with only the latest value of each key.
+    ///
+    /// The new file is written next to the current one and then renamed over it, snapshots
+    /// keep reading the previous file until they are dropped.
+    pub fn compact(&mut self) -> Result<(), MmapDbError> {
+        trace!(
+            "Compacting memory-mapped database, reclaiming {} bytes",
+            self.garbage
+        );
+        let compact_path = self.path.with_extension("compact");
+        let mut index: Index = Default::default();
+        let mut len = 0;
+        {
+            let compact_file = File::create(&compact_path)?;
+            let mut writer = BufWriter::new(&compact_file);
+            let mut buffer = Vec::new();
+            for (kind, entries) in self.index.iter().enumerate() {
+                for (key, location) in entries.iter() {
+                    buffer.clear();
+                    let value = read(&self.mmap, location);
+                    encode_record(&mut buffer, kind, key, Some(&value));
+                    writer.write_all(&buffer)?;
+                    index[kind].insert(
+                        key.clone(),
+                        Location {
+                            offset: len + HEADER_LEN + key.len(),
+                            len: value.len(),
+                            record_len: buffer.len(),
+                        },
+                    );
+                    len += buffer.len();
+                }
+            }
+            writer.flush()?;
+            drop(writer);
+            compact_file.sync_all()?;
+        }
+        std::fs::rename(&compact_path, &self.path)?;
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        self.len = len as u64;
+        self.mmap = map(&self.file, self.len)?;
+        self.index = index;
+        self.garbage = 0;
+        Ok(())
+    }
+
+    /// Size of the file in bytes
+    pub fn file_size(&self) -> u64 {
+        self.len
+    }
+
+    /// Number of bytes of the file occupied by overwritten and removed values
+    pub fn garbage_size(&self) -> u64 {
+        self.garbage
+    }
+
+    fn get_location(&self, key: &DatabaseKey) -> Option<&Location> {
+        self.index[index_of(key)].get(key.as_slice())
+    }
+}
+
+/// A batch used to write changes in the memory-mapped database, appended in a single write
+#[derive(Default)]
+pub struct MmapDbBatch {
+    changes: Vec<(usize, Vec<u8>, Option<Vec<u8>>)>,
+}
+
+#[derive(Debug)]
+pub enum MmapDbError {
+    Io(io::Error),
+    Custom(String),
+}
+
+impl From<io::Error> for MmapDbError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl fmt::Display for MmapDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Memory-mapped database IO error: {}", err),
+            Self::Custom(err) => write!(f, "Memory-mapped database error in trie: {}", err),
+        }
+    }
+}
+
+impl DBError for MmapDbError {}
+
+impl StdError for MmapDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Custom(_) => None,
+        }
+    }
+}
+
+impl<ID: Id> BonsaiDatabase for MmapDb<ID> {
+    type Batch = MmapDbBatch;
+    type DatabaseError = MmapDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for entries in self.index.iter() {
+            for (key, location) in entries.iter() {
+                println!("{:?} {:?}", key, read(&self.mmap, location));
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!(
+            "Inserting into memory-mapped database: {:?} {:?}",
+            key,
+            value
+        );
+        let old_value = self.get(key)?;
+        let change = (index_of(key), key.as_slice().to_vec(), Some(value.to_vec()));
+        if let Some(batch) = batch {
+            batch.changes.push(change);
+        } else {
+            self.append(vec![change])?;
+        }
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from memory-mapped database: {:?}", key);
+        Ok(self
+            .get_location(key)
+            .map(|location| read(&self.mmap, location)))
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from memory-mapped database: {:?}", prefix);
+        Ok(self.index[index_of(prefix)]
+            .range(prefix.as_slice().to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix.as_slice()))
+            .map(|(key, location)| (key.clone(), read(&self.mmap, location)))
+            .collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if memory-mapped database contains: {:?}", key);
+        Ok(self.get_location(key).is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from memory-mapped database: {:?}", key);
+        let old_value = self.get(key)?;
+        if old_value.is_none() {
+            return Ok(None);
+        }
+        let change = (index_of(key), key.as_slice().to_vec(), None);
+        if let Some(batch) = batch {
+            batch.changes.push(change);
+        } else {
+            self.append(vec![change])?;
+        }
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from memory-mapped database: {:?}", prefix);
+        let kind = index_of(prefix);
+        let changes = self.index[kind]
+            .range(prefix.as_slice().to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix.as_slice()))
+            .map(|(key, _)| (kind, key.clone(), None))
+            .collect();
+        self.append(changes)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        self.append(batch.changes)
+    }
+}
+
+/// Mapping of the file and copy of the index at the time of a snapshot.
+///
+/// The file is append-only, so the mapping is never modified by later writes.
+pub struct MmapSnapshot {
+    mmap: Option<Arc<Mmap>>,
+    index: Index,
+}
+
+/// A transaction over a memory-mapped database snapshot, changes are kept in memory on top of
+/// it until the transaction is merged.
+pub struct MmapTransaction {
+    snapshot: Arc<MmapSnapshot>,
+    changes: BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl BonsaiDatabase for MmapTransaction {
+    type Batch = ();
+    type DatabaseError = MmapDbError;
+
+    fn create_batch(&self) -> Self::Batch {}
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for (key, value) in self.changes.iter() {
+            println!("{:?} {:?}", key, value);
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!(
+            "Inserting into memory-mapped database transaction: {:?} {:?}",
+            key,
+            value
+        );
+        let old_value = self.get(key)?;
+        self.changes.insert(
+            (index_of(key), key.as_slice().to_vec()),
+            Some(value.to_vec()),
+        );
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from memory-mapped database transaction: {:?}", key);
+        let kind = index_of(key);
+        match self.changes.get(&(kind, key.as_slice().to_vec())) {
+            Some(value) => Ok(value.clone()),
+            None => Ok(self.snapshot.index[kind]
+                .get(key.as_slice())
+                .map(|location| read(&self.snapshot.mmap, location))),
+        }
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!(
+            "Getting from memory-mapped database transaction: {:?}",
+            prefix
+        );
+        let kind = index_of(prefix);
+        let mut result: BTreeMap<Vec<u8>, Vec<u8>> = self.snapshot.index[kind]
+            .range(prefix.as_slice().to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix.as_slice()))
+            .map(|(key, location)| (key.clone(), read(&self.snapshot.mmap, location)))
+            .collect();
+        let changes = self
+            .changes
+            .range((kind, prefix.as_slice().to_vec())..)
+            .take_while(|((change_kind, key), _)| {
+                *change_kind == kind && key.starts_with(prefix.as_slice())
+            });
+        for ((_, key), value) in changes {
+            match value {
+                Some(value) => result.insert(key.clone(), value.clone()),
+                None => result.remove(key),
+            };
+        }
+        Ok(result.into_iter().collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!(
+            "Checking if memory-mapped database transaction contains: {:?}",
+            key
+        );
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!(
+            "Removing from memory-mapped database transaction: {:?}",
+            key
+        );
+        let old_value = self.get(key)?;
+        self.changes
+            .insert((index_of(key), key.as_slice().to_vec()), None);
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!(
+            "Removing from memory-mapped database transaction: {:?}",
+            prefix
+        );
+        let kind = index_of(prefix);
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.changes.insert((kind, key), None);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, _batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Ok(())
+    }
+}
+
+impl<ID: Id> BonsaiPersistentDatabase<ID> for MmapDb<ID> {
+    type Transaction = MmapTransaction;
+    type DatabaseError = MmapDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating memory-mapped database snapshot");
+        let snapshot = MmapSnapshot {
+            mmap: self.mmap.clone(),
+            index: self.index.clone(),
+        };
+        self.snapshots.insert(id, Arc::new(snapshot));
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating memory-mapped database transaction");
+        self.snapshots.get(&id).map(|snapshot| MmapTransaction {
+            snapshot: Arc::clone(snapshot),
+            changes: BTreeMap::new(),
+        })
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        self.append(
+            transaction
+                .changes
+                .into_iter()
+                .map(|((kind, key), value)| (kind, key, value))
+                .collect(),
+        )
+    }
+}
@@ -6,4 +6,131 @@ pub use hashmap_db::HashMapDb;
 mod rocks_db;
 
 #[cfg(feature = "rocksdb")]
-pub use rocks_db::{create_rocks_db, RocksDB, RocksDBBatch, RocksDBConfig};
+pub use rocks_db::{
+    create_rocks_db, create_rocks_db_with_options, open_rocks_db_secondary, DBCompressionType,
+    RocksDB, RocksDBBatch, RocksDBColumnOptions, RocksDBConfig, RocksDBOptions, RocksDBSecondary,
+};
+
+#[cfg(any(feature = "sled", feature = "parity-db"))]
+mod snapshot_transaction;
+
+#[cfg(feature = "sled")]
+mod sled_db;
+
+#[cfg(feature = "sled")]
+pub use sled_db::{create_sled_db, SledDb, SledDbBatch, SledDbConfig, SledDbError};
+
+#[cfg(feature = "redb")]
+mod redb_db;
+
+#[cfg(feature = "redb")]
+pub use redb_db::{create_redb_db, RedbDb, RedbDbBatch, RedbDbConfig, RedbDbError};
+
+#[cfg(feature = "heed")]
+mod heed_db;
+
+#[cfg(feature = "heed")]
+pub use heed_db::{create_heed_env, HeedDb, HeedDbBatch, HeedDbConfig, HeedDbError};
+
+#[cfg(feature = "mdbx")]
+mod mdbx_db;
+
+#[cfg(feature = "mdbx")]
+pub use mdbx_db::{
+    create_mdbx_db, MdbxDb, MdbxDbBatch, MdbxDbConfig, MdbxDbError, MdbxGeometry, NoWriteMap,
+    WriteMap,
+};
+
+#[cfg(feature = "parity-db")]
+mod parity_db;
+
+#[cfg(feature = "parity-db")]
+pub use self::parity_db::{
+    create_parity_db, ParityDb, ParityDbBatch, ParityDbConfig, ParityDbError,
+};
+
+#[cfg(feature = "sqlite")]
+mod sqlite_db;
+
+#[cfg(feature = "sqlite")]
+pub use sqlite_db::{create_sqlite_db, SqliteDb, SqliteDbBatch, SqliteDbConfig, SqliteDbError};
+
+#[cfg(feature = "postgres")]
+mod postgres_db;
+
+#[cfg(feature = "postgres")]
+pub use postgres_db::{PostgresDb, PostgresDbBatch, PostgresDbConfig, PostgresDbError};
+
+#[cfg(feature = "fjall")]
+mod fjall_db;
+
+#[cfg(feature = "fjall")]
+pub use fjall_db::{
+    create_fjall_db, FjallDb, FjallDbBatch, FjallDbConfig, FjallDbError, FjallTransaction,
+    PersistMode,
+};
+
+#[cfg(feature = "mmap")]
+mod mmap_db;
+
+#[cfg(feature = "mmap")]
+pub use mmap_db::{MmapDb, MmapDbBatch, MmapDbConfig, MmapDbError, MmapTransaction};
+
+#[cfg(feature = "object-store")]
+mod object_store_db;
+
+#[cfg(feature = "object-store")]
+pub use object_store_db::{
+    ObjectStoreDb, ObjectStoreDbBatch, ObjectStoreDbConfig, ObjectStoreDbError,
+    ObjectStoreTransaction,
+};
+
+#[cfg(feature = "zstd")]
+mod compressed_db;
+
+#[cfg(feature = "zstd")]
+pub use compressed_db::{train_dictionary, CompressedDb, CompressedDbConfig, CompressedDbError};
+
+#[cfg(feature = "encryption")]
+mod encrypted_db;
+
+#[cfg(feature = "encryption")]
+pub use encrypted_db::{EncryptedDb, EncryptedDbConfig, EncryptedDbError};
+
+#[cfg(feature = "std")]
+mod instrumented_db;
+
+#[cfg(feature = "std")]
+pub use instrumented_db::{
+    ColumnStats, DatabaseStats, InstrumentedDb, LatencyHistogram, LATENCY_BUCKETS,
+};
+
+#[cfg(feature = "wal")]
+mod wal_db;
+
+#[cfg(feature = "wal")]
+pub use wal_db::{WalDb, WalDbBatch, WalDbError};
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+mod indexed_db;
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub use indexed_db::{IndexedDbBatch, IndexedDbConfig, IndexedDbError, IndexedDbStore};
+
+#[cfg(feature = "cache")]
+mod cached_db;
+
+#[cfg(feature = "cache")]
+pub use cached_db::{CacheStats, CachedDb, CachedDbBatch, CachedDbConfig};
+
+#[cfg(feature = "sharded")]
+mod sharded_db;
+
+#[cfg(feature = "sharded")]
+pub use sharded_db::{hash_router, ShardRouter, ShardedDb, ShardedDbBatch, ShardedDbConfig};
+
+#[cfg(feature = "std")]
+mod tiered_db;
+
+#[cfg(feature = "std")]
+pub use tiered_db::{TieredDb, TieredDbBatch, TieredDbConfig, TieredDbError};
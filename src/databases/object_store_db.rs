@@ -0,0 +1,630 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    error::Error as StdError,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use futures::{future::try_join_all, TryStreamExt};
+use object_store::{path::Path, ObjectStore, PutPayload};
+use tokio::runtime::Runtime;
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+};
+use log::trace;
+
+/// Index of the folder of the bucket used to store the given key.
+fn kind(key: &DatabaseKey) -> usize {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+/// Object names are the hex encoding of the key, prefixed so that the root node key isn't empty.
+fn encode_key(key: &[u8]) -> String {
+    let mut name = String::with_capacity(1 + key.len() * 2);
+    name.push('k');
+    for byte in key {
+        name.push_str(&format!("{:02x}", byte));
+    }
+    name
+}
+
+fn decode_key(name: &str) -> Option<Vec<u8>> {
+    let hex = name.strip_prefix('k')?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Access to the bucket, shared by the database and its transactions.
+struct Remote {
+    store: Arc<dyn ObjectStore>,
+    runtime: Runtime,
+    prefix: String,
+}
+
+impl Remote {
+    fn folder(&self, kind: usize) -> Path {
+        Path::from(format!("{}/{}", self.prefix, kind))
+    }
+
+    fn path(&self, kind: usize, key: &[u8]) -> Path {
+        Path::from(format!("{}/{}/{}", self.prefix, kind, encode_key(key)))
+    }
+
+    fn get(&self, kind: usize, key: &[u8]) -> Result<Option<Vec<u8>>, ObjectStoreDbError> {
+        Ok(self.get_many(kind, &[key.to_vec()])?.pop().flatten())
+    }
+
+    fn get_many(
+        &self,
+        kind: usize,
+        keys: &[Vec<u8>],
+    ) -> Result<Vec<Option<Vec<u8>>>, ObjectStoreDbError> {
+        self.runtime.block_on(try_join_all(keys.iter().map(|key| {
+            let path = self.path(kind, key);
+            async move {
+                let value = match self.store.get(&path).await {
+                    Ok(result) => Some(result.bytes().await?.to_vec()),
+                    Err(object_store::Error::NotFound { .. }) => None,
+                    Err(err) => return Err(err.into()),
+                };
+                Ok::<_, ObjectStoreDbError>(value)
+            }
+        })))
+    }
+
+    /// Names of all the objects stored for a kind of key.
+    fn list(&self, kind: usize) -> Result<BTreeSet<Vec<u8>>, ObjectStoreDbError> {
+        let folder = self.folder(kind);
+        let objects: Vec<_> = self
+            .runtime
+            .block_on(self.store.list(Some(&folder)).try_collect())?;
+        objects
+            .into_iter()
+            .map(|object| {
+                object
+                    .location
+                    .filename()
+                    .and_then(decode_key)
+                    .ok_or_else(|| {
+                        ObjectStoreDbError::Custom(format!(
+                            "Unexpected object in bucket: {}",
+                            object.location
+                        ))
+                    })
+            })
+            .collect()
+    }
+
+    fn write(
+        &self,
+        changes: &BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>,
+    ) -> Result<(), ObjectStoreDbError> {
+        self.runtime
+            .block_on(try_join_all(changes.iter().map(|((kind, key), value)| {
+                let path = self.path(*kind, key);
+                async move {
+                    match value {
+                        Some(value) => {
+                            self.store
+                                .put(&path, PutPayload::from(value.clone()))
+                                .await?;
+                        }
+                        None => self.store.delete(&path).await?,
+                    }
+                    Ok::<_, ObjectStoreDbError>(())
+                }
+            })))?;
+        Ok(())
+    }
+}
+
+/// Bounded cache of values read from the bucket, the oldest entries are evicted first.
+#[derive(Default)]
+struct ValueCache {
+    values: HashMap<(usize, Vec<u8>), Vec<u8>>,
+    order: VecDeque<(usize, Vec<u8>)>,
+}
+
+impl ValueCache {
+    fn insert(&mut self, key: (usize, Vec<u8>), value: Vec<u8>, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        if self.values.insert(key.clone(), value).is_none() {
+            self.order.push_back(key);
+        }
+        while self.values.len() > capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.values.remove(&oldest);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &(usize, Vec<u8>)) {
+        // The key is left in `order`, evicting a key that is no longer cached is harmless.
+        self.values.remove(key);
+    }
+}
+
+/// A struct that implements the `BonsaiDatabase` trait over an S3-compatible object store
+///
+/// Each key is stored as its own object. Writes are kept in a local write-back cache and uploaded
+/// when it holds more than [`ObjectStoreDbConfig::write_back_threshold`] entries, on
+/// [`ObjectStoreDb::flush`] and before each snapshot. Changes that are not flushed are lost if
+/// the process stops.
+///
+/// Calls to the bucket are made on an internal runtime, the database must not be used from
+/// inside an async runtime.
+pub struct ObjectStoreDb<ID: Id> {
+    remote: Arc<Remote>,
+    keys: [BTreeSet<Vec<u8>>; 3],
+    dirty: BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>,
+    cache: Mutex<ValueCache>,
+    config: ObjectStoreDbConfig,
+    snapshots: BTreeMap<ID, Arc<Mutex<UndoLog>>>,
+}
+
+/// Configuration for the object store database
+pub struct ObjectStoreDbConfig {
+    /// Maximum number of snapshots kept in database
+    pub max_saved_snapshots: Option<usize>,
+    /// Path prefix of the objects in the bucket
+    pub prefix: String,
+    /// Number of changes kept locally before they are uploaded
+    pub write_back_threshold: usize,
+    /// Number of values read from the bucket kept in memory
+    pub cache_size: usize,
+}
+
+impl Default for ObjectStoreDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(10),
+            prefix: "bonsai".to_string(),
+            write_back_threshold: 10_000,
+            cache_size: 100_000,
+        }
+    }
+}
+
+impl<ID: Id> ObjectStoreDb<ID> {
+    /// Creates a database over the given bucket, listing the keys already stored under the
+    /// prefix.
+    pub fn new(
+        store: Arc<dyn ObjectStore>,
+        config: ObjectStoreDbConfig,
+    ) -> Result<Self, ObjectStoreDbError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| ObjectStoreDbError::Custom(err.to_string()))?;
+        let remote = Arc::new(Remote {
+            store,
+            runtime,
+            prefix: config.prefix.clone(),
+        });
+        let keys = [remote.list(0)?, remote.list(1)?, remote.list(2)?];
+        trace!("Object store database opened");
+        Ok(Self {
+            remote,
+            keys,
+            dirty: BTreeMap::new(),
+            cache: Mutex::new(ValueCache::default()),
+            config,
+            snapshots: BTreeMap::new(),
+        })
+    }
+
+    /// Uploads all the changes kept in the write-back cache.
+    pub fn flush(&mut self) -> Result<(), ObjectStoreDbError> {
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        trace!("Flushing {} changes to object store", self.dirty.len());
+        self.remote.write(&self.dirty)?;
+        let mut cache = self.cache.lock().unwrap();
+        for (key, value) in core::mem::take(&mut self.dirty) {
+            if let Some(value) = value {
+                cache.insert(key, value, self.config.cache_size);
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of changes waiting to be uploaded
+    pub fn pending_changes(&self) -> usize {
+        self.dirty.len()
+    }
+
+    fn read(&self, kind: usize, key: &[u8]) -> Result<Option<Vec<u8>>, ObjectStoreDbError> {
+        let key = (kind, key.to_vec());
+        if let Some(value) = self.dirty.get(&key) {
+            return Ok(value.clone());
+        }
+        if !self.keys[kind].contains(&key.1) {
+            return Ok(None);
+        }
+        if let Some(value) = self.cache.lock().unwrap().values.get(&key) {
+            return Ok(Some(value.clone()));
+        }
+        let value = self.remote.get(kind, &key.1)?;
+        if let Some(value) = &value {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(key, value.clone(), self.config.cache_size);
+        }
+        Ok(value)
+    }
+
+    /// Records a change in the write-back cache, keeping the previous value for the snapshots
+    /// that haven't seen a change of this key yet.
+    fn set(
+        &mut self,
+        kind: usize,
+        key: Vec<u8>,
+        value: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, ObjectStoreDbError> {
+        let old_value = self.read(kind, &key)?;
+        for undo in self.snapshots.values() {
+            undo.lock()
+                .unwrap()
+                .entry((kind, key.clone()))
+                .or_insert_with(|| old_value.clone());
+        }
+        match value {
+            Some(_) => self.keys[kind].insert(key.clone()),
+            None => self.keys[kind].remove(&key),
+        };
+        self.cache.lock().unwrap().remove(&(kind, key.clone()));
+        self.dirty.insert((kind, key), value);
+        Ok(old_value)
+    }
+
+    fn maybe_flush(&mut self) -> Result<(), ObjectStoreDbError> {
+        if self.dirty.len() >= self.config.write_back_threshold {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn prefix_keys(&self, prefix: &DatabaseKey) -> Vec<Vec<u8>> {
+        self.keys[kind(prefix)]
+            .range(prefix.as_slice().to_vec()..)
+            .take_while(|key| key.starts_with(prefix.as_slice()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// A batch of changes applied to the write-back cache at once
+#[derive(Default)]
+pub struct ObjectStoreDbBatch {
+    changes: BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>,
+}
+
+#[derive(Debug)]
+pub enum ObjectStoreDbError {
+    ObjectStore(object_store::Error),
+    Custom(String),
+}
+
+impl From<object_store::Error> for ObjectStoreDbError {
+    fn from(err: object_store::Error) -> Self {
+        Self::ObjectStore(err)
+    }
+}
+
+impl fmt::Display for ObjectStoreDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ObjectStore(err) => write!(f, "Object store error: {}", err),
+            Self::Custom(err) => write!(f, "Object store error in trie: {}", err),
+        }
+    }
+}
+
+impl DBError for ObjectStoreDbError {}
+
+impl StdError for ObjectStoreDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::ObjectStore(err) => Some(err),
+            Self::Custom(_) => None,
+        }
+    }
+}
+
+impl<ID: Id> BonsaiDatabase for ObjectStoreDb<ID> {
+    type Batch = ObjectStoreDbBatch;
+    type DatabaseError = ObjectStoreDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for (kind, keys) in self.keys.iter().enumerate() {
+            for key in keys.iter() {
+                println!("{:?} {:?}", key, self.read(kind, key));
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into object store: {:?} {:?}", key, value);
+        if let Some(batch) = batch {
+            let old_value = self.get(key)?;
+            batch
+                .changes
+                .insert((kind(key), key.as_slice().to_vec()), Some(value.to_vec()));
+            Ok(old_value)
+        } else {
+            let old_value = self.set(kind(key), key.as_slice().to_vec(), Some(value.to_vec()))?;
+            self.maybe_flush()?;
+            Ok(old_value)
+        }
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from object store: {:?}", key);
+        self.read(kind(key), key.as_slice())
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from object store: {:?}", prefix);
+        let kind = kind(prefix);
+        let keys = self.prefix_keys(prefix);
+        // Values that are neither pending nor cached are downloaded concurrently
+        let missing: Vec<_> = {
+            let cache = self.cache.lock().unwrap();
+            keys.iter()
+                .filter(|key| {
+                    let key = (kind, key.to_vec());
+                    !self.dirty.contains_key(&key) && !cache.values.contains_key(&key)
+                })
+                .cloned()
+                .collect()
+        };
+        let downloaded = self.remote.get_many(kind, &missing)?;
+        {
+            let mut cache = self.cache.lock().unwrap();
+            for (key, value) in missing.into_iter().zip(downloaded) {
+                if let Some(value) = value {
+                    cache.insert((kind, key), value, self.config.cache_size);
+                }
+            }
+        }
+        let mut result = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(value) = self.read(kind, &key)? {
+                result.push((key, value));
+            }
+        }
+        Ok(result)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if object store contains: {:?}", key);
+        Ok(self.keys[kind(key)].contains(key.as_slice()))
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from object store: {:?}", key);
+        if let Some(batch) = batch {
+            let old_value = self.get(key)?;
+            batch
+                .changes
+                .insert((kind(key), key.as_slice().to_vec()), None);
+            Ok(old_value)
+        } else {
+            let old_value = self.set(kind(key), key.as_slice().to_vec(), None)?;
+            self.maybe_flush()?;
+            Ok(old_value)
+        }
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from object store: {:?}", prefix);
+        let mut batch = self.create_batch();
+        for key in self.prefix_keys(prefix) {
+            batch.changes.insert((kind(prefix), key), None);
+        }
+        self.write_batch(batch)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        for ((kind, key), value) in batch.changes {
+            self.set(kind, key, value)?;
+        }
+        self.maybe_flush()
+    }
+}
+
+/// Values of the keys changed since a snapshot, as they were when it was taken.
+type UndoLog = BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>;
+
+/// A transaction over an object store database snapshot.
+///
+/// The bucket is read directly and keys changed since the snapshot are read from its undo log,
+/// changes made in the transaction are kept in memory until it is merged.
+pub struct ObjectStoreTransaction {
+    remote: Arc<Remote>,
+    undo: Arc<Mutex<UndoLog>>,
+    changes: BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl ObjectStoreTransaction {
+    fn read(&self, kind: usize, key: &[u8]) -> Result<Option<Vec<u8>>, ObjectStoreDbError> {
+        let key = (kind, key.to_vec());
+        if let Some(value) = self.changes.get(&key) {
+            return Ok(value.clone());
+        }
+        if let Some(value) = self.undo.lock().unwrap().get(&key) {
+            return Ok(value.clone());
+        }
+        self.remote.get(kind, &key.1)
+    }
+}
+
+impl BonsaiDatabase for ObjectStoreTransaction {
+    type Batch = ();
+    type DatabaseError = ObjectStoreDbError;
+
+    fn create_batch(&self) -> Self::Batch {}
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for (key, value) in self.changes.iter() {
+            println!("{:?} {:?}", key, value);
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!(
+            "Inserting into object store transaction: {:?} {:?}",
+            key,
+            value
+        );
+        let old_value = self.get(key)?;
+        self.changes
+            .insert((kind(key), key.as_slice().to_vec()), Some(value.to_vec()));
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from object store transaction: {:?}", key);
+        self.read(kind(key), key.as_slice())
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from object store transaction: {:?}", prefix);
+        let kind = kind(prefix);
+        let mut keys: BTreeSet<Vec<u8>> = self
+            .remote
+            .list(kind)?
+            .into_iter()
+            .filter(|key| key.starts_with(prefix.as_slice()))
+            .collect();
+        // Keys removed since the snapshot are only known from the undo log
+        keys.extend(
+            self.undo
+                .lock()
+                .unwrap()
+                .keys()
+                .filter(|(undo_kind, key)| *undo_kind == kind && key.starts_with(prefix.as_slice()))
+                .map(|(_, key)| key.clone()),
+        );
+        keys.extend(
+            self.changes
+                .keys()
+                .filter(|(change_kind, key)| {
+                    *change_kind == kind && key.starts_with(prefix.as_slice())
+                })
+                .map(|(_, key)| key.clone()),
+        );
+        let mut result = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(value) = self.read(kind, &key)? {
+                result.push((key, value));
+            }
+        }
+        Ok(result)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if object store transaction contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from object store transaction: {:?}", key);
+        let old_value = self.get(key)?;
+        self.changes
+            .insert((kind(key), key.as_slice().to_vec()), None);
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from object store transaction: {:?}", prefix);
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.changes.insert((kind(prefix), key), None);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, _batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Ok(())
+    }
+}
+
+impl<ID: Id> BonsaiPersistentDatabase<ID> for ObjectStoreDb<ID> {
+    type Transaction = ObjectStoreTransaction;
+    type DatabaseError = ObjectStoreDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating object store snapshot");
+        // A snapshot that can't be flushed is not recorded, transactions at this id will then be
+        // unavailable.
+        if self.flush().is_ok() {
+            self.snapshots
+                .insert(id, Arc::new(Mutex::new(UndoLog::new())));
+        }
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating object store transaction");
+        self.snapshots.get(&id).map(|undo| ObjectStoreTransaction {
+            remote: Arc::clone(&self.remote),
+            undo: Arc::clone(undo),
+            changes: BTreeMap::new(),
+        })
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        let mut batch = self.create_batch();
+        batch.changes = transaction.changes;
+        self.write_batch(batch)
+    }
+}
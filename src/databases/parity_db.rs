@@ -0,0 +1,281 @@
+use std::{collections::BTreeMap, error::Error as StdError, fmt, path::Path, sync::Arc};
+
+use parity_db::{ColId, CompressionType, Db, Options};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    databases::snapshot_transaction::{
+        column, LiveColumns, Snapshot, SnapshotHistory, SnapshotTransaction,
+    },
+    id::Id,
+};
+use log::trace;
+
+const TRIE_COLUMN: ColId = 0;
+const FLAT_COLUMN: ColId = 1;
+const TRIE_LOG_COLUMN: ColId = 2;
+
+const COLUMNS: [ColId; 3] = [TRIE_COLUMN, FLAT_COLUMN, TRIE_LOG_COLUMN];
+
+/// Creates a new ParityDB database from the given path.
+///
+/// All columns use a btree index (required for prefix iteration), trie nodes and flat values
+/// are compressed with lz4.
+pub fn create_parity_db(path: impl AsRef<Path>) -> Result<Db, parity_db::Error> {
+    // Delete folder content
+    if path.as_ref().exists() {
+        std::fs::remove_dir_all(path.as_ref()).unwrap();
+    }
+    std::fs::create_dir_all(path.as_ref()).unwrap();
+    let mut options = Options::with_columns(path.as_ref(), COLUMNS.len() as u8);
+    for column in COLUMNS {
+        options.columns[column as usize].btree_index = true;
+    }
+    options.columns[TRIE_COLUMN as usize].compression = CompressionType::Lz4;
+    options.columns[FLAT_COLUMN as usize].compression = CompressionType::Lz4;
+    Db::open_or_create(&options)
+}
+
+/// ParityDB column used to store the given key.
+fn column_id(key: &DatabaseKey) -> ColId {
+    column(key) as ColId
+}
+
+/// A struct that implements the `BonsaiDatabase` trait using ParityDB as the underlying database
+pub struct ParityDb<ID: Id> {
+    db: Arc<Db>,
+    config: ParityDbConfig,
+    history: SnapshotHistory,
+    snapshots: BTreeMap<ID, Arc<Snapshot>>,
+}
+
+/// Configuration for ParityDB database
+pub struct ParityDbConfig {
+    /// Maximum number of snapshots kept in database
+    ///
+    /// Snapshots don't copy the database, but the values overwritten since the oldest snapshot
+    /// kept are held in memory.
+    pub max_saved_snapshots: Option<usize>,
+}
+
+impl Default for ParityDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(100),
+        }
+    }
+}
+
+impl<ID: Id> ParityDb<ID> {
+    /// Creates a new ParityDB wrapper from the given ParityDB database
+    pub fn new(db: Db, config: ParityDbConfig) -> Self {
+        trace!("ParityDB database opened");
+        Self {
+            db: Arc::new(db),
+            config,
+            history: SnapshotHistory::default(),
+            snapshots: BTreeMap::default(),
+        }
+    }
+
+    /// Commits `changes`, after recording the values they overwrite for the snapshots.
+    fn commit(
+        &mut self,
+        changes: Vec<(ColId, Vec<u8>, Option<Vec<u8>>)>,
+    ) -> Result<(), ParityDbError> {
+        let _guard = self.history.record_writes(
+            &self.db,
+            changes
+                .iter()
+                .map(|(column, key, _)| (*column as usize, key.as_slice())),
+        )?;
+        Ok(self.db.commit(changes)?)
+    }
+}
+
+impl LiveColumns for Arc<Db> {
+    type Error = ParityDbError;
+
+    fn get(&self, column: usize, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(Db::get(self, column as ColId, key)?)
+    }
+
+    fn get_by_prefix(
+        &self,
+        column: usize,
+        prefix: &[u8],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::Error> {
+        let mut iter = self.iter(column as ColId)?;
+        iter.seek(prefix)?;
+        let mut result = Vec::new();
+        while let Some((key, value)) = iter.next()? {
+            if !key.starts_with(prefix) {
+                break;
+            }
+            result.push((key, value));
+        }
+        Ok(result)
+    }
+}
+
+/// A batch used to write changes in the ParityDB database, applied in a single commit
+#[derive(Default)]
+pub struct ParityDbBatch {
+    changes: Vec<(ColId, Vec<u8>, Option<Vec<u8>>)>,
+}
+
+#[derive(Debug)]
+pub enum ParityDbError {
+    ParityDb(parity_db::Error),
+    Custom(String),
+}
+
+impl From<parity_db::Error> for ParityDbError {
+    fn from(err: parity_db::Error) -> Self {
+        Self::ParityDb(err)
+    }
+}
+
+impl fmt::Display for ParityDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ParityDb(err) => write!(f, "ParityDB error: {}", err),
+            Self::Custom(err) => write!(f, "ParityDB error in trie: {}", err),
+        }
+    }
+}
+
+impl DBError for ParityDbError {}
+
+impl StdError for ParityDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::ParityDb(err) => Some(err),
+            Self::Custom(_) => None,
+        }
+    }
+}
+
+impl<ID: Id> BonsaiDatabase for ParityDb<ID> {
+    type Batch = ParityDbBatch;
+    type DatabaseError = ParityDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for column in COLUMNS {
+            for (key, value) in self.db.get_by_prefix(column as usize, &[]).unwrap() {
+                println!("{:?} {:?}", key, value);
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into ParityDB: {:?} {:?}", key, value);
+        let old_value = self.db.get(column(key), key.as_slice())?;
+        let change = (
+            column_id(key),
+            key.as_slice().to_vec(),
+            Some(value.to_vec()),
+        );
+        if let Some(batch) = batch {
+            batch.changes.push(change);
+        } else {
+            self.commit(vec![change])?;
+        }
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from ParityDB: {:?}", key);
+        self.db.get(column(key), key.as_slice())
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from ParityDB: {:?}", prefix);
+        self.db.get_by_prefix(column(prefix), prefix.as_slice())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if ParityDB contains: {:?}", key);
+        Ok(self.db.get_size(column_id(key), key.as_slice())?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from ParityDB: {:?}", key);
+        let old_value = self.db.get(column(key), key.as_slice())?;
+        let change = (column_id(key), key.as_slice().to_vec(), None);
+        if let Some(batch) = batch {
+            batch.changes.push(change);
+        } else {
+            self.commit(vec![change])?;
+        }
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from ParityDB: {:?}", prefix);
+        let mut batch = self.create_batch();
+        for (key, _) in self.get_by_prefix(prefix)? {
+            batch.changes.push((column_id(prefix), key, None));
+        }
+        self.write_batch(batch)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        self.commit(batch.changes)
+    }
+}
+
+/// A transaction over a ParityDB snapshot.
+///
+/// ParityDB doesn't provide point-in-time views of the database, the transaction reads the live
+/// columns and the values recorded by the database when they were overwritten after the snapshot.
+pub type ParityDbTransaction = SnapshotTransaction<Arc<Db>>;
+
+impl<ID: Id> BonsaiPersistentDatabase<ID> for ParityDb<ID> {
+    type Transaction = ParityDbTransaction;
+    type DatabaseError = ParityDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating ParityDB snapshot");
+        self.snapshots.insert(id, self.history.snapshot());
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating ParityDB transaction");
+        self.snapshots
+            .get(&id)
+            .map(|snapshot| ParityDbTransaction::new(self.db.clone(), snapshot.clone()))
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        let mut batch = self.create_batch();
+        batch.changes = transaction
+            .into_changes()
+            .into_iter()
+            .map(|((column, key), value)| (column as ColId, key, value))
+            .collect();
+        self.write_batch(batch)
+    }
+}
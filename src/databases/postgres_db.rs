@@ -0,0 +1,439 @@
+use std::{
+    collections::BTreeMap,
+    error::Error as StdError,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use postgres::{Client, Config, NoTls};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+};
+use log::trace;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS bonsai (
+        kind SMALLINT NOT NULL,
+        key BYTEA NOT NULL,
+        value BYTEA NOT NULL,
+        PRIMARY KEY (kind, key)
+    );
+";
+
+const SELECT_VALUE: &str = "SELECT value FROM bonsai WHERE kind = $1 AND key = $2";
+const SELECT_PREFIX: &str = "SELECT key, value FROM bonsai \
+    WHERE kind = $1 AND key >= $2 AND ($3::BYTEA IS NULL OR key < $3) ORDER BY key";
+const UPSERT_VALUES: &str = "INSERT INTO bonsai (kind, key, value) \
+    SELECT * FROM UNNEST($1::SMALLINT[], $2::BYTEA[], $3::BYTEA[]) \
+    ON CONFLICT (kind, key) DO UPDATE SET value = EXCLUDED.value";
+const DELETE_VALUES: &str = "DELETE FROM bonsai \
+    WHERE (kind, key) IN (SELECT * FROM UNNEST($1::SMALLINT[], $2::BYTEA[]))";
+
+/// Column discriminant used to store the given key.
+fn kind(key: &DatabaseKey) -> i16 {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+/// Smallest key that is greater than all the keys starting with `prefix`, if any.
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut bound = prefix.to_vec();
+    while let Some(last) = bound.pop() {
+        if last < u8::MAX {
+            bound.push(last + 1);
+            return Some(bound);
+        }
+    }
+    None
+}
+
+fn read_value(client: &mut Client, key: &DatabaseKey) -> Result<Option<Vec<u8>>, PostgresDbError> {
+    let row = client.query_opt(SELECT_VALUE, &[&kind(key), &key.as_slice()])?;
+    Ok(row.map(|row| row.get(0)))
+}
+
+fn read_prefix(
+    client: &mut Client,
+    prefix: &DatabaseKey,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>, PostgresDbError> {
+    let rows = client.query(
+        SELECT_PREFIX,
+        &[
+            &kind(prefix),
+            &prefix.as_slice(),
+            &prefix_upper_bound(prefix.as_slice()),
+        ],
+    )?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect())
+}
+
+/// A struct that implements the `BonsaiDatabase` trait using PostgreSQL as the underlying database
+///
+/// The writer holds a session-level advisory lock for as long as it is alive, so that two
+/// replicas can't write to the same trie concurrently.
+pub struct PostgresDb<ID: Id> {
+    client: Mutex<Client>,
+    pg_config: Config,
+    config: PostgresDbConfig,
+    snapshots: BTreeMap<ID, Arc<Mutex<Client>>>,
+}
+
+/// Configuration for PostgreSQL database
+pub struct PostgresDbConfig {
+    /// Maximum number of snapshots kept in database, each snapshot keeps a connection open
+    pub max_saved_snapshots: Option<usize>,
+    /// Key of the advisory lock taken by the writer
+    pub lock_id: i64,
+}
+
+impl Default for PostgresDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(10),
+            lock_id: 0x626f6e736169,
+        }
+    }
+}
+
+impl<ID: Id> PostgresDb<ID> {
+    /// Connects to the PostgreSQL database, creates the bonsai table if needed and takes the
+    /// writer advisory lock.
+    pub fn new(pg_config: Config, config: PostgresDbConfig) -> Result<Self, PostgresDbError> {
+        let mut client = pg_config.connect(NoTls)?;
+        client.batch_execute(SCHEMA)?;
+        let locked: bool = client
+            .query_one("SELECT pg_try_advisory_lock($1)", &[&config.lock_id])?
+            .get(0);
+        if !locked {
+            return Err(PostgresDbError::Custom(format!(
+                "advisory lock {} is held by another writer",
+                config.lock_id
+            )));
+        }
+        trace!("PostgreSQL database opened");
+        Ok(Self {
+            client: Mutex::new(client),
+            pg_config,
+            config,
+            snapshots: BTreeMap::default(),
+        })
+    }
+
+    fn client(&self) -> Result<MutexGuard<'_, Client>, PostgresDbError> {
+        lock(&self.client)
+    }
+
+    /// Opens a new connection with a repeatable read transaction, which keeps seeing the
+    /// database as it is at this point.
+    fn open_snapshot(&self) -> Result<Client, PostgresDbError> {
+        let mut snapshot = self.pg_config.connect(NoTls)?;
+        snapshot.batch_execute(
+            "BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY; SELECT 1 FROM bonsai LIMIT 1;",
+        )?;
+        Ok(snapshot)
+    }
+}
+
+fn lock(client: &Mutex<Client>) -> Result<MutexGuard<'_, Client>, PostgresDbError> {
+    client
+        .lock()
+        .map_err(|_| PostgresDbError::Custom("connection poisoned".to_string()))
+}
+
+/// A batch used to write changes in the PostgreSQL database, applied in a single SQL transaction
+#[derive(Default)]
+pub struct PostgresDbBatch {
+    changes: BTreeMap<(i16, Vec<u8>), Option<Vec<u8>>>,
+}
+
+#[derive(Debug)]
+pub enum PostgresDbError {
+    Postgres(postgres::Error),
+    Custom(String),
+}
+
+impl From<postgres::Error> for PostgresDbError {
+    fn from(err: postgres::Error) -> Self {
+        Self::Postgres(err)
+    }
+}
+
+impl fmt::Display for PostgresDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Postgres(err) => write!(f, "PostgreSQL error: {}", err),
+            Self::Custom(err) => write!(f, "PostgreSQL error in trie: {}", err),
+        }
+    }
+}
+
+impl DBError for PostgresDbError {}
+
+impl StdError for PostgresDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Postgres(err) => Some(err),
+            Self::Custom(_) => None,
+        }
+    }
+}
+
+impl<ID: Id> BonsaiDatabase for PostgresDb<ID> {
+    type Batch = PostgresDbBatch;
+    type DatabaseError = PostgresDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        let rows = self
+            .client()
+            .unwrap()
+            .query(
+                "SELECT kind, key, value FROM bonsai ORDER BY kind, key",
+                &[],
+            )
+            .unwrap();
+        for row in rows {
+            let (kind, key, value): (i16, Vec<u8>, Vec<u8>) = (row.get(0), row.get(1), row.get(2));
+            println!("{:?} {:?} {:?}", kind, key, value);
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into PostgreSQL: {:?} {:?}", key, value);
+        let old_value = self.get(key)?;
+        let change = ((kind(key), key.as_slice().to_vec()), Some(value.to_vec()));
+        if let Some(batch) = batch {
+            batch.changes.insert(change.0, change.1);
+        } else {
+            let mut batch = self.create_batch();
+            batch.changes.insert(change.0, change.1);
+            self.write_batch(batch)?;
+        }
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from PostgreSQL: {:?}", key);
+        read_value(&mut self.client()?, key)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from PostgreSQL: {:?}", prefix);
+        read_prefix(&mut self.client()?, prefix)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if PostgreSQL contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from PostgreSQL: {:?}", key);
+        let old_value = self.get(key)?;
+        let change = (kind(key), key.as_slice().to_vec());
+        if let Some(batch) = batch {
+            batch.changes.insert(change, None);
+        } else {
+            let mut batch = self.create_batch();
+            batch.changes.insert(change, None);
+            self.write_batch(batch)?;
+        }
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from PostgreSQL: {:?}", prefix);
+        let mut batch = self.create_batch();
+        for (key, _) in self.get_by_prefix(prefix)? {
+            batch.changes.insert((kind(prefix), key), None);
+        }
+        self.write_batch(batch)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        let (mut upserts, mut deletes) = ((vec![], vec![], vec![]), (vec![], vec![]));
+        for ((kind, key), value) in batch.changes {
+            match value {
+                Some(value) => {
+                    upserts.0.push(kind);
+                    upserts.1.push(key);
+                    upserts.2.push(value);
+                }
+                None => {
+                    deletes.0.push(kind);
+                    deletes.1.push(key);
+                }
+            }
+        }
+        let mut client = self.client()?;
+        let mut transaction = client.transaction()?;
+        if !deletes.0.is_empty() {
+            transaction.execute(DELETE_VALUES, &[&deletes.0, &deletes.1])?;
+        }
+        if !upserts.0.is_empty() {
+            transaction.execute(UPSERT_VALUES, &[&upserts.0, &upserts.1, &upserts.2])?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// A transaction over a PostgreSQL snapshot.
+///
+/// Reads are served from a dedicated connection holding a repeatable read transaction opened at
+/// snapshot time, while changes are kept in memory on top of it until the transaction is merged.
+pub struct PostgresTransaction {
+    snapshot: Arc<Mutex<Client>>,
+    changes: BTreeMap<(i16, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl BonsaiDatabase for PostgresTransaction {
+    type Batch = ();
+    type DatabaseError = PostgresDbError;
+
+    fn create_batch(&self) -> Self::Batch {}
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for (key, value) in self.changes.iter() {
+            println!("{:?} {:?}", key, value);
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!(
+            "Inserting into PostgreSQL transaction: {:?} {:?}",
+            key,
+            value
+        );
+        let old_value = self.get(key)?;
+        self.changes
+            .insert((kind(key), key.as_slice().to_vec()), Some(value.to_vec()));
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from PostgreSQL transaction: {:?}", key);
+        match self.changes.get(&(kind(key), key.as_slice().to_vec())) {
+            Some(value) => Ok(value.clone()),
+            None => read_value(&mut lock(&self.snapshot)?, key),
+        }
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from PostgreSQL transaction: {:?}", prefix);
+        let prefix_kind = kind(prefix);
+        let mut result: BTreeMap<Vec<u8>, Vec<u8>> =
+            read_prefix(&mut lock(&self.snapshot)?, prefix)?
+                .into_iter()
+                .collect();
+        let changes = self
+            .changes
+            .range((prefix_kind, prefix.as_slice().to_vec())..)
+            .take_while(|((change_kind, key), _)| {
+                *change_kind == prefix_kind && key.starts_with(prefix.as_slice())
+            });
+        for ((_, key), value) in changes {
+            match value {
+                Some(value) => result.insert(key.clone(), value.clone()),
+                None => result.remove(key),
+            };
+        }
+        Ok(result.into_iter().collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if PostgreSQL transaction contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from PostgreSQL transaction: {:?}", key);
+        let old_value = self.get(key)?;
+        self.changes
+            .insert((kind(key), key.as_slice().to_vec()), None);
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from PostgreSQL transaction: {:?}", prefix);
+        let prefix_kind = kind(prefix);
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.changes.insert((prefix_kind, key), None);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, _batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Ok(())
+    }
+}
+
+impl<ID: Id> BonsaiPersistentDatabase<ID> for PostgresDb<ID> {
+    type Transaction = PostgresTransaction;
+    type DatabaseError = PostgresDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating PostgreSQL snapshot");
+        // A snapshot that can't be opened is not recorded, transactions at this id will then be
+        // unavailable.
+        if let Ok(snapshot) = self.open_snapshot() {
+            self.snapshots.insert(id, Arc::new(Mutex::new(snapshot)));
+        }
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating PostgreSQL transaction");
+        self.snapshots.get(&id).map(|snapshot| PostgresTransaction {
+            snapshot: Arc::clone(snapshot),
+            changes: BTreeMap::new(),
+        })
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        self.write_batch(PostgresDbBatch {
+            changes: transaction.changes,
+        })
+    }
+}
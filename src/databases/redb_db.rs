@@ -0,0 +1,409 @@
+use std::{collections::BTreeMap, error::Error as StdError, fmt, path::Path, sync::Arc};
+
+use redb::{Database, ReadTransaction, ReadableTable, TableDefinition};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+};
+use log::trace;
+
+const TRIE_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("trie");
+const FLAT_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("flat");
+const TRIE_LOG_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("trie_log");
+
+const TABLES: [TableDefinition<&[u8], &[u8]>; 3] = [TRIE_TABLE, FLAT_TABLE, TRIE_LOG_TABLE];
+
+/// Creates a new redb database from the given path
+pub fn create_redb_db(path: impl AsRef<Path>) -> Result<Database, RedbDbError> {
+    // Delete file content
+    if path.as_ref().exists() {
+        std::fs::remove_file(path.as_ref()).unwrap();
+    }
+    let db = Database::create(path)?;
+    // Tables must exist before they can be opened by a read transaction
+    let txn = db.begin_write()?;
+    for table in TABLES {
+        txn.open_table(table)?;
+    }
+    txn.commit()?;
+    Ok(db)
+}
+
+/// Index of the redb table used to store the given key.
+fn table_index(key: &DatabaseKey) -> usize {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+fn read_value(txn: &ReadTransaction, key: &DatabaseKey) -> Result<Option<Vec<u8>>, RedbDbError> {
+    let table = txn.open_table(TABLES[table_index(key)])?;
+    let value = table.get(key.as_slice())?;
+    Ok(value.map(|value| value.value().to_vec()))
+}
+
+#[allow(clippy::type_complexity)]
+fn read_prefix(
+    txn: &ReadTransaction,
+    prefix: &DatabaseKey,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>, RedbDbError> {
+    let table = txn.open_table(TABLES[table_index(prefix)])?;
+    let mut result = Vec::new();
+    for kv in table.range::<&[u8]>(prefix.as_slice()..)? {
+        let (key, value) = kv?;
+        if !key.value().starts_with(prefix.as_slice()) {
+            break;
+        }
+        result.push((key.value().to_vec(), value.value().to_vec()));
+    }
+    Ok(result)
+}
+
+/// A struct that implements the `BonsaiDatabase` trait using redb as the underlying database
+pub struct RedbDb<ID: Id> {
+    db: Database,
+    config: RedbDbConfig,
+    snapshots: BTreeMap<ID, Arc<ReadTransaction>>,
+}
+
+/// Configuration for redb database
+pub struct RedbDbConfig {
+    /// Maximum number of snapshots kept in database
+    pub max_saved_snapshots: Option<usize>,
+}
+
+impl Default for RedbDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(100),
+        }
+    }
+}
+
+impl<ID: Id> RedbDb<ID> {
+    /// Creates a new redb wrapper from the given redb database
+    pub fn new(db: Database, config: RedbDbConfig) -> Self {
+        trace!("Redb database opened");
+        Self {
+            db,
+            config,
+            snapshots: BTreeMap::default(),
+        }
+    }
+}
+
+/// A batch used to write changes in the redb database, applied in a single write transaction
+#[derive(Default)]
+pub struct RedbDbBatch {
+    changes: Vec<(usize, Vec<u8>, Option<Vec<u8>>)>,
+}
+
+#[derive(Debug)]
+pub enum RedbDbError {
+    Redb(redb::Error),
+    Custom(String),
+}
+
+macro_rules! impl_from_redb_error {
+    ($($error:ty),*) => {
+        $(
+            impl From<$error> for RedbDbError {
+                fn from(err: $error) -> Self {
+                    Self::Redb(err.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_redb_error!(
+    redb::Error,
+    redb::DatabaseError,
+    redb::TransactionError,
+    redb::TableError,
+    redb::StorageError,
+    redb::CommitError
+);
+
+impl fmt::Display for RedbDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Redb(err) => write!(f, "Redb error: {}", err),
+            Self::Custom(err) => write!(f, "Redb error in trie: {}", err),
+        }
+    }
+}
+
+impl DBError for RedbDbError {}
+
+impl StdError for RedbDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Redb(err) => Some(err),
+            Self::Custom(_) => None,
+        }
+    }
+}
+
+impl<ID: Id> BonsaiDatabase for RedbDb<ID> {
+    type Batch = RedbDbBatch;
+    type DatabaseError = RedbDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        let txn = self.db.begin_read().unwrap();
+        for table in TABLES {
+            let table = txn.open_table(table).unwrap();
+            for (key, value) in table.iter().unwrap().flatten() {
+                println!("{:?} {:?}", key.value(), value.value());
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into redb: {:?} {:?}", key, value);
+        if let Some(batch) = batch {
+            let old_value = self.get(key)?;
+            batch.changes.push((
+                table_index(key),
+                key.as_slice().to_vec(),
+                Some(value.to_vec()),
+            ));
+            Ok(old_value)
+        } else {
+            let txn = self.db.begin_write()?;
+            let old_value = {
+                let mut table = txn.open_table(TABLES[table_index(key)])?;
+                let old_value = table.insert(key.as_slice(), value)?;
+                old_value.map(|value| value.value().to_vec())
+            };
+            txn.commit()?;
+            Ok(old_value)
+        }
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from redb: {:?}", key);
+        read_value(&self.db.begin_read()?, key)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from redb: {:?}", prefix);
+        read_prefix(&self.db.begin_read()?, prefix)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if redb contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from redb: {:?}", key);
+        if let Some(batch) = batch {
+            let old_value = self.get(key)?;
+            batch
+                .changes
+                .push((table_index(key), key.as_slice().to_vec(), None));
+            Ok(old_value)
+        } else {
+            let txn = self.db.begin_write()?;
+            let old_value = {
+                let mut table = txn.open_table(TABLES[table_index(key)])?;
+                let old_value = table.remove(key.as_slice())?;
+                old_value.map(|value| value.value().to_vec())
+            };
+            txn.commit()?;
+            Ok(old_value)
+        }
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from redb: {:?}", prefix);
+        let mut batch = self.create_batch();
+        for (key, _) in self.get_by_prefix(prefix)? {
+            batch.changes.push((table_index(prefix), key, None));
+        }
+        self.write_batch(batch)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut tables = [
+                txn.open_table(TRIE_TABLE)?,
+                txn.open_table(FLAT_TABLE)?,
+                txn.open_table(TRIE_LOG_TABLE)?,
+            ];
+            for (index, key, value) in batch.changes {
+                match value {
+                    Some(value) => {
+                        tables[index].insert(key.as_slice(), value.as_slice())?;
+                    }
+                    None => {
+                        tables[index].remove(key.as_slice())?;
+                    }
+                }
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+/// A transaction over a redb snapshot.
+///
+/// Reads are served from a redb read transaction opened at snapshot time, while changes are kept
+/// in memory on top of it until the transaction is merged.
+pub struct RedbTransaction {
+    snapshot: Arc<ReadTransaction>,
+    changes: BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl BonsaiDatabase for RedbTransaction {
+    type Batch = ();
+    type DatabaseError = RedbDbError;
+
+    fn create_batch(&self) -> Self::Batch {}
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for (key, value) in self.changes.iter() {
+            println!("{:?} {:?}", key, value);
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into redb transaction: {:?} {:?}", key, value);
+        let old_value = self.get(key)?;
+        self.changes.insert(
+            (table_index(key), key.as_slice().to_vec()),
+            Some(value.to_vec()),
+        );
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from redb transaction: {:?}", key);
+        match self
+            .changes
+            .get(&(table_index(key), key.as_slice().to_vec()))
+        {
+            Some(value) => Ok(value.clone()),
+            None => read_value(&self.snapshot, key),
+        }
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from redb transaction: {:?}", prefix);
+        let index = table_index(prefix);
+        let mut result: BTreeMap<Vec<u8>, Vec<u8>> =
+            read_prefix(&self.snapshot, prefix)?.into_iter().collect();
+        let changes = self
+            .changes
+            .range((index, prefix.as_slice().to_vec())..)
+            .take_while(|((change_index, key), _)| {
+                *change_index == index && key.starts_with(prefix.as_slice())
+            });
+        for ((_, key), value) in changes {
+            match value {
+                Some(value) => result.insert(key.clone(), value.clone()),
+                None => result.remove(key),
+            };
+        }
+        Ok(result.into_iter().collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if redb transaction contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from redb transaction: {:?}", key);
+        let old_value = self.get(key)?;
+        self.changes
+            .insert((table_index(key), key.as_slice().to_vec()), None);
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from redb transaction: {:?}", prefix);
+        let index = table_index(prefix);
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.changes.insert((index, key), None);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, _batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Ok(())
+    }
+}
+
+impl<ID: Id> BonsaiPersistentDatabase<ID> for RedbDb<ID> {
+    type Transaction = RedbTransaction;
+    type DatabaseError = RedbDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating redb snapshot");
+        // A snapshot that can't be opened is not recorded, transactions at this id will then be
+        // unavailable.
+        if let Ok(snapshot) = self.db.begin_read() {
+            self.snapshots.insert(id, Arc::new(snapshot));
+        }
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating redb transaction");
+        self.snapshots.get(&id).map(|snapshot| RedbTransaction {
+            snapshot: Arc::clone(snapshot),
+            changes: BTreeMap::new(),
+        })
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        let mut batch = self.create_batch();
+        batch.changes = transaction
+            .changes
+            .into_iter()
+            .map(|((index, key), value)| (index, key, value))
+            .collect();
+        self.write_batch(batch)
+    }
+}
@@ -2,17 +2,23 @@ use std::{
     collections::{BTreeMap, HashMap},
     error::Error as StdError,
     fmt,
+    ops::ControlFlow,
     path::Path,
+    sync::Arc,
 };
 
+pub use rocksdb::DBCompressionType;
 use rocksdb::{
-    ColumnFamilyDescriptor, ColumnFamilyRef, Direction, Error, IteratorMode, MultiThreaded,
-    OptimisticTransactionDB, OptimisticTransactionOptions, Options, ReadOptions,
-    SnapshotWithThreadMode, Transaction, WriteBatchWithTransaction, WriteOptions,
+    BlockBasedOptions, Cache, ColumnFamilyDescriptor, ColumnFamilyRef, DBWithThreadMode, Direction,
+    Error, IteratorMode, MultiThreaded, OptimisticTransactionDB, OptimisticTransactionOptions,
+    Options, ReadOptions, SnapshotWithThreadMode, Transaction, WriteBatchWithTransaction,
+    WriteOptions,
 };
 
 use crate::{
-    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    bonsai_database::{
+        BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey, KeyValueIter,
+    },
     id::Id,
 };
 use log::trace;
@@ -25,6 +31,14 @@ const CF_ERROR: &str = "critical: rocksdb column family operation failed";
 
 /// Creates a new RocksDB database from the given path
 pub fn create_rocks_db(path: impl AsRef<Path>) -> Result<OptimisticTransactionDB, Error> {
+    create_rocks_db_with_options(path, &RocksDBOptions::default())
+}
+
+/// Creates a new RocksDB database from the given path, tuned with the given options
+pub fn create_rocks_db_with_options(
+    path: impl AsRef<Path>,
+    options: &RocksDBOptions,
+) -> Result<OptimisticTransactionDB, Error> {
     // Delete folder content
     if path.as_ref().exists() {
         std::fs::remove_dir_all(path.as_ref()).unwrap();
@@ -33,24 +47,111 @@ pub fn create_rocks_db(path: impl AsRef<Path>) -> Result<OptimisticTransactionDB
     let mut opts = Options::default();
     opts.create_if_missing(true);
     opts.create_missing_column_families(true);
+    if let Some(jobs) = options.max_background_jobs {
+        opts.set_max_background_jobs(jobs);
+    }
+    // The block cache is shared by all the column families
+    let cache = options.block_cache_size.map(Cache::new_lru_cache);
     let db = OptimisticTransactionDB::<MultiThreaded>::open_cf_descriptors(
         &opts,
         path,
         vec![
-            ColumnFamilyDescriptor::new(TRIE_LOG_CF, Options::default()),
-            ColumnFamilyDescriptor::new(TRIE_CF, Options::default()),
-            ColumnFamilyDescriptor::new(FLAT_CF, Options::default()),
+            ColumnFamilyDescriptor::new(TRIE_LOG_CF, options.trie_log.to_options(cache.as_ref())),
+            ColumnFamilyDescriptor::new(TRIE_CF, options.trie.to_options(cache.as_ref())),
+            ColumnFamilyDescriptor::new(FLAT_CF, options.flat.to_options(cache.as_ref())),
         ],
     )?;
 
     Ok(db)
 }
 
+/// Tuning of the RocksDB database created by [`create_rocks_db_with_options`]
+///
+/// The defaults are the RocksDB defaults.
+#[derive(Clone, Debug, Default)]
+pub struct RocksDBOptions {
+    /// Maximum number of concurrent flushes and compactions
+    pub max_background_jobs: Option<i32>,
+    /// Size in bytes of the LRU block cache shared by the column families
+    pub block_cache_size: Option<usize>,
+    /// Options of the column family storing the trie nodes
+    pub trie: RocksDBColumnOptions,
+    /// Options of the column family storing the key/value pairs of the trie
+    pub flat: RocksDBColumnOptions,
+    /// Options of the column family storing the trie logs
+    pub trie_log: RocksDBColumnOptions,
+}
+
+/// Tuning of a RocksDB column family
+#[derive(Clone, Debug)]
+pub struct RocksDBColumnOptions {
+    /// Bits per key of the bloom filter (None = no bloom filter)
+    pub bloom_filter_bits: Option<f64>,
+    /// Compression of the blocks
+    pub compression: DBCompressionType,
+    /// Size in bytes of the memtable
+    pub write_buffer_size: Option<usize>,
+    /// Size in bytes of the blocks
+    pub block_size: Option<usize>,
+}
+
+impl Default for RocksDBColumnOptions {
+    fn default() -> Self {
+        Self {
+            bloom_filter_bits: None,
+            compression: DBCompressionType::Snappy,
+            write_buffer_size: None,
+            block_size: None,
+        }
+    }
+}
+
+impl RocksDBColumnOptions {
+    fn to_options(&self, cache: Option<&Cache>) -> Options {
+        let mut opts = Options::default();
+        opts.set_compression_type(self.compression);
+        if let Some(size) = self.write_buffer_size {
+            opts.set_write_buffer_size(size);
+        }
+        let mut block_opts = BlockBasedOptions::default();
+        if let Some(cache) = cache {
+            block_opts.set_block_cache(cache);
+        }
+        if let Some(bits) = self.bloom_filter_bits {
+            block_opts.set_bloom_filter(bits, false);
+        }
+        if let Some(size) = self.block_size {
+            block_opts.set_block_size(size);
+        }
+        opts.set_block_based_table_factory(&block_opts);
+        opts
+    }
+}
+
+/// Opens the RocksDB database at `primary_path` as a secondary instance.
+///
+/// A secondary instance only reads the files of the primary one and keeps its own logs in
+/// `secondary_path`, it can be used from another process while the primary is being written.
+pub fn open_rocks_db_secondary(
+    primary_path: impl AsRef<Path>,
+    secondary_path: impl AsRef<Path>,
+) -> Result<DBWithThreadMode<MultiThreaded>, Error> {
+    let mut opts = Options::default();
+    // Required by RocksDB for secondary instances
+    opts.set_max_open_files(-1);
+    DBWithThreadMode::<MultiThreaded>::open_cf_as_secondary(
+        &opts,
+        primary_path.as_ref(),
+        secondary_path.as_ref(),
+        [TRIE_LOG_CF, TRIE_CF, FLAT_CF],
+    )
+}
+
 /// A struct that implements the `BonsaiDatabase` trait using RocksDB as the underlying database
 pub struct RocksDB<'db, ID: Id> {
     db: &'db OptimisticTransactionDB<MultiThreaded>,
     config: RocksDBConfig,
-    snapshots: BTreeMap<ID, SnapshotWithThreadMode<'db, OptimisticTransactionDB>>,
+    snapshots: BTreeMap<ID, Arc<SnapshotWithThreadMode<'db, OptimisticTransactionDB>>>,
 }
 
 /// Configuration for RocksDB database
@@ -131,10 +232,25 @@ impl DatabaseKey<'_> {
     }
 }
 
+/// A transaction over the state of a RocksDB database at a snapshot
+///
+/// All the reads, including the ones by prefix, go through the snapshot so commits made on the
+/// main database while the transaction is alive are not seen. The transaction is optimistic:
+/// merging it fails if a key it wrote was also written by the main database in the meantime.
 pub struct RocksDBTransaction<'a> {
     txn: Transaction<'a, OptimisticTransactionDB>,
     read_options: ReadOptions,
     column_families: HashMap<String, ColumnFamilyRef<'a>>,
+    // Kept alive as long as the read options point to it, even if the database drops it
+    snapshot: Arc<SnapshotWithThreadMode<'a, OptimisticTransactionDB>>,
+}
+
+impl RocksDBTransaction<'_> {
+    fn snapshot_read_options(&self) -> ReadOptions {
+        let mut read_options = ReadOptions::default();
+        read_options.set_snapshot(&*self.snapshot);
+        read_options
+    }
 }
 
 impl<'db, ID> BonsaiDatabase for RocksDB<'db, ID>
@@ -202,6 +318,24 @@ where
         Ok(self.db.get_cf(&handle, key.as_slice())?)
     }
 
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<Vec<u8>>>, Self::DatabaseError> {
+        trace!("Getting {} keys from RocksDB", keys.len());
+        let handles: Vec<_> = keys
+            .iter()
+            .map(|key| self.db.cf_handle(key.get_cf()).expect(CF_ERROR))
+            .collect();
+        self.db
+            .multi_get_cf(
+                handles
+                    .iter()
+                    .zip(keys)
+                    .map(|(handle, key)| (handle, key.as_slice())),
+            )
+            .into_iter()
+            .map(|value| Ok(value?))
+            .collect()
+    }
+
     fn get_by_prefix(
         &self,
         prefix: &DatabaseKey,
@@ -227,6 +361,44 @@ where
             .collect())
     }
 
+    fn iter_prefix(&self, prefix: &DatabaseKey) -> KeyValueIter<'_, Self::DatabaseError> {
+        trace!("Iterating over RocksDB: {:?}", prefix);
+        let handle = self.db.cf_handle(prefix.get_cf()).expect(CF_ERROR);
+        let iter = self.db.iterator_cf(
+            &handle,
+            IteratorMode::From(prefix.as_slice(), Direction::Forward),
+        );
+        let prefix = prefix.as_slice().to_vec();
+        Box::new(
+            iter.take_while(move |kv| match kv {
+                Ok((key, _)) => key.starts_with(&prefix),
+                Err(_) => true,
+            })
+            .map(|kv| {
+                let (key, value) = kv?;
+                Ok((key.to_vec(), value.to_vec()))
+            }),
+        )
+    }
+
+    fn visit_prefix(
+        &self,
+        prefix: &DatabaseKey,
+        visitor: &mut dyn FnMut(&[u8], &[u8]) -> ControlFlow<()>,
+    ) -> Result<(), Self::DatabaseError> {
+        trace!("Visiting RocksDB: {:?}", prefix);
+        let handle = self.db.cf_handle(prefix.get_cf()).expect(CF_ERROR);
+        let mut iter = self.db.raw_iterator_cf(&handle);
+        iter.seek(prefix.as_slice());
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            if !key.starts_with(prefix.as_slice()) || visitor(key, value).is_break() {
+                break;
+            }
+            iter.next();
+        }
+        Ok(iter.status()?)
+    }
+
     fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
         trace!("Checking if RocksDB contains: {:?}", key);
         let handle = self.db.cf_handle(key.get_cf()).expect(CF_ERROR);
@@ -348,14 +520,30 @@ impl<'db> BonsaiDatabase for RocksDBTransaction<'db> {
             .get_cf_opt(handle, key.as_slice(), &self.read_options)?)
     }
 
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<Vec<u8>>>, Self::DatabaseError> {
+        trace!("Getting {} keys from RocksDB", keys.len());
+        self.txn
+            .multi_get_cf_opt(
+                keys.iter().map(|key| {
+                    let handle = self.column_families.get(key.get_cf()).expect(CF_ERROR);
+                    (handle, key.as_slice())
+                }),
+                &self.read_options,
+            )
+            .into_iter()
+            .map(|value| Ok(value?))
+            .collect()
+    }
+
     fn get_by_prefix(
         &self,
         prefix: &DatabaseKey,
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
         trace!("Getting from RocksDB: {:?}", prefix);
         let handle = self.column_families.get(prefix.get_cf()).expect(CF_ERROR);
-        let iter = self.txn.iterator_cf(
+        let iter = self.txn.iterator_cf_opt(
             handle,
+            self.snapshot_read_options(),
             IteratorMode::From(prefix.as_slice(), Direction::Forward),
         );
         Ok(iter
@@ -373,6 +561,47 @@ impl<'db> BonsaiDatabase for RocksDBTransaction<'db> {
             .collect())
     }
 
+    fn iter_prefix(&self, prefix: &DatabaseKey) -> KeyValueIter<'_, Self::DatabaseError> {
+        trace!("Iterating over RocksDB: {:?}", prefix);
+        let handle = self.column_families.get(prefix.get_cf()).expect(CF_ERROR);
+        let iter = self.txn.iterator_cf_opt(
+            handle,
+            self.snapshot_read_options(),
+            IteratorMode::From(prefix.as_slice(), Direction::Forward),
+        );
+        let prefix = prefix.as_slice().to_vec();
+        Box::new(
+            iter.take_while(move |kv| match kv {
+                Ok((key, _)) => key.starts_with(&prefix),
+                Err(_) => true,
+            })
+            .map(|kv| {
+                let (key, value) = kv?;
+                Ok((key.to_vec(), value.to_vec()))
+            }),
+        )
+    }
+
+    fn visit_prefix(
+        &self,
+        prefix: &DatabaseKey,
+        visitor: &mut dyn FnMut(&[u8], &[u8]) -> ControlFlow<()>,
+    ) -> Result<(), Self::DatabaseError> {
+        trace!("Visiting RocksDB: {:?}", prefix);
+        let handle = self.column_families.get(prefix.get_cf()).expect(CF_ERROR);
+        let mut iter = self
+            .txn
+            .raw_iterator_cf_opt(handle, self.snapshot_read_options());
+        iter.seek(prefix.as_slice());
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            if !key.starts_with(prefix.as_slice()) || visitor(key, value).is_break() {
+                break;
+            }
+            iter.next();
+        }
+        Ok(iter.status()?)
+    }
+
     fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
         trace!("Checking if RocksDB contains: {:?}", key);
         let handle = self.column_families.get(key.get_cf()).expect(CF_ERROR);
@@ -405,8 +634,9 @@ impl<'db> BonsaiDatabase for RocksDBTransaction<'db> {
         let mut batch = self.create_batch();
         {
             let handle = self.column_families.get(prefix.get_cf()).expect(CF_ERROR);
-            let iter = self.txn.iterator_cf(
+            let iter = self.txn.iterator_cf_opt(
                 handle,
+                self.snapshot_read_options(),
                 IteratorMode::From(prefix.as_slice(), Direction::Forward),
             );
             for kv in iter {
@@ -440,7 +670,7 @@ where
     fn snapshot(&mut self, id: ID) {
         trace!("Generating RocksDB transaction");
         let snapshot = self.db.snapshot();
-        self.snapshots.insert(id, snapshot);
+        self.snapshots.insert(id, Arc::new(snapshot));
         if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
             while self.snapshots.len() > max_number_snapshot {
                 self.snapshots.pop_first();
@@ -457,7 +687,7 @@ where
             let txn = self.db.transaction_opt(&write_opts, &txn_opts);
 
             let mut read_options = ReadOptions::default();
-            read_options.set_snapshot(snapshot);
+            read_options.set_snapshot(&**snapshot);
 
             let mut column_families = HashMap::new();
             column_families.insert(
@@ -476,6 +706,7 @@ where
                 txn,
                 column_families,
                 read_options,
+                snapshot: Arc::clone(snapshot),
             };
             Some(boxed_txn)
         } else {
@@ -488,3 +719,153 @@ where
         Ok(())
     }
 }
+
+/// A struct that implements the `BonsaiDatabase` trait over a RocksDB secondary instance.
+///
+/// All the writing operations fail, use [`RocksDBSecondary::try_catch_up_with_primary`] to see
+/// the latest changes written by the primary instance.
+pub struct RocksDBSecondary<'db> {
+    db: &'db DBWithThreadMode<MultiThreaded>,
+}
+
+impl<'db> RocksDBSecondary<'db> {
+    /// Creates a new RocksDB wrapper from the given RocksDB secondary instance
+    pub fn new(db: &'db DBWithThreadMode<MultiThreaded>) -> Self {
+        trace!("RockDB secondary database opened");
+        Self { db }
+    }
+
+    /// Reads the latest changes written by the primary instance
+    pub fn try_catch_up_with_primary(&self) -> Result<(), RocksDBError> {
+        Ok(self.db.try_catch_up_with_primary()?)
+    }
+
+    fn read_only_error() -> RocksDBError {
+        RocksDBError::Custom("database is opened as secondary and can't be written".to_string())
+    }
+}
+
+impl<'db> BonsaiDatabase for RocksDBSecondary<'db> {
+    type Batch = ();
+    type DatabaseError = RocksDBError;
+
+    fn create_batch(&self) -> Self::Batch {}
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for cf in [TRIE_CF, FLAT_CF, TRIE_LOG_CF] {
+            let handle = self.db.cf_handle(cf).expect(CF_ERROR);
+            let mut iter = self.db.raw_iterator_cf(&handle);
+            iter.seek_to_first();
+            while iter.valid() {
+                let key = iter.key().unwrap();
+                let value = iter.value().unwrap();
+                println!("{:?} {:?}", key, value);
+                iter.next();
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        _key: &DatabaseKey,
+        _value: &[u8],
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        Err(Self::read_only_error())
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from RocksDB secondary: {:?}", key);
+        let handle = self.db.cf_handle(key.get_cf()).expect(CF_ERROR);
+        Ok(self.db.get_cf(&handle, key.as_slice())?)
+    }
+
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<Vec<u8>>>, Self::DatabaseError> {
+        trace!("Getting {} keys from RocksDB secondary", keys.len());
+        let handles: Vec<_> = keys
+            .iter()
+            .map(|key| self.db.cf_handle(key.get_cf()).expect(CF_ERROR))
+            .collect();
+        self.db
+            .multi_get_cf(
+                handles
+                    .iter()
+                    .zip(keys)
+                    .map(|(handle, key)| (handle, key.as_slice())),
+            )
+            .into_iter()
+            .map(|value| Ok(value?))
+            .collect()
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from RocksDB secondary: {:?}", prefix);
+        let handle = self.db.cf_handle(prefix.get_cf()).expect(CF_ERROR);
+        let iter = self.db.iterator_cf(
+            &handle,
+            IteratorMode::From(prefix.as_slice(), Direction::Forward),
+        );
+        Ok(iter
+            .map_while(|kv| {
+                if let Ok((key, value)) = kv {
+                    if key.starts_with(prefix.as_slice()) {
+                        Some((key.to_vec(), value.to_vec()))
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            })
+            .collect())
+    }
+
+    fn iter_prefix(&self, prefix: &DatabaseKey) -> KeyValueIter<'_, Self::DatabaseError> {
+        trace!("Iterating over RocksDB secondary: {:?}", prefix);
+        let handle = self.db.cf_handle(prefix.get_cf()).expect(CF_ERROR);
+        let iter = self.db.iterator_cf(
+            &handle,
+            IteratorMode::From(prefix.as_slice(), Direction::Forward),
+        );
+        let prefix = prefix.as_slice().to_vec();
+        Box::new(
+            iter.take_while(move |kv| match kv {
+                Ok((key, _)) => key.starts_with(&prefix),
+                Err(_) => true,
+            })
+            .map(|kv| {
+                let (key, value) = kv?;
+                Ok((key.to_vec(), value.to_vec()))
+            }),
+        )
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if RocksDB secondary contains: {:?}", key);
+        let handle = self.db.cf_handle(key.get_cf()).expect(CF_ERROR);
+        Ok(self
+            .db
+            .get_cf(&handle, key.as_slice())
+            .map(|value| value.is_some())?)
+    }
+
+    fn remove(
+        &mut self,
+        _key: &DatabaseKey,
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        Err(Self::read_only_error())
+    }
+
+    fn remove_by_prefix(&mut self, _prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        Err(Self::read_only_error())
+    }
+
+    fn write_batch(&mut self, _batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Err(Self::read_only_error())
+    }
+}
@@ -0,0 +1,314 @@
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DatabaseKey},
+    id::Id,
+};
+use log::trace;
+
+/// Picks the shard of a key, given the number of shards. It must always return the same shard
+/// for the same key.
+pub type ShardRouter = fn(&DatabaseKey, usize) -> usize;
+
+/// Default router: keys are spread across all the shards with a FNV-1a hash.
+pub fn hash_router(key: &DatabaseKey, shards: usize) -> usize {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in key.as_slice() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % shards as u64) as usize
+}
+
+/// Configuration for the sharded database
+#[derive(Clone, Copy)]
+pub struct ShardedDbConfig {
+    /// Function routing each key to a shard
+    pub router: ShardRouter,
+}
+
+impl Default for ShardedDbConfig {
+    fn default() -> Self {
+        Self {
+            router: hash_router,
+        }
+    }
+}
+
+/// A database wrapper spreading keys across several inner databases
+///
+/// Each shard can live on its own disk and is compacted independently. Reads and removals by
+/// prefix go through all the shards.
+///
+/// Batches are written shard by shard. If a shard fails to write its part, the parts already
+/// written are reverted so the batch is written to all the shards or to none. A process stopped
+/// between two shard writes can still leave a batch partially written.
+/// Transactions are merged shard by shard.
+///
+/// A storage holds a single trie, so tries that should be kept apart (e.g. the storage tries of
+/// different contracts) are better stored in different storages, sharding is for tries too big
+/// for one database.
+pub struct ShardedDb<D> {
+    shards: Vec<D>,
+    config: ShardedDbConfig,
+}
+
+impl<D: BonsaiDatabase> ShardedDb<D> {
+    /// Creates a new sharded database over the given databases.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no shards.
+    pub fn new(shards: Vec<D>, config: ShardedDbConfig) -> Self {
+        assert!(
+            !shards.is_empty(),
+            "A sharded database needs at least one shard"
+        );
+        Self { shards, config }
+    }
+
+    /// Returns the shards
+    pub fn shards(&self) -> &[D] {
+        &self.shards
+    }
+
+    fn shard_index(&self, key: &DatabaseKey) -> usize {
+        (self.config.router)(key, self.shards.len()) % self.shards.len()
+    }
+}
+
+/// Key along with the discriminant of its kind and its value before the batch, `None` if it
+/// didn't exist.
+type OldValue = (u8, Vec<u8>, Option<Vec<u8>>);
+
+fn kind(key: &DatabaseKey) -> u8 {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+fn database_key(kind: u8, key: &[u8]) -> DatabaseKey {
+    match kind {
+        0 => DatabaseKey::Trie(key),
+        1 => DatabaseKey::Flat(key),
+        _ => DatabaseKey::TrieLog(key),
+    }
+}
+
+/// A batch of changes, one batch per shard along with the values it overwrites
+pub struct ShardedDbBatch<D: BonsaiDatabase> {
+    batches: Vec<D::Batch>,
+    old_values: Vec<Vec<OldValue>>,
+}
+
+impl<D: BonsaiDatabase> Default for ShardedDbBatch<D> {
+    fn default() -> Self {
+        Self {
+            batches: Vec::new(),
+            old_values: Vec::new(),
+        }
+    }
+}
+
+impl<D: BonsaiDatabase> ShardedDbBatch<D> {
+    fn shard(&mut self, index: usize, shards: usize) -> &mut D::Batch {
+        // A batch created with `Default` doesn't know the number of shards yet
+        if self.batches.len() < shards {
+            self.batches.resize_with(shards, Default::default);
+        }
+        &mut self.batches[index]
+    }
+
+    fn record(&mut self, index: usize, key: &DatabaseKey, old_value: Option<Vec<u8>>) {
+        if self.old_values.len() <= index {
+            self.old_values.resize_with(index + 1, Vec::new);
+        }
+        self.old_values[index].push((kind(key), key.as_slice().to_vec(), old_value));
+    }
+}
+
+impl<D: BonsaiDatabase> ShardedDb<D> {
+    /// Writes back the values overwritten by a batch in a shard.
+    fn revert_shard(
+        &mut self,
+        index: usize,
+        old_values: Vec<OldValue>,
+    ) -> Result<(), D::DatabaseError> {
+        let shard = &mut self.shards[index];
+        let mut batch = shard.create_batch();
+        // The first value recorded for a key is the one it had before the batch
+        for (kind, key, old_value) in old_values.into_iter().rev() {
+            let key = database_key(kind, &key);
+            match old_value {
+                Some(value) => shard.insert(&key, &value, Some(&mut batch))?,
+                None => shard.remove(&key, Some(&mut batch))?,
+            };
+        }
+        shard.write_batch(batch)
+    }
+}
+
+impl<D: BonsaiDatabase> BonsaiDatabase for ShardedDb<D> {
+    type Batch = ShardedDbBatch<D>;
+    type DatabaseError = D::DatabaseError;
+
+    fn create_batch(&self) -> Self::Batch {
+        ShardedDbBatch {
+            batches: self
+                .shards
+                .iter()
+                .map(|shard| shard.create_batch())
+                .collect(),
+        }
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for (index, shard) in self.shards.iter().enumerate() {
+            println!("Shard {}:", index);
+            shard.dump_database();
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into sharded database: {:?} {:?}", key, value);
+        let index = self.shard_index(key);
+        let shards = self.shards.len();
+        match batch {
+            Some(batch) => {
+                let old_value =
+                    self.shards[index].insert(key, value, Some(batch.shard(index, shards)))?;
+                batch.record(index, key, old_value.clone());
+                Ok(old_value)
+            }
+            None => self.shards[index].insert(key, value, None),
+        }
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from sharded database: {:?}", key);
+        self.shards[self.shard_index(key)].get(key)
+    }
+
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<Vec<u8>>>, Self::DatabaseError> {
+        trace!("Getting {} keys from sharded database", keys.len());
+        // Keys are read shard by shard, each shard reads all its keys at once
+        let mut by_shard = vec![Vec::new(); self.shards.len()];
+        for (index, key) in keys.iter().enumerate() {
+            by_shard[self.shard_index(key)].push(index);
+        }
+        let mut values = vec![None; keys.len()];
+        for (shard, indexes) in self.shards.iter().zip(by_shard) {
+            if indexes.is_empty() {
+                continue;
+            }
+            let shard_keys: Vec<_> = indexes.iter().map(|index| keys[*index]).collect();
+            for (index, value) in indexes.into_iter().zip(shard.get_many(&shard_keys)?) {
+                values[index] = value;
+            }
+        }
+        Ok(values)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from sharded database: {:?}", prefix);
+        let mut result = Vec::new();
+        for shard in self.shards.iter() {
+            result.extend(shard.get_by_prefix(prefix)?);
+        }
+        result.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(result)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if sharded database contains: {:?}", key);
+        self.shards[self.shard_index(key)].contains(key)
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from sharded database: {:?}", key);
+        let index = self.shard_index(key);
+        let shards = self.shards.len();
+        match batch {
+            Some(batch) => {
+                let old_value = self.shards[index].remove(key, Some(batch.shard(index, shards)))?;
+                batch.record(index, key, old_value.clone());
+                Ok(old_value)
+            }
+            None => self.shards[index].remove(key, None),
+        }
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from sharded database: {:?}", prefix);
+        // Removed in a single batch so the keys are removed from all the shards or from none
+        let mut batch = self.create_batch();
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.remove(&database_key(kind(prefix), &key), Some(&mut batch))?;
+        }
+        self.write_batch(batch)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        let mut old_values = batch.old_values;
+        old_values.resize_with(self.shards.len(), Vec::new);
+        for (failed, batch) in batch.batches.into_iter().enumerate() {
+            if let Err(err) = self.shards[failed].write_batch(batch) {
+                // Nothing more can be done if reverting fails, the original error is returned
+                for (index, old_values) in old_values.into_iter().enumerate().take(failed).rev() {
+                    let _ = self.revert_shard(index, old_values);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<ID, D> BonsaiPersistentDatabase<ID> for ShardedDb<D>
+where
+    ID: Id,
+    D: BonsaiDatabase + BonsaiPersistentDatabase<ID>,
+{
+    type Transaction = ShardedDb<D::Transaction>;
+    type DatabaseError = <D as BonsaiPersistentDatabase<ID>>::DatabaseError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating sharded database snapshot");
+        for shard in self.shards.iter_mut() {
+            shard.snapshot(id);
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating sharded database transaction");
+        let shards = self
+            .shards
+            .iter()
+            .map(|shard| shard.transaction(id))
+            .collect::<Option<Vec<_>>>()?;
+        Some(ShardedDb::new(shards, self.config))
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        for (shard, transaction) in self.shards.iter_mut().zip(transaction.shards) {
+            shard.merge(transaction)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+use std::{collections::BTreeMap, error::Error as StdError, fmt, path::Path, sync::Arc};
+
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError, Transactional},
+    Batch, Db, Tree,
+};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    databases::snapshot_transaction::{
+        column, LiveColumns, Snapshot, SnapshotHistory, SnapshotTransaction,
+    },
+    id::Id,
+};
+use log::trace;
+
+const TRIE_LOG_TREE: &str = "trie_log";
+const TRIE_TREE: &str = "trie";
+const FLAT_TREE: &str = "flat";
+
+/// Creates a new sled database from the given path
+pub fn create_sled_db(path: impl AsRef<Path>) -> Result<Db, sled::Error> {
+    // Delete folder content
+    if path.as_ref().exists() {
+        std::fs::remove_dir_all(path.as_ref()).unwrap();
+    }
+    std::fs::create_dir_all(path.as_ref()).unwrap();
+    sled::open(path)
+}
+
+/// A struct that implements the `BonsaiDatabase` trait using sled as the underlying database
+pub struct SledDb<ID: Id> {
+    db: Db,
+    trees: [Tree; 3],
+    config: SledDbConfig,
+    history: SnapshotHistory,
+    snapshots: BTreeMap<ID, Arc<Snapshot>>,
+}
+
+/// Configuration for sled database
+pub struct SledDbConfig {
+    /// Maximum number of snapshots kept in database
+    ///
+    /// Snapshots don't copy the database, but the values overwritten since the oldest snapshot
+    /// kept are held in memory.
+    pub max_saved_snapshots: Option<usize>,
+}
+
+impl Default for SledDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(100),
+        }
+    }
+}
+
+impl<ID: Id> SledDb<ID> {
+    /// Creates a new sled wrapper from the given sled database
+    pub fn new(db: Db, config: SledDbConfig) -> Result<Self, SledDbError> {
+        trace!("Sled database opened");
+        let trees = [
+            db.open_tree(TRIE_TREE)?,
+            db.open_tree(FLAT_TREE)?,
+            db.open_tree(TRIE_LOG_TREE)?,
+        ];
+        Ok(Self {
+            db,
+            trees,
+            config,
+            history: SnapshotHistory::default(),
+            snapshots: BTreeMap::default(),
+        })
+    }
+
+    /// Flushes all dirty IO buffers of the underlying database to disk.
+    pub fn flush(&self) -> Result<(), SledDbError> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn tree(&self, key: &DatabaseKey) -> &Tree {
+        &self.trees[column(key)]
+    }
+}
+
+/// A batch used to write changes in the sled database, one sled batch per tree
+#[derive(Default)]
+pub struct SledDbBatch {
+    batches: [Batch; 3],
+    keys: Vec<(usize, Vec<u8>)>,
+}
+
+impl SledDbBatch {
+    fn insert(&mut self, index: usize, key: Vec<u8>, value: Vec<u8>) {
+        self.batches[index].insert(key.as_slice(), value);
+        self.keys.push((index, key));
+    }
+
+    fn remove(&mut self, index: usize, key: Vec<u8>) {
+        self.batches[index].remove(key.as_slice());
+        self.keys.push((index, key));
+    }
+}
+
+#[derive(Debug)]
+pub enum SledDbError {
+    Sled(sled::Error),
+    Custom(String),
+}
+
+impl From<sled::Error> for SledDbError {
+    fn from(err: sled::Error) -> Self {
+        Self::Sled(err)
+    }
+}
+
+impl fmt::Display for SledDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sled(err) => write!(f, "Sled error: {}", err),
+            Self::Custom(err) => write!(f, "Sled error in trie: {}", err),
+        }
+    }
+}
+
+impl DBError for SledDbError {}
+
+impl StdError for SledDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Sled(err) => Some(err),
+            Self::Custom(_) => None,
+        }
+    }
+}
+
+impl<ID: Id> BonsaiDatabase for SledDb<ID> {
+    type Batch = SledDbBatch;
+    type DatabaseError = SledDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for tree in self.trees.iter() {
+            for (key, value) in tree.iter().flatten() {
+                println!("{:?} {:?}", key, value);
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into sled: {:?} {:?}", key, value);
+        let index = column(key);
+        if let Some(batch) = batch {
+            let old_value = self.trees[index].get(key.as_slice())?;
+            batch.insert(index, key.as_slice().to_vec(), value.to_vec());
+            Ok(old_value.map(|value| value.to_vec()))
+        } else {
+            let _guard = self
+                .history
+                .record_writes(&self.trees, [(index, key.as_slice())])?;
+            let old_value = self.trees[index].insert(key.as_slice(), value)?;
+            Ok(old_value.map(|value| value.to_vec()))
+        }
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from sled: {:?}", key);
+        Ok(self
+            .tree(key)
+            .get(key.as_slice())?
+            .map(|value| value.to_vec()))
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from sled: {:?}", prefix);
+        self.tree(prefix)
+            .scan_prefix(prefix.as_slice())
+            .map(|kv| {
+                let (key, value) = kv?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+            .collect()
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if sled contains: {:?}", key);
+        Ok(self.tree(key).contains_key(key.as_slice())?)
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from sled: {:?}", key);
+        let index = column(key);
+        if let Some(batch) = batch {
+            let old_value = self.trees[index].get(key.as_slice())?;
+            batch.remove(index, key.as_slice().to_vec());
+            Ok(old_value.map(|value| value.to_vec()))
+        } else {
+            let _guard = self
+                .history
+                .record_writes(&self.trees, [(index, key.as_slice())])?;
+            let old_value = self.trees[index].remove(key.as_slice())?;
+            Ok(old_value.map(|value| value.to_vec()))
+        }
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from sled: {:?}", prefix);
+        let mut batch = self.create_batch();
+        for kv in self.tree(prefix).scan_prefix(prefix.as_slice()) {
+            let (key, _) = kv?;
+            batch.remove(column(prefix), key.to_vec());
+        }
+        self.write_batch(batch)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        let _guard = self.history.record_writes(
+            &self.trees,
+            batch
+                .keys
+                .iter()
+                .map(|(index, key)| (*index, key.as_slice())),
+        )?;
+        let [trie, flat, trie_log] = &self.trees;
+        let [trie_batch, flat_batch, trie_log_batch] = &batch.batches;
+        (trie, flat, trie_log)
+            .transaction(|(trie, flat, trie_log)| {
+                trie.apply_batch(trie_batch)?;
+                flat.apply_batch(flat_batch)?;
+                trie_log.apply_batch(trie_log_batch)?;
+                Ok::<(), ConflictableTransactionError<()>>(())
+            })
+            .map_err(|err| match err {
+                TransactionError::Storage(err) => SledDbError::Sled(err),
+                TransactionError::Abort(()) => {
+                    SledDbError::Custom("write batch transaction aborted".to_string())
+                }
+            })
+    }
+}
+
+/// A transaction over a sled snapshot.
+///
+/// Sled doesn't provide point-in-time views of the database, the transaction reads the live trees
+/// and the values recorded by the database when they were overwritten after the snapshot.
+pub type SledTransaction = SnapshotTransaction<[Tree; 3]>;
+
+impl LiveColumns for [Tree; 3] {
+    type Error = SledDbError;
+
+    fn get(&self, column: usize, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self[column].get(key)?.map(|value| value.to_vec()))
+    }
+
+    fn get_by_prefix(
+        &self,
+        column: usize,
+        prefix: &[u8],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::Error> {
+        self[column]
+            .scan_prefix(prefix)
+            .map(|kv| {
+                let (key, value) = kv?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+            .collect()
+    }
+}
+
+impl<ID: Id> BonsaiPersistentDatabase<ID> for SledDb<ID> {
+    type Transaction = SledTransaction;
+    type DatabaseError = SledDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating sled snapshot");
+        self.snapshots.insert(id, self.history.snapshot());
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating sled transaction");
+        self.snapshots
+            .get(&id)
+            .map(|snapshot| SledTransaction::new(self.trees.clone(), snapshot.clone()))
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        let mut batch = self.create_batch();
+        for ((index, key), value) in transaction.into_changes() {
+            match value {
+                Some(value) => batch.insert(index, key, value),
+                None => batch.remove(index, key),
+            }
+        }
+        self.write_batch(batch)
+    }
+}
@@ -0,0 +1,297 @@
+//! Point-in-time views for backends that don't provide them.
+//!
+//! Instead of copying the database on each snapshot, the value a key had when the newest snapshot
+//! was taken is recorded the first time the key is written after it. A transaction reads the
+//! values recorded from its snapshot onwards, and the live database for the keys that were not
+//! written since. The memory used grows with the number of keys written since the oldest snapshot
+//! still referenced, not with the size of the database.
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    error::Error as StdError,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::bonsai_database::{BonsaiDatabase, DBError, DatabaseKey};
+use log::trace;
+
+/// Index of the column used to store the given key.
+pub fn column(key: &DatabaseKey) -> usize {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+/// Read access to the live content of a database.
+pub trait LiveColumns {
+    type Error: StdError + DBError;
+
+    fn get(&self, column: usize, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error>;
+
+    #[allow(clippy::type_complexity)]
+    fn get_by_prefix(
+        &self,
+        column: usize,
+        prefix: &[u8],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::Error>;
+}
+
+/// Values the keys written after a snapshot had when it was taken, up to the next snapshot.
+#[derive(Default)]
+pub struct SnapshotValues {
+    values: BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>,
+    next: Option<Arc<Snapshot>>,
+}
+
+/// A snapshot of the database, the values written after it are found in it or in the snapshots
+/// taken after it.
+#[derive(Default)]
+pub struct Snapshot {
+    values: Mutex<SnapshotValues>,
+}
+
+impl Snapshot {
+    fn lock(&self) -> MutexGuard<'_, SnapshotValues> {
+        self.values.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get<S: LiveColumns>(
+        self: &Arc<Self>,
+        store: &S,
+        column: usize,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, S::Error> {
+        let mut snapshot = self.clone();
+        loop {
+            let next = {
+                let values = snapshot.lock();
+                if let Some(value) = values.values.get(&(column, key.to_vec())) {
+                    return Ok(value.clone());
+                }
+                match &values.next {
+                    Some(next) => next.clone(),
+                    // The newest snapshot stays locked so no write happens during the read
+                    None => return store.get(column, key),
+                }
+            };
+            snapshot = next;
+        }
+    }
+
+    fn get_by_prefix<S: LiveColumns>(
+        self: &Arc<Self>,
+        store: &S,
+        column: usize,
+        prefix: &[u8],
+    ) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, S::Error> {
+        let mut recorded: BTreeMap<Vec<u8>, Option<Vec<u8>>> = BTreeMap::new();
+        let mut snapshot = self.clone();
+        loop {
+            let next = {
+                let values = snapshot.lock();
+                for ((_, key), value) in
+                    values.values.range((column, prefix.to_vec())..).take_while(
+                        |((key_column, key), _)| *key_column == column && key.starts_with(prefix),
+                    )
+                {
+                    recorded.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+                match &values.next {
+                    Some(next) => next.clone(),
+                    None => {
+                        let mut content: BTreeMap<Vec<u8>, Vec<u8>> =
+                            store.get_by_prefix(column, prefix)?.into_iter().collect();
+                        for (key, value) in recorded {
+                            match value {
+                                Some(value) => {
+                                    content.insert(key, value);
+                                }
+                                None => {
+                                    content.remove(&key);
+                                }
+                            }
+                        }
+                        return Ok(content);
+                    }
+                }
+            };
+            snapshot = next;
+        }
+    }
+}
+
+/// Snapshots of a database, kept by the database to record the values overwritten by its writes.
+#[derive(Default)]
+pub struct SnapshotHistory {
+    newest: Option<Arc<Snapshot>>,
+}
+
+impl SnapshotHistory {
+    /// Takes a snapshot of the current content of the database.
+    pub fn snapshot(&mut self) -> Arc<Snapshot> {
+        let snapshot = Arc::new(Snapshot::default());
+        if let Some(newest) = &self.newest {
+            newest.lock().next = Some(snapshot.clone());
+        }
+        self.newest = Some(snapshot.clone());
+        snapshot
+    }
+
+    /// Records the current values of `keys` before they are written.
+    ///
+    /// The write must be done while the returned guard is held, so transactions don't read the
+    /// live database in the middle of it.
+    pub fn record_writes<'a, 'k, S: LiveColumns>(
+        &'a mut self,
+        store: &S,
+        keys: impl IntoIterator<Item = (usize, &'k [u8])>,
+    ) -> Result<Option<MutexGuard<'a, SnapshotValues>>, S::Error> {
+        // No snapshot or transaction can read the values of the newest snapshot anymore
+        if self
+            .newest
+            .as_ref()
+            .is_some_and(|newest| Arc::strong_count(newest) == 1)
+        {
+            self.newest = None;
+        }
+        let Some(newest) = &self.newest else {
+            return Ok(None);
+        };
+        let mut values = newest.lock();
+        for (column, key) in keys {
+            if let Entry::Vacant(entry) = values.values.entry((column, key.to_vec())) {
+                entry.insert(store.get(column, key)?);
+            }
+        }
+        Ok(Some(values))
+    }
+}
+
+/// A transaction over a snapshot, all changes made to it are recorded so they can be applied back
+/// to the database on merge.
+pub struct SnapshotTransaction<S> {
+    store: S,
+    snapshot: Arc<Snapshot>,
+    changes: BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl<S: LiveColumns> SnapshotTransaction<S> {
+    pub fn new(store: S, snapshot: Arc<Snapshot>) -> Self {
+        Self {
+            store,
+            snapshot,
+            changes: BTreeMap::new(),
+        }
+    }
+
+    /// Changes made to the transaction, by column and key, `None` for a removed key.
+    pub fn into_changes(self) -> BTreeMap<(usize, Vec<u8>), Option<Vec<u8>>> {
+        self.changes
+    }
+
+    fn read(&self, column: usize, key: &[u8]) -> Result<Option<Vec<u8>>, S::Error> {
+        match self.changes.get(&(column, key.to_vec())) {
+            Some(value) => Ok(value.clone()),
+            None => self.snapshot.get(&self.store, column, key),
+        }
+    }
+
+    fn read_by_prefix(
+        &self,
+        column: usize,
+        prefix: &[u8],
+    ) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, S::Error> {
+        let mut content = self.snapshot.get_by_prefix(&self.store, column, prefix)?;
+        for ((_, key), value) in self
+            .changes
+            .range((column, prefix.to_vec())..)
+            .take_while(|((key_column, key), _)| *key_column == column && key.starts_with(prefix))
+        {
+            match value {
+                Some(value) => {
+                    content.insert(key.clone(), value.clone());
+                }
+                None => {
+                    content.remove(key);
+                }
+            }
+        }
+        Ok(content)
+    }
+}
+
+impl<S: LiveColumns> BonsaiDatabase for SnapshotTransaction<S> {
+    type Batch = ();
+    type DatabaseError = S::Error;
+
+    fn create_batch(&self) -> Self::Batch {}
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for column in 0..3 {
+            for (key, value) in self.read_by_prefix(column, &[]).unwrap() {
+                println!("{:?} {:?}", key, value);
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into snapshot transaction: {:?} {:?}", key, value);
+        let old_value = self.read(column(key), key.as_slice())?;
+        self.changes
+            .insert((column(key), key.as_slice().to_vec()), Some(value.to_vec()));
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from snapshot transaction: {:?}", key);
+        self.read(column(key), key.as_slice())
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from snapshot transaction: {:?}", prefix);
+        Ok(self
+            .read_by_prefix(column(prefix), prefix.as_slice())?
+            .into_iter()
+            .collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if snapshot transaction contains: {:?}", key);
+        Ok(self.read(column(key), key.as_slice())?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from snapshot transaction: {:?}", key);
+        let old_value = self.read(column(key), key.as_slice())?;
+        self.changes
+            .insert((column(key), key.as_slice().to_vec()), None);
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from snapshot transaction: {:?}", prefix);
+        let column = column(prefix);
+        for key in self.read_by_prefix(column, prefix.as_slice())?.into_keys() {
+            self.changes.insert((column, key), None);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, _batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Ok(())
+    }
+}
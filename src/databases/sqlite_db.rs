@@ -0,0 +1,417 @@
+use std::{
+    collections::BTreeMap,
+    error::Error as StdError,
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+};
+use log::trace;
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    CREATE TABLE IF NOT EXISTS bonsai (
+        kind INTEGER NOT NULL,
+        key BLOB NOT NULL,
+        value BLOB NOT NULL,
+        PRIMARY KEY (kind, key)
+    ) WITHOUT ROWID;
+";
+
+const SELECT_VALUE: &str = "SELECT value FROM bonsai WHERE kind = ?1 AND key = ?2";
+const SELECT_PREFIX: &str =
+    "SELECT key, value FROM bonsai WHERE kind = ?1 AND key >= ?2 ORDER BY key";
+const UPSERT_VALUE: &str = "INSERT OR REPLACE INTO bonsai (kind, key, value) VALUES (?1, ?2, ?3)";
+const DELETE_VALUE: &str = "DELETE FROM bonsai WHERE kind = ?1 AND key = ?2";
+
+/// Opens (or creates) a SQLite database file at the given path and makes sure the bonsai table exists.
+///
+/// The bonsai table can live next to other tables of an existing application database.
+pub fn create_sqlite_db(path: impl AsRef<Path>) -> Result<Connection, rusqlite::Error> {
+    let connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+    Ok(connection)
+}
+
+/// Column discriminant used to store the given key.
+fn kind(key: &DatabaseKey) -> u8 {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+fn read_value(
+    connection: &Connection,
+    key: &DatabaseKey,
+) -> Result<Option<Vec<u8>>, SqliteDbError> {
+    Ok(connection
+        .prepare_cached(SELECT_VALUE)?
+        .query_row(params![kind(key), key.as_slice()], |row| row.get(0))
+        .optional()?)
+}
+
+#[allow(clippy::type_complexity)]
+fn read_prefix(
+    connection: &Connection,
+    prefix: &DatabaseKey,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>, SqliteDbError> {
+    let mut statement = connection.prepare_cached(SELECT_PREFIX)?;
+    let rows = statement.query_map(params![kind(prefix), prefix.as_slice()], |row| {
+        Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+    })?;
+    let mut result = Vec::new();
+    for row in rows {
+        let (key, value) = row?;
+        if !key.starts_with(prefix.as_slice()) {
+            break;
+        }
+        result.push((key, value));
+    }
+    Ok(result)
+}
+
+/// A struct that implements the `BonsaiDatabase` trait using SQLite as the underlying database
+pub struct SqliteDb<ID: Id> {
+    connection: Connection,
+    config: SqliteDbConfig,
+    snapshots: BTreeMap<ID, Arc<Mutex<Connection>>>,
+}
+
+/// Configuration for SQLite database
+pub struct SqliteDbConfig {
+    /// Maximum number of snapshots kept in database
+    pub max_saved_snapshots: Option<usize>,
+}
+
+impl Default for SqliteDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(100),
+        }
+    }
+}
+
+impl<ID: Id> SqliteDb<ID> {
+    /// Creates a new SQLite wrapper from the given SQLite connection
+    pub fn new(connection: Connection, config: SqliteDbConfig) -> Result<Self, SqliteDbError> {
+        trace!("SQLite database opened");
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection,
+            config,
+            snapshots: BTreeMap::default(),
+        })
+    }
+
+    /// Opens a read-only connection on the same file and starts a read transaction on it.
+    /// In WAL mode, the transaction keeps seeing the database as it is at this point.
+    fn open_snapshot(&self) -> Result<Connection, SqliteDbError> {
+        let path = self.connection.path().ok_or(SqliteDbError::Custom(
+            "snapshots are not supported for in-memory databases".to_string(),
+        ))?;
+        let snapshot = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        snapshot.execute_batch("BEGIN DEFERRED; SELECT COUNT(*) FROM bonsai;")?;
+        Ok(snapshot)
+    }
+}
+
+/// A batch used to write changes in the SQLite database, applied in a single SQL transaction
+#[derive(Default)]
+pub struct SqliteDbBatch {
+    changes: Vec<(u8, Vec<u8>, Option<Vec<u8>>)>,
+}
+
+#[derive(Debug)]
+pub enum SqliteDbError {
+    Sqlite(rusqlite::Error),
+    Custom(String),
+}
+
+impl From<rusqlite::Error> for SqliteDbError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Sqlite(err)
+    }
+}
+
+impl fmt::Display for SqliteDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sqlite(err) => write!(f, "SQLite error: {}", err),
+            Self::Custom(err) => write!(f, "SQLite error in trie: {}", err),
+        }
+    }
+}
+
+impl DBError for SqliteDbError {}
+
+impl StdError for SqliteDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Sqlite(err) => Some(err),
+            Self::Custom(_) => None,
+        }
+    }
+}
+
+impl<ID: Id> BonsaiDatabase for SqliteDb<ID> {
+    type Batch = SqliteDbBatch;
+    type DatabaseError = SqliteDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        let mut statement = self
+            .connection
+            .prepare("SELECT kind, key, value FROM bonsai ORDER BY kind, key")
+            .unwrap();
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, u8>(0)?,
+                    row.get::<_, Vec<u8>>(1)?,
+                    row.get::<_, Vec<u8>>(2)?,
+                ))
+            })
+            .unwrap();
+        for (kind, key, value) in rows.flatten() {
+            println!("{:?} {:?} {:?}", kind, key, value);
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into SQLite: {:?} {:?}", key, value);
+        let old_value = read_value(&self.connection, key)?;
+        if let Some(batch) = batch {
+            batch
+                .changes
+                .push((kind(key), key.as_slice().to_vec(), Some(value.to_vec())));
+        } else {
+            self.connection
+                .prepare_cached(UPSERT_VALUE)?
+                .execute(params![kind(key), key.as_slice(), value])?;
+        }
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from SQLite: {:?}", key);
+        read_value(&self.connection, key)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from SQLite: {:?}", prefix);
+        read_prefix(&self.connection, prefix)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if SQLite contains: {:?}", key);
+        Ok(read_value(&self.connection, key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from SQLite: {:?}", key);
+        let old_value = read_value(&self.connection, key)?;
+        if let Some(batch) = batch {
+            batch
+                .changes
+                .push((kind(key), key.as_slice().to_vec(), None));
+        } else {
+            self.connection
+                .prepare_cached(DELETE_VALUE)?
+                .execute(params![kind(key), key.as_slice()])?;
+        }
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from SQLite: {:?}", prefix);
+        let mut batch = self.create_batch();
+        for (key, _) in read_prefix(&self.connection, prefix)? {
+            batch.changes.push((kind(prefix), key, None));
+        }
+        self.write_batch(batch)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut upsert = transaction.prepare_cached(UPSERT_VALUE)?;
+            let mut delete = transaction.prepare_cached(DELETE_VALUE)?;
+            for (kind, key, value) in batch.changes {
+                match value {
+                    Some(value) => upsert.execute(params![kind, key, value])?,
+                    None => delete.execute(params![kind, key])?,
+                };
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// A transaction over a SQLite snapshot.
+///
+/// Reads are served from a dedicated connection holding a read transaction opened at snapshot
+/// time, while changes are kept in memory on top of it until the transaction is merged.
+pub struct SqliteTransaction {
+    snapshot: Arc<Mutex<Connection>>,
+    changes: BTreeMap<(u8, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl SqliteTransaction {
+    fn snapshot_connection(&self) -> Result<std::sync::MutexGuard<'_, Connection>, SqliteDbError> {
+        self.snapshot
+            .lock()
+            .map_err(|_| SqliteDbError::Custom("snapshot connection poisoned".to_string()))
+    }
+}
+
+impl BonsaiDatabase for SqliteTransaction {
+    type Batch = ();
+    type DatabaseError = SqliteDbError;
+
+    fn create_batch(&self) -> Self::Batch {}
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for (key, value) in self.changes.iter() {
+            println!("{:?} {:?}", key, value);
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into SQLite transaction: {:?} {:?}", key, value);
+        let old_value = self.get(key)?;
+        self.changes
+            .insert((kind(key), key.as_slice().to_vec()), Some(value.to_vec()));
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from SQLite transaction: {:?}", key);
+        match self.changes.get(&(kind(key), key.as_slice().to_vec())) {
+            Some(value) => Ok(value.clone()),
+            None => read_value(&self.snapshot_connection()?, key),
+        }
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from SQLite transaction: {:?}", prefix);
+        let prefix_kind = kind(prefix);
+        let mut result: BTreeMap<Vec<u8>, Vec<u8>> =
+            read_prefix(&self.snapshot_connection()?, prefix)?
+                .into_iter()
+                .collect();
+        let changes = self
+            .changes
+            .range((prefix_kind, prefix.as_slice().to_vec())..)
+            .take_while(|((change_kind, key), _)| {
+                *change_kind == prefix_kind && key.starts_with(prefix.as_slice())
+            });
+        for ((_, key), value) in changes {
+            match value {
+                Some(value) => result.insert(key.clone(), value.clone()),
+                None => result.remove(key),
+            };
+        }
+        Ok(result.into_iter().collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if SQLite transaction contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        _batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from SQLite transaction: {:?}", key);
+        let old_value = self.get(key)?;
+        self.changes
+            .insert((kind(key), key.as_slice().to_vec()), None);
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from SQLite transaction: {:?}", prefix);
+        let prefix_kind = kind(prefix);
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.changes.insert((prefix_kind, key), None);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, _batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        Ok(())
+    }
+}
+
+impl<ID: Id> BonsaiPersistentDatabase<ID> for SqliteDb<ID> {
+    type Transaction = SqliteTransaction;
+    type DatabaseError = SqliteDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating SQLite snapshot");
+        // A snapshot that can't be opened is not recorded, transactions at this id will then be
+        // unavailable.
+        if let Ok(snapshot) = self.open_snapshot() {
+            self.snapshots.insert(id, Arc::new(Mutex::new(snapshot)));
+        }
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating SQLite transaction");
+        self.snapshots.get(&id).map(|snapshot| SqliteTransaction {
+            snapshot: Arc::clone(snapshot),
+            changes: BTreeMap::new(),
+        })
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        let mut batch = self.create_batch();
+        batch.changes = transaction
+            .changes
+            .into_iter()
+            .map(|((kind, key), value)| (kind, key, value))
+            .collect();
+        self.write_batch(batch)
+    }
+}
@@ -0,0 +1,475 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error as StdError,
+    fmt,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+};
+use log::trace;
+
+/// Key of a trie node or flat value along with the discriminant of its kind.
+type TierKey = (u8, Vec<u8>);
+
+fn kind(key: &DatabaseKey) -> u8 {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+fn database_key(kind: u8, key: &[u8]) -> DatabaseKey {
+    match kind {
+        0 => DatabaseKey::Trie(key),
+        1 => DatabaseKey::Flat(key),
+        _ => DatabaseKey::TrieLog(key),
+    }
+}
+
+/// Configuration for the tiered database
+#[derive(Clone, Copy)]
+pub struct TieredDbConfig {
+    /// Number of commits after which a trie node or flat value that hasn't been read or written
+    /// is moved to the cold store.
+    pub demote_after: u64,
+}
+
+impl Default for TieredDbConfig {
+    fn default() -> Self {
+        Self { demote_after: 64 }
+    }
+}
+
+/// Tracks when keys of the hot store were last touched, and the values read from the cold store
+/// that will be moved back to the hot store at the end of the next commit.
+#[derive(Default)]
+struct TierTracker {
+    generations: BTreeMap<u64, BTreeSet<TierKey>>,
+    last_touched: HashMap<TierKey, u64>,
+    promoted: BTreeMap<TierKey, Vec<u8>>,
+}
+
+impl TierTracker {
+    fn touch(&mut self, key: TierKey, commit: u64) {
+        if let Some(previous) = self.last_touched.insert(key.clone(), commit) {
+            if previous == commit {
+                return;
+            }
+            self.remove_from_generation(previous, &key);
+        }
+        self.generations.entry(commit).or_default().insert(key);
+    }
+
+    fn forget(&mut self, key: &TierKey) {
+        if let Some(previous) = self.last_touched.remove(key) {
+            self.remove_from_generation(previous, key);
+        }
+        self.promoted.remove(key);
+    }
+
+    fn remove_from_generation(&mut self, generation: u64, key: &TierKey) {
+        if let Some(keys) = self.generations.get_mut(&generation) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.generations.remove(&generation);
+            }
+        }
+    }
+
+    /// Stops tracking and returns all the keys last touched before the given commit.
+    fn expire_before(&mut self, commit: u64) -> Vec<TierKey> {
+        let kept = self.generations.split_off(&commit);
+        let expired = core::mem::replace(&mut self.generations, kept);
+        let keys: Vec<TierKey> = expired.into_values().flatten().collect();
+        for key in keys.iter() {
+            self.last_touched.remove(key);
+        }
+        keys
+    }
+}
+
+/// A database composing two databases: a fast hot store for recently used trie nodes and flat values,
+/// and a cold store for the rest.
+///
+/// Trie nodes and flat values not touched for [`TieredDbConfig::demote_after`] commits are moved
+/// to the cold store, values read from the cold store are moved back to the hot store at the end
+/// of the next commit. Trie logs always stay in the hot store.
+///
+/// Keys already present in the hot store when the database is created are only tracked once they
+/// are touched.
+pub struct TieredDb<H, C> {
+    hot: H,
+    cold: C,
+    config: TieredDbConfig,
+    commit_count: u64,
+    tracker: Mutex<TierTracker>,
+}
+
+impl<H: BonsaiDatabase, C: BonsaiDatabase> TieredDb<H, C> {
+    /// Creates a new tiered database from the given hot and cold databases
+    pub fn new(hot: H, cold: C, config: TieredDbConfig) -> Self {
+        Self {
+            hot,
+            cold,
+            config,
+            commit_count: 0,
+            tracker: Mutex::default(),
+        }
+    }
+
+    /// Returns the hot store
+    pub fn hot(&self) -> &H {
+        &self.hot
+    }
+
+    /// Returns the cold store
+    pub fn cold(&self) -> &C {
+        &self.cold
+    }
+
+    fn tracker(&self) -> MutexGuard<'_, TierTracker> {
+        self.tracker.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Moves the promoted values to the hot store and the expired keys to the cold store.
+    ///
+    /// Values are always written to their new store before being removed from the old one, so
+    /// that an interruption never loses them.
+    fn end_commit(&mut self) -> Result<(), TieredDbError<H::DatabaseError, C::DatabaseError>> {
+        self.commit_count += 1;
+        let (promoted, expired) = {
+            let commit_count = self.commit_count;
+            let demote_after = self.config.demote_after;
+            let tracker = self
+                .tracker
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner);
+            (
+                core::mem::take(&mut tracker.promoted),
+                tracker.expire_before((commit_count + 1).saturating_sub(demote_after)),
+            )
+        };
+        trace!(
+            "Tiered database: promoting {} and demoting {} values",
+            promoted.len(),
+            expired.len()
+        );
+
+        let mut hot_batch = self.hot.create_batch();
+        let mut cold_batch = self.cold.create_batch();
+        for ((kind, key), value) in promoted.iter() {
+            self.hot
+                .insert(&database_key(*kind, key), value, Some(&mut hot_batch))
+                .map_err(TieredDbError::Hot)?;
+            self.cold
+                .remove(&database_key(*kind, key), Some(&mut cold_batch))
+                .map_err(TieredDbError::Cold)?;
+        }
+        self.hot
+            .write_batch(hot_batch)
+            .map_err(TieredDbError::Hot)?;
+        self.cold
+            .write_batch(cold_batch)
+            .map_err(TieredDbError::Cold)?;
+
+        let mut hot_batch = self.hot.create_batch();
+        let mut cold_batch = self.cold.create_batch();
+        for (kind, key) in expired.iter() {
+            let key = database_key(*kind, key);
+            let Some(value) = self.hot.get(&key).map_err(TieredDbError::Hot)? else {
+                continue;
+            };
+            self.cold
+                .insert(&key, &value, Some(&mut cold_batch))
+                .map_err(TieredDbError::Cold)?;
+            self.hot
+                .remove(&key, Some(&mut hot_batch))
+                .map_err(TieredDbError::Hot)?;
+        }
+        self.cold
+            .write_batch(cold_batch)
+            .map_err(TieredDbError::Cold)?;
+        self.hot
+            .write_batch(hot_batch)
+            .map_err(TieredDbError::Hot)?;
+        Ok(())
+    }
+}
+
+/// A batch of changes for both stores of a tiered database
+pub struct TieredDbBatch<H: BonsaiDatabase, C: BonsaiDatabase> {
+    hot: H::Batch,
+    cold: C::Batch,
+    ends_commit: bool,
+}
+
+impl<H: BonsaiDatabase, C: BonsaiDatabase> Default for TieredDbBatch<H, C> {
+    fn default() -> Self {
+        Self {
+            hot: Default::default(),
+            cold: Default::default(),
+            ends_commit: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TieredDbError<H, C> {
+    Hot(H),
+    Cold(C),
+}
+
+impl<H: fmt::Display, C: fmt::Display> fmt::Display for TieredDbError<H, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hot(err) => write!(f, "Hot store error: {}", err),
+            Self::Cold(err) => write!(f, "Cold store error: {}", err),
+        }
+    }
+}
+
+impl<H: DBError, C: DBError> DBError for TieredDbError<H, C> {}
+
+impl<H: StdError, C: StdError> StdError for TieredDbError<H, C> {}
+
+impl<H: BonsaiDatabase, C: BonsaiDatabase> BonsaiDatabase for TieredDb<H, C> {
+    type Batch = TieredDbBatch<H, C>;
+    type DatabaseError = TieredDbError<H::DatabaseError, C::DatabaseError>;
+
+    fn create_batch(&self) -> Self::Batch {
+        TieredDbBatch {
+            hot: self.hot.create_batch(),
+            cold: self.cold.create_batch(),
+            ends_commit: false,
+        }
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        println!("Hot store:");
+        self.hot.dump_database();
+        println!("Cold store:");
+        self.cold.dump_database();
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Inserting into tiered database: {:?} {:?}", key, value);
+        let (hot_batch, cold_batch) = match batch {
+            Some(batch) => {
+                batch.ends_commit |= matches!(key, DatabaseKey::TrieLog(_));
+                (Some(&mut batch.hot), Some(&mut batch.cold))
+            }
+            None => (None, None),
+        };
+        let old_value = self
+            .hot
+            .insert(key, value, hot_batch)
+            .map_err(TieredDbError::Hot)?;
+        if let DatabaseKey::TrieLog(_) = key {
+            return Ok(old_value);
+        }
+        let cold_value = self
+            .cold
+            .remove(key, cold_batch)
+            .map_err(TieredDbError::Cold)?;
+        let tier_key = (kind(key), key.as_slice().to_vec());
+        let commit_count = self.commit_count;
+        let tracker = self
+            .tracker
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        tracker.promoted.remove(&tier_key);
+        tracker.touch(tier_key, commit_count);
+        Ok(old_value.or(cold_value))
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from tiered database: {:?}", key);
+        let value = self.hot.get(key).map_err(TieredDbError::Hot)?;
+        if let DatabaseKey::TrieLog(_) = key {
+            return Ok(value);
+        }
+        let tier_key = (kind(key), key.as_slice().to_vec());
+        if let Some(value) = value {
+            self.tracker().touch(tier_key, self.commit_count);
+            return Ok(Some(value));
+        }
+        if let Some(value) = self.tracker().promoted.get(&tier_key) {
+            return Ok(Some(value.clone()));
+        }
+        let Some(value) = self.cold.get(key).map_err(TieredDbError::Cold)? else {
+            return Ok(None);
+        };
+        let mut tracker = self.tracker();
+        tracker.promoted.insert(tier_key.clone(), value.clone());
+        tracker.touch(tier_key, self.commit_count);
+        Ok(Some(value))
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from tiered database: {:?}", prefix);
+        let hot_values = self.hot.get_by_prefix(prefix).map_err(TieredDbError::Hot)?;
+        if let DatabaseKey::TrieLog(_) = prefix {
+            return Ok(hot_values);
+        }
+        let mut values: BTreeMap<Vec<u8>, Vec<u8>> = self
+            .cold
+            .get_by_prefix(prefix)
+            .map_err(TieredDbError::Cold)?
+            .into_iter()
+            .collect();
+        values.extend(hot_values);
+        Ok(values.into_iter().collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if tiered database contains: {:?}", key);
+        if self.hot.contains(key).map_err(TieredDbError::Hot)? {
+            return Ok(true);
+        }
+        if let DatabaseKey::TrieLog(_) = key {
+            return Ok(false);
+        }
+        if self
+            .tracker()
+            .promoted
+            .contains_key(&(kind(key), key.as_slice().to_vec()))
+        {
+            return Ok(true);
+        }
+        self.cold.contains(key).map_err(TieredDbError::Cold)
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from tiered database: {:?}", key);
+        let (hot_batch, cold_batch) = match batch {
+            Some(batch) => (Some(&mut batch.hot), Some(&mut batch.cold)),
+            None => (None, None),
+        };
+        let old_value = self
+            .hot
+            .remove(key, hot_batch)
+            .map_err(TieredDbError::Hot)?;
+        if let DatabaseKey::TrieLog(_) = key {
+            return Ok(old_value);
+        }
+        let cold_value = self
+            .cold
+            .remove(key, cold_batch)
+            .map_err(TieredDbError::Cold)?;
+        self.tracker
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .forget(&(kind(key), key.as_slice().to_vec()));
+        Ok(old_value.or(cold_value))
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from tiered database: {:?}", prefix);
+        self.hot
+            .remove_by_prefix(prefix)
+            .map_err(TieredDbError::Hot)?;
+        if let DatabaseKey::TrieLog(_) = prefix {
+            return Ok(());
+        }
+        self.cold
+            .remove_by_prefix(prefix)
+            .map_err(TieredDbError::Cold)?;
+        let prefix_kind = kind(prefix);
+        let tracker = self
+            .tracker
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        let keys: Vec<TierKey> = tracker
+            .last_touched
+            .keys()
+            .chain(tracker.promoted.keys())
+            .filter(|(kind, key)| *kind == prefix_kind && key.starts_with(prefix.as_slice()))
+            .cloned()
+            .collect();
+        for key in keys.iter() {
+            tracker.forget(key);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        self.hot
+            .write_batch(batch.hot)
+            .map_err(TieredDbError::Hot)?;
+        self.cold
+            .write_batch(batch.cold)
+            .map_err(TieredDbError::Cold)?;
+        if batch.ends_commit {
+            self.end_commit()?;
+        }
+        Ok(())
+    }
+}
+
+impl<ID, H, C> BonsaiPersistentDatabase<ID> for TieredDb<H, C>
+where
+    ID: Id,
+    H: BonsaiDatabase + BonsaiPersistentDatabase<ID>,
+    C: BonsaiDatabase + BonsaiPersistentDatabase<ID>,
+{
+    type Transaction = TieredDb<H::Transaction, C::Transaction>;
+    type DatabaseError = TieredDbError<
+        <H as BonsaiPersistentDatabase<ID>>::DatabaseError,
+        <C as BonsaiPersistentDatabase<ID>>::DatabaseError,
+    >;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating tiered database snapshot");
+        self.hot.snapshot(id);
+        self.cold.snapshot(id);
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating tiered database transaction");
+        Some(TieredDb::new(
+            self.hot.transaction(id)?,
+            self.cold.transaction(id)?,
+            self.config,
+        ))
+    }
+
+    fn merge(&mut self, transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        let touched = transaction
+            .tracker
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+            .last_touched;
+        self.hot
+            .merge(transaction.hot)
+            .map_err(TieredDbError::Hot)?;
+        self.cold
+            .merge(transaction.cold)
+            .map_err(TieredDbError::Cold)?;
+        let commit_count = self.commit_count;
+        let tracker = self
+            .tracker
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        for key in touched.into_keys() {
+            tracker.touch(key, commit_count);
+        }
+        Ok(())
+    }
+}
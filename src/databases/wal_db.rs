@@ -0,0 +1,354 @@
+use std::{
+    collections::BTreeMap,
+    error::Error as StdError,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+};
+use log::trace;
+
+/// Size of a log record header: payload length and checksum.
+const HEADER_LEN: usize = 12;
+
+/// Change of a key along with the discriminant of its kind, `None` being a removal.
+type Changes = BTreeMap<(u8, Vec<u8>), Option<Vec<u8>>>;
+
+fn kind(key: &DatabaseKey) -> u8 {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+    }
+}
+
+fn database_key(kind: u8, key: &[u8]) -> DatabaseKey {
+    match kind {
+        0 => DatabaseKey::Trie(key),
+        1 => DatabaseKey::Flat(key),
+        _ => DatabaseKey::TrieLog(key),
+    }
+}
+
+/// Encodes changes as a record: payload length, crc32 of the payload and the payload made of
+/// `[kind, is_removal, key length, value length, key, value]` entries.
+fn encode_record(changes: &Changes) -> Vec<u8> {
+    let mut payload = Vec::new();
+    for ((kind, key), value) in changes {
+        payload.push(*kind);
+        payload.push(value.is_none() as u8);
+        let value = value.as_deref().unwrap_or_default();
+        payload.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        payload.extend_from_slice(key);
+        payload.extend_from_slice(value);
+    }
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    record
+}
+
+/// Decodes a record, returns None if it was only partially written.
+fn decode_record(record: &[u8]) -> Option<Changes> {
+    let header = record.get(..HEADER_LEN)?;
+    let len = u64::from_le_bytes(header[..8].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header[8..].try_into().unwrap());
+    let payload = record.get(HEADER_LEN..HEADER_LEN.checked_add(len)?)?;
+    if crc32fast::hash(payload) != checksum {
+        return None;
+    }
+    let mut changes = Changes::new();
+    let mut offset = 0;
+    while offset < payload.len() {
+        let entry = payload.get(offset..offset + 10)?;
+        let key_len = u32::from_le_bytes(entry[2..6].try_into().unwrap()) as usize;
+        let value_len = u32::from_le_bytes(entry[6..10].try_into().unwrap()) as usize;
+        let key = payload.get(offset + 10..offset + 10 + key_len)?.to_vec();
+        let value = payload.get(offset + 10 + key_len..offset + 10 + key_len + value_len)?;
+        changes.insert((entry[0], key), (entry[1] == 0).then(|| value.to_vec()));
+        offset += 10 + key_len + value_len;
+    }
+    Some(changes)
+}
+
+/// A database wrapper journaling the changes of each commit before applying them to the inner
+/// database
+///
+/// The changes of a commit are kept in memory until its trie log is written, they are then
+/// appended to the log file and synced, applied to the inner database in one batch and the log
+/// is cleared. A commit that was journaled but not fully applied is applied again when the
+/// database is opened, so the inner database either has all the changes of a commit or none of
+/// them. The inner database must have persisted its batches once they are written.
+///
+/// Outside of commits, writes are journaled one by one. Removals by prefix, used to prune old
+/// trie logs, are not journaled.
+pub struct WalDb<D> {
+    inner: D,
+    log: Option<File>,
+    pending: Changes,
+}
+
+impl<D: BonsaiDatabase> WalDb<D> {
+    /// Opens (or creates) the log file at the given path, and applies the commit it holds to
+    /// the inner database if there is one.
+    pub fn open(inner: D, path: impl AsRef<Path>) -> Result<Self, WalDbError<D::DatabaseError>> {
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(WalDbError::Io)?;
+        let mut record = Vec::new();
+        log.read_to_end(&mut record).map_err(WalDbError::Io)?;
+        let mut db = Self {
+            inner,
+            log: None,
+            pending: Changes::new(),
+        };
+        if !record.is_empty() {
+            match decode_record(&record) {
+                Some(changes) => {
+                    trace!("Recovering {} changes from write-ahead log", changes.len());
+                    db.apply(changes)?;
+                }
+                // The commit was not fully journaled, so none of it was applied
+                None => trace!("Discarding partially written write-ahead log record"),
+            }
+            log.set_len(0).map_err(WalDbError::Io)?;
+            log.sync_data().map_err(WalDbError::Io)?;
+        }
+        db.log = Some(log);
+        trace!("Write-ahead log database opened");
+        Ok(db)
+    }
+
+    /// Returns the inner database
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Journals and applies the changes that are not applied yet.
+    pub fn flush(&mut self) -> Result<(), WalDbError<D::DatabaseError>> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let changes = core::mem::take(&mut self.pending);
+        if let Some(log) = &mut self.log {
+            log.write_all(&encode_record(&changes))
+                .map_err(WalDbError::Io)?;
+            log.sync_data().map_err(WalDbError::Io)?;
+        }
+        self.apply(changes)?;
+        if let Some(log) = &mut self.log {
+            // Truncating is synced along with the next record, replaying this one until then
+            // is harmless as it is already applied.
+            log.set_len(0).map_err(WalDbError::Io)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, changes: Changes) -> Result<(), WalDbError<D::DatabaseError>> {
+        let mut batch = self.inner.create_batch();
+        for ((kind, key), value) in changes {
+            let key = database_key(kind, &key);
+            match value {
+                Some(value) => self.inner.insert(&key, &value, Some(&mut batch))?,
+                None => self.inner.remove(&key, Some(&mut batch))?,
+            };
+        }
+        Ok(self.inner.write_batch(batch)?)
+    }
+
+    /// Records a change, writes without batch outside of a commit are applied directly.
+    fn set(
+        &mut self,
+        key: &DatabaseKey,
+        value: Option<Vec<u8>>,
+        batch: Option<&mut WalDbBatch>,
+    ) -> Result<Option<Vec<u8>>, WalDbError<D::DatabaseError>> {
+        let old_value = self.get(key)?;
+        let change = ((kind(key), key.as_slice().to_vec()), value);
+        match batch {
+            Some(batch) => {
+                batch.changes.insert(change.0, change.1);
+            }
+            None => {
+                let standalone = self.pending.is_empty();
+                self.pending.insert(change.0, change.1);
+                if standalone {
+                    self.flush()?;
+                }
+            }
+        }
+        Ok(old_value)
+    }
+}
+
+/// A batch of changes, journaled along with the other batches of the same commit
+#[derive(Default)]
+pub struct WalDbBatch {
+    changes: Changes,
+    ends_commit: bool,
+}
+
+#[derive(Debug)]
+pub enum WalDbError<E> {
+    Inner(E),
+    Io(io::Error),
+}
+
+impl<E> From<E> for WalDbError<E> {
+    fn from(err: E) -> Self {
+        Self::Inner(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for WalDbError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inner(err) => write!(f, "{}", err),
+            Self::Io(err) => write!(f, "Write-ahead log error: {}", err),
+        }
+    }
+}
+
+impl<E: DBError> DBError for WalDbError<E> {}
+
+impl<E: StdError> StdError for WalDbError<E> {}
+
+impl<D: BonsaiDatabase> BonsaiDatabase for WalDb<D> {
+    type Batch = WalDbBatch;
+    type DatabaseError = WalDbError<D::DatabaseError>;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        for (key, value) in self.pending.iter() {
+            println!("{:?} {:?}", key, value);
+        }
+        self.inner.dump_database();
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!(
+            "Inserting into write-ahead log database: {:?} {:?}",
+            key,
+            value
+        );
+        if let (DatabaseKey::TrieLog(_), Some(batch)) = (key, batch.as_deref_mut()) {
+            batch.ends_commit = true;
+        }
+        self.set(key, Some(value.to_vec()), batch)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Getting from write-ahead log database: {:?}", key);
+        match self.pending.get(&(kind(key), key.as_slice().to_vec())) {
+            Some(value) => Ok(value.clone()),
+            None => Ok(self.inner.get(key)?),
+        }
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        trace!("Getting from write-ahead log database: {:?}", prefix);
+        let mut result: BTreeMap<Vec<u8>, Vec<u8>> =
+            self.inner.get_by_prefix(prefix)?.into_iter().collect();
+        let kind = kind(prefix);
+        let pending = self
+            .pending
+            .range((kind, prefix.as_slice().to_vec())..)
+            .take_while(|((pending_kind, key), _)| {
+                *pending_kind == kind && key.starts_with(prefix.as_slice())
+            });
+        for ((_, key), value) in pending {
+            match value {
+                Some(value) => result.insert(key.clone(), value.clone()),
+                None => result.remove(key),
+            };
+        }
+        Ok(result.into_iter().collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if write-ahead log database contains: {:?}", key);
+        match self.pending.get(&(kind(key), key.as_slice().to_vec())) {
+            Some(value) => Ok(value.is_some()),
+            None => Ok(self.inner.contains(key)?),
+        }
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        trace!("Removing from write-ahead log database: {:?}", key);
+        self.set(key, None, batch)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from write-ahead log database: {:?}", prefix);
+        self.flush()?;
+        Ok(self.inner.remove_by_prefix(prefix)?)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        self.pending.extend(batch.changes);
+        if batch.ends_commit {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<ID, D> BonsaiPersistentDatabase<ID> for WalDb<D>
+where
+    ID: Id,
+    D: BonsaiDatabase + BonsaiPersistentDatabase<ID>,
+{
+    // Transactions are in memory, their changes are not journaled
+    type Transaction = WalDb<D::Transaction>;
+    type DatabaseError = WalDbError<<D as BonsaiPersistentDatabase<ID>>::DatabaseError>;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating write-ahead log database snapshot");
+        // A snapshot that can't be flushed is not recorded, transactions at this id will then be
+        // unavailable.
+        if self.flush().is_ok() {
+            self.inner.snapshot(id);
+        }
+    }
+
+    fn transaction(&self, id: ID) -> Option<Self::Transaction> {
+        trace!("Generating write-ahead log database transaction");
+        Some(WalDb {
+            inner: self.inner.transaction(id)?,
+            log: None,
+            pending: Changes::new(),
+        })
+    }
+
+    fn merge(&mut self, mut transaction: Self::Transaction) -> Result<(), Self::DatabaseError> {
+        // Merges happen between commits, when all the changes of this database are applied
+        transaction.flush()?;
+        Ok(self.inner.merge(transaction.inner)?)
+    }
+}
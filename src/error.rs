@@ -23,6 +23,10 @@ where
     Database(DatabaseError),
     /// Error when decoding a node
     NodeDecodeError(parity_scale_codec::Error),
+    /// Error when the data read from the database doesn't match its hash
+    Corruption(String),
+    /// Error when the id of a commit isn't greater than the id of the last commit
+    CommitId(String),
 }
 
 impl<DatabaseError: DBError> core::convert::From<DatabaseError>
@@ -54,6 +58,8 @@ where
             BonsaiStorageError::Merge(e) => write!(f, "Merge error: {}", e),
             BonsaiStorageError::Database(e) => write!(f, "Database error: {}", e),
             BonsaiStorageError::NodeDecodeError(e) => write!(f, "Node decode error: {}", e),
+            BonsaiStorageError::Corruption(e) => write!(f, "Corruption error: {}", e),
+            BonsaiStorageError::CommitId(e) => write!(f, "Commit id error: {}", e),
         }
     }
 }
@@ -41,3 +41,101 @@ impl BasicIdBuilder {
         id
     }
 }
+
+/// An ID that is directly a number growing with the commits, e.g. the number of the block of
+/// each commit.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default)]
+pub struct U64Id(pub u64);
+
+impl Id for U64Id {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+}
+
+impl From<u64> for U64Id {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+/// A builder for IDs made from block numbers.
+#[derive(Default)]
+pub struct U64IdBuilder {
+    next_id: u64,
+}
+
+impl U64IdBuilder {
+    /// Create a new builder whose first ID is `first`.
+    pub fn new(first: u64) -> Self {
+        Self { next_id: first }
+    }
+
+    /// Create a new ID, the one after the last created.
+    pub fn new_id(&mut self) -> U64Id {
+        let id = U64Id(self.next_id);
+        self.next_id = self.next_id.checked_add(1).expect("Id overflow");
+        id
+    }
+}
+
+/// An ID for several commits per block: the number of the block and the index of the commit in
+/// the block. IDs are ordered by block number then by index.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default)]
+pub struct BlockSubId {
+    pub block_number: u64,
+    pub sub_index: u32,
+}
+
+impl Id for BlockSubId {
+    fn to_bytes(&self) -> Vec<u8> {
+        [
+            &self.block_number.to_be_bytes()[..],
+            &self.sub_index.to_be_bytes()[..],
+        ]
+        .concat()
+    }
+}
+
+/// A builder for IDs of several commits per block.
+#[derive(Default)]
+pub struct BlockSubIdBuilder {
+    block_number: u64,
+    next_sub_index: u32,
+}
+
+impl BlockSubIdBuilder {
+    /// Create a new builder whose first ID is the first commit of the block `block_number`.
+    pub fn new(block_number: u64) -> Self {
+        Self {
+            block_number,
+            next_sub_index: 0,
+        }
+    }
+
+    /// Create a new ID, the next commit of the current block.
+    pub fn new_id(&mut self) -> BlockSubId {
+        let id = BlockSubId {
+            block_number: self.block_number,
+            sub_index: self.next_sub_index,
+        };
+        self.next_sub_index = self.next_sub_index.checked_add(1).expect("Id overflow");
+        id
+    }
+
+    /// Move to the block `block_number`, the next ID is its first commit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `block_number` isn't after the current block.
+    pub fn start_block(&mut self, block_number: u64) {
+        assert!(
+            block_number > self.block_number,
+            "Block {} isn't after the current block {}",
+            block_number,
+            self.block_number
+        );
+        self.block_number = block_number;
+        self.next_sub_index = 0;
+    }
+}
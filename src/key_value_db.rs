@@ -2,9 +2,10 @@ use crate::{trie::merkle_tree::bytes_to_bitvec, Change as ExternChange};
 #[cfg(not(feature = "std"))]
 use alloc::{collections::BTreeSet, format, string::ToString, vec::Vec};
 use bitvec::{order::Msb0, vec::BitVec};
-use hashbrown::HashMap;
+use core::{num::NonZeroUsize, ops::ControlFlow};
+use hashbrown::{hash_map::Entry, HashMap, HashSet};
 use log::trace;
-use parity_scale_codec::Decode;
+use parity_scale_codec::{Decode, Encode};
 use starknet_types_core::felt::Felt;
 #[cfg(feature = "std")]
 use std::collections::BTreeSet;
@@ -13,10 +14,41 @@ use crate::{
     bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DatabaseKey},
     changes::{Change, ChangeBatch, ChangeStore},
     id::Id,
-    trie::TrieKey,
-    BonsaiStorageConfig, BonsaiStorageError,
+    trie::{
+        trie_db::{METADATA_PREFIX, ROOT_HASH_INDEX_PREFIX, ROOT_INDEX_PREFIX, TAG_PREFIX},
+        TrieKey,
+    },
+    BonsaiStorageConfig, BonsaiStorageError, PruneReport, TrieLogAge,
 };
 
+pub(crate) fn root_index_key<ID: Id>(id: &ID) -> TrieKey {
+    TrieKey::Trie([&[ROOT_INDEX_PREFIX][..], &id.to_bytes()].concat())
+}
+
+fn tag_key(name: &str) -> TrieKey {
+    TrieKey::Trie([&[TAG_PREFIX][..], name.as_bytes()].concat())
+}
+
+fn root_hash_index_key(root_hash: &Felt) -> TrieKey {
+    TrieKey::Trie([&[ROOT_HASH_INDEX_PREFIX][..], &root_hash.to_bytes_be()].concat())
+}
+
+fn metadata_key<ID: Id>(id: &ID) -> TrieKey {
+    TrieKey::Trie([&[METADATA_PREFIX][..], &id.to_bytes()].concat())
+}
+
+/// Whether `key` is a record of a commit rather than a node or a leaf of the trie: its root hash,
+/// its metadata or its entry in the index by root hash.
+fn is_commit_record(key: &TrieKey) -> bool {
+    let TrieKey::Trie(key) = key else {
+        return false;
+    };
+    matches!(
+        key.first(),
+        Some(&(ROOT_INDEX_PREFIX | METADATA_PREFIX | ROOT_HASH_INDEX_PREFIX))
+    )
+}
+
 /// Crate Trie <= KeyValueDB => BonsaiDatabase
 pub struct KeyValueDB<DB, ID>
 where
@@ -39,6 +71,18 @@ pub struct KeyValueDBConfig {
     pub max_saved_snapshots: Option<usize>,
     /// Interval of commit between two snapshots creation.
     pub snapshot_interval: u64,
+    /// Whether values are read through the trie with the hashes of the nodes checked.
+    pub verify_reads: bool,
+    /// Trie logs of the commits made at most this long before the last commit are kept.
+    pub max_trie_log_age: Option<TrieLogAge>,
+    /// Bytes of the ids of the commits whose trie logs are kept.
+    pub pinned_trie_logs: Vec<Vec<u8>>,
+    /// Whether all the trie logs are kept and the commits are indexed by root hash.
+    pub archive: bool,
+    /// Number of threads hashing the trie during the commits (None = rayon global thread pool).
+    pub hash_threads: Option<usize>,
+    /// Maximum number of nodes kept in the cache of the decoded nodes (None = no cache).
+    pub node_cache_size: Option<NonZeroUsize>,
 }
 
 impl Default for KeyValueDBConfig {
@@ -47,6 +91,12 @@ impl Default for KeyValueDBConfig {
             max_saved_trie_logs: None,
             max_saved_snapshots: None,
             snapshot_interval: 5,
+            verify_reads: false,
+            max_trie_log_age: None,
+            pinned_trie_logs: Vec::new(),
+            archive: false,
+            hash_threads: None,
+            node_cache_size: None,
         }
     }
 }
@@ -57,6 +107,12 @@ impl From<BonsaiStorageConfig> for KeyValueDBConfig {
             max_saved_trie_logs: value.max_saved_trie_logs,
             snapshot_interval: value.snapshot_interval,
             max_saved_snapshots: value.max_saved_snapshots,
+            verify_reads: value.verify_reads,
+            max_trie_log_age: value.max_trie_log_age,
+            pinned_trie_logs: value.pinned_trie_logs,
+            archive: value.archive,
+            hash_threads: value.hash_threads,
+            node_cache_size: value.node_cache_size,
         }
     }
 }
@@ -67,6 +123,12 @@ impl From<KeyValueDBConfig> for BonsaiStorageConfig {
             max_saved_trie_logs: val.max_saved_trie_logs,
             snapshot_interval: val.snapshot_interval,
             max_saved_snapshots: val.max_saved_snapshots,
+            verify_reads: val.verify_reads,
+            max_trie_log_age: val.max_trie_log_age,
+            pinned_trie_logs: val.pinned_trie_logs,
+            archive: val.archive,
+            hash_threads: val.hash_threads,
+            node_cache_size: val.node_cache_size,
         }
     }
 }
@@ -124,33 +186,523 @@ where
         }
     }
 
-    pub(crate) fn commit(&mut self, id: ID) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+    /// Returns the changes recorded in the trie log of the commit `id`.
+    pub(crate) fn get_trie_log(
+        &self,
+        id: ID,
+    ) -> Result<ChangeBatch, BonsaiStorageError<DB::DatabaseError>> {
+        if !self.changes_store.id_queue.contains(&id) {
+            return Err(BonsaiStorageError::GoTo(
+                "ID asked isn't in our ID records".to_string(),
+            ));
+        }
+        Ok(ChangeBatch::deserialize(
+            &id,
+            self.db
+                .get_by_prefix(&DatabaseKey::TrieLog(&id.to_bytes()))?,
+        ))
+    }
+
+    /// Returns the value of `key` as of the commit `id`, read from the committed value and the
+    /// trie logs of the later commits.
+    pub(crate) fn get_at(
+        &self,
+        id: ID,
+        key: &TrieKey,
+    ) -> Result<Option<Vec<u8>>, BonsaiStorageError<DB::DatabaseError>> {
+        if !self.changes_store.id_queue.contains(&id) {
+            return Err(BonsaiStorageError::GoTo(
+                "ID asked isn't in our ID records".to_string(),
+            ));
+        }
+        // The value before the first change after `id` is the value at `id`
+        for later_id in self.changes_store.id_queue.iter().filter(|&&x| x > id) {
+            let (old_key, new_key) = ChangeBatch::log_keys(later_id, key);
+            if let Some(old_value) = self.db.get(&DatabaseKey::TrieLog(&old_key))? {
+                return Ok(Some(old_value));
+            }
+            if self.db.contains(&DatabaseKey::TrieLog(&new_key))? {
+                return Ok(None);
+            }
+        }
+        self.get(key)
+    }
+
+    /// Returns the leaves whose value at the commit `to` differs from their value at the commit
+    /// `from`, along with both values, read from the trie logs of the commits in between.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn get_changes_between(
+        &self,
+        from: ID,
+        to: ID,
+    ) -> Result<HashMap<BitVec<u8, Msb0>, ExternChange>, BonsaiStorageError<DB::DatabaseError>>
+    {
+        for id in [from, to] {
+            if !self.changes_store.id_queue.contains(&id) {
+                return Err(BonsaiStorageError::GoTo(
+                    "ID asked isn't in our ID records".to_string(),
+                ));
+            }
+        }
+        let (first, last) = (from.min(to), from.max(to));
+        let mut changes: HashMap<BitVec<u8, Msb0>, ExternChange> = HashMap::new();
+        for id in self
+            .changes_store
+            .id_queue
+            .iter()
+            .filter(|&&id| id > first && id <= last)
+        {
+            for (key, change) in self.get_changes(*id)? {
+                match changes.entry(key) {
+                    Entry::Occupied(mut entry) => entry.get_mut().new_value = change.new_value,
+                    Entry::Vacant(entry) => {
+                        entry.insert(change);
+                    }
+                }
+            }
+        }
+        changes.retain(|_, change| change.old_value != change.new_value);
+        // Going back in time, the changes are undone
+        if from > to {
+            for change in changes.values_mut() {
+                core::mem::swap(&mut change.old_value, &mut change.new_value);
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Returns the root hash of the trie at the commit `id`, as indexed when the commit was made.
+    /// Returns `None` for the commits made before root hashes were indexed.
+    pub(crate) fn get_root_at(
+        &self,
+        id: ID,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        if !self.changes_store.id_queue.contains(&id) {
+            return Err(BonsaiStorageError::GoTo(
+                "ID asked isn't in our ID records".to_string(),
+            ));
+        }
+        // The entry of a commit is never written again, a revert removes the ones of the
+        // reverted commits
+        Ok(self
+            .get(&root_index_key(&id))?
+            // SAFETY: We are sure that the values are valid Felt because they can be saved only by our crate
+            .map(|root| Felt::decode(&mut root.as_slice()).unwrap()))
+    }
+
+    /// Labels the commit `id` with `name`, replacing the commit the tag was on if any.
+    pub(crate) fn tag(
+        &mut self,
+        id: ID,
+        name: &str,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if !self.changes_store.id_queue.contains(&id) {
+            return Err(BonsaiStorageError::GoTo(
+                "ID asked isn't in our ID records".to_string(),
+            ));
+        }
+        // Written outside of the trie logs, tags aren't part of the state of the trie
+        self.db
+            .insert(&DatabaseKey::from(&tag_key(name)), &id.to_bytes(), None)?;
+        Ok(())
+    }
+
+    /// Returns the commit labelled with `name`, `None` if there is no such tag or if the commit
+    /// was reverted or its trie log removed since.
+    pub(crate) fn resolve_tag(
+        &self,
+        name: &str,
+    ) -> Result<Option<ID>, BonsaiStorageError<DB::DatabaseError>> {
+        let Some(bytes) = self.get(&tag_key(name))? else {
+            return Ok(None);
+        };
+        Ok(self
+            .changes_store
+            .id_queue
+            .iter()
+            .find(|id| id.to_bytes() == bytes)
+            .copied())
+    }
+
+    /// Returns the metadata stored with the commit `id`, if any.
+    pub(crate) fn get_commit_metadata(
+        &self,
+        id: ID,
+    ) -> Result<Option<Vec<u8>>, BonsaiStorageError<DB::DatabaseError>> {
+        if !self.changes_store.id_queue.contains(&id) {
+            return Err(BonsaiStorageError::GoTo(
+                "ID asked isn't in our ID records".to_string(),
+            ));
+        }
+        self.get(&metadata_key(&id))
+    }
+
+    /// Returns the latest commit whose root hash is `root_hash`, `None` if there is none or if
+    /// the storage isn't in archive mode.
+    pub(crate) fn commit_by_root(
+        &self,
+        root_hash: &Felt,
+    ) -> Result<Option<ID>, BonsaiStorageError<DB::DatabaseError>> {
+        let Some(bytes) = self.get(&root_hash_index_key(root_hash))? else {
+            return Ok(None);
+        };
+        Ok(self
+            .changes_store
+            .id_queue
+            .iter()
+            .find(|id| id.to_bytes() == bytes)
+            .copied())
+    }
+
+    /// Checks that `id` can be the id of the next commit.
+    pub(crate) fn check_commit_id(
+        &self,
+        id: ID,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
         if Some(&id) > self.changes_store.id_queue.back() {
-            self.changes_store.id_queue.push_back(id);
+            Ok(())
         } else {
-            return Err(BonsaiStorageError::GoTo(format!(
+            Err(BonsaiStorageError::CommitId(format!(
                 "Commit id {:?} is not greater than the last recorded id",
                 id,
-            )));
+            )))
+        }
+    }
+
+    /// Adds the trie log of the current changes under `id` along with the root hash of the trie
+    /// at that commit and its metadata to `batch`. The commit is only recorded by [`Self::confirm_commit`], once
+    /// the batch is written.
+    ///
+    /// A `forced` commit takes the place of the commits from `id` onward: their changes are
+    /// merged into its trie log and their trie logs are removed in the same batch, see
+    /// [`Self::merge_forgotten`].
+    pub(crate) fn prepare_commit(
+        &mut self,
+        id: ID,
+        root_hash: Felt,
+        metadata: Option<&[u8]>,
+        forced: bool,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if !forced {
+            self.check_commit_id(id)?;
+        }
+        // Part of the trie log, so that reverting the commit removes it
+        self.insert(&root_index_key(&id), &root_hash.encode(), Some(&mut *batch))?;
+        if let Some(metadata) = metadata {
+            self.insert(&metadata_key(&id), metadata, Some(&mut *batch))?;
+        }
+        if self.config.archive {
+            self.insert(
+                &root_hash_index_key(&root_hash),
+                &id.to_bytes(),
+                Some(&mut *batch),
+            )?;
         }
 
         // Insert flat db changes
-        let mut batch = self.db.create_batch();
         let current_changes = core::mem::take(&mut self.changes_store.current_changes);
-        for (key, change) in current_changes.serialize(&id).iter() {
-            self.db
-                .insert(&DatabaseKey::TrieLog(key), change, Some(&mut batch))?;
+        if forced {
+            let (changes, stale) = self.merge_forgotten(id, current_changes, batch)?;
+            self.write_trie_log(&id, &changes, &stale, batch)
+        } else {
+            self.write_trie_log(&id, &current_changes, &[], batch)
+        }
+    }
+
+    /// Records the commit `id` whose batch has been written, forgetting the commits it took the
+    /// place of if it was `forced`.
+    pub(crate) fn confirm_commit(
+        &mut self,
+        id: ID,
+        forced: bool,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if forced {
+            self.forget_from(id);
+        }
+        self.changes_store.id_queue.push_back(id);
+        self.prune()?;
+        Ok(())
+    }
+
+    /// Keeps the trie log of the commit `id` until it is unpinned.
+    pub(crate) fn pin(&mut self, id: ID) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if !self.changes_store.id_queue.contains(&id) {
+            return Err(BonsaiStorageError::GoTo(
+                "ID asked isn't in our ID records".to_string(),
+            ));
+        }
+        let id = id.to_bytes();
+        if !self.config.pinned_trie_logs.contains(&id) {
+            self.config.pinned_trie_logs.push(id);
+        }
+        Ok(())
+    }
+
+    /// Lets the trie log of the commit `id` be pruned again, returns whether it was pinned.
+    pub(crate) fn unpin(&mut self, id: ID) -> bool {
+        let id = id.to_bytes();
+        let pinned = self.config.pinned_trie_logs.len();
+        self.config.pinned_trie_logs.retain(|pinned| *pinned != id);
+        self.config.pinned_trie_logs.len() != pinned
+    }
+
+    /// Whether the pruning policies keep the trie log of the commit at `index` in the id queue.
+    fn is_kept(&self, index: usize, max_saved_trie_logs: usize) -> bool {
+        let ids = &self.changes_store.id_queue;
+        if index + max_saved_trie_logs >= ids.len() {
+            return true;
+        }
+        let id = ids[index].to_bytes();
+        if self.config.pinned_trie_logs.contains(&id) {
+            return true;
+        }
+        match (&self.config.max_trie_log_age, ids.back()) {
+            (Some(age), Some(last)) => {
+                (age.timestamp)(&last.to_bytes()).saturating_sub((age.timestamp)(&id))
+                    <= age.max_age
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes the trie logs of the commits that no pruning policy keeps.
+    ///
+    /// Reverting to a commit needs the trie logs of all the later ones, so the commits older
+    /// than every kept commit are simply dropped while the changes of the other ones are merged
+    /// into the trie log of the next commit.
+    pub(crate) fn prune(&mut self) -> Result<PruneReport, BonsaiStorageError<DB::DatabaseError>> {
+        let Some(max_saved_trie_logs) = self.config.max_saved_trie_logs else {
+            return Ok(PruneReport::default());
+        };
+        if self.config.archive {
+            return Ok(PruneReport::default());
+        }
+        let kept: Vec<bool> = (0..self.changes_store.id_queue.len())
+            .map(|index| self.is_kept(index, max_saved_trie_logs))
+            .collect();
+        self.drop_trie_logs(&kept)
+    }
+
+    /// Keeps the trie log of the first commit of every `every_n` consecutive commits, of the
+    /// last commit and of the pinned commits, the changes of the others are merged into the
+    /// trie log of the next commit kept.
+    pub(crate) fn compact_history(
+        &mut self,
+        every_n: NonZeroUsize,
+    ) -> Result<PruneReport, BonsaiStorageError<DB::DatabaseError>> {
+        // The whole history is kept in archive mode
+        if self.config.archive {
+            return Ok(PruneReport::default());
+        }
+        let kept: Vec<bool> = self
+            .changes_store
+            .id_queue
+            .iter()
+            .enumerate()
+            .map(|(index, id)| {
+                index % every_n.get() == 0 || self.config.pinned_trie_logs.contains(&id.to_bytes())
+            })
+            .collect();
+        self.drop_trie_logs(&kept)
+    }
+
+    /// Removes the trie logs of the commits that are not `kept`, given in the order of the id
+    /// queue. The changes of the ones after the first kept commit are merged into the trie log of
+    /// the next commit, and the last commit is always kept.
+    ///
+    /// The removals and the merged trie logs are written in a single batch, the id queue and the
+    /// snapshots are only updated once it is written.
+    fn drop_trie_logs(
+        &mut self,
+        kept: &[bool],
+    ) -> Result<PruneReport, BonsaiStorageError<DB::DatabaseError>> {
+        let first_kept = kept.iter().position(|kept| *kept);
+        let ids: Vec<ID> = self.changes_store.id_queue.iter().copied().collect();
+        let mut batch = self.db.create_batch();
+        let mut dropped = Vec::new();
+        // Changes of the commits dropped since the last kept one, for the next kept trie log
+        let mut carried: Option<ChangeBatch> = None;
+        for (index, id) in ids.iter().enumerate() {
+            // Nothing to revert to anymore without a kept commit, every trie log goes. Otherwise
+            // the last commit has no next commit to merge into.
+            if first_kept.is_some() && (kept[index] || index + 1 == ids.len()) {
+                if let Some(mut changes) = carried.take() {
+                    let trie_log = self
+                        .db
+                        .get_by_prefix(&DatabaseKey::TrieLog(&id.to_bytes()))?;
+                    let stale: Vec<Vec<u8>> = trie_log.iter().map(|(key, _)| key.clone()).collect();
+                    changes.merge_later(ChangeBatch::deserialize(id, trie_log));
+                    self.write_trie_log(id, &changes, &stale, &mut batch)?;
+                }
+                continue;
+            }
+            let trie_log = self
+                .db
+                .get_by_prefix(&DatabaseKey::TrieLog(&id.to_bytes()))?;
+            self.remove_trie_log(id, &trie_log, &mut batch)?;
+            if first_kept.is_some_and(|first_kept| index > first_kept) {
+                let mut changes = ChangeBatch::deserialize(id, trie_log);
+                // The records of `id` are removed along with its trie log
+                changes.0.retain(|key, _| !is_commit_record(key));
+                carried
+                    .get_or_insert_with(ChangeBatch::default)
+                    .merge_later(changes);
+            }
+            dropped.push(*id);
         }
         self.db.write_batch(batch)?;
 
-        if let Some(max_saved_trie_logs) = self.config.max_saved_trie_logs {
-            while self.changes_store.id_queue.len() > max_saved_trie_logs {
-                // verified by previous conditional statement
-                let id = self.changes_store.id_queue.pop_front().unwrap();
-                self.db
-                    .remove_by_prefix(&DatabaseKey::TrieLog(&id.to_bytes()))?;
+        let mut report = PruneReport::default();
+        self.changes_store
+            .id_queue
+            .retain(|id| !dropped.contains(id));
+        for id in &dropped {
+            report.trie_logs += 1;
+            report.snapshots += self.snap_holder.remove(id) as usize;
+        }
+        Ok(report)
+    }
+
+    /// Merges the changes of the commits from `id` onward into `changes`, made after them, and
+    /// adds the removal of their records to `batch`, without reverting their changes. Returns the
+    /// merged changes and the keys of their trie logs, which the trie log of `id` replaces.
+    ///
+    /// Their root hashes, metadata and entries in the index by root hash that `changes` doesn't
+    /// write again go back to their value before these commits, so they aren't part of the
+    /// merged changes either.
+    #[allow(clippy::type_complexity)]
+    fn merge_forgotten(
+        &mut self,
+        id: ID,
+        changes: ChangeBatch,
+        batch: &mut DB::Batch,
+    ) -> Result<(ChangeBatch, Vec<Vec<u8>>), BonsaiStorageError<DB::DatabaseError>> {
+        let position = self.changes_store.id_queue.partition_point(|i| *i < id);
+        let mut merged = ChangeBatch::default();
+        let mut stale = Vec::new();
+        for old_id in self.changes_store.id_queue.range(position..) {
+            let trie_log = self
+                .db
+                .get_by_prefix(&DatabaseKey::TrieLog(&old_id.to_bytes()))?;
+            stale.extend(trie_log.iter().map(|(key, _)| key.clone()));
+            merged.merge_later(ChangeBatch::deserialize(old_id, trie_log));
+        }
+        let records: Vec<TrieKey> = merged
+            .0
+            .keys()
+            .filter(|key| is_commit_record(key) && !changes.0.contains_key(*key))
+            .cloned()
+            .collect();
+        for key in records {
+            let change = merged.0.remove(&key).expect("key listed from the changes");
+            let key = DatabaseKey::from(&key);
+            match change.old_value {
+                Some(value) => self.db.insert(&key, &value, Some(&mut *batch))?,
+                None => self.db.remove(&key, Some(&mut *batch))?,
+            };
+        }
+        merged.merge_later(changes);
+        Ok((merged, stale))
+    }
+
+    /// Adds the trie log of `changes` under `id` to `batch`, along with the removal of the `stale`
+    /// keys of a previous trie log that it doesn't write again.
+    fn write_trie_log(
+        &mut self,
+        id: &ID,
+        changes: &ChangeBatch,
+        stale: &[Vec<u8>],
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let trie_log = changes.serialize(id);
+        if !stale.is_empty() {
+            let written: HashSet<&[u8]> = trie_log.iter().map(|(key, _)| key.as_slice()).collect();
+            for key in stale {
+                if !written.contains(key.as_slice()) {
+                    self.db
+                        .remove(&DatabaseKey::TrieLog(key), Some(&mut *batch))?;
+                }
             }
         }
+        for (key, change) in trie_log.iter() {
+            self.db
+                .insert(&DatabaseKey::TrieLog(key), change, Some(&mut *batch))?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the commits from `id` onward, pinned or not, once a forced commit took their place.
+    fn forget_from(&mut self, id: ID) {
+        let position = self.changes_store.id_queue.partition_point(|i| *i < id);
+        for old_id in self.changes_store.id_queue.split_off(position) {
+            self.snap_holder.remove(&old_id);
+            let bytes = old_id.to_bytes();
+            self.config
+                .pinned_trie_logs
+                .retain(|pinned| *pinned != bytes);
+        }
+    }
+
+    /// Removes the trie logs and snapshots of the commits before `id`, pinned or not.
+    pub(crate) fn truncate_history_before(
+        &mut self,
+        id: ID,
+    ) -> Result<PruneReport, BonsaiStorageError<DB::DatabaseError>> {
+        let Some(position) = self.changes_store.id_queue.iter().position(|i| *i == id) else {
+            return Err(BonsaiStorageError::GoTo(
+                "ID asked isn't in our ID records".to_string(),
+            ));
+        };
+        // The whole history is kept in archive mode
+        if self.config.archive {
+            return Ok(PruneReport::default());
+        }
+        let old_ids: Vec<ID> = self
+            .changes_store
+            .id_queue
+            .range(..position)
+            .copied()
+            .collect();
+        let mut batch = self.db.create_batch();
+        for old_id in &old_ids {
+            let trie_log = self
+                .db
+                .get_by_prefix(&DatabaseKey::TrieLog(&old_id.to_bytes()))?;
+            self.remove_trie_log(old_id, &trie_log, &mut batch)?;
+        }
+        self.db.write_batch(batch)?;
+
+        let mut report = PruneReport::default();
+        self.changes_store.id_queue.drain(..position);
+        for old_id in old_ids {
+            report.trie_logs += 1;
+            let bytes = old_id.to_bytes();
+            self.config
+                .pinned_trie_logs
+                .retain(|pinned| *pinned != bytes);
+        }
+        let kept = self.snap_holder.split_off(&id);
+        report.snapshots = core::mem::replace(&mut self.snap_holder, kept).len();
+        Ok(report)
+    }
+
+    /// Adds the removal of the commit `id` to `batch`: its `trie_log` as read from the database,
+    /// its root hash and its metadata.
+    fn remove_trie_log(
+        &mut self,
+        id: &ID,
+        trie_log: &[(Vec<u8>, Vec<u8>)],
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        for (key, _) in trie_log {
+            self.db
+                .remove(&DatabaseKey::TrieLog(key), Some(&mut *batch))?;
+        }
+        self.db
+            .remove(&DatabaseKey::from(&root_index_key(id)), Some(&mut *batch))?;
+        self.db
+            .remove(&DatabaseKey::from(&metadata_key(id)), Some(&mut *batch))?;
         Ok(())
     }
 
@@ -170,6 +722,36 @@ where
         Ok(self.db.get(&key.into())?)
     }
 
+    /// Returns the values of the keys, in the same order, read at once from the database.
+    pub(crate) fn get_many(
+        &self,
+        keys: &[TrieKey],
+    ) -> Result<Vec<Option<Vec<u8>>>, BonsaiStorageError<DB::DatabaseError>> {
+        trace!("Getting {} keys from KeyValueDB", keys.len());
+        let keys: Vec<DatabaseKey> = keys.iter().map(Into::into).collect();
+        Ok(self.db.get_many(&keys)?)
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn get_by_prefix(
+        &self,
+        prefix: &TrieKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, BonsaiStorageError<DB::DatabaseError>> {
+        trace!("Getting by prefix from KeyValueDB: {:?}", prefix);
+        Ok(self.db.get_by_prefix(&prefix.into())?)
+    }
+
+    /// Visits the values with keys that start with the prefix without copying them, see
+    /// [`BonsaiDatabase::visit_prefix`].
+    pub(crate) fn visit_prefix(
+        &self,
+        prefix: &TrieKey,
+        visitor: &mut dyn FnMut(&[u8], &[u8]) -> ControlFlow<()>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        trace!("Visiting by prefix KeyValueDB: {:?}", prefix);
+        Ok(self.db.visit_prefix(&prefix.into(), visitor)?)
+    }
+
     pub(crate) fn contains(
         &self,
         key: &TrieKey,
@@ -183,34 +765,34 @@ where
         key: &TrieKey,
         value: &[u8],
         batch: Option<&mut DB::Batch>,
-    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+    ) -> Result<Option<Vec<u8>>, BonsaiStorageError<DB::DatabaseError>> {
         trace!("Inserting into KeyValueDB: {:?} {:?}", key, value);
         let old_value = self.db.insert(&key.into(), value, batch)?;
         self.changes_store.current_changes.insert_in_place(
             key.clone(),
             Change {
-                old_value,
+                old_value: old_value.clone(),
                 new_value: Some(value.to_vec()),
             },
         );
-        Ok(())
+        Ok(old_value)
     }
 
     pub(crate) fn remove(
         &mut self,
         key: &TrieKey,
         batch: Option<&mut DB::Batch>,
-    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+    ) -> Result<Option<Vec<u8>>, BonsaiStorageError<DB::DatabaseError>> {
         trace!("Removing from KeyValueDB: {:?}", key);
         let old_value = self.db.remove(&key.into(), batch)?;
         self.changes_store.current_changes.insert_in_place(
             key.clone(),
             Change {
-                old_value,
+                old_value: old_value.clone(),
                 new_value: None,
             },
         );
-        Ok(())
+        Ok(old_value)
     }
 
     pub(crate) fn write_batch(
@@ -0,0 +1,114 @@
+//! Flat file of the key/value pairs of a trie, written by
+//! [`BonsaiStorage::export_leaves`](crate::BonsaiStorage::export_leaves) and read by
+//! [`BonsaiStorage::import_leaves`](crate::BonsaiStorage::import_leaves).
+//!
+//! # Format
+//!
+//! The file is the list of the pairs sorted by key, without header. Each pair is written as:
+//! - the length of the key in bits, one byte, at most 251
+//! - the bits of the key, most significant first, padded with zero bits to a whole number of
+//!   bytes
+//! - the value, 32 bytes big endian
+//!
+//! The file ends right after the last pair.
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{self, ErrorKind, Read, Write},
+};
+
+use bitvec::{order::Msb0, slice::BitSlice, vec::BitVec, view::BitView};
+use starknet_types_core::felt::Felt;
+
+use crate::{bonsai_database::DBError, BonsaiStorageError};
+
+/// Errors of the export and import of the pairs of a trie.
+#[derive(Debug)]
+pub enum LeafFileError<DatabaseError>
+where
+    DatabaseError: DBError,
+{
+    /// Error from the storage
+    Storage(BonsaiStorageError<DatabaseError>),
+    /// Error when reading or writing the file
+    Io(io::Error),
+    /// Error when a pair of the file isn't in the format of the file
+    InvalidPair(String),
+}
+
+impl<DatabaseError: DBError> From<BonsaiStorageError<DatabaseError>>
+    for LeafFileError<DatabaseError>
+{
+    fn from(value: BonsaiStorageError<DatabaseError>) -> Self {
+        Self::Storage(value)
+    }
+}
+
+impl<DatabaseError: DBError> From<io::Error> for LeafFileError<DatabaseError> {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl<DatabaseError> Display for LeafFileError<DatabaseError>
+where
+    DatabaseError: Error + DBError,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LeafFileError::Storage(e) => write!(f, "Storage error: {}", e),
+            LeafFileError::Io(e) => write!(f, "IO error: {}", e),
+            LeafFileError::InvalidPair(e) => write!(f, "Invalid pair: {}", e),
+        }
+    }
+}
+
+impl<DatabaseError> Error for LeafFileError<DatabaseError> where DatabaseError: Error + DBError {}
+
+pub(crate) fn write_leaf(
+    writer: &mut impl Write,
+    key: &BitSlice<u8, Msb0>,
+    value: &Felt,
+) -> io::Result<()> {
+    let mut bytes = vec![0u8; key.len().div_ceil(8)];
+    bytes.view_bits_mut::<Msb0>()[..key.len()].copy_from_bitslice(key);
+    writer.write_all(&[key.len() as u8])?;
+    writer.write_all(&bytes)?;
+    writer.write_all(&value.to_bytes_be())
+}
+
+/// Reads the next pair, `None` at the end of the file.
+#[allow(clippy::type_complexity)]
+pub(crate) fn read_leaf<E: DBError>(
+    reader: &mut impl Read,
+) -> Result<Option<(BitVec<u8, Msb0>, Felt)>, LeafFileError<E>> {
+    let mut len = [0u8];
+    match reader.read_exact(&mut len) {
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let len = len[0] as usize;
+    if len > 251 {
+        return Err(LeafFileError::InvalidPair(format!(
+            "Key of {} bits, longer than 251 bits",
+            len
+        )));
+    }
+    let mut bytes = vec![0u8; len.div_ceil(8)];
+    reader.read_exact(&mut bytes)?;
+    let bits = bytes.view_bits::<Msb0>();
+    if bits[len..].any() {
+        return Err(LeafFileError::InvalidPair(
+            "Key padded with non zero bits".to_string(),
+        ));
+    }
+    let mut value = [0u8; 32];
+    reader.read_exact(&mut value)?;
+    let felt = Felt::from_bytes_be(&value);
+    if felt.to_bytes_be() != value {
+        return Err(LeafFileError::InvalidPair(
+            "Value out of the range of felts".to_string(),
+        ));
+    }
+    Ok(Some((bits[..len].to_bitvec(), felt)))
+}
@@ -86,32 +86,76 @@
 #[cfg(not(feature = "std"))]
 extern crate alloc;
 
-use crate::trie::merkle_tree::MerkleTree;
+use crate::trie::{
+    merkle_tree::{bitslice_to_bytes, bytes_to_bitvec, MerkleTree},
+    TrieKey,
+};
 #[cfg(not(feature = "std"))]
-use alloc::{format, vec::Vec};
-use bitvec::{order::Msb0, slice::BitSlice, vec::BitVec};
+use alloc::{boxed::Box, collections::BTreeSet, format, string::ToString, vec::Vec};
+use bitvec::{order::Msb0, slice::BitSlice, vec::BitVec, view::BitView};
 use changes::ChangeBatch;
+use core::{
+    num::NonZeroUsize,
+    ops::{ControlFlow, RangeBounds},
+};
 use hashbrown::HashMap;
-use key_value_db::KeyValueDB;
+use key_value_db::{KeyValueDB, KeyValueDBConfig};
+use merge::Writes;
+use observer::CommitObservers;
+use parity_scale_codec::{Decode, Encode};
 use starknet_types_core::{
     felt::Felt,
     hash::{Pedersen, StarkHash},
 };
+#[cfg(feature = "std")]
+use std::collections::BTreeSet;
+use trie_log::{TrieLog, TrieLogKey};
+use watch::Watchers;
 
 mod changes;
+mod cursor;
 mod key_value_db;
+mod merge;
+mod observer;
 mod trie;
 
+mod async_storage;
 mod bonsai_database;
 /// All databases already implemented in this crate.
 pub mod databases;
 mod error;
 /// Definition and basic implementation of an CommitID
 pub mod id;
+#[cfg(feature = "std")]
+pub mod leaf_file;
+pub mod proof;
+pub mod rpc;
+pub mod state_diff;
+pub mod trie_log;
+#[cfg(feature = "std")]
+pub mod trie_log_file;
+mod view;
+mod watch;
+mod witness;
 
-pub use bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey};
+pub use async_storage::AsyncBonsaiStorage;
+pub use bonsai_database::{
+    BonsaiDatabase, BonsaiDatabaseAsync, BonsaiPersistentDatabase, DBError, DatabaseKey,
+    KeyValueIter,
+};
+pub use cursor::TrieCursor;
 pub use error::BonsaiStorageError;
-pub use trie::merkle_tree::{Membership, ProofNode};
+pub use merge::{ConflictPolicy, MergeConflict};
+pub use observer::CommitObserver;
+pub use proof::{
+    ChangeProof, ContractStorageProof, Membership, MultiProof, ProofNode, ProofSize, RangeProof,
+    Witness,
+};
+pub use state_diff::{KeyChange, StateDiff};
+pub use trie::merkle_tree::VisitedNode;
+pub use view::BonsaiStorageView;
+pub use watch::WatchId;
+pub use witness::WitnessRecorder;
 
 #[cfg(test)]
 mod tests;
@@ -122,7 +166,8 @@ mod tests;
 pub struct BonsaiStorageConfig {
     /// Maximal number of trie logs saved.
     /// This corresponds to the number of latest commits that is saved in order to allow reverting or getting transactional state.
-    /// Commits older than this limit are discarded and cannot be used.
+    /// Commits older than this limit are discarded and cannot be used, unless `max_trie_log_age` or `pinned_trie_logs` keep them.
+    /// The changes of a discarded commit that is more recent than a kept one are merged into the trie log of the next commit.
     /// A value of None disables the limit and all commits since the trie creation are kept.
     /// Note that patch of changes between commits occupy space in the database.
     pub max_saved_trie_logs: Option<usize>,
@@ -132,6 +177,44 @@ pub struct BonsaiStorageConfig {
     /// A database snapshot is created every `snapshot_interval` commits.
     /// Having more frequent snapshots occupies more disk space and has a slight performance impact on commits, but allows for more efficient transactional state creation.
     pub snapshot_interval: u64,
+    /// Whether `get` reads committed values through the trie and checks the hash of each node on the path against the hash its parent holds, failing with [`BonsaiStorageError::Corruption`] on a mismatch.
+    /// This detects silent corruption of the database at the cost of reading the whole path instead of a single value.
+    pub verify_reads: bool,
+    /// Trie logs of the commits made at most `max_age` before the last commit are kept even past `max_saved_trie_logs`.
+    pub max_trie_log_age: Option<TrieLogAge>,
+    /// Commits whose trie logs are kept even past `max_saved_trie_logs`, given by the bytes of their id (see [`id::Id::to_bytes`]).
+    pub pinned_trie_logs: Vec<Vec<u8>>,
+    /// Archive mode, for nodes that must serve all historical blocks: the trie logs of all the commits are kept whatever the pruning policies, and the commits are indexed by root hash so that they can be found with [`BonsaiStorage::view_by_root`].
+    pub archive: bool,
+    /// Number of threads hashing the nodes of the trie during the commits, with the `rayon` feature: the independent subtrees modified since the last commit are hashed in parallel.
+    /// A value of None uses the global rayon thread pool, and a value of 1 hashes the trie on the thread of the commit, as without the feature.
+    pub hash_threads: Option<usize>,
+    /// Maximum number of decoded nodes kept in memory, with the `cache` feature: the nodes read from the database are cached by hash and kept across commits, so that the nodes read at every commit, e.g. the top of the trie, are neither read nor decoded again.
+    /// A value of None disables the cache.
+    pub node_cache_size: Option<NonZeroUsize>,
+}
+
+/// Returns the time of a commit from the bytes of its id, in any unit as long as it grows with
+/// the commits, e.g. the timestamp of the block of the commit.
+pub type CommitTimestamp = fn(&[u8]) -> u64;
+
+/// Pruning policy keeping the trie logs of the latest commits by age, see
+/// [`BonsaiStorageConfig::max_trie_log_age`].
+#[derive(Clone, Copy)]
+pub struct TrieLogAge {
+    /// Maximum difference between the time of a commit and the time of the last commit
+    pub max_age: u64,
+    /// Function giving the time of a commit
+    pub timestamp: CommitTimestamp,
+}
+
+/// What a pruning of the trie logs removed, see [`BonsaiStorage::prune`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    /// Number of commits whose trie log was removed
+    pub trie_logs: usize,
+    /// Number of snapshots removed along with them
+    pub snapshots: usize,
 }
 
 impl Default for BonsaiStorageConfig {
@@ -140,6 +223,12 @@ impl Default for BonsaiStorageConfig {
             max_saved_trie_logs: Some(500),
             max_saved_snapshots: Some(100),
             snapshot_interval: 5,
+            verify_reads: false,
+            max_trie_log_age: None,
+            pinned_trie_logs: Vec::new(),
+            archive: false,
+            hash_threads: None,
+            node_cache_size: None,
         }
     }
 }
@@ -164,11 +253,83 @@ where
     H: StarkHash,
 {
     trie: MerkleTree<H, DB, ChangeID>,
+    watchers: Watchers<ChangeID>,
+    observers: CommitObservers<ChangeID>,
 }
 
 /// Trie root hash type.
 pub type BonsaiTrieHash = Felt;
 
+/// In-memory state of a storage saved by [`BonsaiStorage::checkpoint`].
+#[derive(Encode, Decode)]
+struct Checkpoint {
+    /// Root hash of the last commit, which the database must still have
+    root_hash: Felt,
+    /// Bytes of the ids of the commits with a trie log, oldest first
+    ids: Vec<Vec<u8>>,
+    /// Bytes of the ids of the pinned commits
+    pinned: Vec<Vec<u8>>,
+    /// Uncommitted changes of the leaves, `None` for a removed leaf
+    leaves: Vec<(Vec<u8>, Option<Felt>)>,
+}
+
+/// A commit whose hashes are computed and whose changes are in a write batch that isn't written
+/// yet, see [`BonsaiStorage::prepare`].
+#[must_use = "a prepared commit must be confirmed or aborted"]
+pub struct PreparedCommit<ChangeID, Batch> {
+    id: ChangeID,
+    root_hash: BonsaiTrieHash,
+    batch: Batch,
+    changes: Vec<KeyChange>,
+    forced: bool,
+}
+
+impl<ChangeID: Copy, Batch> PreparedCommit<ChangeID, Batch> {
+    /// Id of the commit
+    pub fn id(&self) -> ChangeID {
+        self.id
+    }
+
+    /// Root hash of the trie once the commit is confirmed
+    pub fn root_hash(&self) -> BonsaiTrieHash {
+        self.root_hash
+    }
+
+    /// Write batch of the commit, other changes of the database can be added to it so that they
+    /// are written along with the commit.
+    pub fn batch_mut(&mut self) -> &mut Batch {
+        &mut self.batch
+    }
+}
+
+/// The last `bit_len` bits of `key`, see [`BonsaiStorage::insert_bytes`].
+fn bytes_key<E: DBError>(
+    key: &[u8],
+    bit_len: usize,
+) -> Result<&BitSlice<u8, Msb0>, BonsaiStorageError<E>> {
+    let bits = key.view_bits::<Msb0>();
+    if bit_len > bits.len() {
+        return Err(BonsaiStorageError::Trie(format!(
+            "A key of {} bytes can't have {} bits",
+            key.len(),
+            bit_len
+        )));
+    }
+    Ok(&bits[bits.len() - bit_len..])
+}
+
+/// Bytes of a Starknet key given as a felt, which must fit in the 251 bits of the keys.
+fn felt_key<E: DBError>(key: &Felt) -> Result<[u8; 32], BonsaiStorageError<E>> {
+    let bytes = key.to_bytes_be();
+    if bytes.view_bits::<Msb0>()[..5].any() {
+        return Err(BonsaiStorageError::Trie(format!(
+            "Key {:#x} doesn't fit in 251 bits",
+            key
+        )));
+    }
+    Ok(bytes)
+}
+
 impl<ChangeID, DB, H> BonsaiStorage<ChangeID, DB, H>
 where
     DB: BonsaiDatabase,
@@ -183,6 +344,8 @@ where
         let key_value_db = KeyValueDB::new(db, config.into(), None);
         Ok(Self {
             trie: MerkleTree::new(key_value_db)?,
+            watchers: Watchers::default(),
+            observers: CommitObservers::default(),
         })
     }
 
@@ -194,9 +357,59 @@ where
         let key_value_db = KeyValueDB::new(db, config.into(), Some(created_at));
         Ok(Self {
             trie: MerkleTree::new(key_value_db)?,
+            watchers: Watchers::default(),
+            observers: CommitObservers::default(),
         })
     }
 
+    /// Creates a storage from a database and the in-memory state saved by
+    /// [`checkpoint`](Self::checkpoint), e.g. to resume a block after a restart. `id` gives back
+    /// the id of a commit from its bytes.
+    ///
+    /// The database must be the one of the checkpointed storage, left as it was since. The
+    /// commits pinned when checkpointing are pinned again, along with the ones pinned by `config`.
+    pub fn restore(
+        db: DB,
+        config: BonsaiStorageConfig,
+        checkpoint: &[u8],
+        id: impl Fn(&[u8]) -> Option<ChangeID>,
+    ) -> Result<Self, BonsaiStorageError<DB::DatabaseError>> {
+        let checkpoint = Checkpoint::decode(&mut &checkpoint[..])?;
+        let mut storage = Self::new(db, config)?;
+        if storage.trie.root_hash() != checkpoint.root_hash {
+            return Err(BonsaiStorageError::Trie(
+                "The database doesn't match the checkpoint".to_string(),
+            ));
+        }
+        let kv = storage.trie.db_mut();
+        for bytes in checkpoint.ids {
+            let id = id(&bytes).ok_or_else(|| {
+                BonsaiStorageError::Trie(format!("Invalid id {:?} in the checkpoint", bytes))
+            })?;
+            kv.changes_store.id_queue.push_back(id);
+        }
+        for pinned in checkpoint.pinned {
+            if !kv.config.pinned_trie_logs.contains(&pinned) {
+                kv.config.pinned_trie_logs.push(pinned);
+            }
+        }
+        for (key, value) in checkpoint.leaves {
+            let valid = key.split_first().is_some_and(|(len, bits)| {
+                *len <= 251 && bits.len() == (*len as usize).div_ceil(8)
+            });
+            if !valid {
+                return Err(BonsaiStorageError::Trie(format!(
+                    "Invalid key {:?} in the checkpoint",
+                    key
+                )));
+            }
+            storage
+                .trie
+                .set(&bytes_to_bitvec(&key), value.unwrap_or(Felt::ZERO))?;
+        }
+        Ok(storage)
+    }
+
     /// Insert a new key/value in the trie, overwriting the previous value if it exists.
     /// If the value already exists it will overwrite it.
     pub fn insert(
@@ -226,7 +439,44 @@ where
         self.trie.get(key)
     }
 
-    /// Checks if the key exists in the trie.
+    /// Returns the number of key/value pairs in the trie, uncommitted changes included.
+    ///
+    /// The number of pairs is stored at each commit, so this only reads the keys modified since
+    /// the last commit.
+    pub fn len(&self) -> Result<u64, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.len()
+    }
+
+    /// Checks if the trie has no key/value pairs, uncommitted changes included.
+    pub fn is_empty(&self) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.is_empty()
+    }
+
+    /// Get the values of several keys, in the same order as the keys.
+    ///
+    /// Values are read from the flat column rather than through the trie, and all the values that
+    /// aren't in memory are read from the database at once, which is much cheaper than one `get`
+    /// per key on databases that can read several keys at once.
+    pub fn get_many(
+        &self,
+        keys: &[&BitSlice<u8, Msb0>],
+    ) -> Result<Vec<Option<Felt>>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.get_many(keys)
+    }
+
+    /// Get the value of a key as of the commit `id`, which must be one of the commits with a trie
+    /// log.
+    ///
+    /// Only the trie logs of the commits after `id` are read, the trie isn't rebuilt.
+    pub fn get_at(
+        &self,
+        id: ChangeID,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.get_at(id, key)
+    }
+
+    /// Checks if the key exists in the trie, from the flat column and without decoding its value.
     pub fn contains(
         &self,
         key: &BitSlice<u8, Msb0>,
@@ -234,6 +484,404 @@ where
         self.trie.contains(key)
     }
 
+    /// Checks if several keys exist in the trie, in the same order as the keys.
+    ///
+    /// Like [`BonsaiStorage::contains`], the values aren't decoded, and like
+    /// [`BonsaiStorage::get_many`], the keys that aren't in memory are read from the database at
+    /// once.
+    pub fn contains_many(
+        &self,
+        keys: &[&BitSlice<u8, Msb0>],
+    ) -> Result<Vec<bool>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.contains_many(keys)
+    }
+
+    /// Same as [`BonsaiStorage::insert`] with a key made of the last `bit_len` bits of `key`,
+    /// read as a big endian number: a 32 bytes Starknet key has a length of 251 bits. The key
+    /// is viewed as bits in place, without building a [`BitVec`].
+    ///
+    /// Fails if `key` has less than `bit_len` bits, as the other methods on byte keys.
+    pub fn insert_bytes(
+        &mut self,
+        key: &[u8],
+        bit_len: usize,
+        value: &Felt,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.insert(bytes_key::<DB::DatabaseError>(key, bit_len)?, value)
+    }
+
+    /// Same as [`BonsaiStorage::remove`] with a byte key, see [`BonsaiStorage::insert_bytes`].
+    pub fn remove_bytes(
+        &mut self,
+        key: &[u8],
+        bit_len: usize,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.remove(bytes_key::<DB::DatabaseError>(key, bit_len)?)
+    }
+
+    /// Same as [`BonsaiStorage::get`] with a byte key, see [`BonsaiStorage::insert_bytes`].
+    pub fn get_bytes(
+        &self,
+        key: &[u8],
+        bit_len: usize,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        self.get(bytes_key::<DB::DatabaseError>(key, bit_len)?)
+    }
+
+    /// Same as [`BonsaiStorage::contains`] with a byte key, see [`BonsaiStorage::insert_bytes`].
+    pub fn contains_bytes(
+        &self,
+        key: &[u8],
+        bit_len: usize,
+    ) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
+        self.contains(bytes_key::<DB::DatabaseError>(key, bit_len)?)
+    }
+
+    /// Same as [`BonsaiStorage::insert`] with a Starknet key given as a felt, the 251 bits of the
+    /// key being the felt read as a big endian number. Fails if the felt doesn't fit in 251 bits.
+    pub fn insert_felt_key(
+        &mut self,
+        key: &Felt,
+        value: &Felt,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.insert_bytes(&felt_key::<DB::DatabaseError>(key)?, 251, value)
+    }
+
+    /// Same as [`BonsaiStorage::remove`] with a felt key, see [`BonsaiStorage::insert_felt_key`].
+    pub fn remove_felt_key(
+        &mut self,
+        key: &Felt,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.remove_bytes(&felt_key::<DB::DatabaseError>(key)?, 251)
+    }
+
+    /// Same as [`BonsaiStorage::get`] with a felt key, see [`BonsaiStorage::insert_felt_key`].
+    pub fn get_felt_key(
+        &self,
+        key: &Felt,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        self.get_bytes(&felt_key::<DB::DatabaseError>(key)?, 251)
+    }
+
+    /// Same as [`BonsaiStorage::contains`] with a felt key, see
+    /// [`BonsaiStorage::insert_felt_key`].
+    pub fn contains_felt_key(
+        &self,
+        key: &Felt,
+    ) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
+        self.contains_bytes(&felt_key::<DB::DatabaseError>(key)?, 251)
+    }
+
+    /// Iterate over all the key/value pairs of the trie in key order, uncommitted changes
+    /// included.
+    ///
+    /// The trie is walked lazily, only the nodes leading to the pairs read so far are fetched
+    /// from the database.
+    #[allow(clippy::type_complexity)]
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = Result<(BitVec<u8, Msb0>, Felt), BonsaiStorageError<DB::DatabaseError>>> + '_
+    {
+        self.trie.iter()
+    }
+
+    /// Iterate over the key/value pairs of the trie with a key starting with `prefix`, in key
+    /// order, uncommitted changes included.
+    ///
+    /// Only the subtree under `prefix` is walked, see [`BonsaiStorage::iter`].
+    #[allow(clippy::type_complexity)]
+    pub fn iter_prefix<'a>(
+        &'a self,
+        prefix: &BitSlice<u8, Msb0>,
+    ) -> impl Iterator<Item = Result<(BitVec<u8, Msb0>, Felt), BonsaiStorageError<DB::DatabaseError>>> + 'a
+    {
+        self.trie.iter_prefix(prefix)
+    }
+
+    /// Iterate over the key/value pairs of the trie with a key in `range`, in key order,
+    /// uncommitted changes included.
+    ///
+    /// Only the part of the trie covering the range is walked, see [`BonsaiStorage::iter`].
+    #[allow(clippy::type_complexity)]
+    pub fn range<R: RangeBounds<BitVec<u8, Msb0>>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = Result<(BitVec<u8, Msb0>, Felt), BonsaiStorageError<DB::DatabaseError>>> + '_
+    {
+        self.trie.range(range)
+    }
+
+    /// Iterate over all the key/value pairs of the trie as of the commit `id`, in key order.
+    ///
+    /// `id` must be one of the commits with a trie log. The trie is walked lazily like
+    /// [`BonsaiStorage::iter`], each node being read as it was at that commit from the database
+    /// and the trie logs, without rebuilding the trie.
+    #[allow(clippy::type_complexity)]
+    pub fn iter_at(
+        &self,
+        id: ChangeID,
+    ) -> Result<
+        impl Iterator<Item = Result<(BitVec<u8, Msb0>, Felt), BonsaiStorageError<DB::DatabaseError>>>
+            + '_,
+        BonsaiStorageError<DB::DatabaseError>,
+    > {
+        self.trie.iter_at(id)
+    }
+
+    /// Writes all the key/value pairs of the trie, uncommitted changes included, to `writer` in
+    /// key order and in the format of [`leaf_file`], and returns the number of pairs written.
+    ///
+    /// The trie is walked lazily, the pairs are written as they are read. Each pair is a few
+    /// small writes, so `writer` is better buffered.
+    #[cfg(feature = "std")]
+    pub fn export_leaves(
+        &self,
+        mut writer: impl std::io::Write,
+    ) -> Result<u64, leaf_file::LeafFileError<DB::DatabaseError>> {
+        let mut count = 0;
+        for leaf in self.trie.iter() {
+            let (key, value) = leaf?;
+            leaf_file::write_leaf(&mut writer, &key, &value)?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Inserts the key/value pairs read from `reader`, in the format of [`leaf_file`], and
+    /// returns the number of pairs read. The pairs are read and inserted one by one but, as all
+    /// insertions, are kept in memory until the next commit.
+    #[cfg(feature = "std")]
+    pub fn import_leaves(
+        &mut self,
+        mut reader: impl std::io::Read,
+    ) -> Result<u64, leaf_file::LeafFileError<DB::DatabaseError>> {
+        let mut count = 0;
+        while let Some((key, value)) = leaf_file::read_leaf(&mut reader)? {
+            self.insert(&key, &value)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Returns a read-only view of the trie as of the commit whose root hash is `root_hash`, see
+    /// [`view_at`](Self::view_at). The storage must be in [archive
+    /// mode](BonsaiStorageConfig::archive), if several commits have the same root hash the view is
+    /// the one of the latest.
+    pub fn view_by_root(
+        &self,
+        root_hash: BonsaiTrieHash,
+    ) -> Result<BonsaiStorageView<'_, ChangeID, DB, H>, BonsaiStorageError<DB::DatabaseError>> {
+        let Some(id) = self.trie.db_ref().commit_by_root(&root_hash)? else {
+            return Err(BonsaiStorageError::GoTo(format!(
+                "No commit with root hash {:#x} in the archive",
+                root_hash
+            )));
+        };
+        self.view_at(id)
+    }
+
+    /// Returns a read-only view of the trie as of the commit `id`, which must be one of the
+    /// commits with a trie log, see [`BonsaiStorageView`].
+    pub fn view_at(
+        &self,
+        id: ChangeID,
+    ) -> Result<BonsaiStorageView<'_, ChangeID, DB, H>, BonsaiStorageError<DB::DatabaseError>> {
+        BonsaiStorageView::new(self, id)
+    }
+
+    /// Iterate over the committed nodes of the subtree under `prefix`, along with their paths, in
+    /// a deterministic order, for instance to produce chunks of a snapshot.
+    ///
+    /// The nodes are SCALE encoded as they are stored in the database. They are the nodes with a
+    /// path starting with `prefix`, in path order, preceded by the edge node leading into the
+    /// subtree when it starts above `prefix`.
+    #[allow(clippy::type_complexity)]
+    pub fn export_nodes<'a>(
+        &'a self,
+        prefix: &BitSlice<u8, Msb0>,
+    ) -> impl Iterator<
+        Item = Result<(BitVec<u8, Msb0>, Vec<u8>), BonsaiStorageError<DB::DatabaseError>>,
+    > + 'a {
+        self.trie.export_nodes(prefix)
+    }
+
+    /// Returns a parallel iterator over the key/value pairs of the trie, uncommitted changes
+    /// included, for scans of the whole trie on the threads of the rayon thread pool.
+    ///
+    /// The top of the trie is read when this is called and the leaves of each subtree below it
+    /// are read in parallel. Collecting the iterator gives the pairs sorted by key, as
+    /// [`BonsaiStorage::iter`].
+    #[cfg(feature = "rayon")]
+    #[allow(clippy::type_complexity)]
+    pub fn par_iter(
+        &self,
+    ) -> Result<
+        impl rayon::iter::ParallelIterator<
+                Item = Result<(BitVec<u8, Msb0>, Felt), BonsaiStorageError<DB::DatabaseError>>,
+            > + '_,
+        BonsaiStorageError<DB::DatabaseError>,
+    >
+    where
+        H: Sync,
+        DB: Sync,
+        ChangeID: Sync,
+        DB::DatabaseError: Send,
+    {
+        self.trie.par_iter()
+    }
+
+    /// Returns `n` key/value pairs picked at random from the trie, uncommitted changes included,
+    /// for instance to spot-check a synced trie against another source.
+    ///
+    /// Each pair is picked by descending the trie and going left or right at random at each
+    /// binary node, which only reads the nodes along its path but isn't a uniform sample: a pair
+    /// alone in a big subtree is picked more often than others, and a pair can be picked several
+    /// times. The same seed gives the same pairs on the same trie.
+    #[allow(clippy::type_complexity)]
+    pub fn sample_leaves(
+        &self,
+        n: usize,
+        seed: u64,
+    ) -> Result<Vec<(BitVec<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.sample_leaves(n, seed)
+    }
+
+    /// Returns the pair with the smallest key, uncommitted changes included. Only the nodes
+    /// down to it are read.
+    #[allow(clippy::type_complexity)]
+    pub fn first_leaf(
+        &self,
+    ) -> Result<Option<(BitVec<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.iter().next().transpose()
+    }
+
+    /// Returns the pair with the greatest key, uncommitted changes included, see
+    /// [`BonsaiStorage::first_leaf`].
+    #[allow(clippy::type_complexity)]
+    pub fn last_leaf(
+        &self,
+    ) -> Result<Option<(BitVec<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.iter().reversed().next().transpose()
+    }
+
+    /// Returns the pair with the greatest key lower than `key`, uncommitted changes included.
+    /// `key` doesn't need to be in the trie, so this and [`BonsaiStorage::next_leaf`] give the
+    /// neighbours of an absent key.
+    ///
+    /// Only the nodes along the path of `key` and down to the pair are read.
+    #[allow(clippy::type_complexity)]
+    pub fn prev_leaf(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<(BitVec<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.prev_leaf(key)
+    }
+
+    /// Returns the pair with the smallest key greater than `key`, uncommitted changes included,
+    /// see [`BonsaiStorage::prev_leaf`].
+    #[allow(clippy::type_complexity)]
+    pub fn next_leaf(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<(BitVec<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.next_leaf(key)
+    }
+
+    /// Visits the nodes of the trie depth first, in key order, uncommitted changes included, for
+    /// instance to look at the shape of the trie.
+    ///
+    /// `visitor` is called with the depth of each node, which is the number of nodes above it,
+    /// the path to it and the node itself. The visit stops when it returns [`ControlFlow::Break`].
+    pub fn visit_nodes(
+        &self,
+        visitor: &mut dyn FnMut(usize, &BitSlice<u8, Msb0>, VisitedNode<'_>) -> ControlFlow<()>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.visit_nodes(visitor)
+    }
+
+    /// Subscribes to the changes of the keys starting with `prefix`: after each commit changing
+    /// some of them, `callback` is called with the id of the commit and their changes, sorted by
+    /// key. The changes brought by [`BonsaiStorage::merge`] and [`BonsaiStorage::revert_to`]
+    /// aren't notified.
+    pub fn watch(
+        &mut self,
+        prefix: &BitSlice<u8, Msb0>,
+        callback: impl FnMut(ChangeID, &[KeyChange]) + Send + Sync + 'static,
+    ) -> WatchId {
+        self.watchers.watch(prefix, Box::new(callback))
+    }
+
+    /// Cancels a subscription made with [`BonsaiStorage::watch`], returns whether it existed.
+    pub fn unwatch(&mut self, id: WatchId) -> bool {
+        self.watchers.unwatch(id)
+    }
+
+    /// Values written to the leaves since the creation of this transactional state, or since the
+    /// last commit for a storage that isn't one.
+    fn written_leaves(&self) -> Result<Writes, BonsaiStorageError<DB::DatabaseError>> {
+        let kv = self.trie.db_ref();
+        let mut keys = BTreeSet::new();
+        if let (Some(created_at), Some(last)) = (kv.created_at, kv.changes_store.id_queue.back()) {
+            if created_at != *last {
+                keys.extend(kv.get_changes_between(created_at, *last)?.into_keys());
+            }
+        }
+        keys.extend(self.trie.pending_keys());
+        keys.into_iter()
+            .map(|key| {
+                let value = self.trie.get(&key)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    /// Registers an observer called at each step of the following commits, see
+    /// [`CommitObserver`].
+    pub fn add_commit_observer(&mut self, observer: impl CommitObserver<ChangeID> + 'static) {
+        self.observers.add(Box::new(observer));
+    }
+
+    /// Returns a cursor over the key/value pairs of the trie, see [`TrieCursor`].
+    pub fn cursor(&self) -> TrieCursor<'_, ChangeID, DB, H> {
+        TrieCursor::new(self)
+    }
+
+    /// Discards the changes made since the last commit, the storage goes back to the state of the
+    /// last commit.
+    pub fn rollback_pending(&mut self) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.db_mut().changes_store.current_changes.0.clear();
+        self.trie.reset_to_last_commit()
+    }
+
+    /// Saves the in-memory state of the storage, the ids of the commits with a trie log and the
+    /// changes not committed yet, so that it can be [restored](Self::restore) over the same
+    /// database by another process.
+    ///
+    /// The uncommitted changes are saved as the values of the leaves, the nodes of the trie are
+    /// updated again when restoring. Snapshots live in the memory of the database and aren't part
+    /// of the checkpoint.
+    pub fn checkpoint(&self) -> Result<Vec<u8>, BonsaiStorageError<DB::DatabaseError>> {
+        let kv = self.trie.db_ref();
+        let leaves = self
+            .trie
+            .pending_keys()
+            .map(|key| Ok((bitslice_to_bytes(&key), self.trie.get(&key)?)))
+            .collect::<Result<_, BonsaiStorageError<DB::DatabaseError>>>()?;
+        Ok(Checkpoint {
+            root_hash: self.trie.root_hash(),
+            ids: kv
+                .changes_store
+                .id_queue
+                .iter()
+                .map(|id| id.to_bytes())
+                .collect(),
+            pinned: kv.config.pinned_trie_logs.clone(),
+            leaves,
+        }
+        .encode())
+    }
+
     /// Go to a specific commit ID.
     /// If insert/remove is called between the last `commit()` and a call to this function,
     /// the in-memory changes will be discarded.
@@ -264,41 +912,35 @@ where
             )));
         };
 
-        // Accumulate changes from requested to last recorded
-        let mut full = Vec::new();
-        for id in kv.changes_store.id_queue.iter().skip(id_position).rev() {
-            full.extend(
-                ChangeBatch::deserialize(
-                    id,
-                    kv.db.get_by_prefix(&DatabaseKey::TrieLog(&id.to_bytes()))?,
-                )
-                .0,
+        // Value of each key at the requested id, the old value of its first change after it.
+        // Each key is written once, however many commits changed it since.
+        let mut reverted: HashMap<TrieKey, Option<Vec<u8>>> = HashMap::new();
+        for id in kv.changes_store.id_queue.iter().skip(id_position + 1) {
+            let changes = ChangeBatch::deserialize(
+                id,
+                kv.db.get_by_prefix(&DatabaseKey::TrieLog(&id.to_bytes()))?,
             );
+            for (key, change) in changes.0 {
+                reverted.entry(key).or_insert(change.old_value);
+            }
         }
 
         // Revert changes
         let mut batch = kv.db.create_batch();
-        for (key, change) in full.iter().rev() {
+        for (key, old_value) in reverted.iter() {
             let key = DatabaseKey::from(key);
-            match (&change.old_value, &change.new_value) {
-                (Some(old_value), Some(_)) => {
+            match old_value {
+                Some(old_value) => {
                     kv.db.insert(&key, old_value, Some(&mut batch))?;
                 }
-                (Some(old_value), None) => {
-                    kv.db.insert(&key, old_value, Some(&mut batch))?;
-                }
-                (None, Some(_)) => {
+                None => {
                     kv.db.remove(&key, Some(&mut batch))?;
                 }
-                (None, None) => unreachable!(),
             };
         }
 
-        // Truncate trie logs at the requested id
-        let mut truncated = kv.changes_store.id_queue.split_off(id_position);
-        if let Some(current) = truncated.pop_front() {
-            kv.changes_store.id_queue.push_back(current);
-        }
+        // Truncate trie logs after the requested id
+        let truncated = kv.changes_store.id_queue.split_off(id_position + 1);
         for id in truncated.iter() {
             kv.db
                 .remove_by_prefix(&DatabaseKey::TrieLog(&id.to_bytes()))?;
@@ -319,6 +961,91 @@ where
         self.trie.db_ref().get_changes(id)
     }
 
+    /// Get the raw changes recorded in the trie log of the commit `id`, to the nodes of the trie
+    /// as well as to the leaves, see [`trie_log`].
+    pub fn get_trie_log(
+        &self,
+        id: ChangeID,
+    ) -> Result<TrieLog<ChangeID>, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(TrieLog::new(id, self.trie.db_ref().get_trie_log(id)?))
+    }
+
+    /// Writes the trie logs of the commits with an id in `range` to `writer`, oldest first and in
+    /// the format of [`trie_log_file`], and returns the number of trie logs written.
+    ///
+    /// This is meant to archive the history before it's pruned, the trie logs can be replayed
+    /// from the archive with [`import_trie_logs`](Self::import_trie_logs).
+    #[cfg(feature = "std")]
+    pub fn export_trie_logs(
+        &self,
+        range: impl RangeBounds<ChangeID>,
+        mut writer: impl std::io::Write,
+    ) -> Result<u64, trie_log_file::TrieLogFileError<DB::DatabaseError>> {
+        let mut count = 0;
+        let kv = self.trie.db_ref();
+        for id in kv
+            .changes_store
+            .id_queue
+            .iter()
+            .filter(|id| range.contains(id))
+        {
+            let trie_log = TrieLog::new(*id, kv.get_trie_log(*id)?);
+            trie_log_file::write_trie_log(&mut writer, &id.to_bytes(), &trie_log.entries)?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Get the metadata stored with the commit `id` by
+    /// [`commit_with_metadata`](Self::commit_with_metadata), `None` if it was committed without.
+    /// The metadata is kept as long as the trie log of the commit.
+    pub fn get_commit_metadata(
+        &self,
+        id: ChangeID,
+    ) -> Result<Option<Vec<u8>>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.db_ref().get_commit_metadata(id)
+    }
+
+    /// Get the keys whose value at the commit `to` differs from their value at the commit `from`,
+    /// sorted. Both commits must have a trie log, and the keys are found from the trie logs of
+    /// the commits in between.
+    #[allow(clippy::type_complexity)]
+    pub fn keys_changed_between(
+        &self,
+        from: ChangeID,
+        to: ChangeID,
+    ) -> Result<BTreeSet<BitVec<u8, Msb0>>, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(self
+            .trie
+            .db_ref()
+            .get_changes_between(from, to)?
+            .into_keys()
+            .collect())
+    }
+
+    /// Get the changes of the key/value pairs between the commit `from` and the commit `to`,
+    /// which both must have a trie log, see [`BonsaiStorage::keys_changed_between`].
+    pub fn diff(
+        &self,
+        from: ChangeID,
+        to: ChangeID,
+    ) -> Result<StateDiff, BonsaiStorageError<DB::DatabaseError>> {
+        let mut changes: Vec<_> = self
+            .trie
+            .db_ref()
+            .get_changes_between(from, to)?
+            .into_iter()
+            .map(|(key, change)| KeyChange {
+                key,
+                old_value: change.old_value,
+                new_value: change.new_value,
+            })
+            .collect();
+        changes.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(StateDiff { changes })
+    }
+
     #[cfg(test)]
     pub fn dump_database(&self) {
         self.trie.db_ref().db.dump_database();
@@ -329,21 +1056,213 @@ where
         Ok(self.trie.root_hash())
     }
 
+    /// Get the root hash the trie would have if the changes since the last commit were committed,
+    /// without writing anything to the database.
+    pub fn compute_root_dry_run(
+        &mut self,
+    ) -> Result<BonsaiTrieHash, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.compute_uncommitted_hashes()
+    }
+
+    /// Get trie root hash at the commit `id`, which must still have a trie log.
+    ///
+    /// Root hashes are indexed when committing, the ones of the commits made before the index
+    /// existed are read from the trie logs of the later commits.
+    pub fn root_hash_at(
+        &self,
+        id: ChangeID,
+    ) -> Result<BonsaiTrieHash, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.root_hash_at(id)
+    }
+
+    /// Get the id of the last commit, `None` if nothing was committed or all the trie logs were
+    /// pruned.
+    pub fn latest_id(&self) -> Option<ChangeID> {
+        self.trie.db_ref().changes_store.id_queue.back().copied()
+    }
+
+    /// Get the id of the oldest commit that still has a trie log, the oldest one that can be
+    /// reverted to or viewed.
+    pub fn oldest_retained_id(&self) -> Option<ChangeID> {
+        self.trie.db_ref().changes_store.id_queue.front().copied()
+    }
+
+    /// Iterate over the ids of the commits that still have a trie log, oldest first. These are the
+    /// ids accepted by [`revert_to`](Self::revert_to) and [`view_at`](Self::view_at).
+    pub fn iter_ids(&self) -> impl Iterator<Item = ChangeID> + '_ {
+        self.trie.db_ref().changes_store.id_queue.iter().copied()
+    }
+
+    /// Labels the commit `id` with `name` (e.g. "genesis" or "block-100000") so that it can be
+    /// found back with [`resolve_tag`](Self::resolve_tag). Tagging another commit with the same
+    /// name moves the tag.
+    ///
+    /// Tags are kept in the database but aren't part of the trie, reverting doesn't undo them.
+    pub fn tag(
+        &mut self,
+        id: ChangeID,
+        name: &str,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.db_mut().tag(id, name)
+    }
+
+    /// Returns the commit labelled with `name`, to be given to e.g.
+    /// [`revert_to`](Self::revert_to) or [`view_at`](Self::view_at). Returns `None` if there is
+    /// no such tag or if the commit isn't recorded anymore, because it was reverted or its trie
+    /// log was removed.
+    pub fn resolve_tag(
+        &self,
+        name: &str,
+    ) -> Result<Option<ChangeID>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.db_ref().resolve_tag(name)
+    }
+
+    /// Keeps the trie log of the commit `id` when pruning, as if it was in
+    /// [`BonsaiStorageConfig::pinned_trie_logs`], so that it stays available for
+    /// [`revert_to`](Self::revert_to) and [`view_at`](Self::view_at). The commit must still have a
+    /// trie log.
+    ///
+    /// The trie logs of all the commits after a pinned one are kept too, merged together when
+    /// they would otherwise be pruned.
+    pub fn pin(&mut self, id: ChangeID) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.db_mut().pin(id)
+    }
+
+    /// Unpins the commit `id`, returns whether it was pinned. Its trie log is removed at the next
+    /// commit or call to [`prune`](Self::prune) if no pruning policy keeps it.
+    pub fn unpin(&mut self, id: ChangeID) -> bool {
+        self.trie.db_mut().unpin(id)
+    }
+
+    /// Removes the trie logs of the commits that the pruning policies of the configuration don't
+    /// keep anymore, along with their snapshots. Pruning also happens at each commit.
+    pub fn prune(&mut self) -> Result<PruneReport, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.db_mut().prune()
+    }
+
+    /// Shrinks the history by keeping the trie log of only one commit of every `every_n`
+    /// consecutive commits, the oldest of each run, along with the ones of the last and the
+    /// pinned commits. The changes of the other commits are merged into the trie log of the next
+    /// commit kept, so that the storage can still be reverted to any commit kept, and their
+    /// snapshots are removed. Each call compacts the history kept by the previous one again.
+    ///
+    /// Nothing is removed in [archive mode](BonsaiStorageConfig::archive), which keeps the whole
+    /// history.
+    pub fn compact_history(
+        &mut self,
+        every_n: NonZeroUsize,
+    ) -> Result<PruneReport, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.db_mut().compact_history(every_n)
+    }
+
+    /// Removes the trie logs and snapshots of all the commits before `id`, e.g. to reclaim disk
+    /// space after a checkpoint, whatever the pruning policies. `id` must be one of the commits
+    /// with a trie log, which stays the oldest one that can be reverted to.
+    ///
+    /// Nothing is removed in [archive mode](BonsaiStorageConfig::archive), which keeps the whole
+    /// history.
+    pub fn truncate_history_before(
+        &mut self,
+        id: ChangeID,
+    ) -> Result<PruneReport, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.db_mut().truncate_history_before(id)
+    }
+
     /// This function must be used with transactional state only.
     /// Similar to `commit` but without optimizations.
     pub fn transactional_commit(
         &mut self,
         id: ChangeID,
     ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
-        self.trie.commit()?;
-        self.trie.db_mut().commit(id)?;
+        let prepared = self.prepare_commit(id, None, false)?;
+        let root_hash = self.confirm_commit(prepared)?;
+        self.observers.flush_done(id, root_hash);
         Ok(())
     }
 
+    fn prepare_commit(
+        &mut self,
+        id: ChangeID,
+        metadata: Option<&[u8]>,
+        forced: bool,
+    ) -> Result<PreparedCommit<ChangeID, DB::Batch>, BonsaiStorageError<DB::DatabaseError>> {
+        if !forced {
+            self.trie.db_ref().check_commit_id(id)?;
+        }
+        self.observers.commit_start(id);
+        let mut batch = self.trie.db_ref().create_batch();
+        let root_hash = match self.trie.commit(&mut batch) {
+            Ok(root_hash) => root_hash,
+            Err(err) => return self.abort_commit(id, err),
+        };
+        self.prepared_commit(id, root_hash, metadata, forced, batch)
+    }
+
+    /// Gives up the commit `id` that failed with `err` before its batch was written, the trie goes
+    /// back to its last commit.
+    fn abort_commit<T>(
+        &mut self,
+        id: ChangeID,
+        err: BonsaiStorageError<DB::DatabaseError>,
+    ) -> Result<T, BonsaiStorageError<DB::DatabaseError>> {
+        self.observers.commit_aborted(id);
+        self.rollback_pending()?;
+        Err(err)
+    }
+
+    /// Builds the prepared commit of the changes added to `batch`, the commit is aborted if it
+    /// fails.
+    fn prepared_commit(
+        &mut self,
+        id: ChangeID,
+        root_hash: BonsaiTrieHash,
+        metadata: Option<&[u8]>,
+        forced: bool,
+        mut batch: DB::Batch,
+    ) -> Result<PreparedCommit<ChangeID, DB::Batch>, BonsaiStorageError<DB::DatabaseError>> {
+        let current_changes = &self.trie.db_ref().changes_store.current_changes;
+        self.observers.changes_serialized(id, current_changes);
+        let changes = self.watchers.watched_changes(current_changes);
+        if let Err(err) = self
+            .trie
+            .db_mut()
+            .prepare_commit(id, root_hash, metadata, forced, &mut batch)
+        {
+            return self.abort_commit(id, err);
+        }
+        Ok(PreparedCommit {
+            id,
+            root_hash,
+            batch,
+            changes,
+            forced,
+        })
+    }
+
+    fn confirm_commit(
+        &mut self,
+        prepared: PreparedCommit<ChangeID, DB::Batch>,
+    ) -> Result<BonsaiTrieHash, BonsaiStorageError<DB::DatabaseError>> {
+        let PreparedCommit {
+            id,
+            root_hash,
+            batch,
+            changes,
+            forced,
+        } = prepared;
+        if let Err(err) = self.trie.db_mut().write_batch(batch) {
+            return self.abort_commit(id, err);
+        }
+        self.trie.db_mut().confirm_commit(id, forced)?;
+        self.watchers.notify(id, &changes);
+        Ok(root_hash)
+    }
+
     /// Generates a merkle-proof for a given `key`.
     ///
     /// Returns vector of [`TrieNode`] which form a chain from the root to the key,
-    /// if it exists, or down to the node which proves that the key does not exist.
+    /// if it exists, or down to the node which proves that the key does not exist, see
+    /// [`BonsaiStorage::verify_non_membership`].
     ///
     /// The nodes are returned in order, root first.
     ///
@@ -358,6 +1277,107 @@ where
         self.trie.get_proof(key)
     }
 
+    /// Get the value of `key` along with its merkle-proof, see [`BonsaiStorage::get_proof`].
+    ///
+    /// The value is read from the nodes of the proof, which saves the reads of a separate `get`
+    /// and always matches the proof. Both are the ones of the last commit.
+    #[allow(clippy::type_complexity)]
+    pub fn get_with_proof(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<(Option<Felt>, Vec<ProofNode>), BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.get_with_proof(key)
+    }
+
+    /// Generates a merkle-proof of `key` in the trie with the changes made since the last commit,
+    /// before committing them. The proof is against the returned root hash, which is the root
+    /// hash the trie will have once committed.
+    pub fn get_uncommitted_proof(
+        &mut self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<(BonsaiTrieHash, Vec<ProofNode>), BonsaiStorageError<DB::DatabaseError>> {
+        let root = self.trie.compute_uncommitted_hashes()?;
+        Ok((root, self.trie.get_proof(key)?))
+    }
+
+    /// Size of the merkle-proof of `key`, computed from the nodes on its path without encoding
+    /// them.
+    pub fn estimate_proof_size(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<ProofSize, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(ProofSize::of(&self.get_proof(key)?))
+    }
+
+    /// Generates the proof of a storage slot of a contract against the global state root, this
+    /// storage being the contracts trie and `storage` the storage trie of the contract, see
+    /// [`BonsaiStorage::verify_contract_storage_proof`].
+    pub fn get_contract_storage_proof(
+        &self,
+        storage: &Self,
+        contract_address: Felt,
+        class_hash: Felt,
+        nonce: Felt,
+        storage_key: Felt,
+    ) -> Result<ContractStorageProof, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(ContractStorageProof {
+            contract_proof: self.get_proof(&proof::felt_key(&contract_address))?,
+            class_hash,
+            nonce,
+            storage_root: storage.root_hash()?,
+            storage_proof: storage.get_proof(&proof::felt_key(&storage_key))?,
+        })
+    }
+
+    /// Verifies that the slot `storage_key` of the contract at `contract_address` holds `value`,
+    /// or is empty for `None`, in the state whose global root is `global_root`, see
+    /// [`proof::verify_contract_storage_proof`].
+    pub fn verify_contract_storage_proof(
+        global_root: Felt,
+        classes_root: Felt,
+        contract_address: Felt,
+        storage_key: Felt,
+        value: Option<Felt>,
+        proof: &ContractStorageProof,
+    ) -> bool {
+        proof::verify_contract_storage_proof(
+            global_root,
+            classes_root,
+            contract_address,
+            storage_key,
+            value,
+            proof,
+        )
+    }
+
+    /// Generates the merkle-proofs of several keys, in the order of the keys, walking their paths
+    /// on the threads of the rayon thread pool.
+    #[cfg(feature = "rayon")]
+    pub fn get_proofs_parallel(
+        &self,
+        keys: &[&BitSlice<u8, Msb0>],
+    ) -> Result<Vec<Vec<ProofNode>>, BonsaiStorageError<DB::DatabaseError>>
+    where
+        Self: Sync,
+        DB::DatabaseError: Send,
+    {
+        use rayon::prelude::*;
+        keys.par_iter().map(|key| self.get_proof(key)).collect()
+    }
+
+    /// Generates the merkle-proofs of several keys as the `starknet_getStorageProof` RPC method
+    /// returns them, see [`rpc`].
+    pub fn get_rpc_proof(
+        &self,
+        keys: &[&BitSlice<u8, Msb0>],
+    ) -> Result<Vec<rpc::NodeHashToNode>, BonsaiStorageError<DB::DatabaseError>> {
+        let proofs = keys
+            .iter()
+            .map(|key| self.get_proof(key))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rpc::proof_to_rpc::<H>(proofs.iter().map(Vec::as_slice)))
+    }
+
     /// Verifies a merkle-proof for a given `key` and `value`.
     pub fn verify_proof(
         root: Felt,
@@ -367,6 +1387,81 @@ where
     ) -> Option<Membership> {
         MerkleTree::<Pedersen, DB, ChangeID>::verify_proof(root, key, value, proofs)
     }
+
+    /// Verifies a merkle-proof that a given `key` is not in the trie.
+    pub fn verify_non_membership(
+        root: Felt,
+        key: &BitSlice<u8, Msb0>,
+        proofs: &[ProofNode],
+    ) -> bool {
+        MerkleTree::<H, DB, ChangeID>::verify_non_membership(root, key, proofs)
+    }
+
+    /// Generates a proof of all the key/value pairs with keys between `start` and `end` at the
+    /// last commit, see [`BonsaiStorage::verify_range_proof`]. Fails if there are uncommitted
+    /// changes.
+    pub fn get_range_proof(
+        &self,
+        start: &BitSlice<u8, Msb0>,
+        end: &BitSlice<u8, Msb0>,
+    ) -> Result<RangeProof, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.get_range_proof(start, end)
+    }
+
+    /// Verifies that a range proof holds all the key/value pairs with keys between `start` and
+    /// `end`, bounds included.
+    pub fn verify_range_proof(
+        root: Felt,
+        start: &BitSlice<u8, Msb0>,
+        end: &BitSlice<u8, Msb0>,
+        proof: &RangeProof,
+    ) -> bool {
+        MerkleTree::<H, DB, ChangeID>::verify_range_proof(root, start, end, proof)
+    }
+
+    /// Generates a proof that no key between `start` and `end` is in the trie, see
+    /// [`BonsaiStorage::verify_range_empty`]. Returns `None` if the range isn't empty. Fails if
+    /// there are uncommitted changes.
+    pub fn prove_range_empty(
+        &self,
+        start: &BitSlice<u8, Msb0>,
+        end: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<RangeProof>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.prove_range_empty(start, end)
+    }
+
+    /// Verifies that no key between `start` and `end`, bounds included, is in the trie of root
+    /// `root`.
+    pub fn verify_range_empty(
+        root: Felt,
+        start: &BitSlice<u8, Msb0>,
+        end: &BitSlice<u8, Msb0>,
+        proof: &RangeProof,
+    ) -> bool {
+        proof::verify_range_empty::<H>(root, start, end, proof)
+    }
+
+    /// Generates a proof of all the key/value pairs with keys of `key_len` bits starting with
+    /// `prefix`, for instance the whole storage of a contract, see
+    /// [`BonsaiStorage::verify_subtree_proof`].
+    pub fn get_subtree_proof(
+        &self,
+        prefix: &BitSlice<u8, Msb0>,
+        key_len: usize,
+    ) -> Result<RangeProof, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.get_subtree_proof(prefix, key_len)
+    }
+
+    /// Verifies that a subtree proof holds all the key/value pairs with keys of `key_len` bits
+    /// starting with `prefix`.
+    pub fn verify_subtree_proof(
+        root: Felt,
+        prefix: &BitSlice<u8, Msb0>,
+        key_len: usize,
+        proof: &RangeProof,
+    ) -> bool {
+        proof::verify_subtree_proof::<H>(root, prefix, key_len, proof)
+    }
 }
 
 impl<ChangeID, DB, H> BonsaiStorage<ChangeID, DB, H>
@@ -376,13 +1471,214 @@ where
     H: StarkHash,
 {
     /// Update trie and database using all changes since the last commit.
+    ///
+    /// `id` must be greater than the id of the last commit, see
+    /// [`force_commit`](Self::force_commit).
     pub fn commit(
         &mut self,
         id: ChangeID,
     ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
-        self.trie.commit()?;
-        self.trie.db_mut().commit(id)?;
+        self.commit_and_get_root(id)?;
+        Ok(())
+    }
+
+    /// Same as [`commit`](Self::commit), returning the root hash of the trie computed by the
+    /// commit.
+    pub fn commit_and_get_root(
+        &mut self,
+        id: ChangeID,
+    ) -> Result<BonsaiTrieHash, BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let prepared = self.prepare(id)?;
+        self.confirm(prepared)
+    }
+
+    /// Same as [`commit`](Self::commit) but accepts an `id` that isn't greater than the id of the
+    /// last commit, which [`commit`](Self::commit) rejects with
+    /// [`BonsaiStorageError::CommitId`], e.g. after the numbering of the commits was reset.
+    ///
+    /// The commits with an id greater than or equal to `id` are forgotten, without reverting
+    /// their changes: their changes are merged into the trie log of the new commit, which can't
+    /// be reverted to them anymore but can still be reverted to the commits before `id`. Their
+    /// trie logs are replaced in the write batch of the commit, so they are only forgotten once
+    /// it is written.
+    pub fn force_commit(
+        &mut self,
+        id: ChangeID,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let prepared = self.prepare_commit(id, None, true)?;
+        self.confirm(prepared)?;
+        Ok(())
+    }
+
+    /// Same as [`commit`](Self::commit), storing `metadata` along with the trie log of the commit
+    /// (e.g. the hash, proposer and timestamp of the block), see
+    /// [`get_commit_metadata`](Self::get_commit_metadata).
+    pub fn commit_with_metadata(
+        &mut self,
+        id: ChangeID,
+        metadata: &[u8],
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let prepared = self.prepare_commit(id, Some(metadata), false)?;
+        self.confirm(prepared)?;
+        Ok(())
+    }
+
+    /// First step of a commit in two steps: computes the hashes of the trie and builds the write
+    /// batch of all the changes since the last commit, without writing it.
+    ///
+    /// Other changes can be added to the batch with [`PreparedCommit::batch_mut`], e.g. the block
+    /// data of an application storing it in the same database, so that the whole block is written
+    /// atomically by [`confirm`](Self::confirm). The storage must not be used until the commit is
+    /// confirmed or [aborted](Self::abort).
+    ///
+    /// If computing the commit fails, it is aborted: the trie goes back to its last commit and
+    /// the changes made since then are discarded, as when writing its batch fails in
+    /// [`confirm`](Self::confirm).
+    pub fn prepare(
+        &mut self,
+        id: ChangeID,
+    ) -> Result<
+        PreparedCommit<ChangeID, DB::Batch>,
+        BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>,
+    > {
+        self.prepare_commit(id, None, false)
+    }
+
+    /// Writes the batch of a prepared commit and records the commit, returning its root hash.
+    /// Pruning of the trie logs and snapshots happens after the batch is written.
+    pub fn confirm(
+        &mut self,
+        prepared: PreparedCommit<ChangeID, DB::Batch>,
+    ) -> Result<BonsaiTrieHash, BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let id = prepared.id;
+        let root_hash = self.confirm_commit(prepared)?;
         self.trie.db_mut().create_snapshot(id);
+        self.observers.flush_done(id, root_hash);
+        Ok(root_hash)
+    }
+
+    /// Drops a prepared commit without writing anything. The trie goes back to its last commit,
+    /// the changes made since then are discarded.
+    pub fn abort(
+        &mut self,
+        prepared: PreparedCommit<ChangeID, DB::Batch>,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.observers.commit_aborted(prepared.id);
+        drop(prepared);
+        self.rollback_pending()
+    }
+
+    /// Removes all the leaves of the trie and commits the empty trie under `id`, e.g. when a
+    /// contract is replaced. The changes made since the last commit are discarded.
+    ///
+    /// The nodes and leaves are removed as they are stored instead of through the trie, so
+    /// nothing is hashed. It still costs as much as the size of the trie: each node and leaf is
+    /// read and recorded with its value in the trie log of the commit, so that it can be
+    /// reverted like any other. The metadata, tags and other records of the commits are kept.
+    pub fn clear(
+        &mut self,
+        id: ChangeID,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.trie.db_ref().check_commit_id(id)?;
+        self.rollback_pending()?;
+        self.observers.commit_start(id);
+        let mut batch = self.trie.db_ref().create_batch();
+        if let Err(err) = self.trie.clear(&mut batch) {
+            return self.abort_commit(id, err);
+        }
+        let prepared = self.prepared_commit(id, Felt::ZERO, None, false, batch)?;
+        self.confirm(prepared)?;
+        Ok(())
+    }
+
+    /// Commits the changes of a trie log exported from another storage with
+    /// [`get_trie_log`](Self::get_trie_log), e.g. to replicate the commits of a node. Returns the
+    /// new root hash.
+    ///
+    /// The changes of the leaves are applied again to the trie and committed under the id of the
+    /// trie log, so a storage holding the same state as the source before the commit ends up with
+    /// the same state after it. The value of each leaf before the commit must be the one of the
+    /// trie log, and the root hash computed must be the one recorded by the source, otherwise
+    /// nothing is committed. The changes not committed yet are discarded.
+    ///
+    /// A range of commits is replicated by replaying their trie logs in order.
+    pub fn replay(
+        &mut self,
+        trie_log: &TrieLog<ChangeID>,
+    ) -> Result<BonsaiTrieHash, BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.trie.reset_to_last_commit()?;
+        if let Err(err) = self.apply_trie_log(trie_log) {
+            self.trie.reset_to_last_commit()?;
+            return Err(err);
+        }
+        self.commit_and_get_root(trie_log.id)
+    }
+
+    /// Replays the trie logs read from `reader`, in the format of [`trie_log_file`], and returns
+    /// the number of trie logs replayed. `id` gives back the id of a trie log from its bytes.
+    ///
+    /// The storage must hold the state before the first trie log of the file, e.g. be empty to
+    /// replay an archive starting at the first commit, see [`replay`](Self::replay). Reading stops
+    /// at the first trie log that fails, the ones before it stay committed.
+    #[cfg(feature = "std")]
+    pub fn import_trie_logs(
+        &mut self,
+        mut reader: impl std::io::Read,
+        id: impl Fn(&[u8]) -> Option<ChangeID>,
+    ) -> Result<u64, trie_log_file::TrieLogFileError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let mut count = 0;
+        while let Some((id_bytes, entries)) = trie_log_file::read_trie_log(&mut reader)? {
+            let id = id(&id_bytes).ok_or_else(|| {
+                trie_log_file::TrieLogFileError::InvalidTrieLog(format!(
+                    "Invalid id {:?}",
+                    id_bytes
+                ))
+            })?;
+            self.replay(&TrieLog { id, entries })?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Applies the changes of the leaves of the trie log and checks the root hash they lead to.
+    fn apply_trie_log(
+        &mut self,
+        trie_log: &TrieLog<ChangeID>,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let root_key = TrieLogKey::from(key_value_db::root_index_key(&trie_log.id));
+        let mut expected_root = None;
+        for entry in trie_log.entries.iter() {
+            let key = match &entry.key {
+                TrieLogKey::Flat(key) => key,
+                TrieLogKey::Trie(_) => {
+                    if entry.key == root_key {
+                        expected_root = entry.new_value.as_deref();
+                    }
+                    continue;
+                }
+            };
+            if self.trie.db_ref().get(&TrieKey::Flat(key.clone()))? != entry.old_value {
+                return Err(BonsaiStorageError::Trie(format!(
+                    "Trie log of {:?} doesn't apply to the current state",
+                    trie_log.id
+                )));
+            }
+            let value = match &entry.new_value {
+                Some(value) => Felt::decode(&mut value.as_slice())?,
+                None => Felt::ZERO,
+            };
+            self.trie.set(&bytes_to_bitvec(key), value)?;
+        }
+        // The trie logs written before the root hashes were indexed can't be checked
+        if let Some(expected_root) = expected_root {
+            let root = self.trie.compute_uncommitted_hashes()?;
+            if expected_root != root.encode() {
+                return Err(BonsaiStorageError::Corruption(format!(
+                    "Root hash {:#x} of the replayed commit {:?} isn't the recorded one",
+                    root, trie_log.id
+                )));
+            }
+        }
         Ok(())
     }
 
@@ -410,11 +1706,133 @@ where
         }
     }
 
+    /// Generates a merkle-proof of `key` as of the commit `change_id`.
+    ///
+    /// The nodes of the path are read as they were at that commit from the database and the trie
+    /// logs of the later commits, as [`BonsaiStorageView::get_proof`] does, so no snapshot is
+    /// needed. Returns `None` if `change_id` has no trie log.
+    #[allow(clippy::type_complexity)]
+    pub fn get_proof_at(
+        &self,
+        change_id: ChangeID,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<Vec<ProofNode>>, BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>>
+    {
+        if !self
+            .trie
+            .db_ref()
+            .changes_store
+            .id_queue
+            .contains(&change_id)
+        {
+            return Ok(None);
+        }
+        Ok(Some(self.trie.get_proof_at(change_id, key)?))
+    }
+
+    /// Generates the proofs of the values of `key` at the commits `before` and `after`, see
+    /// [`BonsaiStorage::get_proof_at`] and [`BonsaiStorage::verify_change_proof`]. Returns `None`
+    /// if one of the commits has no trie log.
+    #[allow(clippy::type_complexity)]
+    pub fn get_change_proof(
+        &self,
+        before: ChangeID,
+        after: ChangeID,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<ChangeProof>, BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>>
+    {
+        let Some(before) = self.get_proof_at(before, key)? else {
+            return Ok(None);
+        };
+        let Some(after) = self.get_proof_at(after, key)? else {
+            return Ok(None);
+        };
+        Ok(Some(ChangeProof { before, after }))
+    }
+
+    /// Verifies that `key` had value `before` in the trie of root `root_before` and value `after`
+    /// in the trie of root `root_after`, `None` standing for an absent key.
+    pub fn verify_change_proof(
+        root_before: Felt,
+        root_after: Felt,
+        key: &BitSlice<u8, Msb0>,
+        before: Option<Felt>,
+        after: Option<Felt>,
+        proof: &ChangeProof,
+    ) -> bool {
+        proof::verify_change_proof::<H>(root_before, root_after, key, before, after, proof)
+    }
+
     /// Get a copy of the config that can be used to create a transactional state or a new bonsai storage.
     pub fn get_config(&self) -> BonsaiStorageConfig {
         self.trie.db_ref().get_config().into()
     }
 
+    /// Get the keys written with different values by several of the transactional states, which
+    /// [`merge_transactional_states`](Self::merge_transactional_states) would have to resolve.
+    pub fn find_conflicts(
+        &self,
+        states: &[BonsaiStorage<ChangeID, DB::Transaction, H>],
+    ) -> Result<
+        Vec<MergeConflict>,
+        BonsaiStorageError<<DB::Transaction as BonsaiDatabase>::DatabaseError>,
+    > {
+        let writes = states
+            .iter()
+            .map(|state| state.written_leaves())
+            .collect::<Result<_, _>>()?;
+        Ok(merge::resolve(writes, ConflictPolicy::Fail).1)
+    }
+
+    /// Merge several transactional states created at the same commit into the main trie, for
+    /// instance the states of transactions executed in parallel. Returns the conflicts, keys
+    /// written with different values by several states, resolved according to `policy`.
+    ///
+    /// The values written by all the states, committed or not, are applied to the first one and
+    /// committed under `id` before it is merged, see [`merge`](Self::merge). With
+    /// [`ConflictPolicy::Fail`], nothing is merged if there is any conflict.
+    pub fn merge_transactional_states(
+        &mut self,
+        states: Vec<BonsaiStorage<ChangeID, DB::Transaction, H>>,
+        id: ChangeID,
+        policy: ConflictPolicy,
+    ) -> Result<
+        Vec<MergeConflict>,
+        BonsaiStorageError<<DB::Transaction as BonsaiDatabase>::DatabaseError>,
+    > {
+        let Some(created_at) = states.first().map(|state| state.trie.db_ref().created_at) else {
+            return Ok(Vec::new());
+        };
+        if states
+            .iter()
+            .any(|state| state.trie.db_ref().created_at != created_at)
+        {
+            return Err(BonsaiStorageError::Merge(
+                "Transactional states were created at different commits".to_string(),
+            ));
+        }
+        let writes = states
+            .iter()
+            .map(|state| state.written_leaves())
+            .collect::<Result<_, _>>()?;
+        let (resolved, conflicts) = merge::resolve(writes, policy);
+        if policy == ConflictPolicy::Fail && !conflicts.is_empty() {
+            return Err(BonsaiStorageError::Merge(format!(
+                "{} keys were written with different values",
+                conflicts.len()
+            )));
+        }
+
+        // verified by the check of the created_at of the states
+        let mut merged = states.into_iter().next().unwrap();
+        for (key, value) in resolved {
+            merged.trie.set(&key, value.unwrap_or(Felt::ZERO))?;
+        }
+        merged.transactional_commit(id)?;
+        self.merge(merged)?;
+        Ok(conflicts)
+    }
+
     /// Merge a transactional state into the main trie.
     pub fn merge(
         &mut self,
@@ -426,3 +1844,82 @@ where
             .merge(transactional_bonsai_storage.trie.db())
     }
 }
+
+/// Read-only handle over a trie stored in a database.
+///
+/// No trie log is recorded and nothing is ever written to the database, which makes it suitable
+/// to serve reads from a database opened in read-only mode, like a
+/// [RocksDB secondary instance](crate::databases::RocksDBSecondary).
+pub struct ReadOnlyBonsaiStorage<ChangeID, DB, H>
+where
+    DB: BonsaiDatabase,
+    ChangeID: id::Id,
+    H: StarkHash,
+{
+    trie: MerkleTree<H, DB, ChangeID>,
+}
+
+impl<ChangeID, DB, H> ReadOnlyBonsaiStorage<ChangeID, DB, H>
+where
+    DB: BonsaiDatabase,
+    ChangeID: id::Id,
+    H: StarkHash,
+{
+    /// Create a new read-only bonsai storage instance, an empty database is read as an empty trie.
+    pub fn new(db: DB) -> Result<Self, BonsaiStorageError<DB::DatabaseError>> {
+        let key_value_db = KeyValueDB::new(db, KeyValueDBConfig::default(), None);
+        Ok(Self {
+            trie: MerkleTree::new_read_only(key_value_db)?,
+        })
+    }
+
+    /// Reload the root of the trie from the database, to see the commits made by the writer
+    /// since the creation of this instance or the last reload.
+    pub fn reload(self) -> Result<Self, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(Self {
+            trie: MerkleTree::new_read_only(self.trie.db())?,
+        })
+    }
+
+    /// Get a value in the trie.
+    pub fn get(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.get(key)
+    }
+
+    /// Checks if the key exists in the trie.
+    pub fn contains(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.contains(key)
+    }
+
+    /// Get trie root hash at the latest commit
+    pub fn root_hash(&self) -> Result<BonsaiTrieHash, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(self.trie.root_hash())
+    }
+
+    /// Generates a merkle-proof for a given `key`, see [`BonsaiStorage::get_proof`].
+    pub fn get_proof(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Vec<ProofNode>, BonsaiStorageError<DB::DatabaseError>> {
+        if self.trie.root_hash() == Felt::ZERO {
+            return Ok(Vec::new());
+        }
+        self.trie.get_proof(key)
+    }
+
+    /// Iterate over all the key/value pairs of the trie, in key order, see
+    /// [`BonsaiStorage::iter`].
+    #[allow(clippy::type_complexity)]
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = Result<(BitVec<u8, Msb0>, Felt), BonsaiStorageError<DB::DatabaseError>>> + '_
+    {
+        self.trie.iter()
+    }
+}
@@ -0,0 +1,56 @@
+#[cfg(not(feature = "std"))]
+use alloc::{collections::BTreeMap, vec::Vec};
+use bitvec::{order::Msb0, vec::BitVec};
+use starknet_types_core::felt::Felt;
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+/// Values written to the leaves by a transactional state, `None` for a removal.
+pub(crate) type Writes = BTreeMap<BitVec<u8, Msb0>, Option<Felt>>;
+
+/// How to merge a key written with different values by several transactional states, see
+/// [`BonsaiStorage::merge_transactional_states`](crate::BonsaiStorage::merge_transactional_states).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Merge nothing if there is any conflict
+    Fail,
+    /// Keep the value written by the first state
+    FirstWins,
+    /// Keep the value written by the last state
+    LastWins,
+}
+
+/// A key written with different values by several transactional states.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub key: BitVec<u8, Msb0>,
+    /// Index of each state writing the key along with the value it wrote, `None` for a removal
+    pub values: Vec<(usize, Option<Felt>)>,
+}
+
+/// Gathers the writes of the states, in order, and picks a value for each key according to the
+/// policy. Returns the conflicts sorted by key, `Fail` picks the value of the first state.
+pub(crate) fn resolve(states: Vec<Writes>, policy: ConflictPolicy) -> (Writes, Vec<MergeConflict>) {
+    let mut by_key: BTreeMap<BitVec<u8, Msb0>, Vec<(usize, Option<Felt>)>> = BTreeMap::new();
+    for (index, writes) in states.into_iter().enumerate() {
+        for (key, value) in writes {
+            by_key.entry(key).or_default().push((index, value));
+        }
+    }
+    let mut resolved = Writes::new();
+    let mut conflicts = Vec::new();
+    for (key, values) in by_key {
+        let picked = match policy {
+            ConflictPolicy::Fail | ConflictPolicy::FirstWins => values[0].1,
+            ConflictPolicy::LastWins => values[values.len() - 1].1,
+        };
+        if values.iter().any(|(_, value)| *value != values[0].1) {
+            conflicts.push(MergeConflict {
+                key: key.clone(),
+                values,
+            });
+        }
+        resolved.insert(key, picked);
+    }
+    (resolved, conflicts)
+}
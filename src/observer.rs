@@ -0,0 +1,76 @@
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, vec::Vec};
+use starknet_types_core::felt::Felt;
+
+use crate::{changes::ChangeBatch, trie::TrieKey};
+
+/// Hooks called at each step of [`BonsaiStorage::commit`](crate::BonsaiStorage::commit) and
+/// [`BonsaiStorage::transactional_commit`](crate::BonsaiStorage::transactional_commit), for
+/// instance to record metrics or to replicate the commits. See
+/// [`BonsaiStorage::add_commit_observer`](crate::BonsaiStorage::add_commit_observer).
+///
+/// A commit failing before it is written, or a [prepared](crate::BonsaiStorage::prepare) commit
+/// [aborted](crate::BonsaiStorage::abort), calls [`on_commit_aborted`](Self::on_commit_aborted)
+/// instead of the remaining hooks.
+pub trait CommitObserver<ChangeID>: Send + Sync {
+    /// Called when the commit `id` starts, before the trie is hashed.
+    fn on_commit_start(&mut self, _id: ChangeID) {}
+
+    /// Called once the trie is hashed, with the number of leaves and of trie nodes changed by the
+    /// commit, before its trie log is written.
+    fn on_changes_serialized(&mut self, _id: ChangeID, _leaves: usize, _nodes: usize) {}
+
+    /// Called once the commit is written to the database, with the new root hash of the trie.
+    fn on_flush_done(&mut self, _id: ChangeID, _root_hash: Felt) {}
+
+    /// Called when the commit `id` is given up after it started, nothing of it is written.
+    fn on_commit_aborted(&mut self, _id: ChangeID) {}
+}
+
+/// Observers registered on a storage.
+pub(crate) struct CommitObservers<ChangeID>(Vec<Box<dyn CommitObserver<ChangeID>>>);
+
+impl<ChangeID> Default for CommitObservers<ChangeID> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<ChangeID: Copy> CommitObservers<ChangeID> {
+    pub(crate) fn add(&mut self, observer: Box<dyn CommitObserver<ChangeID>>) {
+        self.0.push(observer);
+    }
+
+    pub(crate) fn commit_start(&mut self, id: ChangeID) {
+        for observer in self.0.iter_mut() {
+            observer.on_commit_start(id);
+        }
+    }
+
+    pub(crate) fn changes_serialized(&mut self, id: ChangeID, batch: &ChangeBatch) {
+        if self.0.is_empty() {
+            return;
+        }
+        let leaves = batch
+            .0
+            .keys()
+            .filter(|key| matches!(key, TrieKey::Flat(_)))
+            .count();
+        let nodes = batch.0.len() - leaves;
+        for observer in self.0.iter_mut() {
+            observer.on_changes_serialized(id, leaves, nodes);
+        }
+    }
+
+    pub(crate) fn flush_done(&mut self, id: ChangeID, root_hash: Felt) {
+        for observer in self.0.iter_mut() {
+            observer.on_flush_done(id, root_hash);
+        }
+    }
+
+    pub(crate) fn commit_aborted(&mut self, id: ChangeID) {
+        for observer in self.0.iter_mut() {
+            observer.on_commit_aborted(id);
+        }
+    }
+}
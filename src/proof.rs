@@ -0,0 +1,1035 @@
+//! Verification of the proofs generated by the trie.
+//!
+//! Verifying a proof only needs the root hash of the trie, the proven key and value and the nodes
+//! of the proof, the functions of this module don't need a [`BonsaiStorage`](crate::BonsaiStorage)
+//! or a database and work under `no_std`.
+//!
+//! # JSON
+//!
+//! The proof types implement `Serialize` and `Deserialize`, with felts as `0x` prefixed hex
+//! strings:
+//! - a binary node is `{"type": "binary", "left": felt, "right": felt}` and an edge node is
+//!   `{"type": "edge", "child": felt, "path": felt, "length": number}`, the path being the bits of
+//!   the edge read as a big endian number and the length its number of bits
+//! - a proof is a list of nodes and a [`MultiProof`] a list of proofs
+//! - the keys of a [`RangeProof`] and of a [`Witness`] are written like the paths of edges, as
+//!   `{"key": felt, "length": number}`, with an additional `"value"` for the leaves of a range
+//! - a [`Witness`] is `{"keys": [...], "values": [...], "proofs": [...]}` with `null` values for
+//!   absent keys
+#[cfg(not(feature = "std"))]
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use bitvec::{
+    prelude::{BitSlice, BitVec, Msb0},
+    view::BitView,
+};
+use core::cmp::Ordering;
+use hashbrown::{HashMap, HashSet};
+use parity_scale_codec::{Compact, Decode, Encode, Error, Input, Output};
+use serde::{Deserialize, Serialize};
+use starknet_types_core::{
+    felt::Felt,
+    hash::{Pedersen, Poseidon, StarkHash},
+};
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+use crate::trie::merkle_node::Direction;
+pub use crate::trie::path::Path;
+
+/// Membership of a key in the trie, as proven by a proof.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Membership {
+    Member,
+    NonMember,
+}
+
+/// A node used in proof generated by the trie.
+///
+/// See pathfinders merkle-tree crate for more information.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "ProofNodeJson", try_from = "ProofNodeJson")]
+pub enum ProofNode {
+    Binary { left: Felt, right: Felt },
+    Edge { child: Felt, path: Path },
+}
+
+impl ProofNode {
+    pub fn hash<H: StarkHash>(&self) -> Felt {
+        match self {
+            ProofNode::Binary { left, right } => H::hash(left, right),
+            ProofNode::Edge { child, path } => {
+                let length = Felt::from(path.0.len() as u8);
+                H::hash(child, &path_felt(&path.0)) + length
+            }
+        }
+    }
+
+    /// Hashes of the left and right children of a binary node.
+    pub fn binary(&self) -> Option<(Felt, Felt)> {
+        match self {
+            ProofNode::Binary { left, right } => Some((*left, *right)),
+            ProofNode::Edge { .. } => None,
+        }
+    }
+
+    /// Hash of the child and path of an edge node.
+    pub fn edge(&self) -> Option<(Felt, &BitSlice<u8, Msb0>)> {
+        match self {
+            ProofNode::Binary { .. } => None,
+            ProofNode::Edge { child, path } => Some((*child, &path.0)),
+        }
+    }
+
+    /// Number of bits of the key the node consumes, one for a binary node.
+    pub fn path_len(&self) -> usize {
+        match self {
+            ProofNode::Binary { .. } => 1,
+            ProofNode::Edge { path, .. } => path.0.len(),
+        }
+    }
+
+    /// The felts the hash of the node is computed from: `[left, right]` for a binary node and
+    /// `[child, path, length]` for an edge node, with the path read as a big endian number.
+    pub fn to_felts(&self) -> Vec<Felt> {
+        match self {
+            ProofNode::Binary { left, right } => vec![*left, *right],
+            ProofNode::Edge { child, path } => {
+                vec![*child, path_felt(&path.0), Felt::from(path.0.len() as u8)]
+            }
+        }
+    }
+
+    /// Node from the felts of [`ProofNode::to_felts`]. Returns `None` if they can't be the felts
+    /// of a node.
+    pub fn from_felts(felts: &[Felt]) -> Option<Self> {
+        match felts {
+            [left, right] => Some(ProofNode::Binary {
+                left: *left,
+                right: *right,
+            }),
+            [child, path, length] => {
+                let length = length.to_bytes_be();
+                let length = match length.split_last() {
+                    Some((length, high)) if high.iter().all(|b| *b == 0) => *length as usize,
+                    _ => return None,
+                };
+                Some(ProofNode::Edge {
+                    child: *child,
+                    path: Path(felt_bits(path, length)?),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Path of an edge read as a big endian number.
+fn path_felt(path: &BitSlice<u8, Msb0>) -> Felt {
+    let mut bytes = [0u8; 32];
+    bytes.view_bits_mut::<Msb0>()[256 - path.len()..].copy_from_bitslice(path);
+    // SAFETY: path len is <= 251
+    Felt::from_bytes_be(&bytes)
+}
+
+/// Path of `length` bits from its number, see [`path_felt`]. Returns `None` if it doesn't fit.
+fn felt_bits(felt: &Felt, length: usize) -> Option<BitVec<u8, Msb0>> {
+    if length > 251 {
+        return None;
+    }
+    let bytes = felt.to_bytes_be();
+    let (high, bits) = bytes.view_bits::<Msb0>().split_at(256 - length);
+    if high.any() {
+        return None;
+    }
+    Some(bits.to_bitvec())
+}
+
+/// JSON layout of a [`ProofNode`], see the [module](self) documentation.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ProofNodeJson {
+    Binary { left: Felt, right: Felt },
+    Edge { child: Felt, path: Felt, length: u8 },
+}
+
+impl From<ProofNode> for ProofNodeJson {
+    fn from(node: ProofNode) -> Self {
+        match node {
+            ProofNode::Binary { left, right } => ProofNodeJson::Binary { left, right },
+            ProofNode::Edge { child, path } => ProofNodeJson::Edge {
+                child,
+                path: path_felt(&path.0),
+                length: path.0.len() as u8,
+            },
+        }
+    }
+}
+
+impl TryFrom<ProofNodeJson> for ProofNode {
+    type Error = &'static str;
+
+    fn try_from(node: ProofNodeJson) -> Result<Self, Self::Error> {
+        match node {
+            ProofNodeJson::Binary { left, right } => Ok(ProofNode::Binary { left, right }),
+            ProofNodeJson::Edge {
+                child,
+                path,
+                length,
+            } => Ok(ProofNode::Edge {
+                child,
+                path: Path(felt_bits(&path, length as usize).ok_or("Invalid edge path")?),
+            }),
+        }
+    }
+}
+
+/// JSON layout of a key, and of the value of a leaf of a range.
+#[derive(Serialize, Deserialize)]
+struct KeyJson {
+    key: Felt,
+    length: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<Felt>,
+}
+
+impl KeyJson {
+    fn new(key: &BitSlice<u8, Msb0>, value: Option<Felt>) -> Self {
+        Self {
+            key: path_felt(key),
+            length: key.len() as u8,
+            value,
+        }
+    }
+
+    fn key(&self) -> Result<BitVec<u8, Msb0>, &'static str> {
+        felt_bits(&self.key, self.length as usize).ok_or("Invalid key")
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RangeProofJson {
+    start: Vec<ProofNode>,
+    end: Vec<ProofNode>,
+    leaves: Vec<KeyJson>,
+}
+
+impl From<RangeProof> for RangeProofJson {
+    fn from(proof: RangeProof) -> Self {
+        Self {
+            start: proof.start,
+            end: proof.end,
+            leaves: proof
+                .leaves
+                .iter()
+                .map(|(key, value)| KeyJson::new(key, Some(*value)))
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<RangeProofJson> for RangeProof {
+    type Error = &'static str;
+
+    fn try_from(proof: RangeProofJson) -> Result<Self, Self::Error> {
+        let leaves = proof
+            .leaves
+            .iter()
+            .map(|leaf| Ok((leaf.key()?, leaf.value.ok_or("Missing leaf value")?)))
+            .collect::<Result<_, Self::Error>>()?;
+        Ok(Self {
+            start: proof.start,
+            end: proof.end,
+            leaves,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WitnessJson {
+    keys: Vec<KeyJson>,
+    values: Vec<Option<Felt>>,
+    proofs: MultiProof,
+}
+
+impl From<Witness> for WitnessJson {
+    fn from(witness: Witness) -> Self {
+        Self {
+            keys: witness
+                .keys
+                .iter()
+                .map(|key| KeyJson::new(key, None))
+                .collect(),
+            values: witness.values,
+            proofs: witness.proofs,
+        }
+    }
+}
+
+impl TryFrom<WitnessJson> for Witness {
+    type Error = &'static str;
+
+    fn try_from(witness: WitnessJson) -> Result<Self, Self::Error> {
+        Ok(Self {
+            keys: witness
+                .keys
+                .iter()
+                .map(KeyJson::key)
+                .collect::<Result<_, _>>()?,
+            values: witness.values,
+            proofs: witness.proofs,
+        })
+    }
+}
+
+/// Proof of all the leaves of the trie with keys between two keys, bounds included.
+///
+/// See [`BonsaiStorage::get_range_proof`](crate::BonsaiStorage::get_range_proof) and
+/// [`verify_range_proof`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "RangeProofJson", try_from = "RangeProofJson")]
+pub struct RangeProof {
+    /// Proof of the first key of the range, or of its absence
+    pub start: Vec<ProofNode>,
+    /// Proof of the last key of the range, or of its absence
+    pub end: Vec<ProofNode>,
+    /// The leaves with keys in the range, sorted by key
+    pub leaves: Vec<(BitVec<u8, Msb0>, Felt)>,
+}
+
+/// Values of keys of the trie before changes, along with their proofs.
+///
+/// Built by a [`WitnessRecorder`](crate::WitnessRecorder) while executing a block, it holds
+/// everything a stateless verifier needs to know the values the block read or overwrote, see
+/// [`Witness::verify`].
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(into = "WitnessJson", try_from = "WitnessJson")]
+pub struct Witness {
+    /// The keys touched, in the order they were first touched
+    pub keys: Vec<BitVec<u8, Msb0>>,
+    /// The value of each key, `None` if it was absent
+    pub values: Vec<Option<Felt>>,
+    /// The proof of each key
+    pub proofs: MultiProof,
+}
+
+impl Witness {
+    /// Checks the proofs of all the keys against the root of the trie before the changes and
+    /// returns the value of each key. Returns `None` if a proof is invalid.
+    #[allow(clippy::type_complexity)]
+    pub fn verify<H: StarkHash>(
+        &self,
+        root: Felt,
+    ) -> Option<BTreeMap<BitVec<u8, Msb0>, Option<Felt>>> {
+        if self.keys.len() != self.values.len() || self.keys.len() != self.proofs.0.len() {
+            return None;
+        }
+        let mut values = BTreeMap::new();
+        for ((key, value), proof) in self.keys.iter().zip(&self.values).zip(&self.proofs.0) {
+            if !verify_value::<H>(root, key, *value, proof) {
+                return None;
+            }
+            values.insert(key.clone(), *value);
+        }
+        Some(values)
+    }
+}
+
+/// Proofs of the values of a key at two commits, see
+/// [`BonsaiStorage::get_change_proof`](crate::BonsaiStorage::get_change_proof).
+///
+/// The nodes of the path that didn't change between the commits are in both proofs, they are only
+/// encoded once as a [`MultiProof`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeProof {
+    /// Proof of the value at the first commit
+    pub before: Vec<ProofNode>,
+    /// Proof of the value at the second commit
+    pub after: Vec<ProofNode>,
+}
+
+impl From<ChangeProof> for MultiProof {
+    fn from(proof: ChangeProof) -> Self {
+        MultiProof(vec![proof.before, proof.after])
+    }
+}
+
+/// Verifies that the key `key` had value `before` in the MPT that has root `root_before` and value
+/// `after` in the MPT that has root `root_after`, `None` standing for an absent key.
+pub fn verify_change_proof<H: StarkHash>(
+    root_before: Felt,
+    root_after: Felt,
+    key: &BitSlice<u8, Msb0>,
+    before: Option<Felt>,
+    after: Option<Felt>,
+    proof: &ChangeProof,
+) -> bool {
+    verify_value::<H>(root_before, key, before, &proof.before)
+        && verify_value::<H>(root_after, key, after, &proof.after)
+}
+
+/// Proof of a storage slot of a contract against the global state root of Starknet.
+///
+/// Each storage trie is hashed in the leaf of its contract in the contracts trie, the proof links
+/// both: the proof of the contract in the contracts trie, the fields hashed with the root of the
+/// storage trie in the contract leaf, and the proof of the slot in the storage trie. Both tries use
+/// Pedersen hashes and are keyed by the 251 bits of the address and of the storage key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractStorageProof {
+    /// Proof of the contract leaf in the contracts trie
+    pub contract_proof: Vec<ProofNode>,
+    /// Class hash of the contract
+    pub class_hash: Felt,
+    /// Nonce of the contract
+    pub nonce: Felt,
+    /// Root of the storage trie of the contract
+    pub storage_root: Felt,
+    /// Proof of the storage slot in the storage trie of the contract
+    pub storage_proof: Vec<ProofNode>,
+}
+
+/// Key of a felt in the contracts and storage tries, its 251 bits.
+pub fn felt_key(felt: &Felt) -> BitVec<u8, Msb0> {
+    felt.to_bytes_be().view_bits::<Msb0>()[5..].to_bitvec()
+}
+
+/// Value of the leaf of a contract in the contracts trie.
+pub fn contract_state_hash(class_hash: Felt, storage_root: Felt, nonce: Felt) -> Felt {
+    const CONTRACT_STATE_HASH_VERSION: Felt = Felt::ZERO;
+    let hash = Pedersen::hash(&class_hash, &storage_root);
+    let hash = Pedersen::hash(&hash, &nonce);
+    Pedersen::hash(&hash, &CONTRACT_STATE_HASH_VERSION)
+}
+
+/// Global state root of Starknet from the roots of the contracts and classes tries.
+pub fn global_state_root(contracts_root: Felt, classes_root: Felt) -> Felt {
+    // Before the classes trie existed, the global root was the root of the contracts trie
+    if classes_root == Felt::ZERO {
+        return contracts_root;
+    }
+    let mut prefix = [0u8; 32];
+    prefix[32 - b"STARKNET_STATE_V0".len()..].copy_from_slice(b"STARKNET_STATE_V0");
+    Poseidon::hash_array(&[Felt::from_bytes_be(&prefix), contracts_root, classes_root])
+}
+
+/// Verifies that the slot `storage_key` of the contract at `contract_address` holds `value`, or
+/// is empty for `None`, in the state whose global root is `global_root`. The root of the classes
+/// trie is needed to link the contracts trie to the global root.
+pub fn verify_contract_storage_proof(
+    global_root: Felt,
+    classes_root: Felt,
+    contract_address: Felt,
+    storage_key: Felt,
+    value: Option<Felt>,
+    proof: &ContractStorageProof,
+) -> bool {
+    let contracts_root = match &proof.contract_proof[..] {
+        // The root of the contracts trie is the hash of the first node of the proof
+        [root, ..] => root.hash::<Pedersen>(),
+        [] => Felt::ZERO,
+    };
+    if global_state_root(contracts_root, classes_root) != global_root {
+        return false;
+    }
+    let contract_hash = contract_state_hash(proof.class_hash, proof.storage_root, proof.nonce);
+    verify_value::<Pedersen>(
+        contracts_root,
+        &felt_key(&contract_address),
+        Some(contract_hash),
+        &proof.contract_proof,
+    ) && verify_value::<Pedersen>(
+        proof.storage_root,
+        &felt_key(&storage_key),
+        value,
+        &proof.storage_proof,
+    )
+}
+
+/// Verifies the membership of `key` with `value`, or its absence for `None`.
+fn verify_value<H: StarkHash>(
+    root: Felt,
+    key: &BitSlice<u8, Msb0>,
+    value: Option<Felt>,
+    proofs: &[ProofNode],
+) -> bool {
+    match value {
+        Some(value) => verify_proof::<H>(root, key, value, proofs) == Some(Membership::Member),
+        None => verify_non_membership::<H>(root, key, proofs),
+    }
+}
+
+/// Verifies that the key `key` with value `value` is indeed part of the MPT that has root
+/// `root`, given `proofs`.
+/// Supports proofs of non-membership as well as proof of membership: this function returns
+/// an enum corresponding to the membership of `value`, or returns `None` if the proof is invalid,
+/// see [`try_verify_proof`] for the reason.
+pub fn verify_proof<H: StarkHash>(
+    root: Felt,
+    key: &BitSlice<u8, Msb0>,
+    value: Felt,
+    proofs: &[ProofNode],
+) -> Option<Membership> {
+    try_verify_proof::<H>(root, key, value, proofs).ok()
+}
+
+/// Value of `key` according to the nodes of `proofs`, `None` if they don't lead to the leaf of
+/// `key`. The proof isn't verified, see [`verify_proof`].
+pub fn proven_value(key: &BitSlice<u8, Msb0>, proofs: &[ProofNode]) -> Option<Felt> {
+    let mut hash = None;
+    let mut remaining_path = key;
+    for proof_node in proofs {
+        match proof_node {
+            ProofNode::Binary { left, right } => {
+                let (bit, rest) = remaining_path.split_first()?;
+                hash = Some(if *bit { *right } else { *left });
+                remaining_path = rest;
+            }
+            ProofNode::Edge { child, path } => {
+                if !remaining_path.starts_with(path.0.as_bitslice()) {
+                    return None;
+                }
+                hash = Some(*child);
+                remaining_path = &remaining_path[path.0.len()..];
+            }
+        }
+    }
+    // The hash of a leaf is its value
+    hash.filter(|_| remaining_path.is_empty())
+}
+
+/// Reason a proof is rejected by [`try_verify_proof`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofError {
+    /// The key is longer than 251 bits
+    KeyTooLong,
+    /// The proof has more nodes than the key has bits
+    TooManyNodes,
+    /// The edge node at this index has an empty path or a path longer than a key
+    InvalidEdge(usize),
+    /// The node at this index doesn't have the hash expected by its parent, or by the root
+    HashMismatch(usize),
+    /// The node at this index goes further than the key
+    PathExhausted(usize),
+    /// The proof has nodes after the one at this index, which proves the absence of the key
+    TrailingNodes(usize),
+    /// The proof ends before reaching a leaf or proving the absence of the key
+    Incomplete,
+    /// The leaf reached by the proof doesn't have the expected value
+    ValueMismatch,
+}
+
+impl core::fmt::Display for ProofError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ProofError::KeyTooLong => write!(f, "Key is longer than 251 bits"),
+            ProofError::TooManyNodes => write!(f, "Proof has more nodes than the key has bits"),
+            ProofError::InvalidEdge(index) => write!(f, "Invalid edge path at node {}", index),
+            ProofError::HashMismatch(index) => write!(f, "Hash mismatch at node {}", index),
+            ProofError::PathExhausted(index) => {
+                write!(f, "Node {} goes further than the key", index)
+            }
+            ProofError::TrailingNodes(index) => {
+                write!(
+                    f,
+                    "Unexpected nodes after the absence proof at node {}",
+                    index
+                )
+            }
+            ProofError::Incomplete => write!(f, "Proof ends before reaching a leaf"),
+            ProofError::ValueMismatch => write!(f, "Leaf doesn't have the expected value"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ProofError {}
+
+/// Same as [`verify_proof`] but tells why an invalid proof is rejected.
+///
+/// Proofs are checked before any node is hashed, so that a malicious proof costs at most one
+/// hash per bit of the key.
+///
+/// The algorithm follows this logic:
+/// 1. init expected_hash <- root hash
+/// 2. loop over nodes: current <- nodes[i]
+///    1. verify the current node's hash matches expected_hash (if not then we have a bad proof)
+///    2. move towards the target - if current is:
+///       1. binary node then choose the child that moves towards the target, else if
+///       2. edge node then check the path against the target bits
+///          1. If it matches then proceed with the child, else
+///          2. if it does not match then we now have a proof that the target does not exist
+///    3. nibble off target bits according to which child you got in (2). If all bits are gone then you
+///       have reached the target and the child hash is the value you wanted and the proof is complete.
+///    4. set expected_hash <- to the child hash
+/// 3. check that the expected_hash is `value` (we should've reached the leaf)
+pub fn try_verify_proof<H: StarkHash>(
+    root: Felt,
+    key: &BitSlice<u8, Msb0>,
+    value: Felt,
+    proofs: &[ProofNode],
+) -> Result<Membership, ProofError> {
+    // Protect from ill-formed keys
+    if key.len() > 251 {
+        return Err(ProofError::KeyTooLong);
+    }
+    // Each node consumes at least one bit of the key
+    if proofs.len() > key.len() {
+        return Err(ProofError::TooManyNodes);
+    }
+    for (index, proof_node) in proofs.iter().enumerate() {
+        if let ProofNode::Edge { path, .. } = proof_node {
+            if path.0.is_empty() || path.0.len() > 251 {
+                return Err(ProofError::InvalidEdge(index));
+            }
+        }
+    }
+
+    let mut expected_hash = root;
+    let mut remaining_path: &BitSlice<u8, Msb0> = key;
+
+    for (index, proof_node) in proofs.iter().enumerate() {
+        // A proof going further than the key is ill-formed.
+        if remaining_path.len() < proof_node.path_len() {
+            return Err(ProofError::PathExhausted(index));
+        }
+        if proof_node.hash::<H>() != expected_hash {
+            return Err(ProofError::HashMismatch(index));
+        }
+        match proof_node {
+            ProofNode::Binary { left, right } => {
+                // Direction will always correspond to the 0th index
+                // because we're removing bits on every iteration.
+                let direction = Direction::from(remaining_path[0]);
+
+                // Set the next hash to be the left or right hash,
+                // depending on the direction
+                expected_hash = match direction {
+                    Direction::Left => *left,
+                    Direction::Right => *right,
+                };
+
+                // Advance by a single bit
+                remaining_path = &remaining_path[1..];
+            }
+            ProofNode::Edge { child, path } => {
+                if path.0 != remaining_path[..path.0.len()] {
+                    // If paths don't match, we've found a proof of non membership because we:
+                    // 1. Correctly moved towards the target insofar as is possible, and
+                    // 2. hashing all the nodes along the path does result in the root hash, which means
+                    // 3. the target definitely does not exist in this tree
+                    if index + 1 != proofs.len() {
+                        return Err(ProofError::TrailingNodes(index));
+                    }
+                    return Ok(Membership::NonMember);
+                }
+
+                // Set the next hash to the child's hash
+                expected_hash = *child;
+
+                // Advance by the whole edge path
+                remaining_path = &remaining_path[path.0.len()..];
+            }
+        }
+    }
+
+    // At this point, we should reach `value` !
+    if !remaining_path.is_empty() {
+        Err(ProofError::Incomplete)
+    } else if expected_hash == value {
+        Ok(Membership::Member)
+    } else {
+        Err(ProofError::ValueMismatch)
+    }
+}
+
+/// Verifies many `(key, value, proof)` items against the same root on the threads of the rayon
+/// thread pool, see [`try_verify_proof`]. Returns the result of each item, in order.
+#[cfg(feature = "rayon")]
+pub fn verify_proofs_batch<H: StarkHash>(
+    root: Felt,
+    items: &[(&BitSlice<u8, Msb0>, Felt, &[ProofNode])],
+) -> Vec<Result<Membership, ProofError>> {
+    use rayon::prelude::*;
+    items
+        .par_iter()
+        .map(|(key, value, proof)| try_verify_proof::<H>(root, key, *value, proof))
+        .collect()
+}
+
+/// Verifies that the key `key` is not part of the MPT that has root `root`, given `proofs`.
+///
+/// An absent key is proven by the path from the root down to the edge node whose path diverges
+/// from the key, see
+/// [`BonsaiStorage::get_proof`](crate::BonsaiStorage::get_proof). An empty trie, with a root hash of zero,
+/// doesn't contain any key and needs no proof.
+pub fn verify_non_membership<H: StarkHash>(
+    root: Felt,
+    key: &BitSlice<u8, Msb0>,
+    proofs: &[ProofNode],
+) -> bool {
+    if root == Felt::ZERO {
+        return true;
+    }
+    // The value doesn't matter, the path must end before reaching a leaf
+    matches!(
+        verify_proof::<H>(root, key, Felt::ZERO, proofs),
+        Some(Membership::NonMember)
+    )
+}
+
+/// Verifies that the leaves of `proof` are all the leaves with keys between `start` and `end`
+/// in the MPT that has root `root`.
+///
+/// The parts of the trie outside the range are only known by the hashes given by the proofs of
+/// the bounds, the part inside the range is rebuilt from the leaves. Hashing all of it back to
+/// the root proves that no leaf of the range is missing from the proof.
+pub fn verify_range_proof<H: StarkHash>(
+    root: Felt,
+    start: &BitSlice<u8, Msb0>,
+    end: &BitSlice<u8, Msb0>,
+    proof: &RangeProof,
+) -> bool {
+    // Protect from ill-formed keys
+    if start.len() > 251 || start.len() != end.len() || start > end {
+        return false;
+    }
+    let leaves = &proof.leaves;
+    let in_range = |key: &BitVec<u8, Msb0>| {
+        key.len() == start.len() && key.as_bitslice() >= start && key.as_bitslice() <= end
+    };
+    if !leaves.iter().all(|(key, _)| in_range(key))
+        || leaves.windows(2).any(|pair| pair[0].0 >= pair[1].0)
+    {
+        return false;
+    }
+    if root == Felt::ZERO {
+        return leaves.is_empty();
+    }
+    range_hash::<H>(start, end, 0, Some(&proof.start), Some(&proof.end), leaves) == Some(root)
+}
+
+/// Verifies that there is no leaf with a key between `start` and `end` in the MPT that has root
+/// `root`, given the proofs of the bounds of the range and no leaves.
+pub fn verify_range_empty<H: StarkHash>(
+    root: Felt,
+    start: &BitSlice<u8, Msb0>,
+    end: &BitSlice<u8, Msb0>,
+    proof: &RangeProof,
+) -> bool {
+    proof.leaves.is_empty() && verify_range_proof::<H>(root, start, end, proof)
+}
+
+/// First and last keys of `key_len` bits starting with `prefix`, the bounds of the range proof of
+/// the subtree under `prefix`. Returns `None` if the prefix is longer than the keys.
+pub fn subtree_bounds(
+    prefix: &BitSlice<u8, Msb0>,
+    key_len: usize,
+) -> Option<(BitVec<u8, Msb0>, BitVec<u8, Msb0>)> {
+    if prefix.len() > key_len || key_len > 251 {
+        return None;
+    }
+    let mut start = prefix.to_bitvec();
+    let mut end = prefix.to_bitvec();
+    start.resize(key_len, false);
+    end.resize(key_len, true);
+    Some((start, end))
+}
+
+/// Verifies that the leaves of `proof` are all the leaves of `key_len` bits starting with
+/// `prefix` in the MPT that has root `root`, see [`subtree_bounds`] and [`verify_range_proof`].
+pub fn verify_subtree_proof<H: StarkHash>(
+    root: Felt,
+    prefix: &BitSlice<u8, Msb0>,
+    key_len: usize,
+    proof: &RangeProof,
+) -> bool {
+    match subtree_bounds(prefix, key_len) {
+        Some((start, end)) => verify_range_proof::<H>(root, &start, &end, proof),
+        None => false,
+    }
+}
+
+/// Hash of the node at `depth` along the paths of the bounds, given the remaining nodes of the
+/// proof of each bound still going through it (`None` once the node is past that bound) and
+/// the leaves below it.
+fn range_hash<H: StarkHash>(
+    start: &BitSlice<u8, Msb0>,
+    end: &BitSlice<u8, Msb0>,
+    depth: usize,
+    start_proof: Option<&[ProofNode]>,
+    end_proof: Option<&[ProofNode]>,
+    leaves: &[(BitVec<u8, Msb0>, Felt)],
+) -> Option<Felt> {
+    let node = match (start_proof, end_proof) {
+        // The whole subtree is in the range
+        (None, None) => return leaves_hash::<H>(depth, leaves),
+        (Some(start_proof), Some(end_proof)) => {
+            let node = start_proof.first()?;
+            // Both bounds go through the same nodes until their paths split
+            if end_proof.first()? != node {
+                return None;
+            }
+            node
+        }
+        (Some(proof), None) | (None, Some(proof)) => proof.first()?,
+    };
+    let start_rest = start_proof.map(|proof| &proof[1..]);
+    let end_rest = end_proof.map(|proof| &proof[1..]);
+    match node {
+        ProofNode::Binary { left, right } => {
+            if depth >= start.len() {
+                return None;
+            }
+            let split = leaves.partition_point(|(key, _)| !key[depth]);
+            let (left_leaves, right_leaves) = leaves.split_at(split);
+            let start_direction = start_proof.map(|_| Direction::from(start[depth]));
+            let end_direction = end_proof.map(|_| Direction::from(end[depth]));
+            // A child before the start or after the end is outside the range, only its hash is
+            // known
+            let left_hash = if start_direction == Some(Direction::Right) {
+                if !left_leaves.is_empty() {
+                    return None;
+                }
+                *left
+            } else {
+                let end_rest = end_rest.filter(|_| end_direction == Some(Direction::Left));
+                range_hash::<H>(start, end, depth + 1, start_rest, end_rest, left_leaves)?
+            };
+            let right_hash = if end_direction == Some(Direction::Left) {
+                if !right_leaves.is_empty() {
+                    return None;
+                }
+                *right
+            } else {
+                let start_rest = start_rest.filter(|_| start_direction == Some(Direction::Right));
+                range_hash::<H>(start, end, depth + 1, start_rest, end_rest, right_leaves)?
+            };
+            Some(H::hash(&left_hash, &right_hash))
+        }
+        ProofNode::Edge { path, .. } => {
+            let child_depth = depth + path.0.len();
+            if child_depth > start.len() {
+                return None;
+            }
+            let start_order =
+                start_proof.map(|_| path.0.as_bitslice().cmp(&start[depth..child_depth]));
+            let end_order = end_proof.map(|_| path.0.as_bitslice().cmp(&end[depth..child_depth]));
+            if start_order == Some(Ordering::Less) || end_order == Some(Ordering::Greater) {
+                // The edge leads outside the range
+                if !leaves.is_empty() {
+                    return None;
+                }
+                return Some(node.hash::<H>());
+            }
+            if leaves
+                .iter()
+                .any(|(key, _)| key[depth..child_depth] != path.0[..])
+            {
+                return None;
+            }
+            let start_rest = start_rest.filter(|_| start_order == Some(Ordering::Equal));
+            let end_rest = end_rest.filter(|_| end_order == Some(Ordering::Equal));
+            let child = if child_depth == start.len() {
+                // The edge leads to a leaf, which is in the range
+                match leaves {
+                    [(_, value)] => *value,
+                    _ => return None,
+                }
+            } else {
+                range_hash::<H>(start, end, child_depth, start_rest, end_rest, leaves)?
+            };
+            Some(
+                ProofNode::Edge {
+                    child,
+                    path: path.clone(),
+                }
+                .hash::<H>(),
+            )
+        }
+    }
+}
+
+/// Hash of the subtree at `depth` holding exactly the given sorted leaves.
+fn leaves_hash<H: StarkHash>(depth: usize, leaves: &[(BitVec<u8, Msb0>, Felt)]) -> Option<Felt> {
+    let ((first, value), (last, _)) = (leaves.first()?, leaves.last()?);
+    if depth == first.len() {
+        return (leaves.len() == 1).then_some(*value);
+    }
+    // The leaves are sorted, the first and the last ones have the shortest common path
+    let common = first[depth..]
+        .iter()
+        .by_vals()
+        .zip(last[depth..].iter().by_vals())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common > 0 {
+        let child = leaves_hash::<H>(depth + common, leaves)?;
+        let path = Path(first[depth..depth + common].to_bitvec());
+        return Some(ProofNode::Edge { child, path }.hash::<H>());
+    }
+    let split = leaves.partition_point(|(key, _)| !key[depth]);
+    let left = leaves_hash::<H>(depth + 1, &leaves[..split])?;
+    let right = leaves_hash::<H>(depth + 1, &leaves[split..])?;
+    Some(H::hash(&left, &right))
+}
+
+/// Proofs of several keys, usually of the same trie, with their canonical binary encoding.
+///
+/// The encoding lists each distinct node once, in the order of their first appearance in the
+/// proofs, then each proof as the indexes of its nodes:
+/// - the number of nodes, as a SCALE compact integer
+/// - each node: `0` followed by the hashes of its children for a binary node, or `1` followed by
+///   its path and the hash of its child for an edge node. Hashes are 32 bytes big endian and a path
+///   is its length in bits as a byte followed by its bits packed most significant bit first.
+/// - the number of proofs, then each proof as its number of nodes followed by their indexes, all
+///   as SCALE compact integers
+///
+/// Decoding rejects anything that isn't the encoding of its own result, so that the same proofs
+/// always have the same bytes. Use [`parity_scale_codec::DecodeAll`] to also reject trailing
+/// bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MultiProof(pub Vec<Vec<ProofNode>>);
+
+/// Size of a proof, to bound the size of responses before encoding them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProofSize {
+    /// Number of nodes
+    pub nodes: usize,
+    /// Number of bytes of the nodes in the encoding of a [`MultiProof`], without the indexes
+    pub bytes: usize,
+}
+
+impl ProofSize {
+    /// Size of the nodes of `proof`.
+    pub fn of(proof: &[ProofNode]) -> Self {
+        Self {
+            nodes: proof.len(),
+            bytes: proof.iter().map(encoded_node_len).sum(),
+        }
+    }
+}
+
+/// Number of bytes of a node in the encoding of a [`MultiProof`].
+fn encoded_node_len(node: &ProofNode) -> usize {
+    match node {
+        ProofNode::Binary { .. } => 1 + 32 + 32,
+        ProofNode::Edge { path, .. } => 1 + 1 + (path.0.len() + 7) / 8 + 32,
+    }
+}
+
+impl Encode for MultiProof {
+    fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+        let mut indexes = HashMap::new();
+        let mut nodes = Vec::new();
+        let mut proofs = Vec::with_capacity(self.0.len());
+        for proof in self.0.iter() {
+            let mut proof_indexes = Vec::with_capacity(proof.len());
+            for node in proof.iter() {
+                let index = *indexes.entry(node).or_insert_with(|| {
+                    nodes.push(node);
+                    nodes.len() as u32 - 1
+                });
+                proof_indexes.push(index);
+            }
+            proofs.push(proof_indexes);
+        }
+
+        Compact(nodes.len() as u32).encode_to(dest);
+        for node in nodes {
+            match node {
+                ProofNode::Binary { left, right } => {
+                    dest.push_byte(0);
+                    dest.write(&left.to_bytes_be());
+                    dest.write(&right.to_bytes_be());
+                }
+                ProofNode::Edge { child, path } => {
+                    dest.push_byte(1);
+                    path.encode_to(dest);
+                    dest.write(&child.to_bytes_be());
+                }
+            }
+        }
+        Compact(proofs.len() as u32).encode_to(dest);
+        for proof in proofs {
+            Compact(proof.len() as u32).encode_to(dest);
+            for index in proof {
+                Compact(index).encode_to(dest);
+            }
+        }
+    }
+}
+
+impl Decode for MultiProof {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+        let nodes_len = Compact::<u32>::decode(input)?.0;
+        let mut nodes = Vec::new();
+        let mut seen = HashSet::new();
+        for _ in 0..nodes_len {
+            let node = match input.read_byte()? {
+                0 => ProofNode::Binary {
+                    left: decode_felt(input)?,
+                    right: decode_felt(input)?,
+                },
+                1 => ProofNode::Edge {
+                    path: decode_path(input)?,
+                    child: decode_felt(input)?,
+                },
+                _ => return Err("Invalid proof node kind".into()),
+            };
+            if !seen.insert(node.clone()) {
+                return Err("Duplicated proof node".into());
+            }
+            nodes.push(node);
+        }
+
+        let proofs_len = Compact::<u32>::decode(input)?.0;
+        let mut proofs = Vec::new();
+        // Nodes must be listed in the order of their first use
+        let mut next_index = 0;
+        for _ in 0..proofs_len {
+            let proof_len = Compact::<u32>::decode(input)?.0;
+            let mut proof = Vec::new();
+            for _ in 0..proof_len {
+                let index = Compact::<u32>::decode(input)?.0;
+                if index > next_index || index >= nodes_len {
+                    return Err("Invalid proof node index".into());
+                }
+                if index == next_index {
+                    next_index += 1;
+                }
+                proof.push(nodes[index as usize].clone());
+            }
+            proofs.push(proof);
+        }
+        if next_index != nodes_len {
+            return Err("Unused proof node".into());
+        }
+        Ok(MultiProof(proofs))
+    }
+}
+
+fn decode_felt<I: Input>(input: &mut I) -> Result<Felt, Error> {
+    let mut bytes = [0u8; 32];
+    input.read(&mut bytes)?;
+    let felt = Felt::from_bytes_be(&bytes);
+    if felt.to_bytes_be() != bytes {
+        return Err("Felt out of range".into());
+    }
+    Ok(felt)
+}
+
+fn decode_path<I: Input>(input: &mut I) -> Result<Path, Error> {
+    let len = input.read_byte()? as usize;
+    if len > 251 {
+        return Err("Path too long".into());
+    }
+    let mut bytes = vec![0u8; (len + 7) / 8];
+    input.read(&mut bytes)?;
+    let mut bits = BitVec::<u8, Msb0>::from_vec(bytes);
+    if bits[len..].any() {
+        return Err("Non-zero path padding".into());
+    }
+    bits.truncate(len);
+    Ok(Path(bits))
+}
@@ -0,0 +1,177 @@
+//! Conversion of proofs to and from the format of the `starknet_getStorageProof` JSON-RPC method
+//! (spec 0.8).
+//!
+//! The RPC returns, for each trie, the nodes of the proofs of all the requested keys as a list of
+//! nodes along with their hashes, in no particular order. [`proof_to_rpc`] builds such a list from
+//! proofs generated by the trie and [`proof_from_rpc`] finds back the proof of one key in it.
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use bitvec::{order::Msb0, slice::BitSlice, vec::BitVec, view::BitView};
+use hashbrown::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+
+use crate::proof::{Path, ProofNode};
+
+/// A node of a proof, `MERKLE_NODE` in the RPC spec.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MerkleNode {
+    /// `BINARY_NODE`, hashes of the two children
+    Binary { left: Felt, right: Felt },
+    /// `EDGE_NODE`, path of the edge as a number of `length` bits and hash of the child
+    Edge { path: Felt, length: u8, child: Felt },
+}
+
+/// An entry of `NODE_HASH_TO_NODE_MAPPING` in the RPC spec.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeHashToNode {
+    pub node_hash: Felt,
+    pub node: MerkleNode,
+}
+
+impl From<&ProofNode> for MerkleNode {
+    fn from(node: &ProofNode) -> Self {
+        match node {
+            ProofNode::Binary { left, right } => MerkleNode::Binary {
+                left: *left,
+                right: *right,
+            },
+            ProofNode::Edge { child, path } => {
+                let mut bytes = [0u8; 32];
+                bytes.view_bits_mut::<Msb0>()[256 - path.0.len()..].copy_from_bitslice(&path.0);
+                MerkleNode::Edge {
+                    path: Felt::from_bytes_be(&bytes),
+                    length: path.0.len() as u8,
+                    child: *child,
+                }
+            }
+        }
+    }
+}
+
+impl TryFrom<&MerkleNode> for ProofNode {
+    type Error = InvalidMerkleNode;
+
+    /// Fails on edges longer than a key or with a path that doesn't fit in their length.
+    fn try_from(node: &MerkleNode) -> Result<Self, Self::Error> {
+        match node {
+            MerkleNode::Binary { left, right } => Ok(ProofNode::Binary {
+                left: *left,
+                right: *right,
+            }),
+            MerkleNode::Edge {
+                path,
+                length,
+                child,
+            } => {
+                if *length > 251 {
+                    return Err(InvalidMerkleNode);
+                }
+                let bytes = path.to_bytes_be();
+                let bits = bytes.view_bits::<Msb0>();
+                let (high, path) = bits.split_at(256 - *length as usize);
+                if high.any() {
+                    return Err(InvalidMerkleNode);
+                }
+                Ok(ProofNode::Edge {
+                    child: *child,
+                    path: Path(path.to_bitvec()),
+                })
+            }
+        }
+    }
+}
+
+/// Error returned when a node received from the RPC can't be a node of the trie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidMerkleNode;
+
+impl core::fmt::Display for InvalidMerkleNode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Invalid merkle node")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidMerkleNode {}
+
+/// Converts proofs, for instance the proofs of several keys of the same trie, to the list of their
+/// nodes along with their hashes. Nodes shared by several proofs are only listed once.
+pub fn proof_to_rpc<'a, H: StarkHash>(
+    proofs: impl IntoIterator<Item = &'a [ProofNode]>,
+) -> Vec<NodeHashToNode> {
+    let mut seen = HashSet::new();
+    let mut nodes = Vec::new();
+    for node in proofs.into_iter().flatten() {
+        let node_hash = node.hash::<H>();
+        if seen.insert(node_hash) {
+            nodes.push(NodeHashToNode {
+                node_hash,
+                node: node.into(),
+            });
+        }
+    }
+    nodes
+}
+
+/// Finds back the proof of `key` in a list of nodes received from the RPC, starting from the
+/// node with hash `root`.
+///
+/// Returns `None` if a node of the path is missing, invalid, or listed with a wrong hash. The
+/// returned proof still needs to be verified, it is built with the same rules as
+/// [`BonsaiStorage::get_proof`](crate::BonsaiStorage::get_proof) so a proof of absence ends at the
+/// edge diverging from the key.
+pub fn proof_from_rpc<H: StarkHash>(
+    root: Felt,
+    key: &BitSlice<u8, Msb0>,
+    nodes: &[NodeHashToNode],
+) -> Option<Vec<ProofNode>> {
+    let mut by_hash = HashMap::new();
+    for entry in nodes {
+        let node = ProofNode::try_from(&entry.node).ok()?;
+        if node.hash::<H>() != entry.node_hash {
+            return None;
+        }
+        by_hash.insert(entry.node_hash, node);
+    }
+
+    let mut proof = Vec::new();
+    // The proof of an empty trie is empty
+    if root == Felt::ZERO {
+        return Some(proof);
+    }
+    let mut hash = root;
+    let mut remaining: &BitSlice<u8, Msb0> = key;
+    while !remaining.is_empty() {
+        let node = by_hash.get(&hash)?.clone();
+        match &node {
+            ProofNode::Binary { left, right } => {
+                hash = if remaining[0] { *right } else { *left };
+                remaining = &remaining[1..];
+            }
+            ProofNode::Edge { child, path } => {
+                if remaining.get(..path.0.len())? != path.0.as_bitslice() {
+                    proof.push(node);
+                    return Some(proof);
+                }
+                hash = *child;
+                remaining = &remaining[path.0.len()..];
+            }
+        }
+        proof.push(node);
+    }
+    Some(proof)
+}
+
+/// Path of a key as the RPC numbers it, the bits of the key read as a big endian number.
+pub fn key_to_felt(key: &BitSlice<u8, Msb0>) -> Felt {
+    let mut bytes = [0u8; 32];
+    bytes.view_bits_mut::<Msb0>()[256 - key.len()..].copy_from_bitslice(key);
+    Felt::from_bytes_be(&bytes)
+}
+
+/// Key of `length` bits, at most 251, from its number in the RPC, see [`key_to_felt`].
+pub fn felt_to_key(felt: &Felt, length: usize) -> BitVec<u8, Msb0> {
+    felt.to_bytes_be().view_bits::<Msb0>()[256 - length..].to_bitvec()
+}
@@ -0,0 +1,68 @@
+//! Changes of the key/value pairs of the trie between two commits, see
+//! [`BonsaiStorage::diff`](crate::BonsaiStorage::diff).
+//!
+//! # JSON
+//!
+//! A [`StateDiff`] implements `Serialize` and `Deserialize` as `{"changes": [...]}`, each change
+//! being `{"key": felt, "length": number, "old_value": felt, "new_value": felt}` with the key
+//! written as in [`crate::proof`] and `null` values for absent keys.
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use bitvec::{order::Msb0, vec::BitVec};
+use serde::{Deserialize, Serialize};
+use starknet_types_core::felt::Felt;
+
+use crate::rpc::{felt_to_key, key_to_felt};
+
+/// The key/value pairs that differ between two commits.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateDiff {
+    /// Changes sorted by key
+    pub changes: Vec<KeyChange>,
+}
+
+/// Change of the value of a key between two commits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "KeyChangeJson", try_from = "KeyChangeJson")]
+pub struct KeyChange {
+    pub key: BitVec<u8, Msb0>,
+    /// Value at the first commit, `None` if the key was absent
+    pub old_value: Option<Felt>,
+    /// Value at the second commit, `None` if the key was removed
+    pub new_value: Option<Felt>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyChangeJson {
+    key: Felt,
+    length: u8,
+    old_value: Option<Felt>,
+    new_value: Option<Felt>,
+}
+
+impl From<KeyChange> for KeyChangeJson {
+    fn from(change: KeyChange) -> Self {
+        Self {
+            key: key_to_felt(&change.key),
+            length: change.key.len() as u8,
+            old_value: change.old_value,
+            new_value: change.new_value,
+        }
+    }
+}
+
+impl TryFrom<KeyChangeJson> for KeyChange {
+    type Error = &'static str;
+
+    fn try_from(change: KeyChangeJson) -> Result<Self, Self::Error> {
+        let key = felt_to_key(&change.key, change.length.min(251) as usize);
+        if change.length > 251 || key_to_felt(&key) != change.key {
+            return Err("Invalid key");
+        }
+        Ok(Self {
+            key,
+            old_value: change.old_value,
+            new_value: change.new_value,
+        })
+    }
+}
@@ -0,0 +1,73 @@
+#![cfg(all(feature = "std", feature = "rocksdb"))]
+use crate::{
+    databases::{create_rocks_db, RocksDB, RocksDBConfig},
+    id::{BasicId, BasicIdBuilder},
+    AsyncBonsaiStorage, BonsaiStorage, BonsaiStorageConfig,
+};
+use bitvec::vec::BitVec;
+use core::{
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+/// Polls the future until completion, the synchronous databases resolve immediately.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut context = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+#[test]
+fn async_reads_match_sync_reads() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let pairs = vec![
+        (
+            BitVec::from_vec(vec![1, 2, 1]),
+            Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap(),
+        ),
+        (
+            BitVec::from_vec(vec![1, 2, 2]),
+            Felt::from_hex("0x66342762FD54D033c195fec3ce2568b62052e").unwrap(),
+        ),
+        (
+            BitVec::from_vec(vec![2, 2, 2]),
+            Felt::from_hex("0x66342762FD54D033c195fec3ce2568b62052f").unwrap(),
+        ),
+    ];
+    for (key, value) in pairs.iter() {
+        bonsai_storage.insert(key, value).unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    let async_storage: AsyncBonsaiStorage<_, Pedersen> = block_on(AsyncBonsaiStorage::new(
+        RocksDB::<BasicId>::new(&db, RocksDBConfig::default()),
+    ))
+    .unwrap();
+    assert_eq!(
+        async_storage.root_hash().unwrap(),
+        bonsai_storage.root_hash().unwrap()
+    );
+    for (key, value) in pairs.iter() {
+        assert_eq!(block_on(async_storage.get(key)).unwrap(), Some(*value));
+        assert_eq!(
+            block_on(async_storage.get_proof(key)).unwrap(),
+            bonsai_storage.get_proof(key).unwrap()
+        );
+    }
+    assert_eq!(
+        block_on(async_storage.iter()).unwrap().collect::<Vec<_>>(),
+        pairs
+    );
+}
@@ -0,0 +1,678 @@
+#![cfg(feature = "std")]
+//! Test suite shared by all the database backends.
+use crate::{
+    databases::HashMapDb,
+    id::{BasicId, BasicIdBuilder},
+    BonsaiDatabase, BonsaiPersistentDatabase, BonsaiStorage, BonsaiStorageConfig,
+};
+use bitvec::vec::BitVec;
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+/// Inserts, commits, reverts and reads a transactional state on `db`.
+fn backend_suite<DB>(db: DB)
+where
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<BasicId>,
+{
+    let config = BonsaiStorageConfig {
+        snapshot_interval: 1,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(db, config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let pair1 = (
+        BitVec::from_vec(vec![1, 2, 1]),
+        Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap(),
+    );
+    let pair2 = (
+        BitVec::from_vec(vec![1, 2, 2]),
+        Felt::from_hex("0x66342762FD54D033c195fec3ce2568b62052e").unwrap(),
+    );
+
+    bonsai_storage.insert(&pair1.0, &pair1.1).unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    let root_hash1 = bonsai_storage.root_hash().unwrap();
+
+    bonsai_storage.insert(&pair2.0, &pair2.1).unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.commit(id2).unwrap();
+    assert_eq!(bonsai_storage.get(&pair2.0).unwrap(), Some(pair2.1));
+    assert!(bonsai_storage.contains(&pair1.0).unwrap());
+
+    let bonsai_at_txn: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+        .get_transactional_state(id1, bonsai_storage.get_config())
+        .unwrap()
+        .unwrap();
+    assert_eq!(bonsai_at_txn.get(&pair1.0).unwrap(), Some(pair1.1));
+    assert_eq!(bonsai_at_txn.get(&pair2.0).unwrap(), None);
+    assert_eq!(bonsai_at_txn.root_hash().unwrap(), root_hash1);
+
+    bonsai_storage.remove(&pair1.0).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.get(&pair1.0).unwrap(), None);
+
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), root_hash1);
+    assert_eq!(bonsai_storage.get(&pair1.0).unwrap(), Some(pair1.1));
+    assert_eq!(bonsai_storage.get(&pair2.0).unwrap(), None);
+    assert_eq!(bonsai_storage.iter().count(), 1);
+}
+
+/// Checks that a transactional state doesn't see the commits made after its snapshot, for the
+/// backends that build their point-in-time views themselves.
+#[cfg(any(feature = "sled", feature = "parity-db"))]
+fn transaction_isolation_suite<DB>(db: DB)
+where
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<BasicId>,
+{
+    let config = BonsaiStorageConfig {
+        snapshot_interval: 1,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(db, config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    bonsai_storage.insert(&key1, &Felt::ONE).unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    let root_hash1 = bonsai_storage.root_hash().unwrap();
+
+    let bonsai_at_txn: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+        .get_transactional_state(id1, bonsai_storage.get_config())
+        .unwrap()
+        .unwrap();
+
+    // Commits made after the transaction was created are not seen by it, even when they
+    // overwrite keys written after several snapshots
+    bonsai_storage.insert(&key2, &Felt::TWO).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    bonsai_storage.insert(&key2, &Felt::from(3u64)).unwrap();
+    bonsai_storage.remove(&key1).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    assert_eq!(bonsai_at_txn.get(&key1).unwrap(), Some(Felt::ONE));
+    assert_eq!(bonsai_at_txn.get(&key2).unwrap(), None);
+    assert_eq!(bonsai_at_txn.iter().count(), 1);
+    assert_eq!(bonsai_at_txn.root_hash().unwrap(), root_hash1);
+}
+
+#[test]
+fn hashmap_db() {
+    backend_suite(HashMapDb::<BasicId>::default());
+}
+
+#[cfg(feature = "rocksdb")]
+#[test]
+fn rocks_db() {
+    use crate::databases::{create_rocks_db, RocksDB, RocksDBConfig};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    backend_suite(RocksDB::new(&db, RocksDBConfig::default()));
+}
+
+#[cfg(feature = "rocksdb")]
+#[test]
+fn rocks_db_transaction_isolation() {
+    use crate::databases::{create_rocks_db, RocksDB, RocksDBConfig};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig {
+        snapshot_interval: 1,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    bonsai_storage.insert(&key1, &Felt::ONE).unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    let root_hash1 = bonsai_storage.root_hash().unwrap();
+
+    let bonsai_at_txn: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+        .get_transactional_state(id1, bonsai_storage.get_config())
+        .unwrap()
+        .unwrap();
+
+    // Commits made after the transaction was created are not seen by it
+    bonsai_storage.insert(&key2, &Felt::TWO).unwrap();
+    bonsai_storage.remove(&key1).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    assert_eq!(bonsai_at_txn.get(&key1).unwrap(), Some(Felt::ONE));
+    assert_eq!(bonsai_at_txn.get(&key2).unwrap(), None);
+    assert_eq!(bonsai_at_txn.iter().count(), 1);
+    assert_eq!(bonsai_at_txn.root_hash().unwrap(), root_hash1);
+}
+
+#[cfg(feature = "rocksdb")]
+#[test]
+fn rocks_db_with_options() {
+    use crate::databases::{
+        create_rocks_db_with_options, DBCompressionType, RocksDB, RocksDBColumnOptions,
+        RocksDBConfig, RocksDBOptions,
+    };
+
+    let column = RocksDBColumnOptions {
+        bloom_filter_bits: Some(10.0),
+        compression: DBCompressionType::Lz4,
+        write_buffer_size: Some(4 * 1024 * 1024),
+        block_size: Some(16 * 1024),
+    };
+    let options = RocksDBOptions {
+        max_background_jobs: Some(2),
+        block_cache_size: Some(8 * 1024 * 1024),
+        trie: column.clone(),
+        flat: column.clone(),
+        trie_log: RocksDBColumnOptions {
+            compression: DBCompressionType::Zstd,
+            ..Default::default()
+        },
+    };
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db_with_options(tempdir.path(), &options).unwrap();
+    backend_suite(RocksDB::new(&db, RocksDBConfig::default()));
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn sled_db() {
+    use crate::databases::{create_sled_db, SledDb, SledDbConfig};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_sled_db(tempdir.path()).unwrap();
+    backend_suite(SledDb::new(db, SledDbConfig::default()).unwrap());
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn sled_db_transaction_isolation() {
+    use crate::databases::{create_sled_db, SledDb, SledDbConfig};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_sled_db(tempdir.path()).unwrap();
+    transaction_isolation_suite(SledDb::new(db, SledDbConfig::default()).unwrap());
+}
+
+#[cfg(feature = "redb")]
+#[test]
+fn redb_db() {
+    use crate::databases::{create_redb_db, RedbDb, RedbDbConfig};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_redb_db(tempdir.path().join("bonsai.redb")).unwrap();
+    backend_suite(RedbDb::new(db, RedbDbConfig::default()));
+}
+
+#[cfg(feature = "heed")]
+#[test]
+fn heed_db() {
+    use crate::databases::{create_heed_env, HeedDb, HeedDbConfig};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let env = create_heed_env(tempdir.path(), 10 * 1024 * 1024).unwrap();
+    backend_suite(HeedDb::new(env, HeedDbConfig::default()).unwrap());
+}
+
+#[cfg(feature = "mdbx")]
+#[test]
+fn mdbx_db() {
+    use crate::databases::{create_mdbx_db, MdbxDb, MdbxDbConfig, MdbxGeometry, WriteMap};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_mdbx_db::<WriteMap>(tempdir.path(), MdbxGeometry::default()).unwrap();
+    backend_suite(MdbxDb::new(&db, MdbxDbConfig::default()));
+}
+
+#[cfg(feature = "parity-db")]
+#[test]
+fn parity_db() {
+    use crate::databases::{create_parity_db, ParityDb, ParityDbConfig};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_parity_db(tempdir.path()).unwrap();
+    backend_suite(ParityDb::new(db, ParityDbConfig::default()));
+}
+
+#[cfg(feature = "parity-db")]
+#[test]
+fn parity_db_transaction_isolation() {
+    use crate::databases::{create_parity_db, ParityDb, ParityDbConfig};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_parity_db(tempdir.path()).unwrap();
+    transaction_isolation_suite(ParityDb::new(db, ParityDbConfig::default()));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_db() {
+    use crate::databases::{create_sqlite_db, SqliteDb, SqliteDbConfig};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let connection = create_sqlite_db(tempdir.path().join("bonsai.sqlite")).unwrap();
+    backend_suite(SqliteDb::new(connection, SqliteDbConfig::default()).unwrap());
+}
+
+#[cfg(feature = "fjall")]
+#[test]
+fn fjall_db() {
+    use crate::databases::{create_fjall_db, FjallDb, FjallDbConfig};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let keyspace = create_fjall_db(tempdir.path()).unwrap();
+    backend_suite(FjallDb::new(keyspace, FjallDbConfig::default()).unwrap());
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap_db() {
+    use crate::databases::{MmapDb, MmapDbConfig};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = MmapDb::open(tempdir.path().join("bonsai.mmap"), MmapDbConfig::default()).unwrap();
+    backend_suite(db);
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap_db_compaction() {
+    use crate::{
+        databases::{MmapDb, MmapDbConfig},
+        DatabaseKey,
+    };
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let path = tempdir.path().join("bonsai.mmap");
+    let config = || MmapDbConfig {
+        compaction_ratio: None,
+        ..Default::default()
+    };
+    let mut db = MmapDb::<BasicId>::open(&path, config()).unwrap();
+    for i in 0..10u8 {
+        db.insert(&DatabaseKey::Flat(&[1, 2]), &[i; 32], None)
+            .unwrap();
+    }
+    db.insert(&DatabaseKey::Flat(&[1, 3]), &[1], None).unwrap();
+    db.remove(&DatabaseKey::Flat(&[1, 3]), None).unwrap();
+    let size = db.file_size();
+    assert!(db.garbage_size() > 0);
+
+    db.compact().unwrap();
+    assert!(db.file_size() < size);
+    assert_eq!(db.garbage_size(), 0);
+    assert_eq!(
+        db.get(&DatabaseKey::Flat(&[1, 2])).unwrap(),
+        Some(vec![9; 32])
+    );
+    drop(db);
+
+    let db = MmapDb::<BasicId>::open(&path, config()).unwrap();
+    assert_eq!(
+        db.get(&DatabaseKey::Flat(&[1, 2])).unwrap(),
+        Some(vec![9; 32])
+    );
+    assert_eq!(db.get(&DatabaseKey::Flat(&[1, 3])).unwrap(), None);
+}
+
+#[cfg(feature = "object-store")]
+#[test]
+fn object_store_db() {
+    use crate::databases::{ObjectStoreDb, ObjectStoreDbConfig};
+    use object_store::memory::InMemory;
+    use std::sync::Arc;
+
+    // Upload on every write so that reads go through the bucket.
+    let config = ObjectStoreDbConfig {
+        write_back_threshold: 1,
+        cache_size: 0,
+        ..Default::default()
+    };
+    backend_suite(ObjectStoreDb::new(Arc::new(InMemory::new()), config).unwrap());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn compressed_db() {
+    use crate::databases::{CompressedDb, CompressedDbConfig};
+
+    // Compress every value, even the ones smaller than the default threshold.
+    let config = CompressedDbConfig {
+        threshold: 0,
+        ..Default::default()
+    };
+    backend_suite(CompressedDb::new(HashMapDb::<BasicId>::default(), config));
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypted_db() {
+    use crate::databases::{EncryptedDb, EncryptedDbConfig};
+
+    let config = EncryptedDbConfig {
+        key: [1; 32],
+        key_encryption_key: None,
+    };
+    backend_suite(EncryptedDb::new(HashMapDb::<BasicId>::default(), config));
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypted_db_with_encrypted_keys() {
+    use crate::databases::{EncryptedDb, EncryptedDbConfig};
+
+    let config = EncryptedDbConfig {
+        key: [1; 32],
+        key_encryption_key: Some([2; 32]),
+    };
+    backend_suite(EncryptedDb::new(HashMapDb::<BasicId>::default(), config));
+}
+
+#[test]
+fn instrumented_db() {
+    use crate::databases::InstrumentedDb;
+
+    backend_suite(InstrumentedDb::new(HashMapDb::<BasicId>::default()));
+}
+
+#[test]
+fn instrumented_db_stats() {
+    use crate::{databases::InstrumentedDb, DatabaseKey};
+
+    let mut db = InstrumentedDb::new(HashMapDb::<BasicId>::default());
+    db.insert(&DatabaseKey::Flat(&[1, 2]), &[3; 10], None)
+        .unwrap();
+    db.insert(&DatabaseKey::Trie(&[4]), &[5; 20], None).unwrap();
+    let mut batch = db.create_batch();
+    db.remove(&DatabaseKey::Flat(&[1, 2]), Some(&mut batch))
+        .unwrap();
+    db.write_batch(batch).unwrap();
+    assert_eq!(db.get(&DatabaseKey::Trie(&[4])).unwrap(), Some(vec![5; 20]));
+    assert_eq!(db.get(&DatabaseKey::Trie(&[6])).unwrap(), None);
+
+    let stats = db.reset_stats();
+    assert_eq!(stats.flat.writes, 1);
+    assert_eq!(stats.flat.write_bytes, 12);
+    assert_eq!(stats.flat.deletes, 1);
+    assert_eq!(stats.flat.write_latency.count(), 2);
+    assert_eq!(stats.trie.reads, 1);
+    assert_eq!(stats.trie.read_bytes, 20);
+    assert_eq!(stats.trie.read_latency.count(), 2);
+    assert_eq!(stats.trie_log, Default::default());
+    assert_eq!(stats.batch_latency.count(), 1);
+    assert_eq!(db.stats(), Default::default());
+}
+
+#[cfg(feature = "wal")]
+#[test]
+fn wal_db() {
+    use crate::databases::WalDb;
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = WalDb::open(HashMapDb::<BasicId>::default(), tempdir.path().join("wal")).unwrap();
+    backend_suite(db);
+}
+
+#[cfg(feature = "wal")]
+#[test]
+fn wal_db_recovery() {
+    use crate::{databases::WalDb, DatabaseKey};
+    use std::io::Write;
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let path = tempdir.path().join("wal");
+    let mut db = WalDb::open(HashMapDb::<BasicId>::default(), &path).unwrap();
+    let mut batch = db.create_batch();
+    db.insert(&DatabaseKey::Flat(&[1]), &[2], Some(&mut batch))
+        .unwrap();
+    db.insert(&DatabaseKey::TrieLog(&[3]), &[4], Some(&mut batch))
+        .unwrap();
+    db.write_batch(batch).unwrap();
+    let inner = db.inner().clone();
+    assert_eq!(inner.get(&DatabaseKey::Flat(&[1])).unwrap(), Some(vec![2]));
+
+    // A partially written record is discarded
+    std::fs::File::options()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(&[1, 2, 3])
+        .unwrap();
+    let db = WalDb::open(inner, &path).unwrap();
+    assert_eq!(db.get(&DatabaseKey::TrieLog(&[3])).unwrap(), Some(vec![4]));
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn cached_db() {
+    use crate::databases::{CachedDb, CachedDbConfig};
+
+    backend_suite(CachedDb::new(
+        HashMapDb::<BasicId>::default(),
+        CachedDbConfig::default(),
+    ));
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn cached_db_invalidation() {
+    use crate::{
+        databases::{CacheStats, CachedDb, CachedDbConfig},
+        DatabaseKey,
+    };
+    use std::num::NonZeroUsize;
+
+    let config = CachedDbConfig {
+        capacity: NonZeroUsize::new(2).unwrap(),
+    };
+    let mut db = CachedDb::new(HashMapDb::<BasicId>::default(), config);
+    db.insert(&DatabaseKey::Flat(&[1]), &[1], None).unwrap();
+    assert_eq!(db.get(&DatabaseKey::Flat(&[1])).unwrap(), Some(vec![1]));
+
+    let mut batch = db.create_batch();
+    db.insert(&DatabaseKey::Flat(&[1]), &[2], Some(&mut batch))
+        .unwrap();
+    db.write_batch(batch).unwrap();
+    assert_eq!(db.get(&DatabaseKey::Flat(&[1])).unwrap(), Some(vec![2]));
+
+    db.remove_by_prefix(&DatabaseKey::Flat(&[])).unwrap();
+    assert!(!db.contains(&DatabaseKey::Flat(&[1])).unwrap());
+    assert!(!db.contains(&DatabaseKey::Flat(&[2])).unwrap());
+    assert!(!db.contains(&DatabaseKey::Flat(&[3])).unwrap());
+
+    assert_eq!(
+        db.cache_stats(),
+        CacheStats {
+            hits: 1,
+            misses: 4,
+            evictions: 1,
+            invalidations: 2,
+        }
+    );
+}
+
+#[cfg(feature = "sharded")]
+#[test]
+fn sharded_db() {
+    use crate::databases::{ShardedDb, ShardedDbConfig};
+
+    let shards = vec![HashMapDb::<BasicId>::default(); 3];
+    backend_suite(ShardedDb::new(shards, ShardedDbConfig::default()));
+}
+
+#[cfg(feature = "sharded")]
+#[test]
+fn sharded_db_get_many() {
+    use crate::databases::{ShardedDb, ShardedDbConfig};
+    use crate::DatabaseKey;
+
+    let shards = vec![HashMapDb::<BasicId>::default(); 3];
+    let mut db = ShardedDb::new(shards, ShardedDbConfig::default());
+    let keys: Vec<Vec<u8>> = (0u8..16).map(|i| vec![i]).collect();
+    for key in keys.iter().step_by(2) {
+        db.insert(&DatabaseKey::Flat(key), key, None).unwrap();
+    }
+    let keys: Vec<_> = keys.iter().map(|key| DatabaseKey::Flat(key)).collect();
+    let values = db.get_many(&keys).unwrap();
+    for (i, value) in values.into_iter().enumerate() {
+        assert_eq!(value, (i % 2 == 0).then(|| vec![i as u8]));
+    }
+}
+
+/// Shard whose batches fail to be written when `fail_writes` is set.
+#[cfg(feature = "sharded")]
+#[derive(Default)]
+struct FailingShard {
+    db: HashMapDb<BasicId>,
+    fail_writes: bool,
+}
+
+#[cfg(feature = "sharded")]
+#[derive(Debug)]
+struct FailingShardError;
+
+#[cfg(feature = "sharded")]
+impl std::fmt::Display for FailingShardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "write failed")
+    }
+}
+
+#[cfg(feature = "sharded")]
+impl std::error::Error for FailingShardError {}
+
+#[cfg(feature = "sharded")]
+impl crate::DBError for FailingShardError {}
+
+#[cfg(feature = "sharded")]
+impl BonsaiDatabase for FailingShard {
+    type Batch = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+    type DatabaseError = FailingShardError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Vec::new()
+    }
+
+    fn get(&self, key: &crate::DatabaseKey) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        Ok(self.db.get(key).unwrap())
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &crate::DatabaseKey,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::DatabaseError> {
+        Ok(self.db.get_by_prefix(prefix).unwrap())
+    }
+
+    fn contains(&self, key: &crate::DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        Ok(self.db.contains(key).unwrap())
+    }
+
+    fn insert(
+        &mut self,
+        key: &crate::DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        match batch {
+            Some(batch) => {
+                batch.push((key.as_slice().to_vec(), Some(value.to_vec())));
+                self.get(key)
+            }
+            None => Ok(self.db.insert(key, value, None).unwrap()),
+        }
+    }
+
+    fn remove(
+        &mut self,
+        key: &crate::DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<Vec<u8>>, Self::DatabaseError> {
+        match batch {
+            Some(batch) => {
+                batch.push((key.as_slice().to_vec(), None));
+                self.get(key)
+            }
+            None => Ok(self.db.remove(key, None).unwrap()),
+        }
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &crate::DatabaseKey) -> Result<(), Self::DatabaseError> {
+        self.db.remove_by_prefix(prefix).unwrap();
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        if self.fail_writes {
+            return Err(FailingShardError);
+        }
+        for (key, value) in batch {
+            let key = crate::DatabaseKey::Flat(&key);
+            match value {
+                Some(value) => self.db.insert(&key, &value, None).unwrap(),
+                None => self.db.remove(&key, None).unwrap(),
+            };
+        }
+        Ok(())
+    }
+
+    fn dump_database(&self) {
+        self.db.dump_database();
+    }
+}
+
+#[cfg(feature = "sharded")]
+#[test]
+fn sharded_db_failed_batch() {
+    use crate::databases::{hash_router, ShardedDb, ShardedDbConfig};
+    use crate::DatabaseKey;
+
+    let shards = vec![
+        FailingShard::default(),
+        FailingShard {
+            fail_writes: true,
+            ..Default::default()
+        },
+    ];
+    let mut db = ShardedDb::new(shards, ShardedDbConfig::default());
+    let keys: Vec<Vec<u8>> = (0u8..16).map(|i| vec![i]).collect();
+    let shard_key = |shard| {
+        keys.iter()
+            .find(|key| hash_router(&DatabaseKey::Flat(key), 2) == shard)
+            .unwrap()
+    };
+    let (key0, key1) = (
+        DatabaseKey::Flat(shard_key(0)),
+        DatabaseKey::Flat(shard_key(1)),
+    );
+    db.insert(&key0, &[1], None).unwrap();
+
+    // The part written to the first shard is reverted when the second shard fails
+    let mut batch = db.create_batch();
+    db.insert(&key0, &[2], Some(&mut batch)).unwrap();
+    db.insert(&key1, &[2], Some(&mut batch)).unwrap();
+    assert!(db.write_batch(batch).is_err());
+    assert_eq!(db.get(&key0).unwrap(), Some(vec![1]));
+    assert_eq!(db.get(&key1).unwrap(), None);
+}
+
+#[test]
+fn tiered_db() {
+    use crate::databases::{TieredDb, TieredDbConfig};
+
+    // Demote after every commit so that reads go through the cold store.
+    let config = TieredDbConfig { demote_after: 1 };
+    backend_suite(TieredDb::new(
+        HashMapDb::<BasicId>::default(),
+        HashMapDb::<BasicId>::default(),
+        config,
+    ));
+}
@@ -0,0 +1,467 @@
+#![cfg(feature = "std")]
+use std::{
+    collections::BTreeMap,
+    ops::{Bound, ControlFlow},
+};
+
+use bitvec::{order::Msb0, slice::BitSlice, vec::BitVec};
+use parity_scale_codec::Decode;
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+use crate::{
+    databases::HashMapDb,
+    id::{BasicId, BasicIdBuilder},
+    leaf_file::LeafFileError,
+    trie::merkle_node::Node,
+    BonsaiStorage, BonsaiStorageConfig, VisitedNode,
+};
+
+type Storage = BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen>;
+
+/// A storage with some committed pairs and some uncommitted changes, along with all its pairs
+fn storage() -> (Storage, BasicIdBuilder, BTreeMap<BitVec<u8, Msb0>, Felt>) {
+    let mut bonsai_storage =
+        Storage::new(HashMapDb::default(), BonsaiStorageConfig::default()).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let mut pairs = BTreeMap::new();
+    for i in 0..40u8 {
+        let key = BitVec::from_vec(vec![i.wrapping_mul(37), i % 3, 7]);
+        bonsai_storage
+            .insert(&key, &Felt::from(i as u64 + 1))
+            .unwrap();
+        pairs.insert(key, Felt::from(i as u64 + 1));
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    for i in (0..40u8).step_by(4) {
+        let key = BitVec::from_vec(vec![i.wrapping_mul(37), i % 3, 7]);
+        bonsai_storage
+            .insert(&key, &Felt::from(50 + i as u64))
+            .unwrap();
+        pairs.insert(key, Felt::from(50 + i as u64));
+    }
+    for i in 0..5u8 {
+        let key = BitVec::from_vec(vec![i, 200, 1]);
+        bonsai_storage
+            .insert(&key, &Felt::from(100 + i as u64))
+            .unwrap();
+        pairs.insert(key, Felt::from(100 + i as u64));
+    }
+    (bonsai_storage, id_builder, pairs)
+}
+
+#[test]
+fn iter() {
+    let bonsai_storage =
+        Storage::new(HashMapDb::default(), BonsaiStorageConfig::default()).unwrap();
+    assert_eq!(bonsai_storage.iter().count(), 0);
+
+    let (mut bonsai_storage, mut id_builder, pairs) = storage();
+    let expected: Vec<_> = pairs.into_iter().collect();
+    let iterated: Vec<_> = bonsai_storage.iter().collect::<Result<_, _>>().unwrap();
+    assert_eq!(iterated, expected);
+
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let iterated: Vec<_> = bonsai_storage.iter().collect::<Result<_, _>>().unwrap();
+    assert_eq!(iterated, expected);
+}
+
+#[test]
+fn iter_prefix() {
+    let (bonsai_storage, _, pairs) = storage();
+    for prefix in [
+        BitVec::new(),
+        BitVec::from_vec(vec![37]),
+        BitVec::from_vec(vec![2, 200]),
+        BitVec::from_vec(vec![3, 200, 1]),
+        BitVec::<u8, Msb0>::from_vec(vec![0b1010_0000])[..3].to_bitvec(),
+        BitVec::from_vec(vec![255]),
+        // Longer than the keys
+        BitVec::from_vec(vec![3, 200, 1, 0]),
+    ] {
+        let expected: Vec<_> = pairs
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix.as_bitslice()))
+            .map(|(key, value)| (key.clone(), *value))
+            .collect();
+        let iterated: Vec<_> = bonsai_storage
+            .iter_prefix(&prefix)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(iterated, expected, "prefix {:?}", prefix);
+    }
+}
+
+#[test]
+fn range() {
+    let (bonsai_storage, _, pairs) = storage();
+    let key = |bytes: [u8; 3]| BitVec::<u8, Msb0>::from_vec(bytes.to_vec());
+    let check = |range: (Bound<BitVec<u8, Msb0>>, Bound<BitVec<u8, Msb0>>)| {
+        let expected: Vec<_> = pairs
+            .range(range.clone())
+            .map(|(key, value)| (key.clone(), *value))
+            .collect();
+        let iterated: Vec<_> = bonsai_storage
+            .range(range.clone())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(iterated, expected, "range {:?}", range);
+    };
+    let (first, last) = (
+        pairs.keys().next().unwrap().clone(),
+        pairs.keys().last().unwrap().clone(),
+    );
+    check((Bound::Unbounded, Bound::Unbounded));
+    check((
+        Bound::Included(first.clone()),
+        Bound::Included(last.clone()),
+    ));
+    check((Bound::Excluded(first), Bound::Excluded(last)));
+    check((
+        Bound::Included(key([37, 1, 7])),
+        Bound::Excluded(key([185, 2, 7])),
+    ));
+    check((
+        Bound::Excluded(key([37, 1, 7])),
+        Bound::Included(key([185, 2, 7])),
+    ));
+    check((Bound::Included(key([2, 0, 0])), Bound::Unbounded));
+    check((Bound::Unbounded, Bound::Excluded(key([2, 200, 1]))));
+    // Empty ranges
+    check((
+        Bound::Included(key([3, 0, 0])),
+        Bound::Excluded(key([3, 0, 0])),
+    ));
+    check((
+        Bound::Included(key([38, 0, 0])),
+        Bound::Excluded(key([40, 0, 0])),
+    ));
+
+    let start = key([37, 1, 7]);
+    let end = key([185, 2, 7]);
+    assert_eq!(
+        bonsai_storage
+            .range(&start..&end)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap(),
+        bonsai_storage
+            .range((Bound::Included(start.clone()), Bound::Excluded(end.clone())))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    );
+}
+
+#[test]
+fn iter_at() {
+    let (mut bonsai_storage, mut id_builder, pairs) = storage();
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    let expected1: Vec<_> = pairs.into_iter().collect();
+
+    let id2 = id_builder.new_id();
+    for (i, (key, _)) in expected1.iter().enumerate().step_by(3) {
+        bonsai_storage
+            .insert(key, &Felt::from(1000 + i as u64))
+            .unwrap();
+    }
+    for i in 0..10u8 {
+        bonsai_storage
+            .insert(
+                &BitVec::from_vec(vec![255 - i, 9, 9]),
+                &Felt::from(i as u64 + 1),
+            )
+            .unwrap();
+    }
+    bonsai_storage.commit(id2).unwrap();
+    let expected2: Vec<_> = bonsai_storage.iter().collect::<Result<_, _>>().unwrap();
+    assert_ne!(expected1, expected2);
+    // Uncommitted changes aren't seen
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![0, 0, 0]), &Felt::ONE)
+        .unwrap();
+
+    let at = |id| -> Vec<_> {
+        bonsai_storage
+            .iter_at(id)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    };
+    assert_eq!(at(id1), expected1);
+    assert_eq!(at(id2), expected2);
+    assert!(bonsai_storage.iter_at(id_builder.new_id()).is_err());
+}
+
+#[test]
+fn export_nodes() {
+    let (mut bonsai_storage, mut id_builder, _) = storage();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    let all: Vec<_> = bonsai_storage
+        .export_nodes(BitSlice::empty())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    // Depth first order is the order of the paths, each node is exported once
+    assert!(all.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    assert!(all[0].0.is_empty());
+    for (_, encoded) in all.iter() {
+        Node::decode(&mut encoded.as_slice()).unwrap();
+    }
+    // Exporting again gives the same nodes
+    assert_eq!(
+        bonsai_storage
+            .export_nodes(BitSlice::empty())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap(),
+        all
+    );
+
+    for prefix in [
+        BitVec::<u8, Msb0>::from_vec(vec![37]),
+        BitVec::from_vec(vec![0b1010_0000])[..3].to_bitvec(),
+        BitVec::from_vec(vec![2, 200]),
+    ] {
+        let exported: Vec<_> = bonsai_storage
+            .export_nodes(&prefix)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let (first, rest) = match exported.first() {
+            Some((path, _)) if !path.starts_with(prefix.as_bitslice()) => {
+                (exported.first(), &exported[1..])
+            }
+            _ => (None, &exported[..]),
+        };
+        let expected: Vec<_> = all
+            .iter()
+            .filter(|(path, _)| path.starts_with(prefix.as_bitslice()))
+            .cloned()
+            .collect();
+        assert_eq!(rest, expected.as_slice());
+        // The edge leading into the subtree starts above the prefix and goes past it
+        if let Some((path, encoded)) = first {
+            let Node::Edge(edge) = Node::decode(&mut encoded.as_slice()).unwrap() else {
+                panic!("the node leading into the subtree isn't an edge");
+            };
+            assert!(prefix.starts_with(path.as_bitslice()));
+            assert!(path.len() + edge.path.0.len() > prefix.len());
+        }
+    }
+}
+
+#[test]
+fn cursor() {
+    let (bonsai_storage, _, pairs) = storage();
+    let expected: Vec<_> = pairs.into_iter().collect();
+
+    let mut cursor = bonsai_storage.cursor();
+    assert_eq!(cursor.current(), None);
+    assert_eq!(cursor.prev().unwrap(), None);
+    let mut forward = Vec::new();
+    while let Some((key, value)) = cursor.next().unwrap() {
+        forward.push((key.to_bitvec(), value));
+    }
+    assert_eq!(forward, expected);
+    assert_eq!(cursor.current(), None);
+    assert_eq!(cursor.next().unwrap(), None);
+
+    let mut backward = Vec::new();
+    while let Some((key, value)) = cursor.prev().unwrap() {
+        backward.push((key.to_bitvec(), value));
+    }
+    backward.reverse();
+    assert_eq!(backward, expected);
+
+    // Seeking to a present key, an absent key and past the last key
+    let (key, value) = &expected[7];
+    assert_eq!(cursor.seek(key).unwrap(), Some((key.as_bitslice(), *value)));
+    let mut absent = key.clone();
+    absent.set(23, !absent[23]);
+    let after = expected.iter().find(|(k, _)| *k > absent).unwrap();
+    assert_eq!(
+        cursor.seek(&absent).unwrap(),
+        Some((after.0.as_bitslice(), after.1))
+    );
+    let before = expected.iter().rev().find(|(k, _)| *k < after.0).unwrap();
+    assert_eq!(
+        cursor.prev().unwrap(),
+        Some((before.0.as_bitslice(), before.1))
+    );
+    let last = BitVec::<u8, Msb0>::from_vec(vec![0xff; 3]);
+    assert_eq!(cursor.seek(&last).unwrap(), None);
+    let (key, value) = expected.last().unwrap();
+    assert_eq!(cursor.prev().unwrap(), Some((key.as_bitslice(), *value)));
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_iter() {
+    use rayon::iter::ParallelIterator;
+
+    let bonsai_storage =
+        Storage::new(HashMapDb::default(), BonsaiStorageConfig::default()).unwrap();
+    assert_eq!(bonsai_storage.par_iter().unwrap().count(), 0);
+
+    let (mut bonsai_storage, mut id_builder, pairs) = storage();
+    let expected: Vec<_> = pairs.into_iter().collect();
+    let iterated: Vec<_> = bonsai_storage
+        .par_iter()
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(iterated, expected);
+
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let iterated: Vec<_> = bonsai_storage
+        .par_iter()
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(iterated, expected);
+}
+
+#[test]
+fn neighbour_leaves() {
+    let (mut bonsai_storage, mut id_builder, pairs) = storage();
+    let expected: Vec<_> = pairs.into_iter().collect();
+
+    for _ in 0..2 {
+        for (index, (key, _)) in expected.iter().enumerate() {
+            let prev = index.checked_sub(1).map(|index| expected[index].clone());
+            let next = expected.get(index + 1).cloned();
+            assert_eq!(bonsai_storage.prev_leaf(key).unwrap(), prev);
+            assert_eq!(bonsai_storage.next_leaf(key).unwrap(), next);
+
+            // Neighbours of an absent key differing from this one in the last bit
+            let mut absent = key.clone();
+            absent.set(23, !absent[23]);
+            let prev = expected.iter().rev().find(|(k, _)| *k < absent).cloned();
+            let next = expected.iter().find(|(k, _)| *k > absent).cloned();
+            assert_eq!(bonsai_storage.prev_leaf(&absent).unwrap(), prev);
+            assert_eq!(bonsai_storage.next_leaf(&absent).unwrap(), next);
+        }
+        bonsai_storage.commit(id_builder.new_id()).unwrap();
+    }
+}
+
+#[test]
+fn visit_nodes() {
+    let (mut bonsai_storage, mut id_builder, pairs) = storage();
+    let expected: Vec<_> = pairs.into_iter().collect();
+
+    for committed in [false, true] {
+        let mut leaves = Vec::new();
+        let mut binaries = 0;
+        let mut previous_depth = None;
+        bonsai_storage
+            .visit_nodes(&mut |depth, path, node| {
+                // Depth first, a node is at most one level below the previous one
+                assert!(previous_depth.map_or(depth == 0, |previous| depth <= previous + 1));
+                previous_depth = Some(depth);
+                match node {
+                    VisitedNode::Binary { hash } => {
+                        assert!(!committed || hash.is_some());
+                        binaries += 1;
+                    }
+                    VisitedNode::Edge { hash, path: edge } => {
+                        assert!(!committed || hash.is_some());
+                        assert!(!edge.is_empty());
+                    }
+                    VisitedNode::Leaf { value } => leaves.push((path.to_bitvec(), value)),
+                }
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert_eq!(leaves, expected);
+        assert_eq!(binaries, expected.len() - 1);
+        bonsai_storage.commit(id_builder.new_id()).unwrap();
+    }
+
+    let mut visited = 0;
+    bonsai_storage
+        .visit_nodes(&mut |_, _, _| {
+            visited += 1;
+            if visited == 3 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .unwrap();
+    assert_eq!(visited, 3);
+}
+
+#[test]
+fn first_and_last_leaves() {
+    let bonsai_storage =
+        Storage::new(HashMapDb::default(), BonsaiStorageConfig::default()).unwrap();
+    assert_eq!(bonsai_storage.first_leaf().unwrap(), None);
+    assert_eq!(bonsai_storage.last_leaf().unwrap(), None);
+
+    let (mut bonsai_storage, mut id_builder, pairs) = storage();
+    let first = pairs.first_key_value().map(|(k, v)| (k.clone(), *v));
+    let last = pairs.last_key_value().map(|(k, v)| (k.clone(), *v));
+    assert_eq!(bonsai_storage.first_leaf().unwrap(), first);
+    assert_eq!(bonsai_storage.last_leaf().unwrap(), last);
+
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.first_leaf().unwrap(), first);
+    assert_eq!(bonsai_storage.last_leaf().unwrap(), last);
+}
+
+#[test]
+fn sample_leaves() {
+    let bonsai_storage =
+        Storage::new(HashMapDb::default(), BonsaiStorageConfig::default()).unwrap();
+    assert!(bonsai_storage.sample_leaves(10, 0).unwrap().is_empty());
+
+    let (mut bonsai_storage, mut id_builder, pairs) = storage();
+    let sample = bonsai_storage.sample_leaves(100, 42).unwrap();
+    assert_eq!(sample.len(), 100);
+    for (key, value) in sample.iter() {
+        assert_eq!(pairs.get(key), Some(value));
+    }
+    // Not always the same leaf
+    assert!(sample.iter().any(|leaf| leaf != &sample[0]));
+    assert!(bonsai_storage.sample_leaves(0, 42).unwrap().is_empty());
+
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.sample_leaves(100, 42).unwrap(), sample);
+    assert_ne!(bonsai_storage.sample_leaves(100, 43).unwrap(), sample);
+}
+
+#[test]
+fn export_and_import_leaves() {
+    let (mut bonsai_storage, mut id_builder, pairs) = storage();
+    let mut file = Vec::new();
+    assert_eq!(
+        bonsai_storage.export_leaves(&mut file).unwrap(),
+        pairs.len() as u64
+    );
+    // The keys are 24 bits long
+    assert_eq!(file.len(), pairs.len() * (1 + 3 + 32));
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    let mut imported = Storage::new(HashMapDb::default(), BonsaiStorageConfig::default()).unwrap();
+    assert_eq!(
+        imported.import_leaves(file.as_slice()).unwrap(),
+        pairs.len() as u64
+    );
+    imported.commit(BasicIdBuilder::new().new_id()).unwrap();
+    assert_eq!(
+        imported.root_hash().unwrap(),
+        bonsai_storage.root_hash().unwrap()
+    );
+    let expected: Vec<_> = pairs.into_iter().collect();
+    let iterated: Vec<_> = imported.iter().collect::<Result<_, _>>().unwrap();
+    assert_eq!(iterated, expected);
+
+    // A file cut in the middle of a pair
+    assert!(matches!(
+        imported.import_leaves(&file[..file.len() - 1]),
+        Err(LeafFileError::Io(_))
+    ));
+    // A key longer than 251 bits
+    assert!(matches!(
+        imported.import_leaves([252u8].as_slice()),
+        Err(LeafFileError::InvalidPair(_))
+    ));
+}
@@ -1,5 +1,9 @@
+mod async_storage;
+mod backends;
+mod iter;
 mod madara_comparison;
 mod proof;
+mod read_only;
 mod simple;
 mod transactional_state;
 mod trie_log;
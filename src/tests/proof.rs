@@ -1,18 +1,25 @@
 #![cfg(feature = "std")]
-use bitvec::vec::BitVec;
+use bitvec::{bitvec, order::Msb0, slice::BitSlice, vec::BitVec};
+use parity_scale_codec::{DecodeAll, Encode};
 use pathfinder_common::{hash::PedersenHash, trie::TrieNode};
 use pathfinder_crypto::Felt as PathfinderFelt;
 use pathfinder_merkle_tree::tree::{MerkleTree, TestStorage};
 use pathfinder_storage::{Node, StoredNode};
 use rand::Rng;
-use starknet_types_core::{felt::Felt, hash::Pedersen};
+use starknet_types_core::{
+    felt::Felt,
+    hash::{Pedersen, Poseidon},
+};
 use std::collections::HashMap;
 
 use crate::{
     databases::{create_rocks_db, RocksDB, RocksDBConfig},
     id::{BasicId, BasicIdBuilder},
+    proof::Path,
+    rpc,
     trie::merkle_tree::{Membership, ProofNode},
-    BonsaiStorage, BonsaiStorageConfig,
+    BonsaiStorage, BonsaiStorageConfig, ChangeProof, ContractStorageProof, MultiProof, RangeProof,
+    WitnessRecorder,
 };
 
 /// Commits the tree changes and persists them to storage.
@@ -311,3 +318,1246 @@ fn zero_not_crashing() {
         .get_proof(&BitVec::from_vec(vec![1, 2, 1]))
         .unwrap();
 }
+
+#[test]
+fn non_membership() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let absent = BitVec::from_vec(vec![1, 2, 3]);
+
+    // Nothing is in an empty trie
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let proof = bonsai_storage.get_proof(&absent).unwrap();
+    assert!(proof.is_empty());
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_non_membership(
+            bonsai_storage.root_hash().unwrap(),
+            &absent,
+            &proof
+        )
+    );
+
+    let present = BitVec::from_vec(vec![1, 2, 1]);
+    let value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap();
+    bonsai_storage.insert(&present, &value).unwrap();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 2]), &value)
+        .unwrap();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![3, 2, 1]), &value)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root = bonsai_storage.root_hash().unwrap();
+
+    let proof = bonsai_storage.get_proof(&absent).unwrap();
+    assert!(matches!(proof.last(), Some(ProofNode::Edge { .. })));
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_non_membership(
+            root, &absent, &proof
+        )
+    );
+    assert_eq!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_proof(
+            root, &absent, value, &proof
+        ),
+        Some(Membership::NonMember)
+    );
+
+    // The proof of a present key doesn't prove its absence
+    let proof = bonsai_storage.get_proof(&present).unwrap();
+    assert!(
+        !BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_non_membership(
+            root, &present, &proof
+        )
+    );
+    // Neither does a truncated proof
+    assert!(
+        !BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_non_membership(
+            root,
+            &present,
+            &proof[..1]
+        )
+    );
+}
+
+#[test]
+fn poseidon_non_membership() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Poseidon>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let absent = BitVec::from_vec(vec![1, 2, 3]);
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 1]), &Felt::ONE)
+        .unwrap();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![3, 2, 1]), &Felt::TWO)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    // Verified with the hash of the storage
+    let proof = bonsai_storage.get_proof(&absent).unwrap();
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Poseidon>::verify_non_membership(
+            bonsai_storage.root_hash().unwrap(),
+            &absent,
+            &proof
+        )
+    );
+}
+
+#[test]
+fn range_proof() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let keys = [
+        vec![1, 2, 1],
+        vec![1, 2, 2],
+        vec![1, 3, 1],
+        vec![7, 0, 0],
+        vec![200, 1, 9],
+    ];
+    for (i, key) in keys.iter().enumerate() {
+        bonsai_storage
+            .insert(&BitVec::from_vec(key.clone()), &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root = bonsai_storage.root_hash().unwrap();
+    let verify = |start: &BitVec<u8, Msb0>, end: &BitVec<u8, Msb0>, proof: &RangeProof| {
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_range_proof(
+            root, start, end, proof,
+        )
+    };
+
+    // Bounds in and out of the trie, an empty range and the whole trie
+    for (start, end, count) in [
+        (vec![1, 2, 2], vec![7, 0, 0], 3),
+        (vec![1, 2, 0], vec![8, 0, 0], 4),
+        (vec![2, 0, 0], vec![6, 0, 0], 0),
+        (vec![0, 0, 0], vec![255, 255, 255], 5),
+    ] {
+        let (start, end) = (BitVec::from_vec(start), BitVec::from_vec(end));
+        let proof = bonsai_storage.get_range_proof(&start, &end).unwrap();
+        assert_eq!(proof.leaves.len(), count);
+        assert!(verify(&start, &end, &proof));
+    }
+
+    let start = BitVec::from_vec(vec![1, 2, 0]);
+    let end = BitVec::from_vec(vec![8, 0, 0]);
+    let proof = bonsai_storage.get_range_proof(&start, &end).unwrap();
+    // A missing leaf is detected
+    let mut incomplete = proof.clone();
+    incomplete.leaves.remove(1);
+    assert!(!verify(&start, &end, &incomplete));
+    // So is a wrong value
+    let mut wrong = proof.clone();
+    wrong.leaves[0].1 = Felt::from(42u64);
+    assert!(!verify(&start, &end, &wrong));
+    // And a proof of another range
+    let other_end = BitVec::from_vec(vec![200, 1, 9]);
+    assert!(!verify(&start, &other_end, &proof));
+
+    // The proofs are the ones of the last commit
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![5, 0, 0]), &Felt::from(42u64))
+        .unwrap();
+    assert!(bonsai_storage.get_range_proof(&start, &end).is_err());
+}
+
+#[test]
+fn poseidon_range_proof() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Poseidon>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    for (i, key) in [vec![1, 2, 1], vec![1, 3, 1], vec![7, 0, 0]]
+        .into_iter()
+        .enumerate()
+    {
+        bonsai_storage
+            .insert(&BitVec::from_vec(key), &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    let start = BitVec::from_vec(vec![1, 2, 0]);
+    let end = BitVec::from_vec(vec![2, 0, 0]);
+    let proof = bonsai_storage.get_range_proof(&start, &end).unwrap();
+    assert_eq!(proof.leaves.len(), 2);
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Poseidon>::verify_range_proof(
+            bonsai_storage.root_hash().unwrap(),
+            &start,
+            &end,
+            &proof
+        )
+    );
+}
+
+#[test]
+fn rpc_proof() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap();
+    let keys = [vec![1, 2, 1], vec![1, 2, 2], vec![3, 2, 1]].map(BitVec::from_vec);
+    for key in keys.iter() {
+        bonsai_storage.insert(key, &value).unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root = bonsai_storage.root_hash().unwrap();
+
+    let absent = BitVec::from_vec(vec![1, 2, 3]);
+    let nodes = bonsai_storage
+        .get_rpc_proof(&[&keys[0], &keys[1], &absent])
+        .unwrap();
+    // The nodes shared by the proofs are listed once
+    let mut hashes: Vec<_> = nodes.iter().map(|node| node.node_hash).collect();
+    hashes.sort();
+    hashes.dedup();
+    assert_eq!(hashes.len(), nodes.len());
+
+    // Nodes go through JSON as in the RPC
+    let json = serde_json::to_string(&nodes).unwrap();
+    let nodes: Vec<rpc::NodeHashToNode> = serde_json::from_str(&json).unwrap();
+
+    for key in &keys[..2] {
+        let proof = rpc::proof_from_rpc::<Pedersen>(root, key, &nodes).unwrap();
+        assert_eq!(proof, bonsai_storage.get_proof(key).unwrap());
+        assert_eq!(
+            BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_proof(
+                root, key, value, &proof
+            ),
+            Some(Membership::Member)
+        );
+    }
+    let proof = rpc::proof_from_rpc::<Pedersen>(root, &absent, &nodes).unwrap();
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_non_membership(
+            root, &absent, &proof
+        )
+    );
+    // The proof of the third key wasn't requested
+    assert!(rpc::proof_from_rpc::<Pedersen>(root, &keys[2], &nodes).is_none());
+
+    let key = &keys[2];
+    assert_eq!(
+        rpc::felt_to_key(&rpc::key_to_felt(key), key.len()),
+        key.as_bitslice()
+    );
+}
+
+#[test]
+fn stateless_verification() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = BitVec::from_vec(vec![1, 2, 1]);
+    let value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap();
+    bonsai_storage.insert(&key, &value).unwrap();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 2]), &value)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root = bonsai_storage.root_hash().unwrap();
+    let proof = bonsai_storage.get_proof(&key).unwrap();
+    drop(bonsai_storage);
+
+    // Only the root, the key, the value and the nodes are needed
+    assert_eq!(
+        crate::proof::verify_proof::<Pedersen>(root, &key, value, &proof),
+        Some(Membership::Member)
+    );
+    assert_eq!(
+        crate::proof::verify_proof::<Pedersen>(root, &key, Felt::ONE, &proof),
+        None
+    );
+    assert!(!crate::proof::verify_non_membership::<Pedersen>(
+        root, &key, &proof
+    ));
+}
+
+#[test]
+fn proof_at_past_commit() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = BitVec::from_vec(vec![1, 2, 1]);
+    let other_key = BitVec::from_vec(vec![1, 2, 2]);
+    let old_value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap();
+    let new_value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052f").unwrap();
+
+    bonsai_storage.insert(&key, &old_value).unwrap();
+    bonsai_storage.insert(&other_key, &old_value).unwrap();
+    let old_id = id_builder.new_id();
+    bonsai_storage.commit(old_id).unwrap();
+    let old_root = bonsai_storage.root_hash().unwrap();
+
+    bonsai_storage.insert(&key, &new_value).unwrap();
+    bonsai_storage.remove(&other_key).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    bonsai_storage.insert(&other_key, &new_value).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_ne!(bonsai_storage.root_hash().unwrap(), old_root);
+
+    let proof = bonsai_storage.get_proof_at(old_id, &key).unwrap().unwrap();
+    assert_eq!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_proof(
+            old_root, &key, old_value, &proof
+        ),
+        Some(Membership::Member)
+    );
+    // The current state is untouched
+    assert_eq!(bonsai_storage.get(&key).unwrap(), Some(new_value));
+
+    // Ids that were never committed have no state
+    assert!(bonsai_storage
+        .get_proof_at(id_builder.new_id(), &key)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn proof_at_without_snapshots() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig {
+        max_saved_snapshots: Some(0),
+        ..Default::default()
+    };
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = BitVec::from_vec(vec![1, 2, 1]);
+
+    let mut ids = Vec::new();
+    let mut roots = Vec::new();
+    for i in 0..4u64 {
+        bonsai_storage.insert(&key, &Felt::from(i + 1)).unwrap();
+        bonsai_storage
+            .insert(&BitVec::from_vec(vec![1, 3, i as u8]), &Felt::from(i + 1))
+            .unwrap();
+        let id = id_builder.new_id();
+        bonsai_storage.commit(id).unwrap();
+        ids.push(id);
+        roots.push(bonsai_storage.root_hash().unwrap());
+    }
+    assert!(bonsai_storage
+        .get_transactional_state(ids[1], bonsai_storage.get_config())
+        .unwrap()
+        .is_none());
+
+    for (i, (id, root)) in ids.iter().zip(roots).enumerate() {
+        let proof = bonsai_storage.get_proof_at(*id, &key).unwrap().unwrap();
+        assert_eq!(
+            BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_proof(
+                root,
+                &key,
+                Felt::from(i as u64 + 1),
+                &proof
+            ),
+            Some(Membership::Member)
+        );
+    }
+}
+
+#[test]
+fn multi_proof_encoding() {
+    let binary = ProofNode::Binary {
+        left: Felt::from(1),
+        right: Felt::from(2),
+    };
+    let edge = ProofNode::Edge {
+        child: Felt::from(3),
+        path: Path(bitvec![u8, Msb0; 1, 0, 1]),
+    };
+    let proofs = MultiProof(vec![vec![binary.clone(), edge], vec![binary]]);
+
+    // Golden vector, the binary node shared by both proofs is only listed once
+    let expected = [
+        &[0x08, 0x00][..],
+        &[0; 31],
+        &[1],
+        &[0; 31],
+        &[2],
+        &[0x01, 0x03, 0b1010_0000],
+        &[0; 31],
+        &[3],
+        &[0x08, 0x08, 0x00, 0x04, 0x04, 0x00],
+    ]
+    .concat();
+    assert_eq!(proofs.encode(), expected);
+    assert_eq!(MultiProof::decode_all(&mut &expected[..]).unwrap(), proofs);
+    assert_eq!(MultiProof::default().encode(), [0x00, 0x00]);
+
+    // Non canonical encodings are rejected
+    let mut padding = expected.clone();
+    padding[68] = 0b1010_0001;
+    assert!(MultiProof::decode_all(&mut &padding[..]).is_err());
+    let mut order = expected.clone();
+    let last = order.len() - 1;
+    order[last - 3] = 0x04;
+    order[last - 2] = 0x00;
+    order[last] = 0x04;
+    assert!(MultiProof::decode_all(&mut &order[..]).is_err());
+    let mut trailing = expected.clone();
+    trailing.push(0);
+    assert!(MultiProof::decode_all(&mut &trailing[..]).is_err());
+
+    // Proofs generated by the trie go through the encoding
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let keys = [vec![1, 2, 1], vec![1, 2, 2], vec![3, 2, 1]].map(BitVec::from_vec);
+    let value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap();
+    for key in keys.iter() {
+        bonsai_storage.insert(key, &value).unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let proofs = MultiProof(
+        keys.iter()
+            .map(|key| bonsai_storage.get_proof(key).unwrap())
+            .collect(),
+    );
+    let encoded = proofs.encode();
+    assert_eq!(MultiProof::decode_all(&mut &encoded[..]).unwrap(), proofs);
+}
+
+#[test]
+fn witness() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let keys = [vec![1, 2, 1], vec![1, 2, 2], vec![3, 2, 1], vec![1, 2, 3]].map(BitVec::from_vec);
+    let value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap();
+    let new_value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052f").unwrap();
+    for key in &keys[..3] {
+        bonsai_storage.insert(key, &value).unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let prior_root = bonsai_storage.root_hash().unwrap();
+
+    // A block reads a key, overwrites another one and creates a third one
+    let mut recorder = WitnessRecorder::new(&mut bonsai_storage);
+    assert_eq!(recorder.get(&keys[0]).unwrap(), Some(value));
+    recorder.insert(&keys[1], &new_value).unwrap();
+    assert_eq!(recorder.get(&keys[1]).unwrap(), Some(new_value));
+    assert_eq!(recorder.get(&keys[3]).unwrap(), None);
+    recorder.insert(&keys[3], &value).unwrap();
+    let witness = recorder.finish().unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.get(&keys[1]).unwrap(), Some(new_value));
+    assert_eq!(bonsai_storage.get(&keys[3]).unwrap(), Some(value));
+
+    // The witness proves the values before the block
+    assert_eq!(
+        witness.keys,
+        [&keys[0], &keys[1], &keys[3]].map(Clone::clone)
+    );
+    let values = witness.verify::<Pedersen>(prior_root).unwrap();
+    assert_eq!(values[&keys[0]], Some(value));
+    assert_eq!(values[&keys[1]], Some(value));
+    assert_eq!(values[&keys[3]], None);
+    assert!(witness
+        .verify::<Pedersen>(bonsai_storage.root_hash().unwrap())
+        .is_none());
+}
+
+#[test]
+fn subtree_proof() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    for (i, key) in [vec![1, 2, 1], vec![1, 2, 2], vec![1, 3, 1], vec![7, 0, 0]]
+        .into_iter()
+        .enumerate()
+    {
+        bonsai_storage
+            .insert(&BitVec::from_vec(key), &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root = bonsai_storage.root_hash().unwrap();
+    let verify = |prefix: &BitVec<u8, Msb0>, proof: &RangeProof| {
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_subtree_proof(
+            root, prefix, 24, proof,
+        )
+    };
+
+    for (prefix, count) in [(vec![1, 2], 2), (vec![1], 3), (vec![2], 0)] {
+        let prefix = BitVec::from_vec(prefix);
+        let proof = bonsai_storage.get_subtree_proof(&prefix, 24).unwrap();
+        assert_eq!(proof.leaves.len(), count);
+        assert!(verify(&prefix, &proof));
+    }
+
+    // The proof of a subtree doesn't prove another one
+    let proof = bonsai_storage
+        .get_subtree_proof(&BitVec::from_vec(vec![1, 2]), 24)
+        .unwrap();
+    assert!(!verify(&BitVec::from_vec(vec![1]), &proof));
+    // Prefixes can't be longer than the keys
+    assert!(bonsai_storage
+        .get_subtree_proof(&BitVec::from_vec(vec![1, 2, 1, 0]), 24)
+        .is_err());
+}
+
+#[test]
+fn poseidon_subtree_proof() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Poseidon>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    for (i, key) in [vec![1, 2, 1], vec![1, 2, 2], vec![7, 0, 0]]
+        .into_iter()
+        .enumerate()
+    {
+        bonsai_storage
+            .insert(&BitVec::from_vec(key), &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    let prefix = BitVec::from_vec(vec![1, 2]);
+    let proof = bonsai_storage.get_subtree_proof(&prefix, 24).unwrap();
+    assert_eq!(proof.leaves.len(), 2);
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Poseidon>::verify_subtree_proof(
+            bonsai_storage.root_hash().unwrap(),
+            &prefix,
+            24,
+            &proof
+        )
+    );
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn parallel_proofs() {
+    use crate::databases::HashMapDb;
+
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(HashMapDb::<BasicId>::default(), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let mut rng = rand::thread_rng();
+    let keys: Vec<BitVec<u8, Msb0>> = (0..200)
+        .map(|_| BitVec::from_vec(rng.gen::<[u8; 3]>().to_vec()))
+        .collect();
+    for (i, key) in keys.iter().enumerate() {
+        bonsai_storage
+            .insert(key, &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    let key_slices: Vec<_> = keys.iter().map(|key| key.as_bitslice()).collect();
+    let proofs = bonsai_storage.get_proofs_parallel(&key_slices).unwrap();
+    assert_eq!(proofs.len(), keys.len());
+    for (key, proof) in keys.iter().zip(proofs) {
+        assert_eq!(proof, bonsai_storage.get_proof(key).unwrap());
+    }
+}
+
+#[test]
+fn proof_node_felts() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = BitVec::from_vec(vec![1, 2, 1]);
+    let value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap();
+    bonsai_storage.insert(&key, &value).unwrap();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 2]), &value)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    let proof = bonsai_storage.get_proof(&key).unwrap();
+    // The path of the proof consumes the whole key
+    assert_eq!(
+        proof.iter().map(ProofNode::path_len).sum::<usize>(),
+        key.len()
+    );
+    for node in proof.iter() {
+        assert_ne!(node.binary().is_some(), node.edge().is_some());
+        assert_eq!(ProofNode::from_felts(&node.to_felts()).as_ref(), Some(node));
+    }
+    let (_, path) = proof[0].edge().unwrap();
+    assert_eq!(path, &key[..path.len()]);
+
+    // Paths must fit in their length
+    assert!(ProofNode::from_felts(&[Felt::ONE, Felt::from(4), Felt::from(2)]).is_none());
+    assert!(ProofNode::from_felts(&[Felt::ONE, Felt::ONE, Felt::from(252)]).is_none());
+    assert!(ProofNode::from_felts(&[Felt::ONE]).is_none());
+}
+
+#[test]
+fn change_proof() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = BitVec::from_vec(vec![1, 2, 1]);
+    let created = BitVec::from_vec(vec![1, 2, 2]);
+    let old_value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap();
+    let new_value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052f").unwrap();
+
+    bonsai_storage.insert(&key, &old_value).unwrap();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![3, 2, 1]), &old_value)
+        .unwrap();
+    let id_a = id_builder.new_id();
+    bonsai_storage.commit(id_a).unwrap();
+    let root_a = bonsai_storage.root_hash().unwrap();
+    bonsai_storage.insert(&key, &new_value).unwrap();
+    bonsai_storage.insert(&created, &new_value).unwrap();
+    let id_b = id_builder.new_id();
+    bonsai_storage.commit(id_b).unwrap();
+    let root_b = bonsai_storage.root_hash().unwrap();
+    let verify = |key: &BitVec<u8, Msb0>, before, after, proof: &ChangeProof| {
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_change_proof(
+            root_a, root_b, key, before, after, proof,
+        )
+    };
+
+    let proof = bonsai_storage
+        .get_change_proof(id_a, id_b, &key)
+        .unwrap()
+        .unwrap();
+    assert!(verify(&key, Some(old_value), Some(new_value), &proof));
+    assert!(!verify(&key, Some(new_value), Some(new_value), &proof));
+    let encoded = MultiProof::from(proof.clone()).encode();
+    assert_eq!(
+        MultiProof::decode_all(&mut &encoded[..]).unwrap().0,
+        [proof.before, proof.after]
+    );
+
+    // Nodes of an unchanged key are shared by both proofs
+    let unchanged = BitVec::from_vec(vec![3, 2, 1]);
+    let proof = bonsai_storage
+        .get_change_proof(id_a, id_b, &unchanged)
+        .unwrap()
+        .unwrap();
+    assert!(verify(&unchanged, Some(old_value), Some(old_value), &proof));
+    assert_eq!(proof.before.last(), proof.after.last());
+
+    // A created key was absent before
+    let proof = bonsai_storage
+        .get_change_proof(id_a, id_b, &created)
+        .unwrap()
+        .unwrap();
+    assert!(verify(&created, None, Some(new_value), &proof));
+}
+
+#[test]
+fn poseidon_change_proof() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Poseidon>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = BitVec::from_vec(vec![1, 2, 1]);
+    bonsai_storage.insert(&key, &Felt::ONE).unwrap();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![3, 2, 1]), &Felt::ONE)
+        .unwrap();
+    let id_a = id_builder.new_id();
+    bonsai_storage.commit(id_a).unwrap();
+    let root_a = bonsai_storage.root_hash().unwrap();
+    bonsai_storage.insert(&key, &Felt::TWO).unwrap();
+    let id_b = id_builder.new_id();
+    bonsai_storage.commit(id_b).unwrap();
+
+    let proof = bonsai_storage
+        .get_change_proof(id_a, id_b, &key)
+        .unwrap()
+        .unwrap();
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Poseidon>::verify_change_proof(
+            root_a,
+            bonsai_storage.root_hash().unwrap(),
+            &key,
+            Some(Felt::ONE),
+            Some(Felt::TWO),
+            &proof
+        )
+    );
+}
+
+#[test]
+fn change_proof_without_snapshots() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig {
+        max_saved_snapshots: Some(0),
+        ..Default::default()
+    };
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = BitVec::from_vec(vec![1, 2, 1]);
+
+    let mut ids = Vec::new();
+    let mut roots = Vec::new();
+    for i in 0..3u64 {
+        bonsai_storage.insert(&key, &Felt::from(i + 1)).unwrap();
+        let id = id_builder.new_id();
+        bonsai_storage.commit(id).unwrap();
+        ids.push(id);
+        roots.push(bonsai_storage.root_hash().unwrap());
+    }
+
+    let proof = bonsai_storage
+        .get_change_proof(ids[1], ids[2], &key)
+        .unwrap()
+        .unwrap();
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_change_proof(
+            roots[1],
+            roots[2],
+            &key,
+            Some(Felt::from(2)),
+            Some(Felt::from(3)),
+            &proof
+        )
+    );
+    // Unknown commits have no proof
+    assert!(bonsai_storage
+        .get_change_proof(ids[1], id_builder.new_id(), &key)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn malformed_proofs() {
+    use crate::proof::{try_verify_proof, ProofError};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = BitVec::from_vec(vec![1, 2, 1]);
+    let value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap();
+    bonsai_storage.insert(&key, &value).unwrap();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 2]), &value)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root = bonsai_storage.root_hash().unwrap();
+    let proof = bonsai_storage.get_proof(&key).unwrap();
+    let verify = |key: &BitSlice<u8, Msb0>, value, proof: &[ProofNode]| {
+        try_verify_proof::<Pedersen>(root, key, value, proof)
+    };
+
+    assert_eq!(verify(&key, value, &proof), Ok(Membership::Member));
+    assert_eq!(
+        verify(&key, Felt::ONE, &proof),
+        Err(ProofError::ValueMismatch)
+    );
+    assert_eq!(
+        verify(&key, value, &proof[..2]),
+        Err(ProofError::Incomplete)
+    );
+    assert_eq!(
+        verify(&BitVec::<u8, Msb0>::repeat(false, 252), value, &proof),
+        Err(ProofError::KeyTooLong)
+    );
+
+    // Long proofs are rejected before hashing anything
+    let binary = ProofNode::Binary {
+        left: Felt::ONE,
+        right: Felt::ONE,
+    };
+    assert_eq!(
+        verify(&key, value, &vec![binary; 25]),
+        Err(ProofError::TooManyNodes)
+    );
+    for length in [0, 300] {
+        let edge = ProofNode::Edge {
+            child: Felt::ONE,
+            path: Path(BitVec::repeat(false, length)),
+        };
+        assert_eq!(
+            verify(&key, value, &[proof[0].clone(), edge]),
+            Err(ProofError::InvalidEdge(1))
+        );
+    }
+    let mut tampered = proof.clone();
+    tampered[1] = ProofNode::Binary {
+        left: Felt::ONE,
+        right: Felt::ONE,
+    };
+    assert_eq!(
+        verify(&key, value, &tampered),
+        Err(ProofError::HashMismatch(1))
+    );
+
+    // Nothing may follow the node proving an absence
+    let absent = BitVec::from_vec(vec![3, 2, 1]);
+    let mut proof = bonsai_storage.get_proof(&absent).unwrap();
+    assert_eq!(verify(&absent, value, &proof), Ok(Membership::NonMember));
+    proof.push(proof[0].clone());
+    assert_eq!(
+        verify(&absent, value, &proof),
+        Err(ProofError::TrailingNodes(0))
+    );
+}
+
+#[test]
+fn proof_json() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let keys = [vec![1, 2, 1], vec![1, 2, 2], vec![3, 2, 1]].map(BitVec::from_vec);
+    for (i, key) in keys.iter().enumerate() {
+        bonsai_storage
+            .insert(key, &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    let edge = ProofNode::Edge {
+        child: Felt::from(3),
+        path: Path(bitvec![u8, Msb0; 1, 0, 1]),
+    };
+    assert_eq!(
+        serde_json::to_value(&edge).unwrap(),
+        serde_json::json!({ "type": "edge", "child": "0x3", "path": "0x5", "length": 3 })
+    );
+    let binary = ProofNode::Binary {
+        left: Felt::from(1),
+        right: Felt::from(2),
+    };
+    assert_eq!(
+        serde_json::to_value(&binary).unwrap(),
+        serde_json::json!({ "type": "binary", "left": "0x1", "right": "0x2" })
+    );
+    // Paths must fit in their length
+    assert!(serde_json::from_value::<ProofNode>(
+        serde_json::json!({ "type": "edge", "child": "0x3", "path": "0x5", "length": 2 })
+    )
+    .is_err());
+
+    let proof = bonsai_storage.get_proof(&keys[0]).unwrap();
+    let json = serde_json::to_string(&proof).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Vec<ProofNode>>(&json).unwrap(),
+        proof
+    );
+
+    let range = bonsai_storage.get_range_proof(&keys[0], &keys[1]).unwrap();
+    let json = serde_json::to_value(&range).unwrap();
+    assert_eq!(
+        json["leaves"][1],
+        serde_json::json!({ "key": "0x10202", "length": 24, "value": "0x2" })
+    );
+    assert_eq!(serde_json::from_value::<RangeProof>(json).unwrap(), range);
+}
+
+#[test]
+fn contract_storage_proof() {
+    use crate::{databases::HashMapDb, proof};
+
+    let config = BonsaiStorageConfig::default();
+    let mut contracts =
+        BonsaiStorage::<_, _, Pedersen>::new(HashMapDb::<BasicId>::default(), config.clone())
+            .unwrap();
+    let mut storage =
+        BonsaiStorage::<_, _, Pedersen>::new(HashMapDb::<BasicId>::default(), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let address =
+        Felt::from_hex("0x4d0390b777b424e43839cd1e744799f3de6c176c7e32c1812a41dbd9c19db6a")
+            .unwrap();
+    let class_hash =
+        Felt::from_hex("0x1a736d6ed154502257f02b1ccdf4d9d1089f80811cd6acad48e6b6a9d1f2003")
+            .unwrap();
+    let nonce = Felt::from(7);
+    let slot = Felt::from_hex("0x5").unwrap();
+    let value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap();
+
+    storage.insert(&proof::felt_key(&slot), &value).unwrap();
+    storage
+        .insert(&proof::felt_key(&Felt::from(6)), &value)
+        .unwrap();
+    storage.commit(id_builder.new_id()).unwrap();
+    let leaf = proof::contract_state_hash(class_hash, storage.root_hash().unwrap(), nonce);
+    contracts.insert(&proof::felt_key(&address), &leaf).unwrap();
+    contracts
+        .insert(&proof::felt_key(&Felt::from(42)), &Felt::ONE)
+        .unwrap();
+    contracts.commit(id_builder.new_id()).unwrap();
+    let classes_root = Felt::from(1234);
+    let global_root = proof::global_state_root(contracts.root_hash().unwrap(), classes_root);
+    let verify = |slot, value, proof: &ContractStorageProof| {
+        BonsaiStorage::<BasicId, HashMapDb<BasicId>, Pedersen>::verify_contract_storage_proof(
+            global_root,
+            classes_root,
+            address,
+            slot,
+            value,
+            proof,
+        )
+    };
+
+    let proof = contracts
+        .get_contract_storage_proof(&storage, address, class_hash, nonce, slot)
+        .unwrap();
+    assert!(verify(slot, Some(value), &proof));
+    assert!(!verify(slot, Some(Felt::ONE), &proof));
+    let mut wrong_nonce = proof.clone();
+    wrong_nonce.nonce = Felt::from(8);
+    assert!(!verify(slot, Some(value), &wrong_nonce));
+
+    // Empty slots are proven too
+    let empty = Felt::from(9);
+    let proof = contracts
+        .get_contract_storage_proof(&storage, address, class_hash, nonce, empty)
+        .unwrap();
+    assert!(verify(empty, None, &proof));
+}
+
+#[test]
+fn proof_size() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let keys = [vec![1, 2, 1], vec![1, 2, 2], vec![3, 2, 1]].map(BitVec::from_vec);
+    for (i, key) in keys.iter().enumerate() {
+        bonsai_storage
+            .insert(key, &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    for key in keys.iter() {
+        let size = bonsai_storage.estimate_proof_size(key).unwrap();
+        let proof = bonsai_storage.get_proof(key).unwrap();
+        assert_eq!(size.nodes, proof.len());
+        // The encoding adds the counts and one byte per index
+        let encoded = MultiProof(vec![proof]).encode();
+        assert_eq!(encoded.len(), size.bytes + 3 + size.nodes);
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn batch_verification() {
+    use crate::proof::{verify_proofs_batch, ProofError};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let keys: Vec<_> = (0..100u8)
+        .map(|i| BitVec::<u8, Msb0>::from_vec(vec![i, 2, i]))
+        .collect();
+    for (i, key) in keys.iter().enumerate() {
+        bonsai_storage
+            .insert(key, &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root = bonsai_storage.root_hash().unwrap();
+
+    let proofs: Vec<_> = keys
+        .iter()
+        .map(|key| bonsai_storage.get_proof(key).unwrap())
+        .collect();
+    let mut items: Vec<_> = keys
+        .iter()
+        .zip(proofs.iter())
+        .enumerate()
+        .map(|(i, (key, proof))| (key.as_bitslice(), Felt::from(i as u64 + 1), &proof[..]))
+        .collect();
+    // One item has a wrong value
+    items[42].1 = Felt::ZERO;
+
+    let results = verify_proofs_batch::<Pedersen>(root, &items);
+    assert_eq!(results.len(), items.len());
+    for (i, result) in results.into_iter().enumerate() {
+        if i == 42 {
+            assert_eq!(result, Err(ProofError::ValueMismatch));
+        } else {
+            assert_eq!(result, Ok(Membership::Member));
+        }
+    }
+}
+
+#[test]
+fn uncommitted_proof() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let keys = [vec![1, 2, 1], vec![1, 2, 2], vec![3, 2, 1], vec![7, 7, 7]].map(BitVec::from_vec);
+    let value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap();
+    let new_value = Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052f").unwrap();
+    bonsai_storage.insert(&keys[0], &value).unwrap();
+    bonsai_storage.insert(&keys[1], &value).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let committed_root = bonsai_storage.root_hash().unwrap();
+
+    // A block changes a key, creates one and removes one
+    bonsai_storage.insert(&keys[0], &new_value).unwrap();
+    bonsai_storage.insert(&keys[2], &value).unwrap();
+    bonsai_storage.remove(&keys[1]).unwrap();
+    let verify = |root, key, value, proof: &[ProofNode]| {
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_proof(root, key, value, proof)
+    };
+
+    let (root, proof) = bonsai_storage.get_uncommitted_proof(&keys[0]).unwrap();
+    assert_ne!(root, committed_root);
+    assert_eq!(
+        verify(root, &keys[0], new_value, &proof),
+        Some(Membership::Member)
+    );
+    let (_, proof) = bonsai_storage.get_uncommitted_proof(&keys[2]).unwrap();
+    assert_eq!(
+        verify(root, &keys[2], value, &proof),
+        Some(Membership::Member)
+    );
+    let (_, proof) = bonsai_storage.get_uncommitted_proof(&keys[1]).unwrap();
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_non_membership(
+            root, &keys[1], &proof
+        )
+    );
+
+    // Changes made after a proof are taken into account by the next one
+    bonsai_storage.insert(&keys[3], &value).unwrap();
+    let (root, proof) = bonsai_storage.get_uncommitted_proof(&keys[0]).unwrap();
+    assert_eq!(
+        verify(root, &keys[0], new_value, &proof),
+        Some(Membership::Member)
+    );
+
+    // The proofs are against the root of the next commit
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), root);
+}
+
+#[test]
+fn empty_range_proof() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let start = BitVec::from_vec(vec![2, 0, 0]);
+    let end = BitVec::from_vec(vec![6, 0, 0]);
+
+    // Any range of an empty trie is empty
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let proof = bonsai_storage
+        .prove_range_empty(&start, &end)
+        .unwrap()
+        .unwrap();
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_range_empty(
+            Felt::ZERO,
+            &start,
+            &end,
+            &proof
+        )
+    );
+
+    for (i, key) in [vec![1, 2, 1], vec![1, 3, 1], vec![7, 0, 0], vec![200, 1, 9]]
+        .into_iter()
+        .enumerate()
+    {
+        bonsai_storage
+            .insert(&BitVec::from_vec(key), &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root = bonsai_storage.root_hash().unwrap();
+    let verify = |start: &BitVec<u8, Msb0>, end: &BitVec<u8, Msb0>, proof: &RangeProof| {
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_range_empty(
+            root, start, end, proof,
+        )
+    };
+
+    let proof = bonsai_storage
+        .prove_range_empty(&start, &end)
+        .unwrap()
+        .unwrap();
+    assert!(proof.leaves.is_empty());
+    assert!(verify(&start, &end, &proof));
+    // The proof doesn't hold for a wider range
+    assert!(!verify(&start, &BitVec::from_vec(vec![7, 0, 0]), &proof));
+
+    // A range with a leaf can't be proven empty
+    assert!(bonsai_storage
+        .prove_range_empty(&start, &BitVec::from_vec(vec![7, 0, 0]))
+        .unwrap()
+        .is_none());
+    let proof = bonsai_storage
+        .get_range_proof(&start, &BitVec::from_vec(vec![8, 0, 0]))
+        .unwrap();
+    assert!(!verify(&start, &BitVec::from_vec(vec![8, 0, 0]), &proof));
+
+    // The proofs are the ones of the last commit
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![3, 0, 0]), &Felt::from(42u64))
+        .unwrap();
+    assert!(bonsai_storage.prove_range_empty(&start, &end).is_err());
+}
+
+#[test]
+fn poseidon_empty_range_proof() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Poseidon>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 1]), &Felt::ONE)
+        .unwrap();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![7, 0, 0]), &Felt::TWO)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    let start = BitVec::from_vec(vec![2, 0, 0]);
+    let end = BitVec::from_vec(vec![6, 0, 0]);
+    let proof = bonsai_storage
+        .prove_range_empty(&start, &end)
+        .unwrap()
+        .unwrap();
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Poseidon>::verify_range_empty(
+            bonsai_storage.root_hash().unwrap(),
+            &start,
+            &end,
+            &proof
+        )
+    );
+}
+
+#[test]
+fn get_with_proof() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(RocksDB::new(&db, RocksDBConfig::default()), config)
+            .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    // Empty trie
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let key = BitVec::from_vec(vec![1, 2, 1]);
+    let (value, proof) = bonsai_storage.get_with_proof(&key).unwrap();
+    assert_eq!((value, proof.len()), (None, 0));
+
+    for i in 0..20u8 {
+        bonsai_storage
+            .insert(
+                &BitVec::from_vec(vec![i * 12, i, 1]),
+                &Felt::from(i as u64 + 1),
+            )
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root = bonsai_storage.root_hash().unwrap();
+
+    for i in 0..20u8 {
+        let key = BitVec::from_vec(vec![i * 12, i, 1]);
+        let (value, proof) = bonsai_storage.get_with_proof(&key).unwrap();
+        assert_eq!(value, bonsai_storage.get(&key).unwrap());
+        assert_eq!(proof, bonsai_storage.get_proof(&key).unwrap());
+        assert_eq!(
+            BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_proof(
+                root,
+                &key,
+                value.unwrap(),
+                &proof
+            ),
+            Some(Membership::Member)
+        );
+    }
+    let key = BitVec::from_vec(vec![5, 5, 5]);
+    let (value, proof) = bonsai_storage.get_with_proof(&key).unwrap();
+    assert_eq!(value, None);
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_non_membership(
+            root, &key, &proof
+        )
+    );
+}
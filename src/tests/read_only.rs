@@ -0,0 +1,66 @@
+#![cfg(all(feature = "std", feature = "rocksdb"))]
+use crate::{
+    databases::{
+        create_rocks_db, open_rocks_db_secondary, RocksDB, RocksDBConfig, RocksDBSecondary,
+    },
+    id::{BasicId, BasicIdBuilder},
+    BonsaiStorage, BonsaiStorageConfig, Membership, ReadOnlyBonsaiStorage,
+};
+use bitvec::vec::BitVec;
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+#[test]
+fn read_from_secondary() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let secondary_dir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let pair1 = (
+        BitVec::from_vec(vec![1, 2, 1]),
+        Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap(),
+    );
+    let pair2 = (
+        BitVec::from_vec(vec![1, 2, 2]),
+        Felt::from_hex("0x66342762FD54D033c195fec3ce2568b62052e").unwrap(),
+    );
+    bonsai_storage.insert(&pair1.0, &pair1.1).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    let secondary = open_rocks_db_secondary(tempdir.path(), secondary_dir.path()).unwrap();
+    let read_only: ReadOnlyBonsaiStorage<BasicId, _, Pedersen> =
+        ReadOnlyBonsaiStorage::new(RocksDBSecondary::new(&secondary)).unwrap();
+    assert_eq!(
+        read_only.root_hash().unwrap(),
+        bonsai_storage.root_hash().unwrap()
+    );
+    assert_eq!(read_only.get(&pair1.0).unwrap(), Some(pair1.1));
+    let proof = read_only.get_proof(&pair1.0).unwrap();
+    assert_eq!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_proof(
+            read_only.root_hash().unwrap(),
+            &pair1.0,
+            pair1.1,
+            &proof
+        ),
+        Some(Membership::Member)
+    );
+
+    bonsai_storage.insert(&pair2.0, &pair2.1).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    RocksDBSecondary::new(&secondary)
+        .try_catch_up_with_primary()
+        .unwrap();
+    let read_only = read_only.reload().unwrap();
+    assert_eq!(
+        read_only.root_hash().unwrap(),
+        bonsai_storage.root_hash().unwrap()
+    );
+    assert_eq!(
+        read_only.iter().collect::<Result<Vec<_>, _>>().unwrap(),
+        vec![pair1, pair2]
+    );
+}
@@ -1,10 +1,16 @@
 #![cfg(feature = "std")]
 use crate::{
     databases::{create_rocks_db, HashMapDb, RocksDB, RocksDBConfig},
-    id::{BasicId, BasicIdBuilder},
-    BonsaiStorage, BonsaiStorageConfig, Change,
+    id::{BasicId, BasicIdBuilder, Id, U64Id, U64IdBuilder},
+    trie::{
+        merkle_node::{Node, NodeHandle},
+        merkle_tree::bitslice_to_bytes,
+    },
+    trie_log::{TrieLogEntry, TrieLogKey},
+    BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig, BonsaiStorageError, Change, DatabaseKey,
 };
-use bitvec::{vec::BitVec, view::BitView};
+use bitvec::{order::Msb0, vec::BitVec, view::BitView};
+use parity_scale_codec::{Decode, Encode};
 use starknet_types_core::{felt::Felt, hash::Pedersen};
 
 #[test]
@@ -333,6 +339,232 @@ fn double_insert() {
     println!("root hash: {root_hash:#x}");
 }
 
+#[test]
+fn commit_and_get_root() {
+    let db = HashMapDb::<BasicId>::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default()).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    for i in 0..3u8 {
+        bonsai_storage
+            .insert(&BitVec::from_vec(vec![1, 2, i]), &Felt::from(i + 1))
+            .unwrap();
+        let root = bonsai_storage
+            .commit_and_get_root(id_builder.new_id())
+            .unwrap();
+        assert_eq!(root, bonsai_storage.root_hash().unwrap());
+    }
+}
+
+#[test]
+fn prepare_confirm_abort() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        RocksDB::new(&db, RocksDBConfig::default()),
+        BonsaiStorageConfig::default(),
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 1]), &Felt::from(1))
+        .unwrap();
+    let id = id_builder.new_id();
+    let mut prepared = bonsai_storage.prepare(id).unwrap();
+    let prepared_root = prepared.root_hash();
+    // Block data written along with the commit
+    prepared.batch_mut().put(b"block", b"1");
+    assert_eq!(db.get(b"block").unwrap(), None);
+    assert_eq!(bonsai_storage.confirm(prepared).unwrap(), prepared_root);
+    assert_eq!(bonsai_storage.root_hash().unwrap(), prepared_root);
+    assert_eq!(db.get(b"block").unwrap(), Some(b"1".to_vec()));
+
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 2]), &Felt::from(2))
+        .unwrap();
+    let id = id_builder.new_id();
+    let mut prepared = bonsai_storage.prepare(id).unwrap();
+    prepared.batch_mut().put(b"block", b"2");
+    bonsai_storage.abort(prepared).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), prepared_root);
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![1, 2, 2]))
+            .unwrap(),
+        None
+    );
+    assert_eq!(db.get(b"block").unwrap(), Some(b"1".to_vec()));
+
+    // The id of the aborted commit can be used again
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 3]), &Felt::from(3))
+        .unwrap();
+    bonsai_storage.commit(id).unwrap();
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![1, 2, 3]))
+            .unwrap(),
+        Some(Felt::from(3))
+    );
+}
+
+#[test]
+fn rollback_pending() {
+    let db = HashMapDb::<BasicId>::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default()).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    for i in 0..4u8 {
+        bonsai_storage
+            .insert(&BitVec::from_vec(vec![1, i, 2]), &Felt::from(i + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let committed = bonsai_storage.root_hash().unwrap();
+
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 9, 2]), &Felt::from(10))
+        .unwrap();
+    bonsai_storage
+        .remove(&BitVec::from_vec(vec![1, 0, 2]))
+        .unwrap();
+    bonsai_storage.rollback_pending().unwrap();
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![1, 9, 2]))
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![1, 0, 2]))
+            .unwrap(),
+        Some(Felt::from(1))
+    );
+
+    let id = id_builder.new_id();
+    bonsai_storage.commit(id).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), committed);
+    // No leaf changed
+    assert!(bonsai_storage
+        .get_trie_log(id)
+        .unwrap()
+        .entries
+        .iter()
+        .all(|entry| matches!(entry.key, TrieLogKey::Trie(_))));
+}
+
+#[test]
+fn checkpoint_restore() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let mut id_builder = U64IdBuilder::new(0);
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        RocksDB::new(&db, RocksDBConfig::default()),
+        BonsaiStorageConfig::default(),
+    )
+    .unwrap();
+    let mut ids = Vec::new();
+    for i in 0..3u8 {
+        bonsai_storage
+            .insert(&BitVec::from_vec(vec![1, i, 2]), &Felt::from(i + 1))
+            .unwrap();
+        let id = id_builder.new_id();
+        bonsai_storage.commit(id).unwrap();
+        ids.push(id);
+    }
+    bonsai_storage.pin(ids[0]).unwrap();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 9, 2]), &Felt::from(10))
+        .unwrap();
+    bonsai_storage
+        .remove(&BitVec::from_vec(vec![1, 0, 2]))
+        .unwrap();
+    let expected_root = bonsai_storage.compute_root_dry_run().unwrap();
+    let checkpoint = bonsai_storage.checkpoint().unwrap();
+    drop(bonsai_storage);
+
+    let id = |bytes: &[u8]| Some(U64Id(u64::from_be_bytes(bytes.try_into().ok()?)));
+    // The pins of the config are kept along with the ones of the checkpoint
+    let config = BonsaiStorageConfig {
+        pinned_trie_logs: vec![ids[1].to_bytes()],
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::restore(
+        RocksDB::new(&db, RocksDBConfig::default()),
+        config,
+        &checkpoint,
+        id,
+    )
+    .unwrap();
+    assert_eq!(bonsai_storage.iter_ids().collect::<Vec<_>>(), ids);
+    assert!(bonsai_storage.unpin(ids[0]));
+    assert!(bonsai_storage.unpin(ids[1]));
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![1, 9, 2]))
+            .unwrap(),
+        Some(Felt::from(10))
+    );
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![1, 0, 2]))
+            .unwrap(),
+        None
+    );
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), expected_root);
+    bonsai_storage.revert_to(ids[1]).unwrap();
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![1, 0, 2]))
+            .unwrap(),
+        Some(Felt::from(1))
+    );
+
+    // The database moved on since the checkpoint
+    assert!(BonsaiStorage::<_, _, Pedersen>::restore(
+        RocksDB::new(&db, RocksDBConfig::default()),
+        BonsaiStorageConfig::default(),
+        &checkpoint,
+        id,
+    )
+    .is_err());
+}
+
+#[test]
+fn compute_root_dry_run() {
+    let db = HashMapDb::<BasicId>::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default()).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    assert_eq!(bonsai_storage.compute_root_dry_run().unwrap(), Felt::ZERO);
+    for i in 0..4u8 {
+        bonsai_storage
+            .insert(&BitVec::from_vec(vec![1, i, 2]), &Felt::from(i + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let committed = bonsai_storage.root_hash().unwrap();
+    assert_eq!(bonsai_storage.compute_root_dry_run().unwrap(), committed);
+
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 9, 2]), &Felt::from(10))
+        .unwrap();
+    bonsai_storage
+        .remove(&BitVec::from_vec(vec![1, 0, 2]))
+        .unwrap();
+    let dry_run = bonsai_storage.compute_root_dry_run().unwrap();
+    // Nothing is committed
+    assert_eq!(bonsai_storage.root_hash().unwrap(), committed);
+    assert_eq!(
+        bonsai_storage
+            .commit_and_get_root(id_builder.new_id())
+            .unwrap(),
+        dry_run
+    );
+}
+
 #[test]
 fn get_changes() {
     let tempdir = tempfile::tempdir().unwrap();
@@ -373,3 +605,402 @@ fn get_changes() {
         }
     );
 }
+
+#[test]
+fn get_trie_log() {
+    let db = HashMapDb::<BasicId>::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default()).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    bonsai_storage.insert(&key1, &Felt::from(1)).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    bonsai_storage.insert(&key1, &Felt::from(2)).unwrap();
+    bonsai_storage.insert(&key2, &Felt::from(3)).unwrap();
+    let id = id_builder.new_id();
+    bonsai_storage.commit(id).unwrap();
+
+    let trie_log = bonsai_storage.get_trie_log(id).unwrap();
+    assert_eq!(trie_log.id, id);
+    assert!(trie_log
+        .entries
+        .windows(2)
+        .all(|entries| entries[0].key < entries[1].key));
+    let leaves: Vec<_> = trie_log
+        .entries
+        .iter()
+        .filter(|entry| matches!(entry.key, TrieLogKey::Flat(_)))
+        .cloned()
+        .collect();
+    assert_eq!(
+        leaves,
+        vec![
+            TrieLogEntry {
+                key: TrieLogKey::Flat(bitslice_to_bytes(&key1)),
+                old_value: Some(Felt::from(1).encode()),
+                new_value: Some(Felt::from(2).encode()),
+            },
+            TrieLogEntry {
+                key: TrieLogKey::Flat(bitslice_to_bytes(&key2)),
+                old_value: None,
+                new_value: Some(Felt::from(3).encode()),
+            },
+        ]
+    );
+    // The root node changed too
+    assert!(trie_log
+        .entries
+        .iter()
+        .any(|entry| entry.key == TrieLogKey::Trie(vec![])));
+    assert!(bonsai_storage.get_trie_log(id_builder.new_id()).is_err());
+}
+
+#[test]
+fn get_many() {
+    let db = HashMapDb::<BasicId>::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default()).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let keys: Vec<BitVec<u8, Msb0>> = (0..6u8)
+        .map(|i| BitVec::from_vec(vec![i * 40, 2, 1]))
+        .collect();
+    for (i, key) in keys.iter().enumerate().take(4) {
+        bonsai_storage
+            .insert(key, &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    // Uncommitted changes are seen
+    bonsai_storage.remove(&keys[1]).unwrap();
+    bonsai_storage.insert(&keys[2], &Felt::from(10)).unwrap();
+    bonsai_storage.insert(&keys[4], &Felt::from(11)).unwrap();
+
+    let request = [
+        &keys[5], &keys[3], &keys[0], &keys[1], &keys[2], &keys[4], &keys[3],
+    ]
+    .map(|key| key.as_bitslice());
+    let values = bonsai_storage.get_many(&request).unwrap();
+    for (key, value) in request.iter().zip(values.iter()) {
+        assert_eq!(*value, bonsai_storage.get(key).unwrap());
+    }
+    assert_eq!(
+        values,
+        [None, Some(4u64), Some(1), None, Some(10), Some(11), Some(4)]
+            .map(|value| value.map(Felt::from))
+    );
+    assert!(bonsai_storage.get_many(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn contains_many() {
+    let db = HashMapDb::<BasicId>::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default()).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let keys: Vec<BitVec<u8, Msb0>> = (0..5u8)
+        .map(|i| BitVec::from_vec(vec![i * 40, 2, 1]))
+        .collect();
+    for (i, key) in keys.iter().enumerate().take(3) {
+        bonsai_storage
+            .insert(key, &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    // Uncommitted changes are seen
+    bonsai_storage.remove(&keys[1]).unwrap();
+    bonsai_storage.insert(&keys[3], &Felt::from(10)).unwrap();
+
+    let request =
+        [&keys[4], &keys[2], &keys[0], &keys[1], &keys[3], &keys[2]].map(|key| key.as_bitslice());
+    let contained = bonsai_storage.contains_many(&request).unwrap();
+    for (key, contained) in request.iter().zip(contained.iter()) {
+        assert_eq!(*contained, bonsai_storage.contains(key).unwrap());
+    }
+    assert_eq!(contained, [false, true, true, false, true, true]);
+    assert!(bonsai_storage.contains_many(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn len() {
+    let db = HashMapDb::<BasicId>::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default()).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    assert_eq!(bonsai_storage.len().unwrap(), 0);
+    assert!(bonsai_storage.is_empty().unwrap());
+
+    let keys: Vec<BitVec<u8, Msb0>> = (0..4u8)
+        .map(|i| BitVec::from_vec(vec![i * 40, 2, 1]))
+        .collect();
+    for key in keys.iter().take(3) {
+        bonsai_storage.insert(key, &Felt::ONE).unwrap();
+    }
+    assert_eq!(bonsai_storage.len().unwrap(), 3);
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.len().unwrap(), 3);
+
+    // Overwriting a key doesn't change the count
+    bonsai_storage.insert(&keys[0], &Felt::TWO).unwrap();
+    bonsai_storage.insert(&keys[3], &Felt::TWO).unwrap();
+    bonsai_storage.remove(&keys[1]).unwrap();
+    assert_eq!(bonsai_storage.len().unwrap(), 3);
+    let id = id_builder.new_id();
+    bonsai_storage.commit(id).unwrap();
+    assert_eq!(bonsai_storage.len().unwrap(), 3);
+
+    bonsai_storage.remove(&keys[0]).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.len().unwrap(), 2);
+    assert!(!bonsai_storage.is_empty().unwrap());
+
+    // The count is part of the state that is reverted
+    bonsai_storage.revert_to(id).unwrap();
+    assert_eq!(bonsai_storage.len().unwrap(), 3);
+}
+
+#[test]
+fn verify_reads() {
+    let config = BonsaiStorageConfig {
+        verify_reads: true,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let keys: Vec<BitVec<u8, Msb0>> = (0..10u8)
+        .map(|i| BitVec::from_vec(vec![i.wrapping_mul(37), i, 1]))
+        .collect();
+    for (i, key) in keys.iter().enumerate() {
+        bonsai_storage
+            .insert(key, &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(
+            bonsai_storage.get(key).unwrap(),
+            Some(Felt::from(i as u64 + 1))
+        );
+    }
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![200, 0, 1]))
+            .unwrap(),
+        None
+    );
+
+    // A value changed behind the back of the trie
+    let flat_key = bitslice_to_bytes(&keys[3]);
+    let db = &mut bonsai_storage.trie.db_mut().db;
+    db.insert(
+        &DatabaseKey::Flat(&flat_key),
+        &Felt::from(100u64).encode(),
+        None,
+    )
+    .unwrap();
+    assert!(matches!(
+        bonsai_storage.get(&keys[3]),
+        Err(BonsaiStorageError::Corruption(_))
+    ));
+    let db = &mut bonsai_storage.trie.db_mut().db;
+    db.insert(
+        &DatabaseKey::Flat(&flat_key),
+        &Felt::from(4u64).encode(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        bonsai_storage.get(&keys[3]).unwrap(),
+        Some(Felt::from(4u64))
+    );
+
+    // A node changed behind the back of the trie
+    let db = &mut bonsai_storage.trie.db_mut().db;
+    let root = db.get(&DatabaseKey::Trie(&[])).unwrap().unwrap();
+    let mut root = Node::decode(&mut root.as_slice()).unwrap();
+    match &mut root {
+        Node::Binary(binary) => binary.left = NodeHandle::Hash(Felt::ONE),
+        Node::Edge(edge) => edge.child = NodeHandle::Hash(Felt::ONE),
+        Node::Unresolved(_) => unreachable!("the trie isn't empty"),
+    }
+    db.insert(&DatabaseKey::Trie(&[]), &root.encode(), None)
+        .unwrap();
+    assert!(matches!(
+        bonsai_storage.get(&keys[0]),
+        Err(BonsaiStorageError::Corruption(_))
+    ));
+}
+
+#[test]
+fn byte_keys() {
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+    )
+    .unwrap();
+    let key = Felt::from_hex("0x2a5e1b0b2c7a3e1d").unwrap().to_bytes_be();
+    let bits = &key.view_bits::<Msb0>()[5..];
+
+    bonsai_storage
+        .insert_bytes(&key, 251, &Felt::from(1u64))
+        .unwrap();
+    assert_eq!(bonsai_storage.get(bits).unwrap(), Some(Felt::from(1u64)));
+    assert_eq!(
+        bonsai_storage.get_bytes(&key, 251).unwrap(),
+        Some(Felt::from(1u64))
+    );
+    assert!(bonsai_storage.contains_bytes(&key, 251).unwrap());
+    // The same bytes with another length are another key
+    assert!(!bonsai_storage.contains_bytes(&key, 250).unwrap());
+
+    bonsai_storage.remove_bytes(&key, 251).unwrap();
+    assert_eq!(bonsai_storage.get(bits).unwrap(), None);
+
+    // A slice too short for the length is rejected
+    assert!(bonsai_storage
+        .insert_bytes(&key[..8], 65, &Felt::from(1u64))
+        .is_err());
+    assert!(bonsai_storage.get_bytes(&key[..8], 65).is_err());
+    assert!(bonsai_storage.contains_bytes(&[], 1).is_err());
+}
+
+#[test]
+fn felt_keys() {
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+    )
+    .unwrap();
+    let key = Felt::from_hex("0x7ff0000000000000000000000000000000000000000000000000000000000a1")
+        .unwrap();
+    bonsai_storage
+        .insert_felt_key(&key, &Felt::from(1u64))
+        .unwrap();
+    let bytes = key.to_bytes_be();
+    assert_eq!(
+        bonsai_storage.get(&bytes.view_bits::<Msb0>()[5..]).unwrap(),
+        Some(Felt::from(1u64))
+    );
+    assert_eq!(
+        bonsai_storage.get_felt_key(&key).unwrap(),
+        Some(Felt::from(1u64))
+    );
+    assert!(bonsai_storage.contains_felt_key(&key).unwrap());
+    bonsai_storage.remove_felt_key(&key).unwrap();
+    assert!(!bonsai_storage.contains_felt_key(&key).unwrap());
+
+    // 2^251 is the first felt out of the key space
+    let too_big =
+        Felt::from_hex("0x800000000000000000000000000000000000000000000000000000000000000")
+            .unwrap();
+    assert!(bonsai_storage
+        .insert_felt_key(&too_big, &Felt::from(1u64))
+        .is_err());
+    assert!(bonsai_storage.get_felt_key(&too_big).is_err());
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn parallel_hashing() {
+    let roots = |hash_threads| {
+        let config = BonsaiStorageConfig {
+            hash_threads,
+            ..Default::default()
+        };
+        let mut bonsai_storage =
+            BonsaiStorage::<_, _, Pedersen>::new(HashMapDb::<BasicId>::default(), config).unwrap();
+        let mut id_builder = BasicIdBuilder::new();
+        let mut roots = Vec::new();
+        for i in 0..500u64 {
+            let key = BitVec::from_vec((i * 7919).to_be_bytes()[5..].to_vec());
+            bonsai_storage.insert(&key, &Felt::from(i + 1)).unwrap();
+        }
+        bonsai_storage.commit(id_builder.new_id()).unwrap();
+        roots.push(bonsai_storage.root_hash().unwrap());
+        // Updates and removals spread over the trie
+        for i in (0..500u64).step_by(3) {
+            let key = BitVec::from_vec((i * 7919).to_be_bytes()[5..].to_vec());
+            if i % 2 == 0 {
+                bonsai_storage.remove(&key).unwrap();
+            } else {
+                bonsai_storage.insert(&key, &Felt::from(i + 1000)).unwrap();
+            }
+        }
+        bonsai_storage.commit(id_builder.new_id()).unwrap();
+        roots.push(bonsai_storage.root_hash().unwrap());
+        roots
+    };
+
+    let sequential = roots(Some(1));
+    assert_eq!(roots(Some(4)), sequential);
+    assert_eq!(roots(None), sequential);
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn node_cache() {
+    use core::num::NonZeroUsize;
+
+    let mut cached = BonsaiStorage::<_, _, Pedersen>::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig {
+            node_cache_size: NonZeroUsize::new(16),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut uncached = BonsaiStorage::<_, _, Pedersen>::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = |i: u64| BitVec::<u8, Msb0>::from_vec((i * 7919).to_be_bytes()[5..].to_vec());
+
+    let mut ids = Vec::new();
+    for round in 0..5u64 {
+        for i in 0..100 {
+            let value = Felt::from(round * 1000 + i + 1);
+            cached.insert(&key(i), &value).unwrap();
+            uncached.insert(&key(i), &value).unwrap();
+        }
+        let id = id_builder.new_id();
+        cached.commit(id).unwrap();
+        uncached.commit(id).unwrap();
+        ids.push(id);
+        assert_eq!(cached.root_hash().unwrap(), uncached.root_hash().unwrap());
+        for i in (0..100).step_by(7) {
+            assert_eq!(
+                cached.get_proof(&key(i)).unwrap(),
+                uncached.get_proof(&key(i)).unwrap()
+            );
+        }
+    }
+
+    // A leaf whose value is the hash of a cached node is still read as a leaf
+    let root = cached.root_hash().unwrap();
+    cached.insert(&key(3), &root).unwrap();
+    uncached.insert(&key(3), &root).unwrap();
+    let id = id_builder.new_id();
+    cached.commit(id).unwrap();
+    uncached.commit(id).unwrap();
+    assert_eq!(cached.root_hash().unwrap(), uncached.root_hash().unwrap());
+    assert_eq!(
+        cached.get_proof(&key(3)).unwrap(),
+        uncached.get_proof(&key(3)).unwrap()
+    );
+
+    // Cached nodes stay valid after a revert
+    cached.revert_to(ids[1]).unwrap();
+    uncached.revert_to(ids[1]).unwrap();
+    assert_eq!(cached.root_hash().unwrap(), uncached.root_hash().unwrap());
+    for i in 0..100 {
+        cached.insert(&key(i), &Felt::from(i + 5)).unwrap();
+        uncached.insert(&key(i), &Felt::from(i + 5)).unwrap();
+    }
+    let id = id_builder.new_id();
+    cached.commit(id).unwrap();
+    uncached.commit(id).unwrap();
+    assert_eq!(cached.root_hash().unwrap(), uncached.root_hash().unwrap());
+}
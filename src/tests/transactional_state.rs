@@ -2,7 +2,7 @@
 use crate::{
     databases::{create_rocks_db, RocksDB, RocksDBConfig},
     id::BasicIdBuilder,
-    BonsaiStorage, BonsaiStorageConfig,
+    BonsaiStorage, BonsaiStorageConfig, ConflictPolicy, MergeConflict,
 };
 use bitvec::vec::BitVec;
 use starknet_types_core::{felt::Felt, hash::Pedersen};
@@ -344,6 +344,68 @@ fn merge_invalid() {
     bonsai_storage.merge(bonsai_at_txn).unwrap_err();
 }
 
+#[test]
+fn merge_many() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let [a, b, c, d] = [1, 2, 3, 4].map(|i| BitVec::from_vec(vec![1, 2, i]));
+
+    let id1 = id_builder.new_id();
+    bonsai_storage.insert(&a, &Felt::from(1)).unwrap();
+    bonsai_storage.commit(id1).unwrap();
+
+    let states = |bonsai_storage: &BonsaiStorage<_, _, Pedersen>,
+                  id_builder: &mut BasicIdBuilder| {
+        let mut state1: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+            .get_transactional_state(id1, BonsaiStorageConfig::default())
+            .unwrap()
+            .unwrap();
+        state1.insert(&b, &Felt::from(2)).unwrap();
+        state1.insert(&c, &Felt::from(3)).unwrap();
+        let mut state2: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+            .get_transactional_state(id1, BonsaiStorageConfig::default())
+            .unwrap()
+            .unwrap();
+        state2.insert(&b, &Felt::from(2)).unwrap();
+        state2.insert(&c, &Felt::from(4)).unwrap();
+        state2.transactional_commit(id_builder.new_id()).unwrap();
+        // Not committed yet
+        state2.insert(&d, &Felt::from(5)).unwrap();
+        state2.remove(&a).unwrap();
+        vec![state1, state2]
+    };
+
+    let conflicts = vec![MergeConflict {
+        key: c.clone(),
+        values: vec![(0, Some(Felt::from(3))), (1, Some(Felt::from(4)))],
+    }];
+    let states1 = states(&bonsai_storage, &mut id_builder);
+    assert_eq!(bonsai_storage.find_conflicts(&states1).unwrap(), conflicts);
+    // Nothing was merged
+    let id = id_builder.new_id();
+    bonsai_storage
+        .merge_transactional_states(states1, id, ConflictPolicy::Fail)
+        .unwrap_err();
+    assert_eq!(bonsai_storage.get(&b).unwrap(), None);
+
+    let states2 = states(&bonsai_storage, &mut id_builder);
+    let id = id_builder.new_id();
+    assert_eq!(
+        bonsai_storage
+            .merge_transactional_states(states2, id, ConflictPolicy::LastWins)
+            .unwrap(),
+        conflicts
+    );
+    assert_eq!(bonsai_storage.get(&a).unwrap(), None);
+    assert_eq!(bonsai_storage.get(&b).unwrap(), Some(Felt::from(2)));
+    assert_eq!(bonsai_storage.get(&c).unwrap(), Some(Felt::from(4)));
+    assert_eq!(bonsai_storage.get(&d).unwrap(), Some(Felt::from(5)));
+}
+
 #[test]
 fn many_snapshots() {
     let tempdir = tempfile::tempdir().unwrap();
@@ -1,11 +1,18 @@
 #![cfg(feature = "std")]
 use crate::{
     databases::{create_rocks_db, RocksDB, RocksDBConfig},
-    id::BasicIdBuilder,
-    BonsaiStorage, BonsaiStorageConfig, BonsaiTrieHash,
+    id::{BasicId, BasicIdBuilder, BlockSubId, BlockSubIdBuilder, Id, U64Id, U64IdBuilder},
+    trie_log::TrieLogKey,
+    BonsaiStorage, BonsaiStorageConfig, BonsaiStorageError, BonsaiTrieHash, CommitObserver,
+    KeyChange, Membership, PruneReport, StateDiff, TrieLogAge,
 };
-use bitvec::vec::BitVec;
+use bitvec::{order::Msb0, vec::BitVec};
+use parity_scale_codec::Encode;
 use starknet_types_core::{felt::Felt, hash::Pedersen};
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+};
 
 #[test]
 fn basics() {
@@ -51,6 +58,58 @@ fn basics() {
     assert_eq!(root_hash1, revert_root_hash1);
 }
 
+#[test]
+fn deep_revert() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut ids = Vec::new();
+    let mut roots = Vec::new();
+    for i in 0..12u64 {
+        let id = id_builder.new_id();
+        // One key changed by every commit, others added or removed along the way
+        bonsai_storage.insert(&key, &Felt::from(i + 1)).unwrap();
+        bonsai_storage
+            .insert(&BitVec::from_vec(vec![1, 3, i as u8]), &Felt::from(i + 100))
+            .unwrap();
+        if i > 0 {
+            bonsai_storage
+                .remove(&BitVec::from_vec(vec![1, 3, i as u8 - 1]))
+                .unwrap();
+        }
+        bonsai_storage.commit(id).unwrap();
+        ids.push(id);
+        roots.push(bonsai_storage.root_hash().unwrap());
+    }
+
+    bonsai_storage.revert_to(ids[3]).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), roots[3]);
+    assert_eq!(bonsai_storage.get(&key).unwrap(), Some(Felt::from(4)));
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![1, 3, 3]))
+            .unwrap(),
+        Some(Felt::from(103))
+    );
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![1, 3, 8]))
+            .unwrap(),
+        None
+    );
+    assert_eq!(bonsai_storage.len().unwrap(), 2);
+    assert_eq!(bonsai_storage.latest_id(), Some(ids[3]));
+
+    bonsai_storage.revert_to(ids[1]).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), roots[1]);
+    assert_eq!(bonsai_storage.get(&key).unwrap(), Some(Felt::from(2)));
+}
+
 #[test]
 fn unrecorded_revert() {
     let tempdir = tempfile::tempdir().unwrap();
@@ -191,3 +250,1141 @@ fn remove_and_reinsert() {
     bonsai_storage.revert_to(id1).unwrap();
     assert_eq!(root_hash1, bonsai_storage.root_hash().unwrap());
 }
+
+#[test]
+fn get_at() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+
+    let id1 = id_builder.new_id();
+    bonsai_storage.insert(&key1, &Felt::from(1)).unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.insert(&key1, &Felt::from(2)).unwrap();
+    bonsai_storage.insert(&key2, &Felt::from(3)).unwrap();
+    bonsai_storage.commit(id2).unwrap();
+    // Nothing changes for the keys at id3
+    let id3 = id_builder.new_id();
+    bonsai_storage.insert(&key2, &Felt::from(3)).unwrap();
+    bonsai_storage.commit(id3).unwrap();
+    let id4 = id_builder.new_id();
+    bonsai_storage.remove(&key1).unwrap();
+    bonsai_storage.commit(id4).unwrap();
+    // Uncommitted changes aren't seen
+    bonsai_storage.insert(&key1, &Felt::from(5)).unwrap();
+
+    for (id, value1, value2) in [
+        (id1, Some(Felt::from(1)), None),
+        (id2, Some(Felt::from(2)), Some(Felt::from(3))),
+        (id3, Some(Felt::from(2)), Some(Felt::from(3))),
+        (id4, None, Some(Felt::from(3))),
+    ] {
+        assert_eq!(bonsai_storage.get_at(id, &key1).unwrap(), value1);
+        assert_eq!(bonsai_storage.get_at(id, &key2).unwrap(), value2);
+    }
+    assert!(bonsai_storage.get_at(id_builder.new_id(), &key1).is_err());
+}
+
+#[test]
+fn root_hash_at() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(3),
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let mut roots = Vec::new();
+    for i in 0..5u64 {
+        let id = id_builder.new_id();
+        bonsai_storage
+            .insert(&BitVec::from_vec(vec![1, 2, i as u8]), &Felt::from(i + 1))
+            .unwrap();
+        if i == 3 {
+            bonsai_storage
+                .remove(&BitVec::from_vec(vec![1, 2, 0]))
+                .unwrap();
+        }
+        bonsai_storage.commit(id).unwrap();
+        roots.push((id, bonsai_storage.root_hash().unwrap()));
+    }
+    // Uncommitted changes aren't seen
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 9]), &Felt::from(10))
+        .unwrap();
+
+    // The first commits don't have a trie log anymore
+    for (id, _) in &roots[..2] {
+        assert!(bonsai_storage.root_hash_at(*id).is_err());
+    }
+    for (id, root) in &roots[2..] {
+        assert_eq!(bonsai_storage.root_hash_at(*id).unwrap(), *root);
+    }
+
+    let (id, root) = roots[3];
+    bonsai_storage.revert_to(id).unwrap();
+    assert_eq!(bonsai_storage.root_hash_at(id).unwrap(), root);
+    assert!(bonsai_storage.root_hash_at(roots[4].0).is_err());
+}
+
+#[test]
+fn replay() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut source: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config.clone()).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let mut ids = Vec::new();
+    for i in 0..4u64 {
+        let id = id_builder.new_id();
+        source
+            .insert(&BitVec::from_vec(vec![1, 2, i as u8]), &Felt::from(i + 1))
+            .unwrap();
+        source
+            .insert(&BitVec::from_vec(vec![1, 2, 0]), &Felt::from(10 + i))
+            .unwrap();
+        if i == 3 {
+            source.remove(&BitVec::from_vec(vec![1, 2, 1])).unwrap();
+        }
+        source.commit(id).unwrap();
+        ids.push(id);
+    }
+
+    let target_dir = tempfile::tempdir().unwrap();
+    let target_db = create_rocks_db(target_dir.path()).unwrap();
+    let mut target: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&target_db, RocksDBConfig::default()), config).unwrap();
+    // The second commit doesn't apply to an empty trie
+    assert!(target
+        .replay(&source.get_trie_log(ids[1]).unwrap())
+        .is_err());
+
+    let mut tampered = source.get_trie_log(ids[0]).unwrap();
+    for entry in tampered.entries.iter_mut() {
+        if let TrieLogKey::Flat(_) = entry.key {
+            entry.new_value = Some(Felt::from(42).encode());
+        }
+    }
+    assert!(matches!(
+        target.replay(&tampered),
+        Err(BonsaiStorageError::Corruption(_))
+    ));
+    assert_eq!(target.root_hash().unwrap(), Felt::ZERO);
+
+    for id in ids.iter() {
+        target.replay(&source.get_trie_log(*id).unwrap()).unwrap();
+        assert_eq!(
+            target.root_hash().unwrap(),
+            source.root_hash_at(*id).unwrap()
+        );
+    }
+    assert_eq!(target.len().unwrap(), source.len().unwrap());
+    assert_eq!(
+        target.get(&BitVec::from_vec(vec![1, 2, 0])).unwrap(),
+        Some(Felt::from(13))
+    );
+}
+
+#[test]
+fn export_import_trie_logs() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut source: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config.clone()).unwrap();
+    let mut id_builder = U64IdBuilder::new(0);
+
+    let mut ids = Vec::new();
+    for i in 0..4u64 {
+        let id = id_builder.new_id();
+        source
+            .insert(&BitVec::from_vec(vec![1, 2, i as u8]), &Felt::from(i + 1))
+            .unwrap();
+        if i == 3 {
+            source.remove(&BitVec::from_vec(vec![1, 2, 1])).unwrap();
+        }
+        source.commit(id).unwrap();
+        ids.push(id);
+    }
+
+    let mut archive = Vec::new();
+    assert_eq!(
+        source
+            .export_trie_logs(ids[1]..=ids[2], &mut archive)
+            .unwrap(),
+        2
+    );
+    let mut archive = Vec::new();
+    assert_eq!(source.export_trie_logs(.., &mut archive).unwrap(), 4);
+
+    let id = |bytes: &[u8]| Some(U64Id(u64::from_be_bytes(bytes.try_into().ok()?)));
+    let target_dir = tempfile::tempdir().unwrap();
+    let target_db = create_rocks_db(target_dir.path()).unwrap();
+    let mut target: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        RocksDB::new(&target_db, RocksDBConfig::default()),
+        config.clone(),
+    )
+    .unwrap();
+    // The trie logs before the truncated one are replayed
+    assert!(target
+        .import_trie_logs(&archive[..archive.len() - 1], id)
+        .is_err());
+    assert_eq!(
+        target.root_hash().unwrap(),
+        source.root_hash_at(ids[2]).unwrap()
+    );
+
+    let target_dir = tempfile::tempdir().unwrap();
+    let target_db = create_rocks_db(target_dir.path()).unwrap();
+    let mut target: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&target_db, RocksDBConfig::default()), config).unwrap();
+    assert_eq!(target.import_trie_logs(archive.as_slice(), id).unwrap(), 4);
+    assert_eq!(target.root_hash().unwrap(), source.root_hash().unwrap());
+    for id in ids.iter() {
+        assert_eq!(
+            target.get_trie_log(*id).unwrap(),
+            source.get_trie_log(*id).unwrap()
+        );
+    }
+}
+
+#[test]
+fn tags() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(3),
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let mut roots = Vec::new();
+    for i in 0..4u64 {
+        let id = id_builder.new_id();
+        bonsai_storage
+            .insert(&BitVec::from_vec(vec![1, 2, i as u8]), &Felt::from(i + 1))
+            .unwrap();
+        bonsai_storage.commit(id).unwrap();
+        roots.push((id, bonsai_storage.root_hash().unwrap()));
+    }
+    // The trie log of the first commit was removed
+    assert!(bonsai_storage.tag(roots[0].0, "genesis").is_err());
+    bonsai_storage.tag(roots[1].0, "pre-upgrade").unwrap();
+    bonsai_storage.tag(roots[3].0, "head").unwrap();
+    assert_eq!(
+        bonsai_storage.resolve_tag("pre-upgrade").unwrap(),
+        Some(roots[1].0)
+    );
+    assert_eq!(bonsai_storage.resolve_tag("genesis").unwrap(), None);
+
+    // Tagging another commit moves the tag
+    bonsai_storage.tag(roots[2].0, "pre-upgrade").unwrap();
+    let id = bonsai_storage.resolve_tag("pre-upgrade").unwrap().unwrap();
+    assert_eq!(id, roots[2].0);
+    assert_eq!(bonsai_storage.view_at(id).unwrap().root_hash(), roots[2].1);
+
+    bonsai_storage.revert_to(id).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), roots[2].1);
+    // The reverted commit isn't recorded anymore
+    assert_eq!(bonsai_storage.resolve_tag("head").unwrap(), None);
+    assert_eq!(bonsai_storage.resolve_tag("pre-upgrade").unwrap(), Some(id));
+}
+
+#[test]
+fn pruning_policies() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let ids: Vec<BasicId> = (0..8).map(|_| id_builder.new_id()).collect();
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(1),
+        // A commit every 10 units of time, the 3 latest ones are recent enough
+        max_trie_log_age: Some(TrieLogAge {
+            max_age: 20,
+            timestamp: |id| u64::from_be_bytes(id.try_into().unwrap()) * 10,
+        }),
+        pinned_trie_logs: vec![ids[2].to_bytes()],
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut roots = Vec::new();
+    for (i, id) in ids.iter().enumerate() {
+        bonsai_storage
+            .insert(
+                &BitVec::from_vec(vec![1, 2, i as u8]),
+                &Felt::from(i as u64 + 1),
+            )
+            .unwrap();
+        bonsai_storage
+            .insert(&key, &Felt::from(100 + i as u64))
+            .unwrap();
+        bonsai_storage.commit(*id).unwrap();
+        roots.push(bonsai_storage.root_hash().unwrap());
+    }
+    // The commits before the pinned one are dropped, the ones between the pinned one and the
+    // recent ones are merged into the next commit
+    for (i, id) in ids.iter().enumerate() {
+        let kept = i == 2 || i >= 5;
+        assert_eq!(bonsai_storage.root_hash_at(*id).is_ok(), kept);
+    }
+    let view = bonsai_storage.view_at(ids[2]).unwrap();
+    assert_eq!(view.root_hash(), roots[2]);
+    assert_eq!(view.get(&key).unwrap(), Some(Felt::from(102)));
+    assert_eq!(view.get(&BitVec::from_vec(vec![1, 2, 4])).unwrap(), None);
+    let view = bonsai_storage.view_at(ids[5]).unwrap();
+    assert_eq!(view.get(&key).unwrap(), Some(Felt::from(105)));
+    assert_eq!(
+        bonsai_storage
+            .keys_changed_between(ids[2], ids[5])
+            .unwrap()
+            .len(),
+        3
+    );
+    assert_eq!(bonsai_storage.prune().unwrap(), PruneReport::default());
+
+    bonsai_storage.revert_to(ids[2]).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), roots[2]);
+    assert_eq!(bonsai_storage.get(&key).unwrap(), Some(Felt::from(102)));
+}
+
+#[test]
+fn truncate_history_before() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut ids = Vec::new();
+    for i in 0..8u64 {
+        let id = id_builder.new_id();
+        bonsai_storage.insert(&key, &Felt::from(i + 1)).unwrap();
+        bonsai_storage.commit(id).unwrap();
+        ids.push(id);
+    }
+    bonsai_storage.pin(ids[0]).unwrap();
+    // Snapshots are taken every 5 commits
+    assert_eq!(
+        bonsai_storage.truncate_history_before(ids[6]).unwrap(),
+        PruneReport {
+            trie_logs: 6,
+            snapshots: 2
+        }
+    );
+    assert!(bonsai_storage.root_hash_at(ids[5]).is_err());
+    assert!(bonsai_storage.revert_to(ids[5]).is_err());
+    assert!(bonsai_storage.truncate_history_before(ids[5]).is_err());
+    assert_eq!(
+        bonsai_storage.view_at(ids[6]).unwrap().get(&key).unwrap(),
+        Some(Felt::from(7))
+    );
+    assert_eq!(bonsai_storage.prune().unwrap(), PruneReport::default());
+
+    bonsai_storage.revert_to(ids[6]).unwrap();
+    assert_eq!(bonsai_storage.get(&key).unwrap(), Some(Felt::from(7)));
+}
+
+#[test]
+fn stored_ids() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(3),
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    assert_eq!(bonsai_storage.latest_id(), None);
+    assert_eq!(bonsai_storage.oldest_retained_id(), None);
+    assert_eq!(bonsai_storage.iter_ids().count(), 0);
+
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut ids = Vec::new();
+    for i in 0..5u64 {
+        let id = id_builder.new_id();
+        bonsai_storage.insert(&key, &Felt::from(i + 1)).unwrap();
+        bonsai_storage.commit(id).unwrap();
+        ids.push(id);
+    }
+    assert_eq!(bonsai_storage.latest_id(), Some(ids[4]));
+    assert_eq!(bonsai_storage.oldest_retained_id(), Some(ids[2]));
+    assert_eq!(bonsai_storage.iter_ids().collect::<Vec<_>>(), ids[2..]);
+    for id in bonsai_storage.iter_ids().collect::<Vec<_>>() {
+        assert!(bonsai_storage.view_at(id).is_ok());
+    }
+
+    bonsai_storage.revert_to(ids[3]).unwrap();
+    assert_eq!(bonsai_storage.latest_id(), Some(ids[3]));
+    assert_eq!(bonsai_storage.iter_ids().collect::<Vec<_>>(), ids[2..4]);
+}
+
+#[test]
+fn archive() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(2),
+        archive: true,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut ids = Vec::new();
+    let mut roots = Vec::new();
+    for i in 0..6u64 {
+        let id = id_builder.new_id();
+        bonsai_storage.insert(&key, &Felt::from(i + 1)).unwrap();
+        bonsai_storage.commit(id).unwrap();
+        ids.push(id);
+        roots.push(bonsai_storage.root_hash().unwrap());
+    }
+    // Nothing is pruned
+    assert_eq!(bonsai_storage.iter_ids().count(), 6);
+    for (i, root) in roots.iter().enumerate() {
+        let view = bonsai_storage.view_by_root(*root).unwrap();
+        assert_eq!(view.id(), ids[i]);
+        assert_eq!(view.get(&key).unwrap(), Some(Felt::from(i as u64 + 1)));
+    }
+    assert!(bonsai_storage.view_by_root(Felt::from(42)).is_err());
+
+    bonsai_storage.revert_to(ids[3]).unwrap();
+    assert!(bonsai_storage.view_by_root(roots[4]).is_err());
+    assert_eq!(bonsai_storage.view_by_root(roots[3]).unwrap().id(), ids[3]);
+    // Truncating doesn't drop the history of an archive
+    assert_eq!(
+        bonsai_storage.truncate_history_before(ids[2]).unwrap(),
+        PruneReport::default()
+    );
+    assert_eq!(bonsai_storage.iter_ids().count(), 4);
+    assert_eq!(bonsai_storage.view_by_root(roots[1]).unwrap().id(), ids[1]);
+    assert!(bonsai_storage.truncate_history_before(ids[4]).is_err());
+    // Neither does compacting it
+    assert_eq!(
+        bonsai_storage
+            .compact_history(NonZeroUsize::new(2).unwrap())
+            .unwrap(),
+        PruneReport::default()
+    );
+    assert_eq!(bonsai_storage.iter_ids().count(), 4);
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        RocksDB::new(&db, RocksDBConfig::default()),
+        BonsaiStorageConfig::default(),
+    )
+    .unwrap();
+    bonsai_storage.insert(&key, &Felt::from(1)).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root = bonsai_storage.root_hash().unwrap();
+    assert!(bonsai_storage.view_by_root(root).is_err());
+}
+
+#[test]
+fn commit_metadata() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(3),
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut ids = Vec::new();
+    for i in 0..4u64 {
+        let id = id_builder.new_id();
+        bonsai_storage.insert(&key, &Felt::from(i + 1)).unwrap();
+        if i == 2 {
+            bonsai_storage.commit(id).unwrap();
+        } else {
+            bonsai_storage
+                .commit_with_metadata(id, format!("block {}", i).as_bytes())
+                .unwrap();
+        }
+        ids.push(id);
+    }
+    // The first commit is pruned along with its metadata
+    assert!(bonsai_storage.get_commit_metadata(ids[0]).is_err());
+    assert_eq!(
+        bonsai_storage.get_commit_metadata(ids[1]).unwrap(),
+        Some(b"block 1".to_vec())
+    );
+    assert_eq!(bonsai_storage.get_commit_metadata(ids[2]).unwrap(), None);
+    assert_eq!(
+        bonsai_storage.get_commit_metadata(ids[3]).unwrap(),
+        Some(b"block 3".to_vec())
+    );
+
+    bonsai_storage.revert_to(ids[2]).unwrap();
+    assert!(bonsai_storage.get_commit_metadata(ids[3]).is_err());
+    bonsai_storage
+        .commit_with_metadata(ids[3], b"other block 3")
+        .unwrap();
+    assert_eq!(
+        bonsai_storage.get_commit_metadata(ids[3]).unwrap(),
+        Some(b"other block 3".to_vec())
+    );
+}
+
+#[test]
+fn compact_history() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: None,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut ids = Vec::new();
+    let mut roots = Vec::new();
+    for i in 0..10u64 {
+        let id = id_builder.new_id();
+        bonsai_storage.insert(&key, &Felt::from(i + 1)).unwrap();
+        bonsai_storage
+            .insert(&BitVec::from_vec(vec![1, 3, i as u8]), &Felt::from(i + 100))
+            .unwrap();
+        bonsai_storage.commit(id).unwrap();
+        ids.push(id);
+        roots.push(bonsai_storage.root_hash().unwrap());
+    }
+    bonsai_storage.pin(ids[5]).unwrap();
+    // The snapshots are the ones of the commits 0 and 5, both kept
+    let report = bonsai_storage
+        .compact_history(NonZeroUsize::new(4).unwrap())
+        .unwrap();
+    assert_eq!(
+        report,
+        PruneReport {
+            trie_logs: 5,
+            snapshots: 0
+        }
+    );
+    assert_eq!(
+        bonsai_storage.iter_ids().collect::<Vec<_>>(),
+        [ids[0], ids[4], ids[5], ids[8], ids[9]]
+    );
+    for i in [0, 4, 5, 8] {
+        assert_eq!(bonsai_storage.root_hash_at(ids[i]).unwrap(), roots[i]);
+        assert_eq!(
+            bonsai_storage.view_at(ids[i]).unwrap().get(&key).unwrap(),
+            Some(Felt::from(i as u64 + 1))
+        );
+    }
+    assert!(bonsai_storage.revert_to(ids[6]).is_err());
+
+    bonsai_storage.revert_to(ids[4]).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), roots[4]);
+    bonsai_storage.revert_to(ids[0]).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), roots[0]);
+    assert_eq!(bonsai_storage.len().unwrap(), 2);
+}
+
+#[test]
+fn commit_id_order() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut ids = Vec::new();
+    let mut roots = Vec::new();
+    for i in 0..4u64 {
+        let id = id_builder.new_id();
+        bonsai_storage.insert(&key, &Felt::from(i + 1)).unwrap();
+        bonsai_storage
+            .insert(&BitVec::from_vec(vec![1, 3, i as u8]), &Felt::from(i + 100))
+            .unwrap();
+        bonsai_storage.commit(id).unwrap();
+        ids.push(id);
+        roots.push(bonsai_storage.root_hash().unwrap());
+    }
+
+    bonsai_storage.insert(&key, &Felt::from(42)).unwrap();
+    assert!(matches!(
+        bonsai_storage.commit(ids[3]),
+        Err(BonsaiStorageError::CommitId(_))
+    ));
+    assert!(matches!(
+        bonsai_storage.commit(ids[1]),
+        Err(BonsaiStorageError::CommitId(_))
+    ));
+
+    bonsai_storage.force_commit(ids[2]).unwrap();
+    assert_eq!(bonsai_storage.iter_ids().collect::<Vec<_>>(), ids[..3]);
+    assert_eq!(bonsai_storage.get(&key).unwrap(), Some(Felt::from(42)));
+    // The changes of the forgotten commits stay
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![1, 3, 3]))
+            .unwrap(),
+        Some(Felt::from(103))
+    );
+    assert_eq!(
+        bonsai_storage.view_at(ids[1]).unwrap().get(&key).unwrap(),
+        Some(Felt::from(2))
+    );
+
+    bonsai_storage.revert_to(ids[1]).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), roots[1]);
+    assert_eq!(bonsai_storage.len().unwrap(), 3);
+}
+
+#[test]
+fn force_commit_forgets_records() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let mut ids = Vec::new();
+    for i in 0..3u8 {
+        let id = id_builder.new_id();
+        bonsai_storage
+            .insert(&BitVec::from_vec(vec![1, 3, i]), &Felt::from(i + 100))
+            .unwrap();
+        bonsai_storage.commit_with_metadata(id, &[i]).unwrap();
+        ids.push(id);
+    }
+
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![2, 2, 2]), &Felt::ONE)
+        .unwrap();
+    bonsai_storage.force_commit(ids[1]).unwrap();
+    assert_eq!(bonsai_storage.iter_ids().collect::<Vec<_>>(), ids[..2]);
+    // The metadata of the forgotten commit isn't the one of the new commit
+    assert_eq!(bonsai_storage.get_commit_metadata(ids[1]).unwrap(), None);
+    assert_eq!(
+        bonsai_storage.get_commit_metadata(ids[0]).unwrap(),
+        Some(vec![0])
+    );
+    // The trie log of the new commit has the changes of the forgotten ones
+    let changes = bonsai_storage.get_changes(ids[1]).unwrap();
+    assert_eq!(changes.len(), 3);
+
+    bonsai_storage.revert_to(ids[0]).unwrap();
+    assert_eq!(bonsai_storage.len().unwrap(), 1);
+    assert_eq!(
+        bonsai_storage.get_commit_metadata(ids[0]).unwrap(),
+        Some(vec![0])
+    );
+}
+
+#[test]
+fn clear() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    for i in 0..10u8 {
+        bonsai_storage
+            .insert(&BitVec::from_vec(vec![1, 3, i]), &Felt::from(i + 100))
+            .unwrap();
+    }
+    let id1 = id_builder.new_id();
+    bonsai_storage
+        .commit_with_metadata(id1, b"block 1")
+        .unwrap();
+    let root1 = bonsai_storage.root_hash().unwrap();
+
+    // Pending changes are discarded
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![2, 2, 2]), &Felt::ONE)
+        .unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.clear(id2).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), Felt::ZERO);
+    assert_eq!(bonsai_storage.len().unwrap(), 0);
+    // The records of the commits aren't part of the trie
+    assert_eq!(
+        bonsai_storage.get_commit_metadata(id1).unwrap(),
+        Some(b"block 1".to_vec())
+    );
+    // Each leaf is recorded in the trie log
+    let removed = bonsai_storage
+        .get_trie_log(id2)
+        .unwrap()
+        .entries
+        .into_iter()
+        .filter(|entry| matches!(entry.key, TrieLogKey::Flat(_)) && entry.new_value.is_none())
+        .count();
+    assert_eq!(removed, 10);
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![1, 3, 4]))
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![2, 2, 2]))
+            .unwrap(),
+        None
+    );
+
+    // The cleared trie is used as a new one
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    bonsai_storage.insert(&key, &Felt::ONE).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let tempdir2 = tempfile::tempdir().unwrap();
+    let db2 = create_rocks_db(tempdir2.path()).unwrap();
+    let mut fresh: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        RocksDB::new(&db2, RocksDBConfig::default()),
+        BonsaiStorageConfig::default(),
+    )
+    .unwrap();
+    fresh.insert(&key, &Felt::ONE).unwrap();
+    fresh.commit(BasicIdBuilder::new().new_id()).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash().unwrap(),
+        fresh.root_hash().unwrap()
+    );
+
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(bonsai_storage.root_hash().unwrap(), root1);
+    assert_eq!(bonsai_storage.len().unwrap(), 10);
+    assert_eq!(
+        bonsai_storage.get_commit_metadata(id1).unwrap(),
+        Some(b"block 1".to_vec())
+    );
+    assert_eq!(
+        bonsai_storage
+            .get(&BitVec::from_vec(vec![1, 3, 4]))
+            .unwrap(),
+        Some(Felt::from(104))
+    );
+}
+
+#[test]
+fn pin() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(2),
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut ids = Vec::new();
+    for i in 0..5u64 {
+        let id = id_builder.new_id();
+        bonsai_storage.insert(&key, &Felt::from(i + 1)).unwrap();
+        bonsai_storage.commit(id).unwrap();
+        if i == 0 {
+            bonsai_storage.pin(id).unwrap();
+        }
+        ids.push(id);
+    }
+    assert!(bonsai_storage.pin(ids[1]).is_err());
+    assert!(bonsai_storage.root_hash_at(ids[0]).is_ok());
+    assert_eq!(
+        bonsai_storage.view_at(ids[0]).unwrap().get(&key).unwrap(),
+        Some(Felt::from(1))
+    );
+
+    assert!(bonsai_storage.unpin(ids[0]));
+    assert!(!bonsai_storage.unpin(ids[0]));
+    // The first commit is the only one with a snapshot
+    assert_eq!(
+        bonsai_storage.prune().unwrap(),
+        PruneReport {
+            trie_logs: 1,
+            snapshots: 1
+        }
+    );
+    assert!(bonsai_storage.root_hash_at(ids[0]).is_err());
+    assert_eq!(
+        bonsai_storage.view_at(ids[3]).unwrap().get(&key).unwrap(),
+        Some(Felt::from(4))
+    );
+}
+
+#[test]
+fn block_sub_ids() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<BlockSubId, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BlockSubIdBuilder::new(100);
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+
+    let mut ids = Vec::new();
+    for block_number in [100, 101] {
+        if block_number != 100 {
+            id_builder.start_block(block_number);
+        }
+        for sub_index in 0..3u64 {
+            let id = id_builder.new_id();
+            bonsai_storage
+                .insert(&key, &Felt::from(block_number * 10 + sub_index))
+                .unwrap();
+            bonsai_storage.commit(id).unwrap();
+            ids.push(id);
+        }
+    }
+    assert_eq!(
+        ids[3],
+        BlockSubId {
+            block_number: 101,
+            sub_index: 0
+        }
+    );
+    assert!(ids.windows(2).all(|ids| ids[0] < ids[1]));
+    assert_eq!(
+        bonsai_storage.view_at(ids[2]).unwrap().get(&key).unwrap(),
+        Some(Felt::from(1002))
+    );
+    assert_eq!(U64IdBuilder::new(7).new_id(), U64Id::from(7));
+}
+
+#[derive(Debug, PartialEq)]
+enum CommitEvent {
+    Start(BasicId),
+    Serialized(BasicId, usize, usize),
+    Flushed(BasicId, Felt),
+    Aborted(BasicId),
+}
+
+struct Recorder(Arc<Mutex<Vec<CommitEvent>>>);
+
+impl CommitObserver<BasicId> for Recorder {
+    fn on_commit_start(&mut self, id: BasicId) {
+        self.0.lock().unwrap().push(CommitEvent::Start(id));
+    }
+
+    fn on_changes_serialized(&mut self, id: BasicId, leaves: usize, nodes: usize) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(CommitEvent::Serialized(id, leaves, nodes));
+    }
+
+    fn on_flush_done(&mut self, id: BasicId, root_hash: Felt) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(CommitEvent::Flushed(id, root_hash));
+    }
+
+    fn on_commit_aborted(&mut self, id: BasicId) {
+        self.0.lock().unwrap().push(CommitEvent::Aborted(id));
+    }
+}
+
+#[test]
+fn commit_observer() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    bonsai_storage.add_commit_observer(Recorder(events.clone()));
+
+    let id1 = id_builder.new_id();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 1]), &Felt::from(1))
+        .unwrap();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 2]), &Felt::from(2))
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    let root1 = bonsai_storage.root_hash().unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage
+        .remove(&BitVec::from_vec(vec![1, 2, 1]))
+        .unwrap();
+    bonsai_storage.commit(id2).unwrap();
+    let root2 = bonsai_storage.root_hash().unwrap();
+    // An aborted commit ends with its abort
+    let id3 = id_builder.new_id();
+    bonsai_storage
+        .insert(&BitVec::from_vec(vec![1, 2, 3]), &Felt::from(3))
+        .unwrap();
+    let prepared = bonsai_storage.prepare(id3).unwrap();
+    bonsai_storage.abort(prepared).unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 9);
+    assert_eq!(events[0], CommitEvent::Start(id1));
+    assert!(matches!(events[1], CommitEvent::Serialized(id, 2, nodes) if id == id1 && nodes > 0));
+    assert_eq!(events[2], CommitEvent::Flushed(id1, root1));
+    assert_eq!(events[3], CommitEvent::Start(id2));
+    assert!(matches!(events[4], CommitEvent::Serialized(id, 1, nodes) if id == id2 && nodes > 0));
+    assert_eq!(events[5], CommitEvent::Flushed(id2, root2));
+    assert_eq!(events[6], CommitEvent::Start(id3));
+    assert!(matches!(events[7], CommitEvent::Serialized(id, 1, _) if id == id3));
+    assert_eq!(events[8], CommitEvent::Aborted(id3));
+}
+
+#[test]
+fn watch() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let watched1 = BitVec::from_vec(vec![1, 2, 1]);
+    let watched2 = BitVec::from_vec(vec![1, 2, 2]);
+    let other = BitVec::from_vec(vec![3, 2, 1]);
+
+    let notified = Arc::new(Mutex::new(Vec::new()));
+    let watch_id = bonsai_storage.watch(&BitVec::<u8, Msb0>::from_vec(vec![1, 2]), {
+        let notified = notified.clone();
+        move |id, changes: &[KeyChange]| notified.lock().unwrap().push((id, changes.to_vec()))
+    });
+
+    let id1 = id_builder.new_id();
+    bonsai_storage.insert(&watched2, &Felt::from(1)).unwrap();
+    bonsai_storage.insert(&watched1, &Felt::from(2)).unwrap();
+    bonsai_storage.insert(&other, &Felt::from(3)).unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    // No watched key changes
+    bonsai_storage.insert(&other, &Felt::from(4)).unwrap();
+    bonsai_storage.insert(&watched1, &Felt::from(2)).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let id3 = id_builder.new_id();
+    bonsai_storage.remove(&watched2).unwrap();
+    bonsai_storage.commit(id3).unwrap();
+
+    assert_eq!(
+        *notified.lock().unwrap(),
+        vec![
+            (
+                id1,
+                vec![
+                    KeyChange {
+                        key: watched1.clone(),
+                        old_value: None,
+                        new_value: Some(Felt::from(2)),
+                    },
+                    KeyChange {
+                        key: watched2.clone(),
+                        old_value: None,
+                        new_value: Some(Felt::from(1)),
+                    },
+                ]
+            ),
+            (
+                id3,
+                vec![KeyChange {
+                    key: watched2.clone(),
+                    old_value: Some(Felt::from(1)),
+                    new_value: None,
+                }]
+            ),
+        ]
+    );
+
+    assert!(bonsai_storage.unwatch(watch_id));
+    assert!(!bonsai_storage.unwatch(watch_id));
+    bonsai_storage.insert(&watched1, &Felt::from(5)).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(notified.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn view_at() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let keys: Vec<BitVec<u8, Msb0>> = (0..20u8)
+        .map(|i| BitVec::from_vec(vec![i.wrapping_mul(97), i % 4, 1]))
+        .collect();
+    let absent = BitVec::<u8, Msb0>::from_vec(vec![255, 255, 255]);
+
+    let id1 = id_builder.new_id();
+    for (i, key) in keys.iter().enumerate() {
+        bonsai_storage
+            .insert(key, &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id1).unwrap();
+    let root1 = bonsai_storage.root_hash().unwrap();
+    let pairs1: Vec<_> = bonsai_storage.iter().collect::<Result<_, _>>().unwrap();
+
+    for key in keys.iter().step_by(3) {
+        bonsai_storage.remove(key).unwrap();
+    }
+    bonsai_storage
+        .insert(&keys[1], &Felt::from(100u64))
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    // Uncommitted changes aren't seen
+    bonsai_storage.insert(&absent, &Felt::from(1u64)).unwrap();
+
+    let view = bonsai_storage.view_at(id1).unwrap();
+    assert_eq!(view.id(), id1);
+    assert_eq!(view.root_hash(), root1);
+    assert_eq!(
+        view.iter().unwrap().collect::<Result<Vec<_>, _>>().unwrap(),
+        pairs1
+    );
+    for (key, value) in pairs1.iter() {
+        assert_eq!(view.get(key).unwrap(), Some(*value));
+        assert!(view.contains(key).unwrap());
+        let proof = view.get_proof(key).unwrap();
+        assert_eq!(
+            BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_proof(
+                root1, key, *value, &proof
+            ),
+            Some(Membership::Member)
+        );
+    }
+    assert_eq!(view.get(&absent).unwrap(), None);
+    let proof = view.get_proof(&absent).unwrap();
+    assert!(
+        BonsaiStorage::<BasicId, RocksDB<BasicId>, Pedersen>::verify_non_membership(
+            root1, &absent, &proof
+        )
+    );
+
+    assert!(bonsai_storage.view_at(id_builder.new_id()).is_err());
+}
+
+#[test]
+fn keys_changed_between() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = |i: u8| BitVec::from_vec(vec![i, 2, 1]);
+
+    let id1 = id_builder.new_id();
+    for i in 0..4 {
+        bonsai_storage.insert(&key(i), &Felt::from(i)).unwrap();
+    }
+    bonsai_storage.commit(id1).unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.insert(&key(0), &Felt::from(10)).unwrap();
+    bonsai_storage.insert(&key(1), &Felt::from(11)).unwrap();
+    bonsai_storage.commit(id2).unwrap();
+    let id3 = id_builder.new_id();
+    // Back to its value at id1
+    bonsai_storage.insert(&key(1), &Felt::from(1)).unwrap();
+    bonsai_storage.remove(&key(2)).unwrap();
+    bonsai_storage.insert(&key(4), &Felt::from(4)).unwrap();
+    bonsai_storage.commit(id3).unwrap();
+
+    let changed = |from, to| {
+        bonsai_storage
+            .keys_changed_between(from, to)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(changed(id1, id1), vec![]);
+    assert_eq!(changed(id1, id2), vec![key(0), key(1)]);
+    assert_eq!(changed(id2, id3), vec![key(1), key(2), key(4)]);
+    assert_eq!(changed(id1, id3), vec![key(0), key(2), key(4)]);
+    assert_eq!(changed(id3, id1), changed(id1, id3));
+    assert!(bonsai_storage
+        .keys_changed_between(id1, id_builder.new_id())
+        .is_err());
+}
+
+#[test]
+fn diff() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = |i: u8| BitVec::from_vec(vec![i, 2, 1]);
+
+    let id1 = id_builder.new_id();
+    for i in 0..3 {
+        bonsai_storage.insert(&key(i), &Felt::from(i + 1)).unwrap();
+    }
+    bonsai_storage.commit(id1).unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.insert(&key(0), &Felt::from(10)).unwrap();
+    bonsai_storage.remove(&key(1)).unwrap();
+    bonsai_storage.insert(&key(3), &Felt::from(4)).unwrap();
+    bonsai_storage.commit(id2).unwrap();
+
+    let change = |i, old_value: Option<u8>, new_value: Option<u8>| KeyChange {
+        key: key(i),
+        old_value: old_value.map(Felt::from),
+        new_value: new_value.map(Felt::from),
+    };
+    let diff = bonsai_storage.diff(id1, id2).unwrap();
+    assert_eq!(
+        diff.changes,
+        vec![
+            change(0, Some(1), Some(10)),
+            change(1, Some(2), None),
+            change(3, None, Some(4))
+        ]
+    );
+    assert_eq!(
+        bonsai_storage.diff(id2, id1).unwrap().changes,
+        vec![
+            change(0, Some(10), Some(1)),
+            change(1, None, Some(2)),
+            change(3, Some(4), None)
+        ]
+    );
+    assert_eq!(bonsai_storage.diff(id2, id2).unwrap(), StateDiff::default());
+
+    let json = serde_json::to_value(&diff).unwrap();
+    assert_eq!(
+        json["changes"][1],
+        serde_json::json!({"key": "0x10201", "length": 24, "old_value": "0x2", "new_value": null})
+    );
+    assert_eq!(serde_json::from_value::<StateDiff>(json).unwrap(), diff);
+    assert!(
+        serde_json::from_value::<StateDiff>(serde_json::json!({"changes": [
+            {"key": "0x10201", "length": 8, "old_value": null, "new_value": "0x1"}
+        ]}))
+        .is_err()
+    );
+}
@@ -7,62 +7,38 @@ use bitvec::{
 use core::iter::once;
 use core::marker::PhantomData;
 use core::mem;
+use core::ops::{Bound, ControlFlow, RangeBounds};
 use derive_more::Constructor;
 #[cfg(not(feature = "std"))]
 use hashbrown::HashMap;
+#[cfg(feature = "cache")]
+use lru::LruCache;
 use parity_scale_codec::{Decode, Encode};
 use starknet_types_core::{felt::Felt, hash::StarkHash};
 #[cfg(feature = "std")]
 use std::collections::HashMap;
+#[cfg(feature = "cache")]
+use std::sync::{Mutex, PoisonError};
 
 use crate::{error::BonsaiStorageError, id::Id, BonsaiDatabase, KeyValueDB};
 
+pub use crate::proof::{Membership, ProofNode, RangeProof};
+
 use super::{
     merkle_node::{BinaryNode, Direction, EdgeNode, Node, NodeHandle, NodeId},
     path::Path,
+    trie_db::{is_node_key, LEAF_COUNT_KEY},
     TrieKey,
 };
 
 #[cfg(test)]
 use log::trace;
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum Membership {
-    Member,
-    NonMember,
-}
-
 /// Wrapper type for a [HashMap<NodeId, Node>] object. (It's not really a wrapper it's a
 /// copy of the type but we implement the necessary traits.)
 #[derive(Clone, Debug, PartialEq, Eq, Default, Constructor)]
 pub struct NodesMapping(HashMap<NodeId, Node>);
 
-/// A node used in proof generated by the trie.
-///
-/// See pathfinders merkle-tree crate for more information.
-#[derive(Debug, Clone, PartialEq)]
-pub enum ProofNode {
-    Binary { left: Felt, right: Felt },
-    Edge { child: Felt, path: Path },
-}
-
-impl ProofNode {
-    pub fn hash<H: StarkHash>(&self) -> Felt {
-        match self {
-            ProofNode::Binary { left, right } => H::hash(left, right),
-            ProofNode::Edge { child, path } => {
-                let mut bytes = [0u8; 32];
-                bytes.view_bits_mut::<Msb0>()[256 - path.0.len()..].copy_from_bitslice(&path.0);
-                // SAFETY: path len is <= 251
-                let path_hash = Felt::from_bytes_be(&bytes);
-
-                let length = Felt::from(path.0.len() as u8);
-                H::hash(child, &path_hash) + length
-            }
-        }
-    }
-}
-
 /// A Starknet binary Merkle-Patricia tree with a specific root entry-point and storage.
 ///
 /// This is used to update, mutate and access global Starknet state as well as individual contract
@@ -86,10 +62,53 @@ pub struct MerkleTree<H: StarkHash, DB: BonsaiDatabase, ID: Id> {
     death_row: Vec<TrieKey>,
     /// The list of leaves that have been modified during the current commit.
     cache_leaf_modified: HashMap<Vec<u8>, InsertOrRemove<Felt>>,
+    /// The thread pool hashing the nodes during the commits, built at the first commit when
+    /// the number of threads is configured.
+    #[cfg(feature = "rayon")]
+    hash_pool: Option<rayon::ThreadPool>,
+    /// The most recently read nodes of the database by hash, kept across the commits as a node
+    /// with a given hash never changes.
+    #[cfg(feature = "cache")]
+    node_cache: Option<Mutex<LruCache<Felt, Node>>>,
     /// The hasher used to hash the nodes.
     _hasher: PhantomData<H>,
 }
 
+/// Computes the hash of the subtree of `node_handle` from its in-memory nodes, pushing the hash
+/// of each of them to `hashes`. The two children of the binary nodes are hashed in parallel.
+///
+/// Returns `None` if a node is missing.
+#[cfg(feature = "rayon")]
+fn par_subtree_hash<H: StarkHash>(
+    nodes: &HashMap<NodeId, Node>,
+    node_handle: NodeHandle,
+    hashes: &mut Vec<(NodeId, Felt)>,
+) -> Option<Felt> {
+    let node_id = match node_handle {
+        NodeHandle::Hash(hash) => return Some(hash),
+        NodeHandle::InMemory(node_id) => node_id,
+    };
+    let hash = match nodes.get(&node_id)? {
+        Node::Unresolved(hash) => return Some(*hash),
+        Node::Binary(binary) => {
+            let mut right_hashes = Vec::new();
+            let (left_hash, right_hash) = rayon::join(
+                || par_subtree_hash::<H>(nodes, binary.left, hashes),
+                || par_subtree_hash::<H>(nodes, binary.right, &mut right_hashes),
+            );
+            hashes.append(&mut right_hashes);
+            H::hash(&left_hash?, &right_hash?)
+        }
+        Node::Edge(edge) => ProofNode::Edge {
+            child: par_subtree_hash::<H>(nodes, edge.child, hashes)?,
+            path: edge.path.clone(),
+        }
+        .hash::<H>(),
+    };
+    hashes.push((node_id, hash));
+    Some(hash)
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum InsertOrRemove<T> {
     Insert(T),
@@ -101,20 +120,34 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
     /// [`MerkleTree::<RcNodeStorage>::load`] for persistent trees and [`MerkleTree::empty`] for
     /// transient ones.
     pub fn new(mut db: KeyValueDB<DB, ID>) -> Result<Self, BonsaiStorageError<DB::DatabaseError>> {
-        let nodes_mapping: HashMap<NodeId, Node> = HashMap::new();
-        let root_node = db.get(&TrieKey::Trie(vec![]))?;
-        let node = if let Some(root_node) = root_node {
-            Node::decode(&mut root_node.as_slice())?
-        } else {
+        if db.get(&TrieKey::Trie(vec![]))?.is_none() {
             db.insert(
                 &TrieKey::Trie(vec![]),
                 &Node::Unresolved(Felt::ZERO).encode(),
                 None,
             )?;
-            Node::Unresolved(Felt::ZERO)
-        };
-        // SAFETY: The root node has been created just above
-        let root = node.hash().unwrap();
+        }
+        Self::new_read_only(db)
+    }
+
+    /// Same as [`MerkleTree::new`] but never writes in the underlying database, a database
+    /// without root node is read as an empty trie.
+    pub fn new_read_only(
+        db: KeyValueDB<DB, ID>,
+    ) -> Result<Self, BonsaiStorageError<DB::DatabaseError>> {
+        let nodes_mapping: HashMap<NodeId, Node> = HashMap::new();
+        let root =
+            match db.get(&TrieKey::Trie(vec![]))? {
+                Some(root_node) => Node::decode(&mut root_node.as_slice())?.hash().ok_or(
+                    BonsaiStorageError::Trie("Root doesn't exist in the storage".to_string()),
+                )?,
+                None => Felt::ZERO,
+            };
+        #[cfg(feature = "cache")]
+        let node_cache = db
+            .config
+            .node_cache_size
+            .map(|size| Mutex::new(LruCache::new(size)));
         Ok(Self {
             root_handle: NodeHandle::Hash(root),
             root_hash: root,
@@ -123,6 +156,10 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
             latest_node_id: NodeId(0),
             death_row: Vec::new(),
             cache_leaf_modified: HashMap::new(),
+            #[cfg(feature = "rayon")]
+            hash_pool: None,
+            #[cfg(feature = "cache")]
+            node_cache,
             _hasher: PhantomData,
         })
     }
@@ -131,6 +168,18 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
         self.root_hash
     }
 
+    /// Returns the root hash of the trie at the commit `id`, from the index of the root hashes
+    /// or, for the commits made before it existed, from the trie logs of the later commits.
+    pub fn root_hash_at(&self, id: ID) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        if let Some(root_hash) = self.db.get_root_at(id)? {
+            return Ok(root_hash);
+        }
+        Ok(self
+            .get_trie_branch_at(id, &Path(BitVec::new()))?
+            .and_then(|node| node.hash())
+            .unwrap_or(Felt::ZERO))
+    }
+
     /// Remove all the modifications that have been done since the last commit.
     pub fn reset_to_last_commit(&mut self) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
         let node = self
@@ -144,36 +193,178 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
         self.latest_node_id.reset();
         self.storage_nodes.0.clear();
         self.cache_leaf_modified.clear();
+        self.death_row.clear();
         self.root_handle = NodeHandle::Hash(node_hash);
         self.root_hash = node_hash;
         Ok(())
     }
 
     /// Persists all changes to storage and returns the new root hash.
-    pub fn commit(&mut self) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
-        let mut batch = self.db.create_batch();
+    ///
+    /// The changes are added to `batch`, the trie must be reset to its last commit if the batch
+    /// isn't written.
+    pub fn commit(
+        &mut self,
+        batch: &mut DB::Batch,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
         for node_key in mem::take(&mut self.death_row) {
-            self.db.remove(&node_key, Some(&mut batch))?;
+            self.db.remove(&node_key, Some(batch))?;
         }
-        let root_hash = self.commit_subtree(self.root_handle, Path(BitVec::new()), &mut batch)?;
+        let mut leaf_count = self.committed_len()?;
+        #[cfg(feature = "rayon")]
+        let hashes = self.par_hashes()?;
+        #[cfg(not(feature = "rayon"))]
+        let hashes = HashMap::new();
+        let root_hash =
+            self.commit_subtree(self.root_handle, Path(BitVec::new()), &hashes, batch)?;
         for (key, value) in mem::take(&mut self.cache_leaf_modified) {
             match value {
                 InsertOrRemove::Insert(value) => {
-                    self.db
-                        .insert(&TrieKey::Flat(key), &value.encode(), Some(&mut batch))?;
+                    let old_value =
+                        self.db
+                            .insert(&TrieKey::Flat(key), &value.encode(), Some(batch))?;
+                    if old_value.is_none() {
+                        leaf_count += 1;
+                    }
                 }
                 InsertOrRemove::Remove => {
-                    self.db.remove(&TrieKey::Flat(key), Some(&mut batch))?;
+                    if self.db.remove(&TrieKey::Flat(key), Some(batch))?.is_some() {
+                        leaf_count -= 1;
+                    }
                 }
             }
         }
-        self.db.write_batch(batch)?;
+        self.db.insert(
+            &TrieKey::Trie(LEAF_COUNT_KEY.to_vec()),
+            &leaf_count.encode(),
+            Some(batch),
+        )?;
         self.latest_node_id.reset();
         self.root_hash = root_hash;
         self.root_handle = NodeHandle::Hash(root_hash);
         Ok(root_hash)
     }
 
+    /// Removes all the nodes and leaves of the trie as of its last commit, leaving an empty trie.
+    /// Like [`MerkleTree::commit`], the changes are added to `batch` and the changes made since
+    /// the last commit are discarded.
+    ///
+    /// Each node and leaf is removed on its own so that its value is recorded in the trie log,
+    /// the database removals by prefix aren't part of a batch and leave nothing to revert to.
+    pub fn clear(
+        &mut self,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.reset_to_last_commit()?;
+        // The other keys of the trie space, e.g. the metadata of the commits, are kept
+        for (key, _) in self.db.get_by_prefix(&TrieKey::Trie(vec![]))? {
+            if !key.is_empty() && is_node_key(&key) {
+                self.db.remove(&TrieKey::Trie(key), Some(batch))?;
+            }
+        }
+        for (key, _) in self.db.get_by_prefix(&TrieKey::Flat(vec![]))? {
+            self.db.remove(&TrieKey::Flat(key), Some(batch))?;
+        }
+        self.db.insert(
+            &TrieKey::Trie(vec![]),
+            &Node::Unresolved(Felt::ZERO).encode(),
+            Some(batch),
+        )?;
+        self.db.insert(
+            &TrieKey::Trie(LEAF_COUNT_KEY.to_vec()),
+            &0u64.encode(),
+            Some(batch),
+        )?;
+        self.root_hash = Felt::ZERO;
+        self.root_handle = NodeHandle::Hash(Felt::ZERO);
+        Ok(())
+    }
+
+    /// Computes the hashes of the nodes modified since the last commit without writing anything,
+    /// and returns the root hash the trie will have once committed.
+    pub fn compute_uncommitted_hashes(
+        &mut self,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        self.compute_subtree_hash(self.root_handle)
+    }
+
+    /// Computes and stores the hash of the in-memory nodes of this subtree, and returns its hash.
+    fn compute_subtree_hash(
+        &mut self,
+        node_handle: NodeHandle,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        let node_id = match node_handle {
+            NodeHandle::Hash(hash) => return Ok(hash),
+            NodeHandle::InMemory(node_id) => node_id,
+        };
+        let node = self
+            .storage_nodes
+            .0
+            .get(&node_id)
+            .ok_or(BonsaiStorageError::Trie(
+                "Couldn't fetch node in the temporary storage".to_string(),
+            ))?
+            .clone();
+        let hash = match node {
+            Node::Unresolved(hash) => return Ok(hash),
+            Node::Binary(binary) => {
+                let left_hash = self.compute_subtree_hash(binary.left)?;
+                let right_hash = self.compute_subtree_hash(binary.right)?;
+                H::hash(&left_hash, &right_hash)
+            }
+            Node::Edge(edge) => {
+                let child = self.compute_subtree_hash(edge.child)?;
+                ProofNode::Edge {
+                    child,
+                    path: edge.path,
+                }
+                .hash::<H>()
+            }
+        };
+        match self.storage_nodes.0.get_mut(&node_id) {
+            Some(Node::Binary(binary)) => binary.hash = Some(hash),
+            Some(Node::Edge(edge)) => edge.hash = Some(hash),
+            _ => {}
+        }
+        Ok(hash)
+    }
+
+    /// Computes the hashes of the in-memory nodes on the threads of the hashing thread pool, see
+    /// [`BonsaiStorageConfig::hash_threads`](crate::BonsaiStorageConfig::hash_threads). Nothing
+    /// is computed with a single thread, the nodes are then hashed as they are committed.
+    #[cfg(feature = "rayon")]
+    fn par_hashes(
+        &mut self,
+    ) -> Result<HashMap<NodeId, Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        let threads = self.db.config.hash_threads;
+        if threads == Some(1) {
+            return Ok(HashMap::new());
+        }
+        if let (Some(threads), None) = (threads, &self.hash_pool) {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .map_err(|err| {
+                    BonsaiStorageError::Trie(format!(
+                        "Couldn't build the hashing thread pool: {}",
+                        err
+                    ))
+                })?;
+            self.hash_pool = Some(pool);
+        }
+        let (nodes, root) = (&self.storage_nodes.0, self.root_handle);
+        let mut hashes = Vec::new();
+        let hash = |hashes: &mut Vec<(NodeId, Felt)>| par_subtree_hash::<H>(nodes, root, hashes);
+        let hash = match &self.hash_pool {
+            Some(pool) => pool.install(|| hash(&mut hashes)),
+            None => hash(&mut hashes),
+        };
+        hash.ok_or(BonsaiStorageError::Trie(
+            "Couldn't fetch node in the temporary storage".to_string(),
+        ))?;
+        Ok(hashes.into_iter().collect())
+    }
+
     /// Persists any changes in this subtree to storage.
     ///
     /// This necessitates recursively calculating the hash of, and
@@ -185,10 +376,13 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
     /// # Arguments
     ///
     /// * `node` - The top node from the subtree to commit.
+    /// * `hashes` - Hashes of the in-memory nodes already computed, the others are computed
+    ///   along the way.
     fn commit_subtree(
         &mut self,
         node_handle: NodeHandle,
         path: Path,
+        hashes: &HashMap<NodeId, Felt>,
         batch: &mut DB::Batch,
     ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
         use Node::*;
@@ -218,10 +412,13 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
             }
             Binary(mut binary) => {
                 let left_path = path.new_with_direction(Direction::Left);
-                let left_hash = self.commit_subtree(binary.left, left_path, batch)?;
+                let left_hash = self.commit_subtree(binary.left, left_path, hashes, batch)?;
                 let right_path = path.new_with_direction(Direction::Right);
-                let right_hash = self.commit_subtree(binary.right, right_path, batch)?;
-                let hash = H::hash(&left_hash, &right_hash);
+                let right_hash = self.commit_subtree(binary.right, right_path, hashes, batch)?;
+                let hash = match hashes.get(&node_id) {
+                    Some(hash) => *hash,
+                    None => H::hash(&left_hash, &right_hash),
+                };
                 binary.hash = Some(hash);
                 binary.left = NodeHandle::Hash(left_hash);
                 binary.right = NodeHandle::Hash(right_hash);
@@ -241,18 +438,23 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
             Edge(mut edge) => {
                 let mut child_path = path.clone();
                 child_path.0.extend(&edge.path.0);
-                let child_hash = self.commit_subtree(edge.child, child_path, batch)?;
-                let mut bytes = [0u8; 32];
-                bytes.view_bits_mut::<Msb0>()[256 - edge.path.0.len()..]
-                    .copy_from_bitslice(&edge.path.0);
-
-                let felt_path = Felt::from_bytes_be(&bytes);
-                let mut length = [0; 32];
-                // Safe as len() is guaranteed to be <= 251
-                length[31] = edge.path.0.len() as u8;
-
-                let length = Felt::from_bytes_be(&length);
-                let hash = H::hash(&child_hash, &felt_path) + length;
+                let child_hash = self.commit_subtree(edge.child, child_path, hashes, batch)?;
+                let hash = match hashes.get(&node_id) {
+                    Some(hash) => *hash,
+                    None => {
+                        let mut bytes = [0u8; 32];
+                        bytes.view_bits_mut::<Msb0>()[256 - edge.path.0.len()..]
+                            .copy_from_bitslice(&edge.path.0);
+
+                        let felt_path = Felt::from_bytes_be(&bytes);
+                        let mut length = [0; 32];
+                        // Safe as len() is guaranteed to be <= 251
+                        length[31] = edge.path.0.len() as u8;
+
+                        let length = Felt::from_bytes_be(&length);
+                        H::hash(&child_hash, &felt_path) + length
+                    }
+                };
                 edge.hash = Some(hash);
                 edge.child = NodeHandle::Hash(child_hash);
                 let key_bytes = if path.0.is_empty() {
@@ -270,6 +472,13 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
         }
     }
 
+    /// Returns the keys of the leaves modified since the last commit.
+    pub(crate) fn pending_keys(&self) -> impl Iterator<Item = BitVec<u8, Msb0>> + '_ {
+        self.cache_leaf_modified
+            .keys()
+            .map(|key| bytes_to_bitvec(key))
+    }
+
     /// Sets the value of a key. To delete a key, set the value to [Felt::ZERO].
     ///
     /// # Arguments
@@ -591,16 +800,139 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
         &self,
         key: &BitSlice<u8, Msb0>,
     ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
-        let key = bitslice_to_bytes(key);
-        if let Some(value) = self.cache_leaf_modified.get(&key) {
+        let bytes = bitslice_to_bytes(key);
+        if let Some(value) = self.cache_leaf_modified.get(&bytes) {
             match value {
                 InsertOrRemove::Remove => return Ok(None),
                 InsertOrRemove::Insert(value) => return Ok(Some(*value)),
             }
         }
+        let value = self
+            .db
+            .get(&TrieKey::Flat(bytes))?
+            .map(|value| Felt::decode(&mut value.as_slice()).unwrap());
+        if self.db.config.verify_reads && self.get_verified(key)? != value {
+            return Err(BonsaiStorageError::Corruption(
+                "The stored value of the key isn't the one of its leaf".to_string(),
+            ));
+        }
+        Ok(value)
+    }
+
+    /// Returns the value of `key` read through the trie, checking the hash of each node read
+    /// from the database against the hash held by its parent, or the root hash.
+    fn get_verified(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        let mut path = Path(BitVec::new());
+        let mut handle = self.root_handle;
+        loop {
+            let node = match self.resolve(&path, handle, None)? {
+                Some(node) => node,
+                // Leaves aren't stored as nodes, their handle is their value
+                None => match handle {
+                    NodeHandle::Hash(value) if path.0.as_bitslice() == key => {
+                        return Ok(Some(value))
+                    }
+                    _ => return Ok(None),
+                },
+            };
+            if let NodeHandle::Hash(expected) = handle {
+                if Self::committed_hash(&node) != Some(expected) {
+                    return Err(BonsaiStorageError::Corruption(format!(
+                        "The node at {:?} doesn't match its hash {:#x}",
+                        path.0, expected
+                    )));
+                }
+            }
+            match node {
+                // Root of an empty trie
+                Node::Unresolved(_) => return Ok(None),
+                Node::Binary(binary) => {
+                    let Some(bit) = key.get(path.0.len()) else {
+                        return Ok(None);
+                    };
+                    let direction = Direction::from(*bit);
+                    handle = binary.get_child(direction);
+                    path = path.new_with_direction(direction);
+                }
+                Node::Edge(edge) => {
+                    let end = path.0.len() + edge.path.0.len();
+                    if key.get(path.0.len()..end) != Some(edge.path.0.as_bitslice()) {
+                        return Ok(None);
+                    }
+                    handle = edge.child;
+                    path.0.extend_from_bitslice(&edge.path.0);
+                }
+            }
+        }
+    }
+
+    /// Hash of a node read from the database, computed from the hashes of its children. `None`
+    /// if a child is in memory, which can't be the case of a committed node.
+    fn committed_hash(node: &Node) -> Option<Felt> {
+        match node {
+            Node::Unresolved(hash) => Some(*hash),
+            Node::Binary(binary) => match (binary.left, binary.right) {
+                (NodeHandle::Hash(left), NodeHandle::Hash(right)) => Some(H::hash(&left, &right)),
+                _ => None,
+            },
+            Node::Edge(edge) => match edge.child {
+                NodeHandle::Hash(child) => Some(
+                    ProofNode::Edge {
+                        child,
+                        path: edge.path.clone(),
+                    }
+                    .hash::<H>(),
+                ),
+                NodeHandle::InMemory(_) => None,
+            },
+        }
+    }
+
+    /// Returns the values stored at several keys, in the same order as the keys.
+    ///
+    /// Values are read from their flat column and not through the trie, the ones that aren't in
+    /// memory are read at once from the database, in key order.
+    pub fn get_many(
+        &self,
+        keys: &[&BitSlice<u8, Msb0>],
+    ) -> Result<Vec<Option<Felt>>, BonsaiStorageError<DB::DatabaseError>> {
+        let mut values = vec![None; keys.len()];
+        let mut missing = Vec::new();
+        for (index, key) in keys.iter().enumerate() {
+            let key = bitslice_to_bytes(key);
+            match self.cache_leaf_modified.get(&key) {
+                Some(InsertOrRemove::Insert(value)) => values[index] = Some(*value),
+                Some(InsertOrRemove::Remove) => {}
+                None => missing.push((key, index)),
+            }
+        }
+        missing.sort();
+        let (missing_keys, indexes): (Vec<_>, Vec<_>) = missing
+            .into_iter()
+            .map(|(key, index)| (TrieKey::Flat(key), index))
+            .unzip();
+        for (index, value) in indexes.into_iter().zip(self.db.get_many(&missing_keys)?) {
+            values[index] = value
+                .map(|value| Felt::decode(&mut value.as_slice()))
+                .transpose()?;
+        }
+        Ok(values)
+    }
+
+    /// Returns the value stored at key as of the commit `id`, or `None` if it did not exist.
+    pub fn get_at(
+        &self,
+        id: ID,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
         self.db
-            .get(&TrieKey::Flat(key.to_vec()))
-            .map(|r| r.map(|opt| Felt::decode(&mut opt.as_slice()).unwrap()))
+            .get_at(id, &TrieKey::Flat(bitslice_to_bytes(key)))?
+            .map(|value| Felt::decode(&mut value.as_slice()))
+            .transpose()
+            .map_err(Into::into)
     }
 
     pub fn contains(
@@ -617,10 +949,458 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
         self.db.contains(&TrieKey::Flat(key.to_vec()))
     }
 
+    /// Returns whether each key is in the trie, in the same order as the keys, see
+    /// [`MerkleTree::get_many`]. The values read from the database aren't decoded.
+    pub fn contains_many(
+        &self,
+        keys: &[&BitSlice<u8, Msb0>],
+    ) -> Result<Vec<bool>, BonsaiStorageError<DB::DatabaseError>> {
+        let mut contained = vec![false; keys.len()];
+        let mut missing = Vec::new();
+        for (index, key) in keys.iter().enumerate() {
+            let key = bitslice_to_bytes(key);
+            match self.cache_leaf_modified.get(&key) {
+                Some(InsertOrRemove::Insert(_)) => contained[index] = true,
+                Some(InsertOrRemove::Remove) => {}
+                None => missing.push((key, index)),
+            }
+        }
+        missing.sort();
+        let (missing_keys, indexes): (Vec<_>, Vec<_>) = missing
+            .into_iter()
+            .map(|(key, index)| (TrieKey::Flat(key), index))
+            .unzip();
+        for (index, value) in indexes.into_iter().zip(self.db.get_many(&missing_keys)?) {
+            contained[index] = value.is_some();
+        }
+        Ok(contained)
+    }
+
+    /// Returns the number of leaves of the trie, uncommitted changes included.
+    ///
+    /// The number of leaves is stored at each commit, only the keys modified since the last
+    /// commit are read.
+    pub fn len(&self) -> Result<u64, BonsaiStorageError<DB::DatabaseError>> {
+        let mut len = self.committed_len()?;
+        for (key, value) in self.cache_leaf_modified.iter() {
+            let committed = self.db.contains(&TrieKey::Flat(key.clone()))?;
+            match value {
+                InsertOrRemove::Insert(_) if !committed => len += 1,
+                InsertOrRemove::Remove if committed => len -= 1,
+                _ => {}
+            }
+        }
+        Ok(len)
+    }
+
+    /// Returns true if the trie has no leaves, uncommitted changes included.
+    pub fn is_empty(&self) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Number of leaves at the last commit. Databases written before it was stored have their
+    /// leaves counted.
+    fn committed_len(&self) -> Result<u64, BonsaiStorageError<DB::DatabaseError>> {
+        if let Some(count) = self.db.get(&TrieKey::Trie(LEAF_COUNT_KEY.to_vec()))? {
+            return Ok(u64::decode(&mut count.as_slice())?);
+        }
+        let mut count = 0;
+        self.db.visit_prefix(&TrieKey::Flat(vec![]), &mut |_, _| {
+            count += 1;
+            ControlFlow::Continue(())
+        })?;
+        Ok(count)
+    }
+
+    /// Returns a lazy iterator over the leaves of the trie sorted by key, uncommitted changes
+    /// included. The nodes are read from the database as the iteration goes.
+    pub fn iter(&self) -> TrieIter<'_, H, DB, ID> {
+        self.iter_prefix(BitSlice::empty())
+    }
+
+    /// Same as [`MerkleTree::iter`] but only over the leaves with a key starting with `prefix`,
+    /// the subtrees outside of the prefix aren't read.
+    pub fn iter_prefix(&self, prefix: &BitSlice<u8, Msb0>) -> TrieIter<'_, H, DB, ID> {
+        TrieIter {
+            tree: self,
+            prefix: prefix.to_bitvec(),
+            start: Bound::Unbounded,
+            end: Bound::Unbounded,
+            at: None,
+            rev: false,
+            stack: vec![(Path(BitVec::new()), self.root_handle)],
+        }
+    }
+
+    /// Same as [`MerkleTree::iter`] but only over the leaves with a key in `range`, the subtrees
+    /// outside of the range aren't read and the iteration stops at the end of the range.
+    pub fn range<R: RangeBounds<BitVec<u8, Msb0>>>(&self, range: R) -> TrieIter<'_, H, DB, ID> {
+        TrieIter {
+            tree: self,
+            prefix: BitVec::new(),
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+            at: None,
+            rev: false,
+            stack: vec![(Path(BitVec::new()), self.root_handle)],
+        }
+    }
+
+    /// Returns the first leaf with a key greater than or equal to `key`, uncommitted changes
+    /// included.
+    #[allow(clippy::type_complexity)]
+    pub fn seek(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<(BitVec<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        self.range((Bound::Included(key.to_bitvec()), Bound::Unbounded))
+            .next()
+            .transpose()
+    }
+
+    /// Returns the first leaf with a key greater than `key`, which may be absent from the trie,
+    /// uncommitted changes included.
+    ///
+    /// Only the nodes along the path of `key` and down to the leaf are read.
+    #[allow(clippy::type_complexity)]
+    pub fn next_leaf(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<(BitVec<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        self.range((Bound::Excluded(key.to_bitvec()), Bound::Unbounded))
+            .next()
+            .transpose()
+    }
+
+    /// Returns the last leaf with a key lower than `key`, which may be absent from the trie,
+    /// uncommitted changes included, see [`MerkleTree::next_leaf`].
+    #[allow(clippy::type_complexity)]
+    pub fn prev_leaf(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<(BitVec<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        self.range((Bound::Unbounded, Bound::Excluded(key.to_bitvec())))
+            .reversed()
+            .next()
+            .transpose()
+    }
+
+    /// Returns a parallel iterator over the leaves of the trie, uncommitted changes included.
+    ///
+    /// The top of the trie is read first and split in subtrees, a few per thread of the rayon
+    /// thread pool, whose leaves are then iterated in parallel. Collecting the iterator gives
+    /// the leaves sorted by key, as [`MerkleTree::iter`].
+    #[cfg(feature = "rayon")]
+    #[allow(clippy::type_complexity)]
+    pub fn par_iter(
+        &self,
+    ) -> Result<
+        impl rayon::iter::ParallelIterator<
+                Item = Result<(BitVec<u8, Msb0>, Felt), BonsaiStorageError<DB::DatabaseError>>,
+            > + '_,
+        BonsaiStorageError<DB::DatabaseError>,
+    >
+    where
+        H: Sync,
+        DB: Sync,
+        ID: Sync,
+        DB::DatabaseError: Send,
+    {
+        use rayon::prelude::*;
+
+        let target = rayon::current_num_threads() * 4;
+        // Roots of the subtrees in key order, split level by level until there are enough of
+        // them or only leaves are left
+        let mut subtrees = vec![(Path(BitVec::new()), self.root_handle)];
+        let mut split = true;
+        while split && subtrees.len() < target {
+            split = false;
+            let mut next = Vec::with_capacity(subtrees.len() * 2);
+            for (path, handle) in subtrees {
+                match self.resolve(&path, handle, None)? {
+                    // Root of an empty trie
+                    Some(Node::Unresolved(_)) => {}
+                    Some(Node::Binary(binary)) => {
+                        next.push((path.new_with_direction(Direction::Left), binary.left));
+                        next.push((path.new_with_direction(Direction::Right), binary.right));
+                        split = true;
+                    }
+                    Some(Node::Edge(edge)) => {
+                        let mut child = path;
+                        child.0.extend_from_bitslice(&edge.path.0);
+                        next.push((child, edge.child));
+                        split = true;
+                    }
+                    None => next.push((path, handle)),
+                }
+            }
+            subtrees = next;
+        }
+
+        Ok(subtrees
+            .into_par_iter()
+            .flat_map_iter(move |(path, handle)| TrieIter {
+                tree: self,
+                prefix: BitVec::new(),
+                start: Bound::Unbounded,
+                end: Bound::Unbounded,
+                at: None,
+                rev: false,
+                stack: vec![(path, handle)],
+            }))
+    }
+
+    /// Returns `n` leaves picked at random, uncommitted changes included, each by descending the
+    /// trie from the root and going left or right at random at each binary node.
+    ///
+    /// The same seed gives the same leaves on the same trie. A leaf can be picked several times,
+    /// and the leaves that are the only ones in a big subtree are picked more often than others.
+    #[allow(clippy::type_complexity)]
+    pub fn sample_leaves(
+        &self,
+        n: usize,
+        seed: u64,
+    ) -> Result<Vec<(BitVec<u8, Msb0>, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        // splitmix64
+        let mut state = seed;
+        let mut random_bit = || {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            (z ^ (z >> 31)) & 1 == 1
+        };
+        let mut leaves = Vec::with_capacity(n);
+        for _ in 0..n {
+            let mut path = Path(BitVec::new());
+            let mut handle = self.root_handle;
+            loop {
+                match self.resolve(&path, handle, None)? {
+                    // Root of an empty trie
+                    Some(Node::Unresolved(_)) => return Ok(leaves),
+                    Some(Node::Binary(binary)) => {
+                        let direction = Direction::from(random_bit());
+                        handle = binary.get_child(direction);
+                        path = path.new_with_direction(direction);
+                    }
+                    Some(Node::Edge(edge)) => {
+                        handle = edge.child;
+                        path.0.extend_from_bitslice(&edge.path.0);
+                    }
+                    None => match handle {
+                        NodeHandle::Hash(value) => {
+                            leaves.push((path.0, value));
+                            break;
+                        }
+                        NodeHandle::InMemory(_) => {
+                            unreachable!("In memory handles are always nodes")
+                        }
+                    },
+                }
+            }
+        }
+        Ok(leaves)
+    }
+
+    /// Visits the nodes of the trie depth first, in key order, uncommitted changes included.
+    ///
+    /// `visitor` is called with the depth of each node, which is the number of nodes above it,
+    /// the path to it and the node itself. The visit stops when it returns [`ControlFlow::Break`].
+    pub fn visit_nodes(
+        &self,
+        visitor: &mut dyn FnMut(usize, &BitSlice<u8, Msb0>, VisitedNode<'_>) -> ControlFlow<()>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let mut stack = vec![(0, Path(BitVec::new()), self.root_handle)];
+        while let Some((depth, path, handle)) = stack.pop() {
+            let flow = match self.resolve(&path, handle, None)? {
+                // Root of an empty trie
+                Some(Node::Unresolved(_)) => ControlFlow::Continue(()),
+                Some(Node::Binary(binary)) => {
+                    stack.push((
+                        depth + 1,
+                        path.new_with_direction(Direction::Right),
+                        binary.right,
+                    ));
+                    stack.push((
+                        depth + 1,
+                        path.new_with_direction(Direction::Left),
+                        binary.left,
+                    ));
+                    visitor(depth, &path.0, VisitedNode::Binary { hash: binary.hash })
+                }
+                Some(Node::Edge(edge)) => {
+                    let mut child = path.clone();
+                    child.0.extend_from_bitslice(&edge.path.0);
+                    stack.push((depth + 1, child, edge.child));
+                    visitor(
+                        depth,
+                        &path.0,
+                        VisitedNode::Edge {
+                            hash: edge.hash,
+                            path: &edge.path.0,
+                        },
+                    )
+                }
+                None => match handle {
+                    NodeHandle::Hash(value) => visitor(depth, &path.0, VisitedNode::Leaf { value }),
+                    NodeHandle::InMemory(_) => unreachable!("In memory handles are always nodes"),
+                },
+            };
+            if flow.is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a lazy iterator over the leaves of the trie as of the commit `id`, sorted by key.
+    ///
+    /// Each node is read as it was at that commit, from the database and the trie logs of the
+    /// later commits.
+    pub fn iter_at(
+        &self,
+        id: ID,
+    ) -> Result<TrieIter<'_, H, DB, ID>, BonsaiStorageError<DB::DatabaseError>> {
+        let root = self
+            .get_trie_branch_at(id, &Path(BitVec::new()))?
+            .and_then(|node| node.hash())
+            .unwrap_or(Felt::ZERO);
+        Ok(TrieIter {
+            tree: self,
+            prefix: BitVec::new(),
+            start: Bound::Unbounded,
+            end: Bound::Unbounded,
+            at: Some(id),
+            rev: false,
+            stack: vec![(Path(BitVec::new()), NodeHandle::Hash(root))],
+        })
+    }
+
+    /// Returns a lazy iterator over the committed nodes of the subtree under `prefix`, encoded as
+    /// they are stored in the database, along with their paths.
+    ///
+    /// The nodes are the ones with a path starting with `prefix`, preceded by the edge leading
+    /// into the subtree if it starts above `prefix`. They come in depth first order, left child
+    /// first, which is the order of their paths.
+    pub fn export_nodes(&self, prefix: &BitSlice<u8, Msb0>) -> NodeExportIter<'_, H, DB, ID> {
+        NodeExportIter {
+            tree: self,
+            prefix: prefix.to_bitvec(),
+            stack: vec![Path(BitVec::new())],
+        }
+    }
+
+    /// Resolves the node of `handle` at `path`, as of the commit `at` if any, `None` if `handle`
+    /// is the value of a leaf.
+    fn resolve(
+        &self,
+        path: &Path,
+        handle: NodeHandle,
+        at: Option<ID>,
+    ) -> Result<Option<Node>, BonsaiStorageError<DB::DatabaseError>> {
+        match handle {
+            NodeHandle::InMemory(id) => {
+                self.storage_nodes
+                    .0
+                    .get(&id)
+                    .cloned()
+                    .map(Some)
+                    .ok_or(BonsaiStorageError::Trie(
+                        "Couldn't fetch node in the temporary storage".to_string(),
+                    ))
+            }
+            // Leaves aren't stored as nodes, their handle is their value
+            NodeHandle::Hash(hash) => {
+                let node = match at {
+                    Some(id) => self.get_trie_branch_at(id, path)?,
+                    None => self.get_trie_branch_with_hash(path, hash)?,
+                };
+                match node {
+                    Some(node) => Ok(Some(node)),
+                    None if path.0.is_empty() => Err(BonsaiStorageError::Trie(
+                        "Couldn't fetch root node in db".to_string(),
+                    )),
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+
+    /// Same as [`MerkleTree::get_proof`] as of the commit `id`, each node of the path being read
+    /// as it was at that commit from the database and the trie logs of the later commits.
+    pub fn get_proof_at(
+        &self,
+        id: ID,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Vec<ProofNode>, BonsaiStorageError<DB::DatabaseError>> {
+        let mut nodes = Vec::new();
+        let mut path = Path(BitVec::new());
+        let mut node = self
+            .get_trie_branch_at(id, &path)?
+            .ok_or(BonsaiStorageError::Trie(
+                "Couldn't fetch root node in db".to_string(),
+            ))?;
+        if node.is_empty() {
+            return Ok(nodes);
+        }
+        // Committed nodes only have hashes as children
+        let hash = |handle| -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+            match handle {
+                NodeHandle::Hash(hash) => Ok(hash),
+                NodeHandle::InMemory(_) => Err(BonsaiStorageError::Trie(
+                    "Committed node with an in memory child".to_string(),
+                )),
+            }
+        };
+        loop {
+            match node {
+                Node::Unresolved(_) => {
+                    return Err(BonsaiStorageError::Trie(
+                        "Unresolved node in the trie".to_string(),
+                    ))
+                }
+                Node::Binary(binary) => {
+                    nodes.push(ProofNode::Binary {
+                        left: hash(binary.left)?,
+                        right: hash(binary.right)?,
+                    });
+                    let direction = key
+                        .get(path.0.len())
+                        .map(|bit| Direction::from(*bit))
+                        .ok_or(BonsaiStorageError::Trie("Key too short".to_string()))?;
+                    path = path.new_with_direction(direction);
+                }
+                Node::Edge(edge) => {
+                    nodes.push(ProofNode::Edge {
+                        child: hash(edge.child)?,
+                        path: edge.path.clone(),
+                    });
+                    // The edge diverging from the key proves its absence
+                    if !key[path.0.len()..].starts_with(&edge.path.0) {
+                        return Ok(nodes);
+                    }
+                    path.0.extend_from_bitslice(&edge.path.0);
+                }
+            }
+            if path.0.len() >= key.len() {
+                return Ok(nodes);
+            }
+            node = self
+                .get_trie_branch_at(id, &path)?
+                .ok_or(BonsaiStorageError::Trie(
+                    "Couldn't fetch node in db".to_string(),
+                ))?;
+        }
+    }
+
     /// Returns the list of nodes along the path.
     ///
     /// if it exists, or down to the node which proves that the key does not exist.
     ///
+    /// When the key doesn't exist, the last node is the edge node whose path diverges from the key,
+    /// leading either to a subtree or to the leaf of another key. The proof is empty when the trie
+    /// is empty. Such proofs are checked with [`MerkleTree::verify_non_membership`].
+    ///
     /// The nodes are returned in order, root first.
     ///
     /// Verification is performed by confirming that:
@@ -641,9 +1421,9 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
     ) -> Result<Vec<ProofNode>, BonsaiStorageError<DB::DatabaseError>> {
         let mut nodes = Vec::with_capacity(251);
         let mut node = match self.root_handle {
-            NodeHandle::Hash(_) => {
+            NodeHandle::Hash(hash) => {
                 let node = self
-                    .get_trie_branch_in_db_from_path(&Path(BitVec::<u8, Msb0>::new()))?
+                    .get_trie_branch_with_hash(&Path(BitVec::<u8, Msb0>::new()), hash)?
                     .ok_or(BonsaiStorageError::Trie(
                         "Couldn't fetch root node in db".to_string(),
                     ))?;
@@ -667,7 +1447,7 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
                     let child_path = key[..edge.height as usize + edge.path.0.len()].to_bitvec();
                     let child_node = match edge.child {
                         NodeHandle::Hash(hash) => {
-                            let node = self.get_trie_branch_in_db_from_path(&Path(child_path))?;
+                            let node = self.get_trie_branch_with_hash(&Path(child_path), hash)?;
                             if let Some(node) = node {
                                 node
                             } else {
@@ -710,8 +1490,8 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
                     let next = binary.get_child(next_direction);
                     let next_path = key[..binary.height as usize + 1].to_bitvec();
                     let next_node = match next {
-                        NodeHandle::Hash(_) => self
-                            .get_trie_branch_in_db_from_path(&Path(next_path))?
+                        NodeHandle::Hash(hash) => self
+                            .get_trie_branch_with_hash(&Path(next_path), hash)?
                             .ok_or(BonsaiStorageError::Trie(
                                 "Couldn't fetch next node in db".to_string(),
                             ))?,
@@ -773,6 +1553,17 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
         }
     }
 
+    /// Returns the value stored at key along with its proof, see [`MerkleTree::get_proof`]. The
+    /// value is read from the proof, which makes a single traversal of the trie.
+    #[allow(clippy::type_complexity)]
+    pub fn get_with_proof(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<(Option<Felt>, Vec<ProofNode>), BonsaiStorageError<DB::DatabaseError>> {
+        let proof = self.get_proof(key)?;
+        Ok((crate::proof::proven_value(key, &proof), proof))
+    }
+
     /// preload_nodes from the current root towards the destination [Leaf](Node::Leaf) node.
     /// If the destination node exists, it will be the final node in the list.
     ///
@@ -797,9 +1588,9 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
     ) -> Result<Vec<NodeId>, BonsaiStorageError<DB::DatabaseError>> {
         let mut nodes = Vec::with_capacity(251);
         let node_id = match self.root_handle {
-            NodeHandle::Hash(_) => {
+            NodeHandle::Hash(hash) => {
                 let node = self
-                    .get_trie_branch_in_db_from_path(&Path(BitVec::<u8, Msb0>::new()))?
+                    .get_trie_branch_with_hash(&Path(BitVec::<u8, Msb0>::new()), hash)?
                     .ok_or(BonsaiStorageError::Trie(
                         "Couldn't fetch root node in db".to_string(),
                     ))?;
@@ -850,8 +1641,8 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
                 path.0.push(bool::from(next_direction));
                 let next = binary_node.get_child(next_direction);
                 match next {
-                    NodeHandle::Hash(_) => {
-                        let node = self.get_trie_branch_in_db_from_path(&path)?;
+                    NodeHandle::Hash(hash) => {
+                        let node = self.get_trie_branch_with_hash(&path, hash)?;
                         if let Some(node) = node {
                             self.latest_node_id.next_id();
                             self.storage_nodes.0.insert(self.latest_node_id, node);
@@ -889,8 +1680,8 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
                 }
                 let next = edge_node.child;
                 match next {
-                    NodeHandle::Hash(_) => {
-                        let node = self.get_trie_branch_in_db_from_path(&path)?;
+                    NodeHandle::Hash(hash) => {
+                        let node = self.get_trie_branch_with_hash(&path, hash)?;
                         if let Some(node) = node {
                             self.latest_node_id.next_id();
                             self.storage_nodes.0.insert(self.latest_node_id, node);
@@ -913,13 +1704,18 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
         }
     }
 
-    /// Get the node of the trie that corresponds to the path.
+    /// Get the node of the trie that corresponds to the path, `None` at the path of a leaf.
     fn get_trie_branch_in_db_from_path(
         &self,
         path: &Path,
     ) -> Result<Option<Node>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::from(path);
+        // The key of a leaf path may be another key of the trie space
+        if !is_node_key(key.as_slice()) {
+            return Ok(None);
+        }
         self.db
-            .get(&path.into())?
+            .get(&key)?
             .map(|node| {
                 Node::decode(&mut node.as_slice()).map_err(|err| {
                     BonsaiStorageError::Trie(format!("Couldn't decode node: {}", err))
@@ -928,6 +1724,63 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
             .map_or(Ok(None), |r| r.map(Some))
     }
 
+    /// Same as [`MerkleTree::get_trie_branch_in_db_from_path`] for a node whose hash is known from
+    /// its parent, read from the cache of the nodes when it's there, see
+    /// [`BonsaiStorageConfig::node_cache_size`](crate::BonsaiStorageConfig::node_cache_size).
+    #[cfg_attr(not(feature = "cache"), allow(unused_variables))]
+    fn get_trie_branch_with_hash(
+        &self,
+        path: &Path,
+        hash: Felt,
+    ) -> Result<Option<Node>, BonsaiStorageError<DB::DatabaseError>> {
+        #[cfg(feature = "cache")]
+        if let Some(cache) = &self.node_cache {
+            let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+            // The value of a leaf can be the hash of a node, no node starts at the height of a leaf
+            let cached = cache.get(&hash).filter(|node| match node {
+                Node::Binary(binary) => binary.height as usize == path.0.len(),
+                Node::Edge(edge) => edge.height as usize == path.0.len(),
+                Node::Unresolved(_) => false,
+            });
+            if let Some(node) = cached {
+                return Ok(Some(node.clone()));
+            }
+        }
+        let node = self.get_trie_branch_in_db_from_path(path)?;
+        #[cfg(feature = "cache")]
+        if let (Some(cache), Some(node @ (Node::Binary(_) | Node::Edge(_)))) =
+            (&self.node_cache, &node)
+        {
+            if node.hash() == Some(hash) {
+                cache
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .put(hash, node.clone());
+            }
+        }
+        Ok(node)
+    }
+
+    /// Get the node of the trie that corresponds to the path as of the commit `id`.
+    fn get_trie_branch_at(
+        &self,
+        id: ID,
+        path: &Path,
+    ) -> Result<Option<Node>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::from(path);
+        if !is_node_key(key.as_slice()) {
+            return Ok(None);
+        }
+        self.db
+            .get_at(id, &key)?
+            .map(|node| {
+                Node::decode(&mut node.as_slice()).map_err(|err| {
+                    BonsaiStorageError::Trie(format!("Couldn't decode node: {}", err))
+                })
+            })
+            .transpose()
+    }
+
     /// This is a convenience function which merges the edge node with its child __iff__ it is also
     /// an edge.
     ///
@@ -963,82 +1816,112 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
 
     /// Function that come from pathfinder_merkle_tree::merkle_tree::MerkleTree
     /// Verifies that the key `key` with value `value` is indeed part of the MPT that has root
-    /// `root`, given `proofs`.
-    /// Supports proofs of non-membership as well as proof of membership: this function returns
-    /// an enum corresponding to the membership of `value`, or returns `None` in case of a hash mismatch.
-    /// The algorithm follows this logic:
-    /// 1. init expected_hash <- root hash
-    /// 2. loop over nodes: current <- nodes[i]
-    ///    1. verify the current node's hash matches expected_hash (if not then we have a bad proof)
-    ///    2. move towards the target - if current is:
-    ///       1. binary node then choose the child that moves towards the target, else if
-    ///       2. edge node then check the path against the target bits
-    ///          1. If it matches then proceed with the child, else
-    ///          2. if it does not match then we now have a proof that the target does not exist
-    ///    3. nibble off target bits according to which child you got in (2). If all bits are gone then you
-    ///       have reached the target and the child hash is the value you wanted and the proof is complete.
-    ///    4. set expected_hash <- to the child hash
-    /// 3. check that the expected_hash is `value` (we should've reached the leaf)
+    /// `root`, given `proofs`, see [`crate::proof::verify_proof`].
     pub fn verify_proof(
         root: Felt,
         key: &BitSlice<u8, Msb0>,
         value: Felt,
         proofs: &[ProofNode],
     ) -> Option<Membership> {
-        // Protect from ill-formed keys
-        if key.len() > 251 {
-            return None;
-        }
-
-        let mut expected_hash = root;
-        let mut remaining_path: &BitSlice<u8, Msb0> = key;
-
-        for proof_node in proofs.iter() {
-            // Hash mismatch? Return None.
-            if proof_node.hash::<H>() != expected_hash {
-                return None;
-            }
-            match proof_node {
-                ProofNode::Binary { left, right } => {
-                    // Direction will always correspond to the 0th index
-                    // because we're removing bits on every iteration.
-                    let direction = Direction::from(remaining_path[0]);
-
-                    // Set the next hash to be the left or right hash,
-                    // depending on the direction
-                    expected_hash = match direction {
-                        Direction::Left => *left,
-                        Direction::Right => *right,
-                    };
-
-                    // Advance by a single bit
-                    remaining_path = &remaining_path[1..];
-                }
-                ProofNode::Edge { child, path } => {
-                    if path.0 != remaining_path[..path.0.len()] {
-                        // If paths don't match, we've found a proof of non membership because we:
-                        // 1. Correctly moved towards the target insofar as is possible, and
-                        // 2. hashing all the nodes along the path does result in the root hash, which means
-                        // 3. the target definitely does not exist in this tree
-                        return Some(Membership::NonMember);
-                    }
+        crate::proof::verify_proof::<H>(root, key, value, proofs)
+    }
 
-                    // Set the next hash to the child's hash
-                    expected_hash = *child;
+    /// Verifies that the key `key` is not part of the MPT that has root `root`, given `proofs`,
+    /// see [`crate::proof::verify_non_membership`].
+    pub fn verify_non_membership(
+        root: Felt,
+        key: &BitSlice<u8, Msb0>,
+        proofs: &[ProofNode],
+    ) -> bool {
+        crate::proof::verify_non_membership::<H>(root, key, proofs)
+    }
 
-                    // Advance by the whole edge path
-                    remaining_path = &remaining_path[path.0.len()..];
-                }
-            }
+    /// Generates a proof of all the leaves with keys between `start` and `end`, bounds included,
+    /// at the last commit. Fails if there are uncommitted changes.
+    ///
+    /// The proof is made of the proofs of both bounds, which may be absent from the trie, and of
+    /// the leaves in between, which are the only ones read. An empty range is proven by the
+    /// bounds alone.
+    pub fn get_range_proof(
+        &self,
+        start: &BitSlice<u8, Msb0>,
+        end: &BitSlice<u8, Msb0>,
+    ) -> Result<RangeProof, BonsaiStorageError<DB::DatabaseError>> {
+        if start > end {
+            return Err(BonsaiStorageError::Trie(
+                "Range start is after its end".to_string(),
+            ));
+        }
+        // The proofs of the bounds hold for the root of the last commit only
+        if !self.cache_leaf_modified.is_empty() || !self.death_row.is_empty() {
+            return Err(BonsaiStorageError::Trie(
+                "Range proofs can't be generated with uncommitted changes".to_string(),
+            ));
         }
+        let leaves = self
+            .range(start.to_bitvec()..=end.to_bitvec())
+            .collect::<Result<_, _>>()?;
+        Ok(RangeProof {
+            start: self.get_proof(start)?,
+            end: self.get_proof(end)?,
+            leaves,
+        })
+    }
 
-        // At this point, we should reach `value` !
-        if expected_hash == value {
-            Some(Membership::Member)
-        } else {
-            // Hash mismatch. Return `None`.
-            None
+    /// Generates a proof that there is no leaf with a key between `start` and `end` at the last
+    /// commit, made of the proofs of both bounds only. Returns `None` if the range isn't empty.
+    /// Fails if there are uncommitted changes, as [`MerkleTree::get_range_proof`].
+    ///
+    /// Unlike [`MerkleTree::get_range_proof`], the leaves of the trie aren't read: the proofs of
+    /// the bounds are enough to check that nothing lies between them.
+    pub fn prove_range_empty(
+        &self,
+        start: &BitSlice<u8, Msb0>,
+        end: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<RangeProof>, BonsaiStorageError<DB::DatabaseError>> {
+        if start > end {
+            return Err(BonsaiStorageError::Trie(
+                "Range start is after its end".to_string(),
+            ));
+        }
+        if !self.cache_leaf_modified.is_empty() || !self.death_row.is_empty() {
+            return Err(BonsaiStorageError::Trie(
+                "Range proofs can't be generated with uncommitted changes".to_string(),
+            ));
         }
+        let proof = RangeProof {
+            start: self.get_proof(start)?,
+            end: self.get_proof(end)?,
+            leaves: Vec::new(),
+        };
+        Ok(
+            crate::proof::verify_range_empty::<H>(self.root_hash, start, end, &proof)
+                .then_some(proof),
+        )
+    }
+
+    /// Generates a proof of all the leaves of `key_len` bits starting with `prefix`, which is the
+    /// range proof of the subtree under `prefix`, see [`crate::proof::subtree_bounds`].
+    pub fn get_subtree_proof(
+        &self,
+        prefix: &BitSlice<u8, Msb0>,
+        key_len: usize,
+    ) -> Result<RangeProof, BonsaiStorageError<DB::DatabaseError>> {
+        let (start, end) = crate::proof::subtree_bounds(prefix, key_len).ok_or(
+            BonsaiStorageError::Trie("Prefix is longer than the keys".to_string()),
+        )?;
+        self.get_range_proof(&start, &end)
+    }
+
+    /// Verifies that the leaves of `proof` are all the leaves with keys between `start` and `end`
+    /// in the MPT that has root `root`, see [`crate::proof::verify_range_proof`].
+    pub fn verify_range_proof(
+        root: Felt,
+        start: &BitSlice<u8, Msb0>,
+        end: &BitSlice<u8, Msb0>,
+        proof: &RangeProof,
+    ) -> bool {
+        crate::proof::verify_range_proof::<H>(root, start, end, proof)
     }
 
     #[cfg(test)]
@@ -1097,12 +1980,226 @@ impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> MerkleTree<H, DB, ID> {
     }
 }
 
+/// A node of a [`MerkleTree`], as given to the visitor of [`MerkleTree::visit_nodes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisitedNode<'a> {
+    /// A node with two children, its hash is `None` until it's committed
+    Binary { hash: Option<Felt> },
+    /// A node with a single child under `path`, its hash is `None` until it's committed
+    Edge {
+        hash: Option<Felt>,
+        path: &'a BitSlice<u8, Msb0>,
+    },
+    /// A leaf with its value
+    Leaf { value: Felt },
+}
+
+/// Lazy iterator over the leaves of a [`MerkleTree`], see [`MerkleTree::iter`].
+///
+/// The trie is walked depth first, left child first, which yields the leaves in key order, or
+/// right child first once [reversed](TrieIter::reversed). The iteration stops after the first
+/// error.
+pub struct TrieIter<'a, H: StarkHash, DB: BonsaiDatabase, ID: Id> {
+    tree: &'a MerkleTree<H, DB, ID>,
+    /// Prefix of the keys of the leaves to visit
+    prefix: BitVec<u8, Msb0>,
+    /// Bounds of the keys of the leaves to visit
+    start: Bound<BitVec<u8, Msb0>>,
+    end: Bound<BitVec<u8, Msb0>>,
+    /// Commit at which the trie is read, the current state if `None`
+    at: Option<ID>,
+    /// Whether the leaves are visited in reverse key order
+    rev: bool,
+    /// Nodes left to visit along with their path, the next one on top.
+    stack: Vec<(Path, NodeHandle)>,
+}
+
+impl<'a, H: StarkHash, DB: BonsaiDatabase, ID: Id> TrieIter<'a, H, DB, ID> {
+    /// Visits the leaves in reverse key order, right child first. It must be called before the
+    /// first leaf is read.
+    pub fn reversed(mut self) -> Self {
+        self.rev = true;
+        self
+    }
+
+    /// Adds a node to visit unless none of the keys under it start with the prefix or are in the
+    /// range.
+    fn push(&mut self, path: Path, handle: NodeHandle) {
+        let len = path.0.len().min(self.prefix.len());
+        if path.0[..len] != self.prefix[..len] {
+            return;
+        }
+        // The keys under `path` are all before the start if `path` is before the start of the
+        // same length, and all after the end if it's after the end of the same length
+        if let Bound::Included(start) | Bound::Excluded(start) = &self.start {
+            let len = path.0.len().min(start.len());
+            if path.0[..len] < start[..len] {
+                return;
+            }
+        }
+        if let Bound::Included(end) | Bound::Excluded(end) = &self.end {
+            let len = path.0.len().min(end.len());
+            if path.0[..len] > end[..len] {
+                return;
+            }
+        }
+        self.stack.push((path, handle));
+    }
+
+    fn after_start(&self, key: &BitSlice<u8, Msb0>) -> bool {
+        match &self.start {
+            Bound::Included(start) => key >= start.as_bitslice(),
+            Bound::Excluded(start) => key > start.as_bitslice(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn before_end(&self, key: &BitSlice<u8, Msb0>) -> bool {
+        match &self.end {
+            Bound::Included(end) => key <= end.as_bitslice(),
+            Bound::Excluded(end) => key < end.as_bitslice(),
+            Bound::Unbounded => true,
+        }
+    }
+}
+
+impl<'a, H: StarkHash, DB: BonsaiDatabase, ID: Id> Iterator for TrieIter<'a, H, DB, ID> {
+    type Item = Result<(BitVec<u8, Msb0>, Felt), BonsaiStorageError<DB::DatabaseError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, handle)) = self.stack.pop() {
+            let node = match self.tree.resolve(&path, handle, self.at) {
+                Ok(Some(node)) => node,
+                Ok(None) => match handle {
+                    // A key shorter than the prefix doesn't start with it
+                    NodeHandle::Hash(_) if path.0.len() < self.prefix.len() => continue,
+                    // The next leaves are all after this one, or before it in reverse order
+                    NodeHandle::Hash(_)
+                        if (self.rev && !self.after_start(&path.0))
+                            || (!self.rev && !self.before_end(&path.0)) =>
+                    {
+                        self.stack.clear();
+                        return None;
+                    }
+                    NodeHandle::Hash(_)
+                        if !self.after_start(&path.0) || !self.before_end(&path.0) =>
+                    {
+                        continue
+                    }
+                    NodeHandle::Hash(value) => return Some(Ok((path.0, value))),
+                    NodeHandle::InMemory(_) => unreachable!("In memory handles are always nodes"),
+                },
+                Err(err) => {
+                    self.stack.clear();
+                    return Some(Err(err));
+                }
+            };
+            match node {
+                // Root of an empty trie
+                Node::Unresolved(_) => {}
+                Node::Binary(binary) => {
+                    let mut children = [
+                        (path.new_with_direction(Direction::Right), binary.right),
+                        (path.new_with_direction(Direction::Left), binary.left),
+                    ];
+                    if self.rev {
+                        children.reverse();
+                    }
+                    for (child, handle) in children {
+                        self.push(child, handle);
+                    }
+                }
+                Node::Edge(edge) => {
+                    let mut child = path;
+                    child.0.extend_from_bitslice(&edge.path.0);
+                    self.push(child, edge.child);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Lazy iterator over the encoded nodes of a subtree of a [`MerkleTree`], see
+/// [`MerkleTree::export_nodes`]. The iteration stops after the first error.
+pub struct NodeExportIter<'a, H: StarkHash, DB: BonsaiDatabase, ID: Id> {
+    tree: &'a MerkleTree<H, DB, ID>,
+    prefix: BitVec<u8, Msb0>,
+    /// Paths of the nodes left to visit, the next one on top.
+    stack: Vec<Path>,
+}
+
+impl<'a, H: StarkHash, DB: BonsaiDatabase, ID: Id> NodeExportIter<'a, H, DB, ID> {
+    /// Whether some keys under `path` start with the prefix.
+    fn overlaps(&self, path: &BitSlice<u8, Msb0>) -> bool {
+        let len = path.len().min(self.prefix.len());
+        path[..len] == self.prefix[..len]
+    }
+
+    fn next_node(
+        &mut self,
+    ) -> Result<Option<(BitVec<u8, Msb0>, Vec<u8>)>, BonsaiStorageError<DB::DatabaseError>> {
+        while let Some(path) = self.stack.pop() {
+            // Leaves aren't stored as nodes
+            let key = TrieKey::from(&path);
+            if !is_node_key(key.as_slice()) {
+                continue;
+            }
+            let Some(encoded) = self.tree.db.get(&key)? else {
+                continue;
+            };
+            let node = Node::decode(&mut encoded.as_slice()).map_err(|err| {
+                BonsaiStorageError::Trie(format!("Couldn't decode node: {}", err))
+            })?;
+            let mut export = path.0.starts_with(self.prefix.as_bitslice());
+            match node {
+                Node::Unresolved(_) => {}
+                Node::Binary(_) => {
+                    for direction in [Direction::Right, Direction::Left] {
+                        let child = path.new_with_direction(direction);
+                        if self.overlaps(&child.0) {
+                            self.stack.push(child);
+                        }
+                    }
+                }
+                Node::Edge(edge) => {
+                    let mut child = path.clone();
+                    child.0.extend_from_bitslice(&edge.path.0);
+                    if self.overlaps(&child.0) {
+                        // The edge going from above the prefix into the subtree
+                        export |= child.0.len() > self.prefix.len();
+                        self.stack.push(child);
+                    }
+                }
+            }
+            if export {
+                return Ok(Some((path.0, encoded)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a, H: StarkHash, DB: BonsaiDatabase, ID: Id> Iterator for NodeExportIter<'a, H, DB, ID> {
+    type Item = Result<(BitVec<u8, Msb0>, Vec<u8>), BonsaiStorageError<DB::DatabaseError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_node() {
+            Ok(node) => node.map(Ok),
+            Err(err) => {
+                self.stack.clear();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
 pub(crate) fn bitslice_to_bytes(bitslice: &BitSlice<u8, Msb0>) -> Vec<u8> {
     [&[bitslice.len() as u8], bitslice.to_bitvec().as_raw_slice()].concat()
 }
 
 pub(crate) fn bytes_to_bitvec(bytes: &[u8]) -> BitVec<u8, Msb0> {
-    BitSlice::from_slice(&bytes[1..]).to_bitvec()
+    BitSlice::from_slice(&bytes[1..])[..bytes[0] as usize].to_bitvec()
 }
 
 #[cfg(test)]
@@ -1,6 +1,6 @@
-mod merkle_node;
+pub(crate) mod merkle_node;
 pub mod merkle_tree;
-mod path;
-mod trie_db;
+pub(crate) mod path;
+pub(crate) mod trie_db;
 
 pub use trie_db::TrieKey;
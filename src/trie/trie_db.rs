@@ -3,6 +3,55 @@ use crate::bonsai_database::DatabaseKey;
 #[cfg(not(feature = "std"))]
 use alloc::vec::Vec;
 
+// Keys of the trie space.
+//
+// The key of a node is the length of its path followed by the bits of the path, the root having
+// the empty key. Leaves are stored apart, in the flat space, so a node path is at most
+// `MAX_NODE_PATH_LEN` bits long. The other keys of the trie space start with one of the reserved
+// bytes below, which are all greater than `MAX_NODE_PATH_LEN`. Reads of nodes and scans of the
+// trie space tell them apart with `is_node_key`.
+
+/// Length of the longest path of a node, one bit shorter than the longest keys.
+pub(crate) const MAX_NODE_PATH_LEN: u8 = 250;
+
+/// Key of the number of leaves of the trie.
+pub(crate) const LEAF_COUNT_KEY: [u8; 1] = [u8::MAX];
+/// First byte of the keys of the root hashes of the trie at each commit, followed by the id of
+/// the commit.
+pub(crate) const ROOT_INDEX_PREFIX: u8 = u8::MAX - 1;
+/// First byte of the keys of the tags of the commits, followed by the name of the tag.
+pub(crate) const TAG_PREFIX: u8 = u8::MAX - 2;
+/// First byte of the keys of the index of the commits by root hash kept in archive mode, followed
+/// by the root hash.
+pub(crate) const ROOT_HASH_INDEX_PREFIX: u8 = u8::MAX - 3;
+/// First byte of the keys of the metadata of the commits, followed by the id of the commit.
+pub(crate) const METADATA_PREFIX: u8 = u8::MAX - 4;
+
+/// First bytes of the keys of the trie space that aren't nodes.
+const RESERVED_PREFIXES: [u8; 5] = [
+    LEAF_COUNT_KEY[0],
+    ROOT_INDEX_PREFIX,
+    TAG_PREFIX,
+    ROOT_HASH_INDEX_PREFIX,
+    METADATA_PREFIX,
+];
+
+const _: () = {
+    let mut i = 0;
+    while i < RESERVED_PREFIXES.len() {
+        assert!(RESERVED_PREFIXES[i] > MAX_NODE_PATH_LEN);
+        i += 1;
+    }
+};
+
+/// Whether `key`, a key of the trie space, is the key of a node.
+pub(crate) fn is_node_key(key: &[u8]) -> bool {
+    match key.first() {
+        None => true,
+        Some(first) => !RESERVED_PREFIXES.contains(first) && *first <= MAX_NODE_PATH_LEN,
+    }
+}
+
 /// Key in the database of the different elements that are used in the storage of the trie data.
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum TrieKey {
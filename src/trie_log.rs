@@ -0,0 +1,64 @@
+//! Raw changes made by a commit to the keys of the database, as recorded in its trie log, see
+//! [`BonsaiStorage::get_trie_log`](crate::BonsaiStorage::get_trie_log).
+//!
+//! Unlike [`BonsaiStorage::get_changes`](crate::BonsaiStorage::get_changes) which only reports
+//! the leaves, a trie log has the changes of the nodes of the trie too, along with the other data
+//! stored with them, so that it can be audited or replicated exactly.
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::{changes::ChangeBatch, trie::TrieKey};
+
+/// Key of the database changed by a commit.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum TrieLogKey {
+    /// A node of the trie by its path, its length in bits followed by its bits, or other data
+    /// stored along with the nodes (number of leaves, root hashes of the commits)
+    Trie(Vec<u8>),
+    /// The value of a leaf by its key, its length in bits followed by its bits
+    Flat(Vec<u8>),
+}
+
+/// Change of the value of a key of the database made by a commit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrieLogEntry {
+    pub key: TrieLogKey,
+    /// Encoded value before the commit, `None` if the key was absent
+    pub old_value: Option<Vec<u8>>,
+    /// Encoded value after the commit, `None` if the key was removed
+    pub new_value: Option<Vec<u8>>,
+}
+
+/// The changes recorded for a commit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrieLog<ChangeID> {
+    pub id: ChangeID,
+    /// Changes sorted by key
+    pub entries: Vec<TrieLogEntry>,
+}
+
+impl From<TrieKey> for TrieLogKey {
+    fn from(key: TrieKey) -> Self {
+        match key {
+            TrieKey::Trie(key) => TrieLogKey::Trie(key),
+            TrieKey::Flat(key) => TrieLogKey::Flat(key),
+        }
+    }
+}
+
+impl<ChangeID> TrieLog<ChangeID> {
+    pub(crate) fn new(id: ChangeID, changes: ChangeBatch) -> Self {
+        let mut entries: Vec<_> = changes
+            .0
+            .into_iter()
+            .map(|(key, change)| TrieLogEntry {
+                key: key.into(),
+                old_value: change.old_value,
+                new_value: change.new_value,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        Self { id, entries }
+    }
+}
@@ -0,0 +1,191 @@
+//! Archive of the trie logs of a range of commits, written by
+//! [`BonsaiStorage::export_trie_logs`](crate::BonsaiStorage::export_trie_logs) and read by
+//! [`BonsaiStorage::import_trie_logs`](crate::BonsaiStorage::import_trie_logs), so that the
+//! history can be kept off the database before it's pruned.
+//!
+//! # Format
+//!
+//! The file is the list of the trie logs in commit order, without header. Each trie log is
+//! written as:
+//! - the length of the bytes of the id (see [`Id::to_bytes`](crate::id::Id::to_bytes)), 4 bytes
+//!   big endian, followed by the bytes
+//! - the number of entries, 4 bytes big endian
+//! - the entries sorted by key, see [`TrieLogEntry`]
+//!
+//! Each entry is written as:
+//! - the kind of the key, one byte, 0 for [`TrieLogKey::Trie`] and 1 for [`TrieLogKey::Flat`]
+//! - the key, written as a byte string
+//! - the old value and the new value, each written as one byte, 0 if there is no value and 1
+//!   otherwise, followed by the value written as a byte string
+//!
+//! A byte string is its length, 4 bytes big endian, followed by its bytes. The file ends right
+//! after the last trie log.
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{self, ErrorKind, Read, Write},
+};
+
+use crate::{
+    bonsai_database::DBError,
+    trie_log::{TrieLogEntry, TrieLogKey},
+    BonsaiStorageError,
+};
+
+/// Errors of the export and import of trie logs.
+#[derive(Debug)]
+pub enum TrieLogFileError<DatabaseError>
+where
+    DatabaseError: DBError,
+{
+    /// Error from the storage
+    Storage(BonsaiStorageError<DatabaseError>),
+    /// Error when reading or writing the file
+    Io(io::Error),
+    /// Error when a trie log of the file isn't in the format of the file
+    InvalidTrieLog(String),
+}
+
+impl<DatabaseError: DBError> From<BonsaiStorageError<DatabaseError>>
+    for TrieLogFileError<DatabaseError>
+{
+    fn from(value: BonsaiStorageError<DatabaseError>) -> Self {
+        Self::Storage(value)
+    }
+}
+
+impl<DatabaseError: DBError> From<io::Error> for TrieLogFileError<DatabaseError> {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl<DatabaseError> Display for TrieLogFileError<DatabaseError>
+where
+    DatabaseError: Error + DBError,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrieLogFileError::Storage(e) => write!(f, "Storage error: {}", e),
+            TrieLogFileError::Io(e) => write!(f, "IO error: {}", e),
+            TrieLogFileError::InvalidTrieLog(e) => write!(f, "Invalid trie log: {}", e),
+        }
+    }
+}
+
+impl<DatabaseError> Error for TrieLogFileError<DatabaseError> where DatabaseError: Error + DBError {}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn write_value(writer: &mut impl Write, value: &Option<Vec<u8>>) -> io::Result<()> {
+    match value {
+        Some(value) => {
+            writer.write_all(&[1])?;
+            write_bytes(writer, value)
+        }
+        None => writer.write_all(&[0]),
+    }
+}
+
+pub(crate) fn write_trie_log(
+    writer: &mut impl Write,
+    id: &[u8],
+    entries: &[TrieLogEntry],
+) -> io::Result<()> {
+    write_bytes(writer, id)?;
+    writer.write_all(&(entries.len() as u32).to_be_bytes())?;
+    for entry in entries {
+        let (kind, key) = match &entry.key {
+            TrieLogKey::Trie(key) => (0, key),
+            TrieLogKey::Flat(key) => (1, key),
+        };
+        writer.write_all(&[kind])?;
+        write_bytes(writer, key)?;
+        write_value(writer, &entry.old_value)?;
+        write_value(writer, &entry.new_value)?;
+    }
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_byte(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_bytes(reader: &mut impl Read, len: u32) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len as usize {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn read_value<E: DBError>(reader: &mut impl Read) -> Result<Option<Vec<u8>>, TrieLogFileError<E>> {
+    match read_byte(reader)? {
+        0 => Ok(None),
+        1 => {
+            let len = read_u32(reader)?;
+            Ok(Some(read_bytes(reader, len)?))
+        }
+        flag => Err(TrieLogFileError::InvalidTrieLog(format!(
+            "Invalid value flag {}",
+            flag
+        ))),
+    }
+}
+
+/// Reads the next trie log, the bytes of its id along with its entries, `None` at the end of the
+/// file.
+#[allow(clippy::type_complexity)]
+pub(crate) fn read_trie_log<E: DBError>(
+    reader: &mut impl Read,
+) -> Result<Option<(Vec<u8>, Vec<TrieLogEntry>)>, TrieLogFileError<E>> {
+    // The end of the file is only valid before a trie log
+    let mut len = [0u8; 4];
+    let mut read = 0;
+    while read < len.len() {
+        match reader.read(&mut len[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+            Ok(n) => read += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let id = read_bytes(reader, u32::from_be_bytes(len))?;
+
+    let count = read_u32(reader)?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let kind = read_byte(reader)?;
+        let len = read_u32(reader)?;
+        let key = read_bytes(reader, len)?;
+        let key = match kind {
+            0 => TrieLogKey::Trie(key),
+            1 => TrieLogKey::Flat(key),
+            kind => {
+                return Err(TrieLogFileError::InvalidTrieLog(format!(
+                    "Invalid key kind {}",
+                    kind
+                )))
+            }
+        };
+        entries.push(TrieLogEntry {
+            key,
+            old_value: read_value(reader)?,
+            new_value: read_value(reader)?,
+        });
+    }
+    Ok(Some((id, entries)))
+}
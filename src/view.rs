@@ -0,0 +1,91 @@
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use bitvec::{order::Msb0, slice::BitSlice, vec::BitVec};
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+
+use crate::{
+    id::Id, trie::merkle_tree::MerkleTree, BonsaiDatabase, BonsaiStorage, BonsaiStorageError,
+    BonsaiTrieHash, ProofNode,
+};
+
+/// Read-only view of a [`BonsaiStorage`] as of a past commit, see [`BonsaiStorage::view_at`].
+///
+/// Nodes and values are read as they were at that commit from the database and the trie logs of
+/// the later commits, the storage isn't cloned nor reverted. The view borrows the storage, which
+/// can't be modified while the view is alive.
+pub struct BonsaiStorageView<'a, ChangeID, DB, H>
+where
+    DB: BonsaiDatabase,
+    ChangeID: Id,
+    H: StarkHash,
+{
+    trie: &'a MerkleTree<H, DB, ChangeID>,
+    id: ChangeID,
+    root_hash: Felt,
+}
+
+impl<'a, ChangeID, DB, H> BonsaiStorageView<'a, ChangeID, DB, H>
+where
+    DB: BonsaiDatabase,
+    ChangeID: Id,
+    H: StarkHash,
+{
+    pub(crate) fn new(
+        storage: &'a BonsaiStorage<ChangeID, DB, H>,
+        id: ChangeID,
+    ) -> Result<Self, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(Self {
+            trie: &storage.trie,
+            id,
+            root_hash: storage.trie.root_hash_at(id)?,
+        })
+    }
+
+    /// Commit of the view.
+    pub fn id(&self) -> ChangeID {
+        self.id
+    }
+
+    /// Get trie root hash at the commit of the view.
+    pub fn root_hash(&self) -> BonsaiTrieHash {
+        self.root_hash
+    }
+
+    /// Get a value in the trie.
+    pub fn get(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.get_at(self.id, key)
+    }
+
+    /// Checks if the key exists in the trie.
+    pub fn contains(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Generates a merkle-proof of `key` against the root hash of the view, see
+    /// [`BonsaiStorage::get_proof`].
+    pub fn get_proof(
+        &self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Vec<ProofNode>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie.get_proof_at(self.id, key)
+    }
+
+    /// Iterate over all the key/value pairs of the trie, in key order, see
+    /// [`BonsaiStorage::iter_at`].
+    #[allow(clippy::type_complexity)]
+    pub fn iter(
+        &self,
+    ) -> Result<
+        impl Iterator<Item = Result<(BitVec<u8, Msb0>, Felt), BonsaiStorageError<DB::DatabaseError>>>
+            + 'a,
+        BonsaiStorageError<DB::DatabaseError>,
+    > {
+        self.trie.iter_at(self.id)
+    }
+}
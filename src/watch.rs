@@ -0,0 +1,108 @@
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, vec::Vec};
+use bitvec::{order::Msb0, slice::BitSlice, vec::BitVec};
+use parity_scale_codec::Decode;
+use starknet_types_core::felt::Felt;
+
+use crate::{
+    changes::ChangeBatch,
+    id::Id,
+    trie::{merkle_tree::bytes_to_bitvec, TrieKey},
+    KeyChange,
+};
+
+/// Identifier of a subscription made with [`BonsaiStorage::watch`](crate::BonsaiStorage::watch).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatchId(u64);
+
+/// Callback of a subscription, called with the id of the commit and the changes of the watched
+/// keys, sorted by key.
+pub(crate) type WatchCallback<ChangeID> = Box<dyn FnMut(ChangeID, &[KeyChange]) + Send + Sync>;
+
+struct Watcher<ChangeID> {
+    id: WatchId,
+    prefix: BitVec<u8, Msb0>,
+    callback: WatchCallback<ChangeID>,
+}
+
+/// Subscriptions to the changes of the keys starting with some prefixes.
+#[derive(Default)]
+pub(crate) struct Watchers<ChangeID> {
+    watchers: Vec<Watcher<ChangeID>>,
+    next_id: u64,
+}
+
+impl<ChangeID: Id> Watchers<ChangeID> {
+    pub(crate) fn watch(
+        &mut self,
+        prefix: &BitSlice<u8, Msb0>,
+        callback: WatchCallback<ChangeID>,
+    ) -> WatchId {
+        let id = WatchId(self.next_id);
+        self.next_id += 1;
+        self.watchers.push(Watcher {
+            id,
+            prefix: prefix.to_bitvec(),
+            callback,
+        });
+        id
+    }
+
+    /// Returns whether the subscription existed.
+    pub(crate) fn unwatch(&mut self, id: WatchId) -> bool {
+        let len = self.watchers.len();
+        self.watchers.retain(|watcher| watcher.id != id);
+        self.watchers.len() != len
+    }
+
+    /// Changes of the values of the batch to the keys watched by any subscription, sorted by key.
+    pub(crate) fn watched_changes(&self, batch: &ChangeBatch) -> Vec<KeyChange> {
+        if self.watchers.is_empty() {
+            return Vec::new();
+        }
+        let mut changes: Vec<_> = batch
+            .0
+            .iter()
+            .filter_map(|(key, change)| {
+                let TrieKey::Flat(key) = key else {
+                    return None;
+                };
+                if change.old_value == change.new_value {
+                    return None;
+                }
+                let key = bytes_to_bitvec(key);
+                self.watchers
+                    .iter()
+                    .any(|watcher| key.starts_with(watcher.prefix.as_bitslice()))
+                    .then(|| KeyChange {
+                        key,
+                        // SAFETY: We are sure that the values are valid Felt because they can be saved only by our crate
+                        old_value: change
+                            .old_value
+                            .as_ref()
+                            .map(|value| Felt::decode(&mut value.as_slice()).unwrap()),
+                        new_value: change
+                            .new_value
+                            .as_ref()
+                            .map(|value| Felt::decode(&mut value.as_slice()).unwrap()),
+                    })
+            })
+            .collect();
+        changes.sort_by(|a, b| a.key.cmp(&b.key));
+        changes
+    }
+
+    /// Calls each subscription with the changes to its keys, if there are any.
+    pub(crate) fn notify(&mut self, id: ChangeID, changes: &[KeyChange]) {
+        for watcher in self.watchers.iter_mut() {
+            let watched: Vec<_> = changes
+                .iter()
+                .filter(|change| change.key.starts_with(watcher.prefix.as_bitslice()))
+                .cloned()
+                .collect();
+            if !watched.is_empty() {
+                (watcher.callback)(id, &watched);
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap;
+use bitvec::{order::Msb0, slice::BitSlice, vec::BitVec};
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+use crate::{error::BonsaiStorageError, id::Id, proof::Witness, BonsaiDatabase, BonsaiStorage};
+
+/// Records the keys read and written through it, to build the [`Witness`] of a block.
+///
+/// The recorder must be created right after a commit: the witness proves the values of the
+/// touched keys as of the last commit. Writes are kept aside so that the storage stays at that
+/// commit while the block runs, and are applied to the storage by [`WitnessRecorder::finish`].
+pub struct WitnessRecorder<'a, ChangeID, DB, H>
+where
+    DB: BonsaiDatabase,
+    ChangeID: Id,
+    H: StarkHash,
+{
+    storage: &'a mut BonsaiStorage<ChangeID, DB, H>,
+    /// Index of each touched key in the witness
+    touched: BTreeMap<BitVec<u8, Msb0>, usize>,
+    witness: Witness,
+    /// Pending writes, `None` for a removal
+    writes: BTreeMap<BitVec<u8, Msb0>, Option<Felt>>,
+}
+
+impl<'a, ChangeID, DB, H> WitnessRecorder<'a, ChangeID, DB, H>
+where
+    DB: BonsaiDatabase,
+    ChangeID: Id,
+    H: StarkHash,
+{
+    /// Starts recording the accesses to `storage`.
+    pub fn new(storage: &'a mut BonsaiStorage<ChangeID, DB, H>) -> Self {
+        Self {
+            storage,
+            touched: BTreeMap::new(),
+            witness: Witness::default(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Get a value in the trie, pending writes included.
+    pub fn get(
+        &mut self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        if let Some(value) = self.writes.get(key) {
+            return Ok(*value);
+        }
+        let index = self.record(key)?;
+        Ok(self.witness.values[index])
+    }
+
+    /// Insert a new key/value in the trie once the recording is finished.
+    pub fn insert(
+        &mut self,
+        key: &BitSlice<u8, Msb0>,
+        value: &Felt,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.record(key)?;
+        // Setting a key to zero removes it
+        let value = (*value != Felt::ZERO).then_some(*value);
+        self.writes.insert(key.to_bitvec(), value);
+        Ok(())
+    }
+
+    /// Remove a key/value in the trie once the recording is finished.
+    pub fn remove(
+        &mut self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.record(key)?;
+        self.writes.insert(key.to_bitvec(), None);
+        Ok(())
+    }
+
+    /// Applies the pending writes to the storage and returns the witness of all the keys
+    /// touched. The changes still have to be committed.
+    pub fn finish(self) -> Result<Witness, BonsaiStorageError<DB::DatabaseError>> {
+        for (key, value) in self.writes.iter() {
+            match value {
+                Some(value) => self.storage.insert(key, value)?,
+                None => self.storage.remove(key)?,
+            }
+        }
+        Ok(self.witness)
+    }
+
+    /// Adds the value and proof of `key` at the last commit to the witness, once.
+    fn record(
+        &mut self,
+        key: &BitSlice<u8, Msb0>,
+    ) -> Result<usize, BonsaiStorageError<DB::DatabaseError>> {
+        if let Some(index) = self.touched.get(key) {
+            return Ok(*index);
+        }
+        let index = self.witness.keys.len();
+        self.witness.keys.push(key.to_bitvec());
+        self.witness.values.push(self.storage.get(key)?);
+        self.witness.proofs.0.push(self.storage.get_proof(key)?);
+        self.touched.insert(key.to_bitvec(), index);
+        Ok(index)
+    }
+}